@@ -24,34 +24,159 @@ typedef enum RenderVertexInputIndex
 #define tilerGroupWidth 16
 #define tilerGroupHeight 2
 
-// The number of bytes in a buffer for a single tile.
-// For prototyping, this is a hard maximum, but for production we'd want
-// a mechanism to overflow.
-#define tileBufSize 4096
+// The command list of each tile starts in its own chunk of the tile buffer,
+// and continues in chunks from a pool shared by all tiles, which follows the
+// first chunks of the tiles in the tile buffer.
+#define tileChunkSize 512
 
-// For simplicity, we're going to hardcode these dimensions. For production,
-// they need to be dynamic.
-#define maxTilesWidth 256
-#define maxTilesHeight 256
+// The dimensions of the tile grid are not fixed: they are given by the TileGrid
+// of piet_metal.h, passed to the kernels as a uint2 in buffer(2).
+
+// State of the pool of command list chunks, in buffer(3) of the tiler. The
+// host resets it before tiling. If the pool runs out, the tiler sets overflow,
+// and the host should grow the pool and tile again.
+typedef struct TilePool {
+    // Capacity of the pool, in chunks
+    uint nChunks;
+    // Number of chunks handed out, incremented atomically
+    uint nAllocated;
+    uint overflow;
+} TilePool;
+
+// Maximum nesting depth of groups, as the tiler keeps a fixed-size stack.
+#define maxGroupDepth 8
+
+// Tolerance in pixels for flattening curve segments in the tiler, and a cap
+// on the number of lines a single segment is flattened into. The encoder splits
+// curves that would need more.
+#define flattenTolerance 0.1
+#define maxFlattenSegs 64
+
+// Distance in pixels that a miter or bevel join piece extends along the lines
+// it joins, so that it overlaps their bodies rather than leaving a seam.
+#define joinOverlap 1.0
 
 // This should be an enum but the storage needs to be of fixed size
 #define PIET_ITEM_CIRCLE 1
 #define PIET_ITEM_LINE 2
 #define PIET_ITEM_FILL 3
 #define PIET_ITEM_STROKE_POLYLINE 4
+#define PIET_ITEM_GROUP 5
+#define PIET_ITEM_FILL_PATH 6
+#define PIET_ITEM_STROKE_PATH 7
+#define PIET_ITEM_CLIP 8
+#define PIET_ITEM_LAYER 9
+
+#define PIET_SEG_LINE 1
+#define PIET_SEG_QUAD 2
+#define PIET_SEG_CUBIC 3
+
+// Flag bits for fill items
+#define PIET_FILL_EVEN_ODD 1
+
+// Flag bit in draw items whose rgbaColor is the offset of a PietGradient
+// rather than a color.
+#define PIET_PAINT_GRADIENT (1 << 8)
+
+// Flag bit in draw items whose rgbaColor is the offset of a PietImageFill.
+#define PIET_PAINT_IMAGE (1 << 9)
+
+// Image fill flag selecting bilinear rather than nearest-neighbor sampling.
+#define PIET_IMAGE_BILINEAR 1
+
+// Gradient flags: the shape, and how it extends past its end stops.
+#define PIET_GRADIENT_RADIAL 1
+#define PIET_GRADIENT_EXTEND_MASK (3 << 1)
+#define PIET_GRADIENT_EXTEND_PAD 0
+#define PIET_GRADIENT_EXTEND_REPEAT (1 << 1)
+#define PIET_GRADIENT_EXTEND_REFLECT (2 << 1)
+
+// Blend modes of layers, as in the W3C Compositing and Blending spec.
+#define PIET_BLEND_NORMAL 0
+#define PIET_BLEND_MULTIPLY 1
+#define PIET_BLEND_SCREEN 2
+#define PIET_BLEND_OVERLAY 3
+#define PIET_BLEND_DARKEN 4
+#define PIET_BLEND_LIGHTEN 5
+#define PIET_BLEND_COLOR_DODGE 6
+#define PIET_BLEND_COLOR_BURN 7
+#define PIET_BLEND_HARD_LIGHT 8
+#define PIET_BLEND_SOFT_LIGHT 9
+#define PIET_BLEND_DIFFERENCE 10
+#define PIET_BLEND_EXCLUSION 11
+#define PIET_BLEND_HUE 12
+#define PIET_BLEND_SATURATION 13
+#define PIET_BLEND_COLOR 14
+#define PIET_BLEND_LUMINOSITY 15
+
+// Stroke style flags. The default of 0 is round caps and joins.
+#define PIET_STROKE_CAP_MASK 3
+#define PIET_STROKE_CAP_ROUND 0
+#define PIET_STROKE_CAP_BUTT 1
+#define PIET_STROKE_CAP_SQUARE 2
+#define PIET_STROKE_JOIN_MASK (3 << 2)
+#define PIET_STROKE_JOIN_ROUND 0
+#define PIET_STROKE_JOIN_MITER (1 << 2)
+#define PIET_STROKE_JOIN_BEVEL (2 << 2)
+// Set on a polyline whose last point is the same as its first, to join the
+// ends rather than cap them.
+#define PIET_STROKE_CLOSED (1 << 4)
+
+// Segment flags, marking the first and last segments of subpaths. Closed
+// subpaths have PIET_SEG_CLOSED on both, and are joined rather than capped.
+#define PIET_SEG_SUBPATH_START 1
+#define PIET_SEG_SUBPATH_END 2
+#define PIET_SEG_CLOSED 4
 
 // Autogen part follows:
 
+typedef uint SceneHeaderRef;
 typedef uint SimpleGroupRef;
 typedef uint PietCircleRef;
 typedef uint PietStrokeLineRef;
 typedef uint PietFillRef;
 typedef uint PietStrokePolyLineRef;
+typedef uint PietGroupRef;
+typedef uint PietSegmentRef;
+typedef uint PietFillPathRef;
+typedef uint PietStrokePathRef;
+typedef uint PietClipRef;
+typedef uint PietGradientStopRef;
+typedef uint PietGradientRef;
+typedef uint PietImageFillRef;
+typedef uint PietImageRef;
+typedef uint PietLayerRef;
 typedef uint PietItemRef;
+struct SceneHeaderPacked {
+    SimpleGroupRef root;
+    uint n_images;
+    PietImageRef images;
+    uint background;
+};
+SceneHeaderPacked SceneHeader_read(const device char *buf, SceneHeaderRef ref) {
+    return *((const device SceneHeaderPacked *)(buf + ref));
+}
+SimpleGroupRef SceneHeader_root(const device char *buf, SceneHeaderRef ref) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->root;
+}
+uint SceneHeader_n_images(const device char *buf, SceneHeaderRef ref) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->n_images;
+}
+PietImageRef SceneHeader_images(const device char *buf, SceneHeaderRef ref) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->images;
+}
+uint SceneHeader_background(const device char *buf, SceneHeaderRef ref) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->background;
+}
+uint SceneHeader_images_len(const device char *buf, SceneHeaderRef ref) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->n_images;
+}
+PietImageRef SceneHeader_images_at(const device char *buf, SceneHeaderRef ref, uint i) {
+    return ((const device SceneHeaderPacked *)(buf + ref))->images + i * 12;
+}
 struct SimpleGroupPacked {
     uint n_items;
     PietItemRef items_ix;
-    ushort4 bbox;
 };
 SimpleGroupPacked SimpleGroup_read(const device char *buf, SimpleGroupRef ref) {
     return *((const device SimpleGroupPacked *)(buf + ref));
@@ -62,8 +187,17 @@ uint SimpleGroup_n_items(const device char *buf, SimpleGroupRef ref) {
 PietItemRef SimpleGroup_items_ix(const device char *buf, SimpleGroupRef ref) {
     return ((const device SimpleGroupPacked *)(buf + ref))->items_ix;
 }
-ushort4 SimpleGroup_bbox(const device char *buf, SimpleGroupRef ref) {
-    return ((const device SimpleGroupPacked *)(buf + ref))->bbox;
+uint SimpleGroup_items_ix_len(const device char *buf, SimpleGroupRef ref) {
+    return ((const device SimpleGroupPacked *)(buf + ref))->n_items;
+}
+PietItemRef SimpleGroup_items_ix_at(const device char *buf, SimpleGroupRef ref, uint i) {
+    return ((const device SimpleGroupPacked *)(buf + ref))->items_ix + i * 32;
+}
+uint SimpleGroup_bboxes_len(const device char *buf, SimpleGroupRef ref) {
+    return ((const device SimpleGroupPacked *)(buf + ref))->n_items;
+}
+int4 SimpleGroup_bboxes_at(const device char *buf, SimpleGroupRef ref, uint i) {
+    return ((const device packed_int4 *)(buf + ref + 8))[i];
 }
 struct PietCirclePacked {
     uint tag;
@@ -119,12 +253,21 @@ uint PietFill_n_points(const device char *buf, PietFillRef ref) {
 uint PietFill_points_ix(const device char *buf, PietFillRef ref) {
     return ((const device PietFillPacked *)(buf + ref))->points_ix;
 }
+uint PietFill_points_ix_len(const device char *buf, PietFillRef ref) {
+    return ((const device PietFillPacked *)(buf + ref))->n_points;
+}
+float2 PietFill_points_ix_at(const device char *buf, PietFillRef ref, uint i) {
+    uint base = ((const device PietFillPacked *)(buf + ref))->points_ix;
+    return ((const device packed_float2 *)(buf + base))[i];
+}
 struct PietStrokePolyLinePacked {
     uint tag;
     uint rgba_color;
     float width;
     uint n_points;
     uint points_ix;
+    uint flags;
+    float miter_limit;
 };
 PietStrokePolyLinePacked PietStrokePolyLine_read(const device char *buf, PietStrokePolyLineRef ref) {
     return *((const device PietStrokePolyLinePacked *)(buf + ref));
@@ -141,6 +284,258 @@ uint PietStrokePolyLine_n_points(const device char *buf, PietStrokePolyLineRef r
 uint PietStrokePolyLine_points_ix(const device char *buf, PietStrokePolyLineRef ref) {
     return ((const device PietStrokePolyLinePacked *)(buf + ref))->points_ix;
 }
+uint PietStrokePolyLine_flags(const device char *buf, PietStrokePolyLineRef ref) {
+    return ((const device PietStrokePolyLinePacked *)(buf + ref))->flags;
+}
+float PietStrokePolyLine_miter_limit(const device char *buf, PietStrokePolyLineRef ref) {
+    return ((const device PietStrokePolyLinePacked *)(buf + ref))->miter_limit;
+}
+uint PietStrokePolyLine_points_ix_len(const device char *buf, PietStrokePolyLineRef ref) {
+    return ((const device PietStrokePolyLinePacked *)(buf + ref))->n_points;
+}
+float2 PietStrokePolyLine_points_ix_at(const device char *buf, PietStrokePolyLineRef ref, uint i) {
+    uint base = ((const device PietStrokePolyLinePacked *)(buf + ref))->points_ix;
+    return ((const device packed_float2 *)(buf + base))[i];
+}
+struct PietGroupPacked {
+    uint tag;
+    SimpleGroupRef group;
+    float2 translate;
+    float4 mat;
+};
+PietGroupPacked PietGroup_read(const device char *buf, PietGroupRef ref) {
+    return *((const device PietGroupPacked *)(buf + ref));
+}
+SimpleGroupRef PietGroup_group(const device char *buf, PietGroupRef ref) {
+    return ((const device PietGroupPacked *)(buf + ref))->group;
+}
+float2 PietGroup_translate(const device char *buf, PietGroupRef ref) {
+    return ((const device PietGroupPacked *)(buf + ref))->translate;
+}
+float4 PietGroup_mat(const device char *buf, PietGroupRef ref) {
+    return ((const device PietGroupPacked *)(buf + ref))->mat;
+}
+struct PietSegmentPacked {
+    uint seg_type;
+    uint flags;
+    float2 p0;
+    float2 p1;
+    float2 p2;
+    float2 p3;
+};
+PietSegmentPacked PietSegment_read(const device char *buf, PietSegmentRef ref) {
+    return *((const device PietSegmentPacked *)(buf + ref));
+}
+uint PietSegment_seg_type(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->seg_type;
+}
+uint PietSegment_flags(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->flags;
+}
+float2 PietSegment_p0(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->p0;
+}
+float2 PietSegment_p1(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->p1;
+}
+float2 PietSegment_p2(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->p2;
+}
+float2 PietSegment_p3(const device char *buf, PietSegmentRef ref) {
+    return ((const device PietSegmentPacked *)(buf + ref))->p3;
+}
+struct PietFillPathPacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+};
+PietFillPathPacked PietFillPath_read(const device char *buf, PietFillPathRef ref) {
+    return *((const device PietFillPathPacked *)(buf + ref));
+}
+uint PietFillPath_flags(const device char *buf, PietFillPathRef ref) {
+    return ((const device PietFillPathPacked *)(buf + ref))->flags;
+}
+uint PietFillPath_rgba_color(const device char *buf, PietFillPathRef ref) {
+    return ((const device PietFillPathPacked *)(buf + ref))->rgba_color;
+}
+uint PietFillPath_n_segs(const device char *buf, PietFillPathRef ref) {
+    return ((const device PietFillPathPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietFillPath_segs_ix(const device char *buf, PietFillPathRef ref) {
+    return ((const device PietFillPathPacked *)(buf + ref))->segs_ix;
+}
+uint PietFillPath_segs_ix_len(const device char *buf, PietFillPathRef ref) {
+    return ((const device PietFillPathPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietFillPath_segs_ix_at(const device char *buf, PietFillPathRef ref, uint i) {
+    return ((const device PietFillPathPacked *)(buf + ref))->segs_ix + i * 40;
+}
+struct PietStrokePathPacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    float width;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    float miter_limit;
+};
+PietStrokePathPacked PietStrokePath_read(const device char *buf, PietStrokePathRef ref) {
+    return *((const device PietStrokePathPacked *)(buf + ref));
+}
+uint PietStrokePath_flags(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->flags;
+}
+uint PietStrokePath_rgba_color(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->rgba_color;
+}
+float PietStrokePath_width(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->width;
+}
+uint PietStrokePath_n_segs(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietStrokePath_segs_ix(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->segs_ix;
+}
+float PietStrokePath_miter_limit(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->miter_limit;
+}
+uint PietStrokePath_segs_ix_len(const device char *buf, PietStrokePathRef ref) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietStrokePath_segs_ix_at(const device char *buf, PietStrokePathRef ref, uint i) {
+    return ((const device PietStrokePathPacked *)(buf + ref))->segs_ix + i * 40;
+}
+struct PietClipPacked {
+    uint tag;
+    uint flags;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    SimpleGroupRef group;
+};
+PietClipPacked PietClip_read(const device char *buf, PietClipRef ref) {
+    return *((const device PietClipPacked *)(buf + ref));
+}
+uint PietClip_flags(const device char *buf, PietClipRef ref) {
+    return ((const device PietClipPacked *)(buf + ref))->flags;
+}
+uint PietClip_n_segs(const device char *buf, PietClipRef ref) {
+    return ((const device PietClipPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietClip_segs_ix(const device char *buf, PietClipRef ref) {
+    return ((const device PietClipPacked *)(buf + ref))->segs_ix;
+}
+SimpleGroupRef PietClip_group(const device char *buf, PietClipRef ref) {
+    return ((const device PietClipPacked *)(buf + ref))->group;
+}
+uint PietClip_segs_ix_len(const device char *buf, PietClipRef ref) {
+    return ((const device PietClipPacked *)(buf + ref))->n_segs;
+}
+PietSegmentRef PietClip_segs_ix_at(const device char *buf, PietClipRef ref, uint i) {
+    return ((const device PietClipPacked *)(buf + ref))->segs_ix + i * 40;
+}
+struct PietGradientStopPacked {
+    float offset;
+    uint rgba_color;
+};
+PietGradientStopPacked PietGradientStop_read(const device char *buf, PietGradientStopRef ref) {
+    return *((const device PietGradientStopPacked *)(buf + ref));
+}
+float PietGradientStop_offset(const device char *buf, PietGradientStopRef ref) {
+    return ((const device PietGradientStopPacked *)(buf + ref))->offset;
+}
+uint PietGradientStop_rgba_color(const device char *buf, PietGradientStopRef ref) {
+    return ((const device PietGradientStopPacked *)(buf + ref))->rgba_color;
+}
+struct PietGradientPacked {
+    float4 mat;
+    float2 translate;
+    uint flags;
+    uint n_stops;
+    PietGradientStopRef stops_ix;
+};
+PietGradientPacked PietGradient_read(const device char *buf, PietGradientRef ref) {
+    return *((const device PietGradientPacked *)(buf + ref));
+}
+float4 PietGradient_mat(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->mat;
+}
+float2 PietGradient_translate(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->translate;
+}
+uint PietGradient_flags(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->flags;
+}
+uint PietGradient_n_stops(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->n_stops;
+}
+PietGradientStopRef PietGradient_stops_ix(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->stops_ix;
+}
+uint PietGradient_stops_ix_len(const device char *buf, PietGradientRef ref) {
+    return ((const device PietGradientPacked *)(buf + ref))->n_stops;
+}
+PietGradientStopRef PietGradient_stops_ix_at(const device char *buf, PietGradientRef ref, uint i) {
+    return ((const device PietGradientPacked *)(buf + ref))->stops_ix + i * 8;
+}
+struct PietImageFillPacked {
+    float4 mat;
+    float2 translate;
+    uint flags;
+    uint image_ix;
+};
+PietImageFillPacked PietImageFill_read(const device char *buf, PietImageFillRef ref) {
+    return *((const device PietImageFillPacked *)(buf + ref));
+}
+float4 PietImageFill_mat(const device char *buf, PietImageFillRef ref) {
+    return ((const device PietImageFillPacked *)(buf + ref))->mat;
+}
+float2 PietImageFill_translate(const device char *buf, PietImageFillRef ref) {
+    return ((const device PietImageFillPacked *)(buf + ref))->translate;
+}
+uint PietImageFill_flags(const device char *buf, PietImageFillRef ref) {
+    return ((const device PietImageFillPacked *)(buf + ref))->flags;
+}
+uint PietImageFill_image_ix(const device char *buf, PietImageFillRef ref) {
+    return ((const device PietImageFillPacked *)(buf + ref))->image_ix;
+}
+struct PietImagePacked {
+    uint width;
+    uint height;
+    uint data_ix;
+};
+PietImagePacked PietImage_read(const device char *buf, PietImageRef ref) {
+    return *((const device PietImagePacked *)(buf + ref));
+}
+uint PietImage_width(const device char *buf, PietImageRef ref) {
+    return ((const device PietImagePacked *)(buf + ref))->width;
+}
+uint PietImage_height(const device char *buf, PietImageRef ref) {
+    return ((const device PietImagePacked *)(buf + ref))->height;
+}
+uint PietImage_data_ix(const device char *buf, PietImageRef ref) {
+    return ((const device PietImagePacked *)(buf + ref))->data_ix;
+}
+struct PietLayerPacked {
+    uint tag;
+    float alpha;
+    SimpleGroupRef group;
+    uint blend;
+};
+PietLayerPacked PietLayer_read(const device char *buf, PietLayerRef ref) {
+    return *((const device PietLayerPacked *)(buf + ref));
+}
+float PietLayer_alpha(const device char *buf, PietLayerRef ref) {
+    return ((const device PietLayerPacked *)(buf + ref))->alpha;
+}
+SimpleGroupRef PietLayer_group(const device char *buf, PietLayerRef ref) {
+    return ((const device PietLayerPacked *)(buf + ref))->group;
+}
+uint PietLayer_blend(const device char *buf, PietLayerRef ref) {
+    return ((const device PietLayerPacked *)(buf + ref))->blend;
+}
 struct PietItem {
     uint tag;
     uint body[7];
@@ -152,4 +547,9 @@ uint PietItem_tag(const device char *buf, PietItemRef ref) {
 #define PietItem_Line 2
 #define PietItem_Fill 3
 #define PietItem_Poly 4
+#define PietItem_Group 5
+#define PietItem_FillPath 6
+#define PietItem_StrokePath 7
+#define PietItem_Clip 8
+#define PietItem_Layer 9
 
@@ -48,11 +48,57 @@ fragment half4 fragmentShader(RenderData in [[stage_in]],
 void stroke(thread float &df, float2 pos, float2 start, float2 end) {
     float2 lineVec = end - start;
     float2 dPos = pos - start;
-    float t = saturate(dot(lineVec, dPos) / dot(lineVec, lineVec));
+    float len2 = dot(lineVec, lineVec);
+    // A zero length line is a dot, used for round caps and joins.
+    float t = len2 > 0.0 ? saturate(dot(lineVec, dPos) / len2) : 0.0;
     float field = length(lineVec * t - dPos);
     df = min(df, field);
 }
 
+// Accumulate a line with butt ends. This is the distance field of a rectangle,
+// offset by the half width so that it combines with the round lines.
+void strokeButt(thread float &df, float2 pos, float2 start, float2 end, float halfWidth) {
+    float2 dir = normalize(end - start);
+    float2 dPos = pos - start;
+    float len = dot(dir, end - start);
+    float u = dot(dir, dPos);
+    float v = abs(dir.x * dPos.y - dir.y * dPos.x);
+    df = min(df, max(v, max(halfWidth - u, halfWidth + u - len)));
+}
+
+// The unit normals of a join on its outer side, which is opposite to the
+// direction of the turn.
+void joinNormals(float2 dirIn, float2 dirOut, thread float2 &nIn, thread float2 &nOut) {
+    float s = dirIn.x * dirOut.y - dirIn.y * dirOut.x >= 0.0 ? -1.0 : 1.0;
+    nIn = s * float2(-dirIn.y, dirIn.x);
+    nOut = s * float2(-dirOut.y, dirOut.x);
+}
+
+// Accumulate a miter or bevel join. This is an intersection of half-planes: the
+// outer edges of both lines, the cut, and limits on how far the piece extends
+// along the lines and to the inner side.
+void strokeJoinPiece(thread float &df, float2 pos, float2 point, float2 dirIn, float2 dirOut,
+                float cut, float halfWidth) {
+    float2 nIn, nOut;
+    joinNormals(dirIn, dirOut, nIn, nOut);
+    float2 m = normalize(nIn + nOut);
+    float2 dPos = pos - point;
+    float edge = max(dot(dPos, nIn), dot(dPos, nOut)) - halfWidth;
+    float along = max(-dot(dPos, dirIn), dot(dPos, dirOut)) - joinOverlap;
+    float mid = max(dot(dPos, m) - cut, -dot(dPos, m) - halfWidth);
+    df = min(df, max(edge, max(along, mid)) + halfWidth);
+}
+
+// Coverage of a fill from its signed area, with the backdrop added.
+half fillAlpha(half area, uint flags) {
+    if (flags & PIET_FILL_EVEN_ODD) {
+        // distance to the nearest even winding number
+        return abs(area - 2.0h * round(0.5h * area));
+    } else {
+        return min(abs(area), 1.0h); // nonzero winding rule
+    }
+}
+
 // TODO: figure out precision so we can move more stuff to half
 half renderDf(float df, float halfWidth) {
     return saturate(halfWidth + 0.5 - df);
@@ -67,13 +113,21 @@ struct CmdEnd {
     ushort cmd;
 };
 
+// Continue the command list at an offset in the tile buffer, the start of a
+// chunk from the pool.
+struct CmdJump {
+    ushort cmd;
+    ushort _padding;
+    uint next;
+};
+
 // Render a circle. Used mainly for debugging (color is fixed and
 // coordinates are limited to integers), but could be adapted to
 // real use.
 struct CmdCircle {
     ushort cmd;
     ushort _padding;
-    packed_ushort4 bbox;
+    packed_int4 bbox;
 };
 
 // Render one line segment to the distance field buffer.
@@ -84,6 +138,25 @@ struct CmdLine {
     packed_float2 end;
 };
 
+// Render one line segment with butt ends to the distance field buffer.
+struct CmdLineButt {
+    ushort cmd;
+    half halfWidth;
+    packed_float2 start;
+    packed_float2 end;
+};
+
+// Render a miter or bevel join to the distance field buffer. The outer
+// edge is cut off at distance cut from the point.
+struct CmdJoin {
+    ushort cmd;
+    half halfWidth;
+    packed_float2 point;
+    packed_float2 dirIn;
+    packed_float2 dirOut;
+    float cut;
+};
+
 // Draw a stroke based on the distance field buffer.
 struct CmdStroke {
     ushort cmd;
@@ -113,6 +186,8 @@ struct CmdDrawFill {
     ushort cmd;
     short backdrop;
     uint rgba;
+    // PIET_FILL_EVEN_ODD selects the fill rule, as in the fill item.
+    uint flags;
 };
 
 struct CmdSolid {
@@ -121,6 +196,51 @@ struct CmdSolid {
     uint rgba;
 };
 
+// Push a clip, with coverage given by the signed area buffer, as for CmdDrawFill.
+struct CmdBeginClip {
+    ushort cmd;
+    short backdrop;
+    // PIET_FILL_EVEN_ODD selects the fill rule, as in the clip item.
+    uint flags;
+};
+
+// Pop the innermost clip.
+struct CmdEndClip {
+    ushort cmd;
+};
+
+// Push a layer, drawing the following commands onto a transparent buffer.
+struct CmdBeginLayer {
+    ushort cmd;
+};
+
+// Pop the innermost layer, compositing it onto the one underneath.
+struct CmdEndLayer {
+    ushort cmd;
+    half alpha;
+    uint blend;
+};
+
+// Paint the next CmdStroke, CmdDrawFill or CmdSolid with a gradient, rather
+// than its color. The transform is from pixels to the coordinates of the gradient.
+struct CmdGradient {
+    ushort cmd;
+    ushort _padding;
+    uint gradient;
+    packed_float2 translate;
+    packed_float4 mat;
+};
+
+// Paint the next draw command with an image fill, as for CmdGradient. The
+// transform is from pixels to those of the image.
+struct CmdImage {
+    ushort cmd;
+    ushort _padding;
+    uint fill;
+    packed_float2 translate;
+    packed_float4 mat;
+};
+
 // Maybe these should be an enum.
 #define CMD_END 0
 #define CMD_CIRCLE 1
@@ -130,16 +250,33 @@ struct CmdSolid {
 #define CMD_FILL_EDGE 5
 #define CMD_DRAW_FILL 6
 #define CMD_SOLID 7
+#define CMD_LINE_BUTT 8
+#define CMD_JOIN 9
+#define CMD_BEGIN_CLIP 10
+#define CMD_END_CLIP 11
+#define CMD_BEGIN_LAYER 12
+#define CMD_END_LAYER 13
+#define CMD_GRADIENT 14
+#define CMD_IMAGE 15
+#define CMD_JUMP 16
 #define CMD_BAIL 86
 
 struct TileEncoder {
 public:
-    TileEncoder(device char *dst) {
-        this->dst = dst;
-        this->tileBegin = dst;
-        this->solidColor = 0xffffffff;
+    TileEncoder(device char *tiles, uint tileIx, uint2 tileGrid, device TilePool *pool, uint background) {
+        this->tiles = tiles;
+        this->pool = pool;
+        this->poolBegin = tiles + tileGrid.x * tileGrid.y * tileChunkSize;
+        this->tileBegin = tiles + tileIx * tileChunkSize;
+        this->dst = tileBegin;
+        this->chunkEnd = tileBegin + tileChunkSize;
+        // Only an opaque color can be output without conversion.
+        this->solidColor = (background & 0xff000000) == 0xff000000 ? background : 0;
+        this->pushDepth = 0;
+        this->paintPending = false;
     }
-    void encodeCircle(ushort4 bbox) {
+    void encodeCircle(int4 bbox) {
+        reserve(sizeof(CmdCircle));
         device CmdCircle *cmd = (device CmdCircle *)dst;
         cmd->cmd = CMD_CIRCLE;
         cmd->bbox = bbox;
@@ -147,21 +284,45 @@ public:
         dst += sizeof(CmdCircle);
     }
     void encodeLine(float2 start, float2 end) {
+        reserve(sizeof(CmdLine));
         device CmdLine *cmd = (device CmdLine *)dst;
         cmd->cmd = CMD_LINE;
         cmd->start = start;
         cmd->end = end;
         dst += sizeof(CmdLine);
     }
+    void encodeLineButt(float2 start, float2 end, float halfWidth) {
+        reserve(sizeof(CmdLineButt));
+        device CmdLineButt *cmd = (device CmdLineButt *)dst;
+        cmd->cmd = CMD_LINE_BUTT;
+        cmd->halfWidth = halfWidth;
+        cmd->start = start;
+        cmd->end = end;
+        dst += sizeof(CmdLineButt);
+    }
+    void encodeJoin(float2 point, float2 dirIn, float2 dirOut, float cut, float halfWidth) {
+        reserve(sizeof(CmdJoin));
+        device CmdJoin *cmd = (device CmdJoin *)dst;
+        cmd->cmd = CMD_JOIN;
+        cmd->halfWidth = halfWidth;
+        cmd->point = point;
+        cmd->dirIn = dirIn;
+        cmd->dirOut = dirOut;
+        cmd->cut = cut;
+        dst += sizeof(CmdJoin);
+    }
     void encodeStroke(uint rgbaColor, float width) {
+        reserve(sizeof(CmdStroke));
         device CmdStroke *cmd = (device CmdStroke *)dst;
         cmd->cmd = CMD_STROKE;
         cmd->rgba = rgbaColor;
         cmd->halfWidth = 0.5 * width;
         solidColor = 0;
+        paintPending = false;
         dst += sizeof(CmdStroke);
     }
     void encodeFill(float2 start, float2 end) {
+        reserve(sizeof(CmdFill));
         device CmdFill *cmd = (device CmdFill *)dst;
         cmd->cmd = CMD_FILL;
         cmd->start = start;
@@ -169,18 +330,22 @@ public:
         dst += sizeof(CmdFill);
     }
     void encodeFillEdge(float sign, float y) {
+        reserve(sizeof(CmdFillEdge));
         device CmdFillEdge *cmd = (device CmdFillEdge *)dst;
         cmd->cmd = CMD_FILL_EDGE;
         cmd->sign = sign;
         cmd->y = y;
         dst += sizeof(CmdFillEdge);
     }
-    void encodeDrawFill(const thread PietFillPacked &fill, int backdrop) {
+    void encodeDrawFill(uint rgbaColor, uint flags, int backdrop) {
+        reserve(sizeof(CmdDrawFill));
         device CmdDrawFill *cmd = (device CmdDrawFill *)dst;
         cmd->cmd = CMD_DRAW_FILL;
         cmd->backdrop = backdrop;
-        cmd->rgba = fill.rgba_color;
+        cmd->rgba = rgbaColor;
+        cmd->flags = flags & PIET_FILL_EVEN_ODD;
         solidColor = 0;
+        paintPending = false;
         dst += sizeof(CmdDrawFill);
     }
     void encodeSolid(uint rgba) {
@@ -188,16 +353,75 @@ public:
         // solid blocks.
         
         // Another optimization is to skip encoding the default bg color.
-        if ((rgba & 0xff000000) == 0xff000000) {
+        // Inside a clip or layer, even an opaque solid doesn't cover what is underneath.
+        // With a gradient, rgba is not a color.
+        if ((rgba & 0xff000000) == 0xff000000 && pushDepth == 0 && !paintPending) {
             solidColor = rgba;
             dst = tileBegin;
+            chunkEnd = tileBegin + tileChunkSize;
+        } else {
+            solidColor = 0;
         }
+        reserve(sizeof(CmdSolid));
         device CmdSolid *cmd = (device CmdSolid *)dst;
         // Note: could defer writing, not sure how much of a win that is
         cmd->cmd = CMD_SOLID;
         cmd->rgba = rgba;
+        paintPending = false;
         dst += sizeof(CmdSolid);
     }
+    void encodeGradient(uint gradient, float4 mat, float2 translate) {
+        reserve(sizeof(CmdGradient));
+        device CmdGradient *cmd = (device CmdGradient *)dst;
+        cmd->cmd = CMD_GRADIENT;
+        cmd->gradient = gradient;
+        cmd->translate = translate;
+        cmd->mat = mat;
+        paintPending = true;
+        dst += sizeof(CmdGradient);
+    }
+    void encodeImage(uint fill, float4 mat, float2 translate) {
+        reserve(sizeof(CmdImage));
+        device CmdImage *cmd = (device CmdImage *)dst;
+        cmd->cmd = CMD_IMAGE;
+        cmd->fill = fill;
+        cmd->translate = translate;
+        cmd->mat = mat;
+        paintPending = true;
+        dst += sizeof(CmdImage);
+    }
+    void encodeBeginClip(uint flags, int backdrop) {
+        reserve(sizeof(CmdBeginClip));
+        device CmdBeginClip *cmd = (device CmdBeginClip *)dst;
+        cmd->cmd = CMD_BEGIN_CLIP;
+        cmd->backdrop = backdrop;
+        cmd->flags = flags & PIET_FILL_EVEN_ODD;
+        pushDepth++;
+        dst += sizeof(CmdBeginClip);
+    }
+    void encodeEndClip() {
+        reserve(sizeof(CmdEndClip));
+        device CmdEndClip *cmd = (device CmdEndClip *)dst;
+        cmd->cmd = CMD_END_CLIP;
+        pushDepth--;
+        dst += sizeof(CmdEndClip);
+    }
+    void encodeBeginLayer() {
+        reserve(sizeof(CmdBeginLayer));
+        device CmdBeginLayer *cmd = (device CmdBeginLayer *)dst;
+        cmd->cmd = CMD_BEGIN_LAYER;
+        pushDepth++;
+        dst += sizeof(CmdBeginLayer);
+    }
+    void encodeEndLayer(float alpha, uint blend) {
+        reserve(sizeof(CmdEndLayer));
+        device CmdEndLayer *cmd = (device CmdEndLayer *)dst;
+        cmd->cmd = CMD_END_LAYER;
+        cmd->alpha = alpha;
+        cmd->blend = blend;
+        pushDepth--;
+        dst += sizeof(CmdEndLayer);
+    }
     // return solid color
     uint end() {
         if (solidColor) {
@@ -209,25 +433,387 @@ public:
         return solidColor;
     }
 private:
-    // Pointer to command buffer for tile.
+    // Make room for a command of the given size, continuing in a new chunk if
+    // this one is full. Room for the largest command is always left at the end
+    // of a chunk, for the jump, and because the renderer reads commands at that
+    // size.
+    void reserve(uint size) {
+        if (dst + size + sizeof(CmdJoin) <= chunkEnd) {
+            return;
+        }
+        device atomic_uint *nAllocated = (device atomic_uint *)&pool->nAllocated;
+        uint chunkIx = atomic_fetch_add_explicit(nAllocated, 1, memory_order_relaxed);
+        if (chunkIx >= pool->nChunks) {
+            // Report the overflow, and start the tile over so that writes stay
+            // in bounds. The host grows the pool for the next frame.
+            atomic_store_explicit((device atomic_uint *)&pool->overflow, 1, memory_order_relaxed);
+            dst = tileBegin;
+            chunkEnd = tileBegin + tileChunkSize;
+            return;
+        }
+        device char *chunk = poolBegin + chunkIx * tileChunkSize;
+        device CmdJump *cmd = (device CmdJump *)dst;
+        cmd->cmd = CMD_JUMP;
+        cmd->next = chunk - tiles;
+        dst = chunk;
+        chunkEnd = chunk + tileChunkSize;
+    }
+
+    device char *tiles;
+    device TilePool *pool;
+    // Start of the chunks of the pool in the tile buffer.
+    device char *poolBegin;
+    // Pointer to command buffer for tile, and the end of the current chunk.
     device char *dst;
+    device char *chunkEnd;
     device char *tileBegin;
     uint solidColor;
+    // Number of clips and layers pushed in the command list and not yet popped.
+    uint pushDepth;
+    // Whether a paint has been encoded for the next draw command.
+    bool paintPending;
+};
+
+// Accumulate one line of a fill into the tile at (x0, y0).
+void fillLine(thread TileEncoder &encoder, float2 start, float2 end, ushort x0, ushort y0,
+              thread float &backdrop, thread bool &anyFill) {
+    float2 xymin = min(start, end);
+    float2 xymax = max(start, end);
+    if (!(xymax.y >= y0 && xymin.y < y0 + tileHeight)) {
+        return;
+    }
+    // set up line equation, ax + by + c = 0
+    float a = end.y - start.y;
+    float b = start.x - end.x;
+    float c = -(a * start.x + b * start.y);
+    float left = a * x0;
+    float right = a * (x0 + tileWidth);
+    float ytop = max(float(y0), xymin.y);
+    float ybot = min(float(y0 + tileHeight), xymax.y);
+    float top = b * ytop;
+    float bot = b * ybot;
+    // top left of tile
+    float sTopLeft = sign(left + float(y0) * b + c);
+    float s00 = sign(top + left + c);
+    float s01 = sign(top + right + c);
+    float s10 = sign(bot + left + c);
+    float s11 = sign(bot + right + c);
+    if (sTopLeft == sign(a) && xymin.y <= y0) {
+        backdrop -= s00;
+    }
+    // Lines touching the left edge count as crossing it, and vertical lines on
+    // it belong to this tile rather than the one to the left.
+    if (xymin.x < x0 && xymax.x >= x0) {
+        float yEdge = mix(start.y, end.y, (start.x - x0) / b);
+        if (yEdge >= y0 && yEdge < y0 + tileHeight) {
+            // line intersects left edge of this tile; the sign is that of
+            // the line equation above the intersection
+            encoder.encodeFillEdge(-sign(b), yEdge);
+            if (b > 0.0) {
+                encoder.encodeFill(start, float2(x0, yEdge));
+            } else {
+                encoder.encodeFill(float2(x0, yEdge), end);
+            }
+            anyFill = true;
+        } else if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0) {
+            encoder.encodeFill(start, end);
+            anyFill = true;
+        }
+    } else if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0
+               && xymin.x < x0 + tileWidth && xymax.x >= x0) {
+        encoder.encodeFill(start, end);
+        anyFill = true;
+    }
+}
+
+// Test whether a line is within distance d of the tile at (x0, y0).
+bool lineNear(float2 start, float2 end, ushort x0, ushort y0, float d) {
+    float2 xymin = min(start, end);
+    float2 xymax = max(start, end);
+    if (xymax.y > y0 - d && xymin.y < y0 + tileHeight + d &&
+        xymax.x > x0 - d && xymin.x < x0 + tileWidth + d) {
+        float a = end.y - start.y;
+        float b = start.x - end.x;
+        float c = -(a * start.x + b * start.y);
+        float left = a * (x0 - d);
+        float right = a * (x0 + tileWidth + d);
+        float top = b * (y0 - d);
+        float bot = b * (y0 + tileHeight + d);
+        // If all four corners are on same side of line, cull
+        float s00 = sign(top + left + c);
+        float s01 = sign(top + right + c);
+        float s10 = sign(bot + left + c);
+        float s11 = sign(bot + right + c);
+        return s00 * s01 + s00 * s10 + s00 * s11 < 3.0;
+    }
+    return false;
+}
+
+float2 safeNormalize(float2 v) {
+    float len = length(v);
+    return len > 0.0 ? v / len : float2(0.0);
+}
+
+struct StrokeStyle {
+    float width;
+    uint flags;
+    float miterLimit;
 };
 
+// The maximum distance from the path that the stroke can cover.
+float strokeReach(StrokeStyle style) {
+    float scale = 1.0;
+    if ((style.flags & PIET_STROKE_JOIN_MASK) == PIET_STROKE_JOIN_MITER) {
+        scale = max(scale, style.miterLimit);
+    }
+    if ((style.flags & PIET_STROKE_CAP_MASK) == PIET_STROKE_CAP_SQUARE) {
+        scale = max(scale, M_SQRT2_F);
+    }
+    return 0.5 * style.width * scale;
+}
+
+// Add the join between lines in directions dirIn and dirOut at point.
+void strokeJoin(thread TileEncoder &encoder, float2 point, float2 dirIn, float2 dirOut, StrokeStyle style) {
+    uint join = style.flags & PIET_STROKE_JOIN_MASK;
+    if (join == PIET_STROKE_JOIN_ROUND) {
+        encoder.encodeLine(point, point);
+        return;
+    }
+    float2 nIn, nOut;
+    joinNormals(dirIn, dirOut, nIn, nOut);
+    float mLen = length(nIn + nOut);
+    if (mLen < 1e-6) {
+        // The path doubles back; the butt ends of the lines coincide.
+        return;
+    }
+    // Cosine of half the angle between the normals.
+    float k = dot(nIn, nIn + nOut) / mLen;
+    float hw = 0.5 * style.width;
+    float cut = join == PIET_STROKE_JOIN_MITER && k * style.miterLimit >= 1.0 ? hw / k : hw * k;
+    encoder.encodeJoin(point, dirIn, dirOut, cut, hw);
+}
+
+// Add one line of a stroke to the tile at (x0, y0), if it is near enough to
+// affect it. Along with the line itself, this adds the join with the previous
+// line and the caps, as needed. With round caps and joins, only the line is
+// needed, as the distance field takes care of those.
+bool strokeStyledLine(thread TileEncoder &encoder, float2 start, float2 end,
+                      bool hasPrev, float2 prevDir, bool capStart, bool capEnd,
+                      StrokeStyle style, ushort x0, ushort y0) {
+    // TODO: is this bound as tight as it can be?
+    if (!lineNear(start, end, x0, y0, strokeReach(style) + 0.5)) {
+        return false;
+    }
+    if (style.flags == 0) {
+        encoder.encodeLine(start, end);
+        return true;
+    }
+    uint cap = style.flags & PIET_STROKE_CAP_MASK;
+    float hw = 0.5 * style.width;
+    float2 dir = normalize(end - start);
+    float2 bodyStart = start;
+    float2 bodyEnd = end;
+    if (capStart) {
+        if (cap == PIET_STROKE_CAP_ROUND) {
+            encoder.encodeLine(start, start);
+        } else if (cap == PIET_STROKE_CAP_SQUARE) {
+            bodyStart -= hw * dir;
+        }
+    }
+    if (capEnd) {
+        if (cap == PIET_STROKE_CAP_ROUND) {
+            encoder.encodeLine(end, end);
+        } else if (cap == PIET_STROKE_CAP_SQUARE) {
+            bodyEnd += hw * dir;
+        }
+    }
+    encoder.encodeLineButt(bodyStart, bodyEnd, hw);
+    if (hasPrev) {
+        strokeJoin(encoder, start, prevDir, dir, style);
+    }
+    return true;
+}
+
+// Curve segments are flattened at tile time, subdividing uniformly in t.
+// The number of lines is given by Wang's formula.
+uint flattenCount(const thread PietSegmentPacked &seg) {
+    float m;
+    if (seg.seg_type == PIET_SEG_CUBIC) {
+        m = 0.75 * max(length(seg.p0 - 2.0 * seg.p1 + seg.p2), length(seg.p1 - 2.0 * seg.p2 + seg.p3));
+    } else if (seg.seg_type == PIET_SEG_QUAD) {
+        m = 0.25 * length(seg.p0 - 2.0 * seg.p1 + seg.p2);
+    } else {
+        return 1;
+    }
+    return uint(clamp(ceil(sqrt(m / flattenTolerance)), 1.0, float(maxFlattenSegs)));
+}
+
+float2 evalSegment(const thread PietSegmentPacked &seg, float t) {
+    float mt = 1.0 - t;
+    if (seg.seg_type == PIET_SEG_CUBIC) {
+        return (mt * mt * mt) * seg.p0 + (3.0 * mt * mt * t) * seg.p1
+            + (3.0 * mt * t * t) * seg.p2 + (t * t * t) * seg.p3;
+    } else if (seg.seg_type == PIET_SEG_QUAD) {
+        return (mt * mt) * seg.p0 + (2.0 * mt * t) * seg.p1 + (t * t) * seg.p2;
+    } else {
+        return mix(seg.p0, seg.p1, t);
+    }
+}
+
+// Point i of the segment flattened into n lines.
+float2 flatPoint(const thread PietSegmentPacked &seg, uint i, uint n) {
+    if (i == 0) {
+        return seg.p0;
+    } else if (i == n) {
+        return seg.p3;
+    } else {
+        return evalSegment(seg, float(i) / float(n));
+    }
+}
+
+// Direction of the first and last lines of the flattened segment.
+float2 segStartDir(const thread PietSegmentPacked &seg) {
+    return safeNormalize(flatPoint(seg, 1, flattenCount(seg)) - seg.p0);
+}
+
+float2 segEndDir(const thread PietSegmentPacked &seg) {
+    uint n = flattenCount(seg);
+    return safeNormalize(seg.p3 - flatPoint(seg, n - 1, n));
+}
+
+// An affine transform, as in PietGroup: the linear part as the first four
+// coefficients of a kurbo Affine, and the translation.
+struct Transform {
+    float4 mat;
+    float2 translate;
+};
+
+float2 transformPoint(Transform t, float2 p) {
+    return t.mat.xy * p.x + t.mat.zw * p.y + t.translate;
+}
+
+// The transform that applies b, then a.
+Transform transformMul(Transform a, Transform b) {
+    Transform result;
+    result.mat = float4(a.mat.xy * b.mat.x + a.mat.zw * b.mat.y,
+                        a.mat.xy * b.mat.z + a.mat.zw * b.mat.w);
+    result.translate = transformPoint(a, b.translate);
+    return result;
+}
+
+// The inverse transform. A degenerate transform maps everything to the
+// translation, rather than having no inverse.
+Transform transformInverse(Transform t) {
+    float det = t.mat.x * t.mat.w - t.mat.y * t.mat.z;
+    float invDet = det != 0.0 ? 1.0 / det : 0.0;
+    Transform result;
+    result.mat = float4(t.mat.w, -t.mat.y, -t.mat.z, t.mat.x) * invDet;
+    result.translate = -(result.mat.xy * t.translate.x + result.mat.zw * t.translate.y);
+    return result;
+}
+
+// The factor by which stroke widths are scaled.
+float transformScale(Transform t) {
+    return sqrt(abs(t.mat.x * t.mat.w - t.mat.y * t.mat.z));
+}
+
+// Bounding box of a transformed bbox, as (xmin, ymin, xmax, ymax).
+float4 transformBbox(Transform t, int4 bbox) {
+    float2 p0 = transformPoint(t, float2(bbox.xy));
+    float2 p1 = transformPoint(t, float2(bbox.zy));
+    float2 p2 = transformPoint(t, float2(bbox.xw));
+    float2 p3 = transformPoint(t, float2(bbox.zw));
+    return float4(min(min(p0, p1), min(p2, p3)), max(max(p0, p1), max(p2, p3)));
+}
+
+void transformSegment(thread PietSegmentPacked &seg, Transform t) {
+    seg.p0 = transformPoint(t, seg.p0);
+    seg.p1 = transformPoint(t, seg.p1);
+    seg.p2 = transformPoint(t, seg.p2);
+    seg.p3 = transformPoint(t, seg.p3);
+}
+
+// Accumulate the segments of a fill into the tile at (x0, y0). Segments are not
+// voted on as a threadgroup, as the number of lines is only known after
+// flattening; cull by control point bbox, then flatten.
+void fillSegments(thread TileEncoder &encoder, device const char *scene, PietSegmentRef segsIx,
+                  uint nSegs, Transform transform, ushort x0, ushort y0,
+                  thread float &backdrop, thread bool &anyFill) {
+    for (uint j = 0; j < nSegs; j++) {
+        PietSegmentPacked seg = PietSegment_read(scene, segsIx + j * sizeof(PietSegmentPacked));
+        transformSegment(seg, transform);
+        float2 xymin = min(min(seg.p0, seg.p1), min(seg.p2, seg.p3));
+        float2 xymax = max(max(seg.p0, seg.p1), max(seg.p2, seg.p3));
+        if (!(xymax.y >= y0 && xymin.y < y0 + tileHeight)) {
+            continue;
+        }
+        uint nLines = flattenCount(seg);
+        float2 start = seg.p0;
+        for (uint k = 1; k <= nLines; k++) {
+            float2 end = k == nLines ? seg.p3 : evalSegment(seg, float(k) / float(nLines));
+            fillLine(encoder, start, end, x0, y0, backdrop, anyFill);
+            start = end;
+        }
+    }
+}
+
+// Whether a winding number is inside a fill, used for tiles with no edges.
+bool windingInside(int winding, uint flags) {
+    return (flags & PIET_FILL_EVEN_ODD) ? (winding & 1) != 0 : winding != 0;
+}
+
+// Encode the paint of a draw item, ahead of its draw command, if it is not a
+// solid color. The transform is that of the item's group.
+void encodePaint(thread TileEncoder &encoder, device const char *scene, uint flags, uint rgbaColor,
+                 Transform transform) {
+    if (flags & PIET_PAINT_GRADIENT) {
+        Transform toGradient = {PietGradient_mat(scene, rgbaColor), PietGradient_translate(scene, rgbaColor)};
+        Transform t = transformMul(toGradient, transformInverse(transform));
+        encoder.encodeGradient(rgbaColor, t.mat, t.translate);
+    } else if (flags & PIET_PAINT_IMAGE) {
+        Transform toImage = {PietImageFill_mat(scene, rgbaColor), PietImageFill_translate(scene, rgbaColor)};
+        Transform t = transformMul(toImage, transformInverse(transform));
+        encoder.encodeImage(rgbaColor, t.mat, t.translate);
+    }
+}
+
+// Emit the draw command for a fill, after its lines have been accumulated.
+void drawFill(thread TileEncoder &encoder, device const char *scene, uint rgbaColor, uint flags,
+              Transform transform, float backdrop, bool anyFill) {
+    int winding = int(backdrop);
+    if (anyFill) {
+        encodePaint(encoder, scene, flags, rgbaColor, transform);
+        encoder.encodeDrawFill(rgbaColor, flags, winding);
+    } else if (windingInside(winding, flags)) {
+        // The tile is entirely inside the fill.
+        encodePaint(encoder, scene, flags, rgbaColor, transform);
+        encoder.encodeSolid(rgbaColor);
+    }
+}
+
+// State of a group in the tile kernel: nothing pushed to the command list in
+// this tile, a clip or layer pushed (and to be popped when the group is done),
+// or entirely clipped out.
+#define GROUP_NONE 0
+#define GROUP_CLIP_PUSHED 1
+#define GROUP_CULLED 2
+#define GROUP_LAYER_PUSHED 3
+
 // Traverse the scene graph and produce a command list for a tile.
 kernel void
 tileKernel(device const char *scene [[buffer(0)]],
            device char *tiles [[buffer(1)]],
+           constant uint2 &tileGrid [[buffer(2)]],
+           device TilePool *pool [[buffer(3)]],
            texture2d<half, access::write> outTexture [[texture(0)]],
            uint2 gid [[thread_position_in_grid]],
            uint tix [[thread_index_in_threadgroup]])
 {
-    uint tileIx = gid.y * maxTilesWidth + gid.x;
+    // The grid is whole threadgroups, so every thread has a tile.
+    uint tileIx = gid.y * tileGrid.x + gid.x;
     ushort x0 = gid.x * tileWidth;
     ushort y0 = gid.y * tileHeight;
-    device char *dst = tiles + tileIx * tileBufSize;
-    TileEncoder encoder(dst);
+    TileEncoder encoder(tiles, tileIx, tileGrid, pool, SceneHeader_background(scene, 0));
     // TODO: correct calculation of size
     const ushort tgs = tilerGroupWidth * tilerGroupHeight;
     const ushort nBitmap = tgs / 32;
@@ -241,299 +827,642 @@ tileKernel(device const char *scene [[buffer(0)]],
     ushort sx0 = x0 & ~(stw - 1);
     ushort sy0 = y0 & ~(sth - 1);
     
-    SimpleGroupRef group_ref = 0;
-    // TODO: write accessor functions for variable-sized array here
-    device const SimpleGroupPacked *group = (device const SimpleGroupPacked *)scene;
-    device const ushort4 *bboxes = (device const ushort4 *)&group->bbox;
+    // Stack of enclosing groups, for traversing nested groups. Whether to
+    // descend is decided by the vote for the whole threadgroup, so that
+    // control flow (and thus the barriers) stays uniform.
+    SimpleGroupRef groupStack[maxGroupDepth];
+    uint nextStack[maxGroupDepth];
+    uint voteStack[maxGroupDepth];
+    Transform transformStack[maxGroupDepth];
+    uint stateStack[maxGroupDepth];
+    PietItemRef openerStack[maxGroupDepth];
+    uint stackDepth = 0;
+    // Transform of the current group, to pixels.
+    Transform transform = {float4(1.0, 0.0, 0.0, 1.0), float2(0.0)};
+    uint groupState = GROUP_NONE;
+    // The item that opened the current group, read again when it is popped.
+    PietItemRef opener = 0;
+
+    SimpleGroupRef group_ref = SceneHeader_root(scene, 0);
     uint n = SimpleGroup_n_items(scene, group_ref);
-    PietItemRef items_ref = SimpleGroup_items_ix(scene, group_ref);
-    for (uint i = 0; i < n; i += tgs) {
-        if (tix < nBitmap) {
-            atomic_store_explicit(&bitmap, 0, relaxed);
-        }
-        threadgroup_barrier(mem_flags::mem_threadgroup);
-        if (i + tix < n) {
-            ushort4 bbox = bboxes[i + tix];
-            if (bbox.z >= sx0 && bbox.x < sx0 + stw && bbox.w >= sy0 && bbox.y < sy0 + sth) {
-                uint mask = 1 << (tix & 31);
-                atomic_fetch_or_explicit(&bitmap, mask, relaxed);
+    // Index of the first item of the next chunk to vote on.
+    uint next = 0;
+    uint v = 0;
+    while (1) {
+        if (v == 0) {
+            if (next >= n) {
+                // Done with this group, pop back out to the parent.
+                if (stackDepth == 0) {
+                    break;
+                }
+                if (groupState == GROUP_CLIP_PUSHED) {
+                    encoder.encodeEndClip();
+                } else if (groupState == GROUP_LAYER_PUSHED) {
+                    encoder.encodeEndLayer(PietLayer_alpha(scene, opener), PietLayer_blend(scene, opener));
+                }
+                stackDepth--;
+                group_ref = groupStack[stackDepth];
+                next = nextStack[stackDepth];
+                v = voteStack[stackDepth];
+                transform = transformStack[stackDepth];
+                groupState = stateStack[stackDepth];
+                opener = openerStack[stackDepth];
+                n = SimpleGroup_n_items(scene, group_ref);
+                continue;
             }
+            threadgroup_barrier(mem_flags::mem_threadgroup);
+            if (tix < nBitmap) {
+                atomic_store_explicit(&bitmap, 0, relaxed);
+            }
+            threadgroup_barrier(mem_flags::mem_threadgroup);
+            if (next + tix < n) {
+                float4 bbox = transformBbox(transform, SimpleGroup_bboxes_at(scene, group_ref, next + tix));
+                if (bbox.z >= sx0 && bbox.x < sx0 + stw && bbox.w >= sy0 && bbox.y < sy0 + sth) {
+                    uint mask = 1 << (tix & 31);
+                    atomic_fetch_or_explicit(&bitmap, mask, relaxed);
+                }
+            }
+            threadgroup_barrier(mem_flags::mem_threadgroup);
+            if (tix == 0) {
+                rd = atomic_load_explicit(&bitmap, relaxed);
+                atomic_store_explicit(&bitmap, 0, relaxed);
+            }
+            threadgroup_barrier(mem_flags::mem_threadgroup);
+            v = rd;
+            next += tgs;
+            continue;
         }
-        threadgroup_barrier(mem_flags::mem_threadgroup);
-        if (tix == 0) {
-            rd = atomic_load_explicit(&bitmap, relaxed);
-            atomic_store_explicit(&bitmap, 0, relaxed);
-        }
-        threadgroup_barrier(mem_flags::mem_threadgroup);
-        //for (ushort bitmapIx = 0; bitmapIx <= bitmapCount; bitmapIx++) {
-            uint v = rd;
-            while (v) {
-                uint ix = i + ctz(v);
-                ushort4 bbox = bboxes[ix];
-                bool hit = bbox.z >= x0 && bbox.x < x0 + tileWidth && bbox.w >= y0 && bbox.y < y0 + tileHeight;
-                PietItemRef item_ref = items_ref + ix * sizeof(PietItem);
-                ushort itemType = PietItem_tag(scene, item_ref);
-                switch (itemType) {
-                    case PIET_ITEM_CIRCLE:
-                        if (hit) {
-                            encoder.encodeCircle(bbox);
+        uint ix = next - tgs + ctz(v);
+        v &= v - 1;
+        float4 bbox = transformBbox(transform, SimpleGroup_bboxes_at(scene, group_ref, ix));
+        bool hit = bbox.z >= x0 && bbox.x < x0 + tileWidth && bbox.w >= y0 && bbox.y < y0 + tileHeight
+            && groupState != GROUP_CULLED;
+        PietItemRef item_ref = SimpleGroup_items_ix_at(scene, group_ref, ix);
+        ushort itemType = PietItem_tag(scene, item_ref);
+        switch (itemType) {
+            case PIET_ITEM_GROUP:
+                // Save our place and descend into the child group.
+                if (stackDepth < maxGroupDepth - 1) {
+                    groupStack[stackDepth] = group_ref;
+                    nextStack[stackDepth] = next;
+                    voteStack[stackDepth] = v;
+                    transformStack[stackDepth] = transform;
+                    stateStack[stackDepth] = groupState;
+                    openerStack[stackDepth] = opener;
+                    stackDepth++;
+                    opener = item_ref;
+                    Transform child = {PietGroup_mat(scene, item_ref), PietGroup_translate(scene, item_ref)};
+                    transform = transformMul(transform, child);
+                    if (groupState != GROUP_CULLED) {
+                        groupState = GROUP_NONE;
+                    }
+                    group_ref = PietGroup_group(scene, item_ref);
+                    n = SimpleGroup_n_items(scene, group_ref);
+                    next = 0;
+                    v = 0;
+                }
+                break;
+            case PIET_ITEM_CLIP:
+                // Like a group, but the clip is pushed in tiles that its edges
+                // cross, and the group is culled in tiles outside it.
+                if (stackDepth < maxGroupDepth - 1) {
+                    groupStack[stackDepth] = group_ref;
+                    nextStack[stackDepth] = next;
+                    voteStack[stackDepth] = v;
+                    transformStack[stackDepth] = transform;
+                    stateStack[stackDepth] = groupState;
+                    openerStack[stackDepth] = opener;
+                    stackDepth++;
+                    opener = item_ref;
+                    PietClipPacked clip = PietClip_read(scene, item_ref);
+                    groupState = GROUP_CULLED;
+                    if (hit) {
+                        float backdrop = 0;
+                        bool anyFill = false;
+                        fillSegments(encoder, scene, clip.segs_ix, clip.n_segs, transform, x0, y0, backdrop, anyFill);
+                        if (anyFill) {
+                            encoder.encodeBeginClip(clip.flags, int(backdrop));
+                            groupState = GROUP_CLIP_PUSHED;
+                        } else if (windingInside(int(backdrop), clip.flags)) {
+                            groupState = GROUP_NONE;
                         }
-                        break;
-                    case PIET_ITEM_LINE: {
-                        // set up line equation, ax + by + c = 0
+                    }
+                    group_ref = clip.group;
+                    n = SimpleGroup_n_items(scene, group_ref);
+                    next = 0;
+                    v = 0;
+                }
+                break;
+            case PIET_ITEM_LAYER:
+                // Like a group, but drawn onto a layer of its own in the tiles
+                // it hits, which is composited when the group is popped.
+                if (stackDepth < maxGroupDepth - 1) {
+                    groupStack[stackDepth] = group_ref;
+                    nextStack[stackDepth] = next;
+                    voteStack[stackDepth] = v;
+                    transformStack[stackDepth] = transform;
+                    stateStack[stackDepth] = groupState;
+                    openerStack[stackDepth] = opener;
+                    stackDepth++;
+                    opener = item_ref;
+                    groupState = GROUP_CULLED;
+                    if (hit) {
+                        encoder.encodeBeginLayer();
+                        groupState = GROUP_LAYER_PUSHED;
+                    }
+                    group_ref = PietLayer_group(scene, item_ref);
+                    n = SimpleGroup_n_items(scene, group_ref);
+                    next = 0;
+                    v = 0;
+                }
+                break;
+            case PIET_ITEM_CIRCLE:
+                if (hit) {
+                    // Keep the conversion in range.
+                    encoder.encodeCircle(int4(clamp(bbox, -1e9, 1e9)));
+                }
+                break;
+            case PIET_ITEM_LINE: {
+                if (hit) {
+                    PietStrokeLinePacked line = PietStrokeLine_read(scene, item_ref);
+                    float2 start = transformPoint(transform, line.start);
+                    float2 end = transformPoint(transform, line.end);
+                    StrokeStyle style = {line.width * transformScale(transform), line.flags, 1.0};
+                    if (strokeStyledLine(encoder, start, end, false, 0.0, true, true, style, x0, y0)) {
+                        encodePaint(encoder, scene, line.flags, line.rgba_color, transform);
+                        encoder.encodeStroke(line.rgba_color, style.width);
+                    }
+                }
+                break;
+            }
+            case PIET_ITEM_FILL: {
+                PietFillPacked fill = PietFill_read(scene, item_ref);
+                device const float2 *pts = (device const float2 *)(scene + fill.points_ix);
+                uint nPoints = fill.n_points;
+                float backdrop = 0;
+                bool anyFill = false;
+                // use simd ballot to quick-reject segments with no contribution
+                // Note: we just do 16 at a time for now, there's the option of doing
+                // a 16x2 strip of tiles, with more complexity in the left-ray test.
+                for (uint j = 0; j < nPoints; j += 16) {
+                    bool fillHit = false;
+                    uint fillIx = j + (tix & 15);
+                    if (fillIx < nPoints) {
+                        float2 start = transformPoint(transform, pts[fillIx]);
+                        float2 end = transformPoint(transform, pts[fillIx + 1 == nPoints ? 0 : fillIx + 1]);
+                        float2 xymin = min(start, end);
+                        float2 xymax = max(start, end);
+                        if (xymax.y >= y0 && xymin.y < y0 + tileHeight && xymin.x < sx0 + stw) {
+                            // set up line equation, ax + by + c = 0
+                            float a = end.y - start.y;
+                            float b = start.x - end.x;
+                            float c = -(a * start.x + b * start.y);
+                            float left = a * sx0;
+                            float right = a * (sx0 + stw);
+                            float ytop = max(float(y0), xymin.y);
+                            float ybot = min(float(y0 + tileHeight), xymax.y);
+                            float top = b * ytop;
+                            float bot = b * ybot;
+                            // top left of rightmost tile in strip
+                            float sTopLeft = sign(right - a * (tileWidth) + float(y0) * b + c);
+                            float s00 = sign(top + left + c);
+                            float s01 = sign(top + right + c);
+                            float s10 = sign(bot + left + c);
+                            float s11 = sign(bot + right + c);
+                            if (sTopLeft == sign(a) && xymin.y <= y0) {
+                                // left ray intersects, need backdrop
+                                fillHit = true;
+                            }
+                            if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0 && xymax.x >= sx0) {
+                                // intersects strip
+                                fillHit = true;
+                            }
+                            // TODO: maybe avoid boolean - does it cost a register?
+                            if (fillHit) {
+                                atomic_fetch_or_explicit(&bitmap, 1 << tix, relaxed);
+                            }
+                        }
+                    }
+                    threadgroup_barrier(mem_flags::mem_threadgroup);
+                    if (tix == 0) {
+                        rd = atomic_load_explicit(&bitmap, relaxed);
+                        atomic_store_explicit(&bitmap, 0, relaxed);
+                    }
+                    threadgroup_barrier(mem_flags::mem_threadgroup);
+                    uint fillVote = (rd >> (tix & 16)) & 0xffff;
+                    while (fillVote) {
+                        uint fillSubIx = ctz(fillVote);
+                        fillIx = j + fillSubIx;
+
                         if (hit) {
-                            PietStrokeLinePacked line = PietStrokeLine_read(scene, item_ref);
-                            float a = line.end.y - line.start.y;
-                            float b = line.start.x - line.end.x;
-                            float c = -(a * line.start.x + b * line.start.y);
-                            // TODO: is this bound as tight as it can be?
-                            float hw = 0.5 * line.width + 0.5;
-                            float left = a * (x0 - hw);
-                            float right = a * (x0 + tileWidth + hw);
+                            float2 start = transformPoint(transform, pts[fillIx]);
+                            float2 end = transformPoint(transform, pts[fillIx + 1 == nPoints ? 0 : fillIx + 1]);
+                            fillLine(encoder, start, end, x0, y0, backdrop, anyFill);
+                        } // end if (hit)
+
+                        fillVote &= ~(1 << fillSubIx);
+                    }
+                }
+                drawFill(encoder, scene, fill.rgba_color, fill.flags, transform, backdrop, anyFill);
+                break;
+            }
+            case PIET_ITEM_STROKE_POLYLINE: {
+                PietStrokePolyLinePacked poly = PietStrokePolyLine_read(scene, item_ref);
+                device const float2 *pts = (device const float2 *)(scene + poly.points_ix);
+                uint nPoints = poly.n_points - 1;
+                // The last point of a closed polyline is its first, so the line before
+                // the first one is found by wrapping around.
+                bool closed = (poly.flags & PIET_STROKE_CLOSED) != 0;
+                bool anyStroke = false;
+                StrokeStyle style = {poly.width * transformScale(transform), poly.flags, poly.miter_limit};
+                float hw = strokeReach(style) + 0.5;
+                // use simd ballot to quick-reject segments with no contribution
+                for (uint j = 0; j < nPoints; j += 32) {
+                    uint polyIx = j + tix;
+                    if (polyIx < nPoints) {
+                        float2 start = transformPoint(transform, pts[polyIx]);
+                        float2 end = transformPoint(transform, pts[polyIx + 1]);
+                        float2 xymin = min(start, end);
+                        float2 xymax = max(start, end);
+                        if (xymax.y > sy0 - hw && xymin.y < sy0 + sth + hw &&
+                            xymax.x > sx0 - hw && xymin.x < sx0 + stw + hw) {
+                            // set up line equation, ax + by + c = 0
+                            float a = end.y - start.y;
+                            float b = start.x - end.x;
+                            float c = -(a * start.x + b * start.y);
+                            float left = a * (sx0 - hw);
+                            float right = a * (sx0 + stw + hw);
                             float top = b * (y0 - hw);
                             float bot = b * (y0 + tileHeight + hw);
-                            // If all four corners are on same side of line, cull
                             float s00 = sign(top + left + c);
                             float s01 = sign(top + right + c);
                             float s10 = sign(bot + left + c);
                             float s11 = sign(bot + right + c);
                             if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0) {
-                                encoder.encodeLine(line.start, line.end);
-                                encoder.encodeStroke(line.rgba_color, line.width);
+                                // intersects strip
+                                atomic_fetch_or_explicit(&bitmap, 1 << tix, relaxed);
                             }
                         }
-                        break;
                     }
-                    case PIET_ITEM_FILL: {
-                        PietFillPacked fill = PietFill_read(scene, item_ref);
-                        device const float2 *pts = (device const float2 *)(scene + fill.points_ix);
-                        uint nPoints = fill.n_points;
-                        float backdrop = 0;
-                        bool anyFill = false;
-                        // use simd ballot to quick-reject segments with no contribution
-                        // Note: we just do 16 at a time for now, there's the option of doing
-                        // a 16x2 strip of tiles, with more complexity in the left-ray test.
-                        for (uint j = 0; j < nPoints; j += 16) {
-                            bool fillHit = false;
-                            uint fillIx = j + (tix & 15);
-                            if (fillIx < nPoints) {
-                                float2 start = pts[fillIx];
-                                float2 end = pts[fillIx + 1 == nPoints ? 0 : fillIx + 1];
-                                float2 xymin = min(start, end);
-                                float2 xymax = max(start, end);
-                                if (xymax.y >= y0 && xymin.y < y0 + tileHeight && xymin.x < sx0 + stw) {
-                                    // set up line equation, ax + by + c = 0
-                                    float a = end.y - start.y;
-                                    float b = start.x - end.x;
-                                    float c = -(a * start.x + b * start.y);
-                                    float left = a * sx0;
-                                    float right = a * (sx0 + stw);
-                                    float ytop = max(float(y0), xymin.y);
-                                    float ybot = min(float(y0 + tileHeight), xymax.y);
-                                    float top = b * ytop;
-                                    float bot = b * ybot;
-                                    // top left of rightmost tile in strip
-                                    float sTopLeft = sign(right - a * (tileWidth) + float(y0) * b + c);
-                                    float s00 = sign(top + left + c);
-                                    float s01 = sign(top + right + c);
-                                    float s10 = sign(bot + left + c);
-                                    float s11 = sign(bot + right + c);
-                                    if (sTopLeft == sign(a) && xymin.y <= y0) {
-                                        // left ray intersects, need backdrop
-                                        fillHit = true;
-                                    }
-                                    if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0 && xymax.x > sx0) {
-                                        // intersects strip
-                                        fillHit = true;
-                                    }
-                                    // TODO: maybe avoid boolean - does it cost a register?
-                                    if (fillHit) {
-                                        atomic_fetch_or_explicit(&bitmap, 1 << tix, relaxed);
-                                    }
-                                }
-                            }
-                            threadgroup_barrier(mem_flags::mem_threadgroup);
-                            if (tix == 0) {
-                                rd = atomic_load_explicit(&bitmap, relaxed);
-                                atomic_store_explicit(&bitmap, 0, relaxed);
-                            }
-                            threadgroup_barrier(mem_flags::mem_threadgroup);
-                            uint fillVote = (rd >> (tix & 16)) & 0xffff;
-                            while (fillVote) {
-                                uint fillSubIx = ctz(fillVote);
-                                fillIx = j + fillSubIx;
-
-                                if (hit) {
-                                    float2 start = pts[fillIx];
-                                    float2 end = pts[fillIx + 1 == nPoints ? 0 : fillIx + 1];
-                                    float2 xymin = min(start, end);
-                                    float2 xymax = max(start, end);
-                                    // Note: no y-based cull here because it's been done in the earlier pass.
-                                    // If we change that to do a strip taller than 1 tile, re-introduce here.
-
-                                    // set up line equation, ax + by + c = 0
-                                    float a = end.y - start.y;
-                                    float b = start.x - end.x;
-                                    float c = -(a * start.x + b * start.y);
-                                    float left = a * x0;
-                                    float right = a * (x0 + tileWidth);
-                                    float ytop = max(float(y0), xymin.y);
-                                    float ybot = min(float(y0 + tileHeight), xymax.y);
-                                    float top = b * ytop;
-                                    float bot = b * ybot;
-                                    // top left of tile
-                                    float sTopLeft = sign(left + float(y0) * b + c);
-                                    float s00 = sign(top + left + c);
-                                    float s01 = sign(top + right + c);
-                                    float s10 = sign(bot + left + c);
-                                    float s11 = sign(bot + right + c);
-                                    if (sTopLeft == sign(a) && xymin.y <= y0) {
-                                        backdrop -= s00;
-                                    }
-                                    if (xymin.x < x0 && xymax.x > x0) {
-                                        float yEdge = mix(start.y, end.y, (start.x - x0) / b);
-                                        if (yEdge >= y0 && yEdge < y0 + tileHeight) {
-                                            // line intersects left edge of this tile
-                                            encoder.encodeFillEdge(s00, yEdge);
-                                            if (b > 0.0) {
-                                                encoder.encodeFill(start, float2(x0, yEdge));
-                                            } else {
-                                                encoder.encodeFill(float2(x0, yEdge), end);
-                                            }
-                                            anyFill = true;
-                                        } else if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0) {
-                                            encoder.encodeFill(start, end);
-                                            anyFill = true;
-                                        }
-                                    } else if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0
-                                               && xymin.x < x0 + tileWidth && xymax.x > x0) {
-                                        encoder.encodeFill(start, end);
-                                        anyFill = true;
-                                    }
-                                } // end if (hit)
-
-                                fillVote &= ~(1 << fillSubIx);
-                            }
-                        }
-                        if (anyFill) {
-                            encoder.encodeDrawFill(fill, backdrop);
-                        } else if (backdrop != 0.0) {
-                            encoder.encodeSolid(fill.rgba_color);
-                        }
-                        break;
+                    threadgroup_barrier(mem_flags::mem_threadgroup);
+                    if (tix == 0) {
+                        rd = atomic_load_explicit(&bitmap, relaxed);
+                        atomic_store_explicit(&bitmap, 0, relaxed);
                     }
-                    case PIET_ITEM_STROKE_POLYLINE: {
-                        PietStrokePolyLinePacked poly = PietStrokePolyLine_read(scene, item_ref);
-                        device const float2 *pts = (device const float2 *)(scene + poly.points_ix);
-                        uint nPoints = poly.n_points - 1;
-                        bool anyStroke = false;
-                        float hw = 0.5 * poly.width + 0.5;
-                        // use simd ballot to quick-reject segments with no contribution
-                        for (uint j = 0; j < nPoints; j += 32) {
-                            uint polyIx = j + tix;
-                            if (polyIx < nPoints) {
-                                float2 start = pts[polyIx];
-                                float2 end = pts[polyIx + 1];
-                                float2 xymin = min(start, end);
-                                float2 xymax = max(start, end);
-                                if (xymax.y > sy0 - hw && xymin.y < sy0 + sth + hw &&
-                                    xymax.x > sx0 - hw && xymin.x < sx0 + stw + hw) {
-                                    // set up line equation, ax + by + c = 0
-                                    float a = end.y - start.y;
-                                    float b = start.x - end.x;
-                                    float c = -(a * start.x + b * start.y);
-                                    float left = a * (sx0 - hw);
-                                    float right = a * (sx0 + stw + hw);
-                                    float top = b * (y0 - hw);
-                                    float bot = b * (y0 + tileHeight + hw);
-                                    float s00 = sign(top + left + c);
-                                    float s01 = sign(top + right + c);
-                                    float s10 = sign(bot + left + c);
-                                    float s11 = sign(bot + right + c);
-                                    if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0) {
-                                        // intersects strip
-                                        atomic_fetch_or_explicit(&bitmap, 1 << tix, relaxed);
-                                    }
+                    threadgroup_barrier(mem_flags::mem_threadgroup);
+                    uint polyVote = rd;
+                    while (polyVote) {
+                        uint polySubIx = ctz(polyVote);
+                        polyIx = j + polySubIx;
+                        
+                        if (hit) {
+                            float2 p0 = pts[polyIx];
+                            float2 p1 = pts[polyIx + 1];
+                            if (any(p0 != p1)) {
+                                // Repeated points are skipped when looking for neighboring lines.
+                                int prevIx = int(polyIx) - 1;
+                                while (prevIx >= 0 && all(pts[prevIx] == p0)) {
+                                    prevIx--;
                                 }
-                            }
-                            threadgroup_barrier(mem_flags::mem_threadgroup);
-                            if (tix == 0) {
-                                rd = atomic_load_explicit(&bitmap, relaxed);
-                                atomic_store_explicit(&bitmap, 0, relaxed);
-                            }
-                            threadgroup_barrier(mem_flags::mem_threadgroup);
-                            uint polyVote = rd;
-                            while (polyVote) {
-                                uint polySubIx = ctz(polyVote);
-                                polyIx = j + polySubIx;
-                                
-                                if (hit) {
-                                    float2 start = pts[polyIx];
-                                    float2 end = pts[polyIx + 1];
-                                    float2 xymin = min(start, end);
-                                    float2 xymax = max(start, end);
-                                    if (xymax.y > y0 - hw && xymin.y < y0 + tileHeight + hw &&
-                                        xymax.x > x0 - hw && xymin.x < x0 + tileWidth + hw) {
-                                        float a = end.y - start.y;
-                                        float b = start.x - end.x;
-                                        float c = -(a * start.x + b * start.y);
-                                        float hw = 0.5 * poly.width + 0.5;
-                                        float left = a * (x0 - hw);
-                                        float right = a * (x0 + tileWidth + hw);
-                                        float top = b * (y0 - hw);
-                                        float bot = b * (y0 + tileHeight + hw);
-                                        // If all four corners are on same side of line, cull
-                                        float s00 = sign(top + left + c);
-                                        float s01 = sign(top + right + c);
-                                        float s10 = sign(bot + left + c);
-                                        float s11 = sign(bot + right + c);
-                                        if (s00 * s01 + s00 * s10 + s00 * s11 < 3.0) {
-                                            encoder.encodeLine(start, end);
-                                            anyStroke = true;
-                                        }
+                                if (prevIx < 0 && closed) {
+                                    // This stops at the end of the line at the latest.
+                                    prevIx = int(nPoints);
+                                    while (all(pts[prevIx] == p0)) {
+                                        prevIx--;
                                     }
-                                } // end if (hit)
-                                
-                                polyVote &= ~(1 << polySubIx);
+                                }
+                                uint nextIx = polyIx + 2;
+                                while (nextIx <= nPoints && all(pts[nextIx] == p1)) {
+                                    nextIx++;
+                                }
+                                float2 start = transformPoint(transform, p0);
+                                float2 end = transformPoint(transform, p1);
+                                bool hasPrev = prevIx >= 0;
+                                float2 prevDir = hasPrev ? normalize(start - transformPoint(transform, pts[prevIx])) : float2(0.0);
+                                if (strokeStyledLine(encoder, start, end, hasPrev, prevDir, !hasPrev,
+                                                     !closed && nextIx > nPoints, style, x0, y0)) {
+                                    anyStroke = true;
+                                }
                             }
+                        } // end if (hit)
+                        
+                        polyVote &= ~(1 << polySubIx);
+                    }
+                }
+                if (anyStroke) {
+                    encodePaint(encoder, scene, poly.flags, poly.rgba_color, transform);
+                    encoder.encodeStroke(poly.rgba_color, style.width);
+                }
+                break;
+            }
+            case PIET_ITEM_FILL_PATH: {
+                if (!hit) {
+                    break;
+                }
+                PietFillPathPacked fill = PietFillPath_read(scene, item_ref);
+                float backdrop = 0;
+                bool anyFill = false;
+                fillSegments(encoder, scene, fill.segs_ix, fill.n_segs, transform, x0, y0, backdrop, anyFill);
+                drawFill(encoder, scene, fill.rgba_color, fill.flags, transform, backdrop, anyFill);
+                break;
+            }
+            case PIET_ITEM_STROKE_PATH: {
+                if (!hit) {
+                    break;
+                }
+                PietStrokePathPacked stroke = PietStrokePath_read(scene, item_ref);
+                StrokeStyle style = {stroke.width * transformScale(transform), stroke.flags, stroke.miter_limit};
+                float hw = strokeReach(style) + 0.5;
+                bool anyStroke = false;
+                // The first segment of the current subpath.
+                uint firstIx = 0;
+                for (uint j = 0; j < stroke.n_segs; j++) {
+                    PietSegmentRef segRef = stroke.segs_ix + j * sizeof(PietSegmentPacked);
+                    PietSegmentPacked seg = PietSegment_read(scene, segRef);
+                    transformSegment(seg, transform);
+                    if (seg.flags & PIET_SEG_SUBPATH_START) {
+                        firstIx = j;
+                    }
+                    float2 xymin = min(min(seg.p0, seg.p1), min(seg.p2, seg.p3));
+                    float2 xymax = max(max(seg.p0, seg.p1), max(seg.p2, seg.p3));
+                    if (!(xymax.y > y0 - hw && xymin.y < y0 + tileHeight + hw &&
+                          xymax.x > x0 - hw && xymin.x < x0 + tileWidth + hw)) {
+                        continue;
+                    }
+                    bool open = (seg.flags & PIET_SEG_CLOSED) == 0;
+                    bool hasPrev = (seg.flags & PIET_SEG_SUBPATH_START) == 0;
+                    float2 prevDir = float2(0.0);
+                    if (hasPrev) {
+                        PietSegmentPacked prev = PietSegment_read(scene, segRef - sizeof(PietSegmentPacked));
+                        transformSegment(prev, transform);
+                        prevDir = segEndDir(prev);
+                    }
+                    uint nLines = flattenCount(seg);
+                    float2 start = seg.p0;
+                    for (uint k = 1; k <= nLines; k++) {
+                        float2 end = flatPoint(seg, k, nLines);
+                        if (all(start == end)) {
+                            continue;
                         }
-                        if (anyStroke) {
-                            encoder.encodeStroke(poly.rgba_color, poly.width);
+                        bool capStart = k == 1 && open && !hasPrev;
+                        bool last = k == nLines && (seg.flags & PIET_SEG_SUBPATH_END) != 0;
+                        bool near = strokeStyledLine(encoder, start, end, hasPrev || k > 1, prevDir,
+                                                     capStart, last && open, style, x0, y0);
+                        float2 dir = normalize(end - start);
+                        if (near && last && !open) {
+                            // Join the end of a closed subpath to its start.
+                            PietSegmentPacked first = PietSegment_read(scene, stroke.segs_ix + firstIx * sizeof(PietSegmentPacked));
+                            transformSegment(first, transform);
+                            strokeJoin(encoder, end, dir, segStartDir(first), style);
                         }
-                        break;
+                        if (near) {
+                            anyStroke = true;
+                        }
+                        prevDir = dir;
+                        start = end;
                     }
-                } // end switch(itemType);
-                v &= v - 1;
-            } // end while (v)
-            threadgroup_barrier(mem_flags::mem_threadgroup);
-        //} // end for (bitmapIx)
-    }
+                }
+                if (anyStroke) {
+                    encodePaint(encoder, scene, stroke.flags, stroke.rgba_color, transform);
+                    encoder.encodeStroke(stroke.rgba_color, style.width);
+                }
+                break;
+            }
+        } // end switch(itemType);
+    } // end while (1)
     uint solidColor = encoder.end();
     outTexture.write(unpack_unorm4x8_to_half(solidColor), gid);
 }
 
+// The color of a gradient at a pixel. Stops are interpolated in linear space
+// with premultiplied alpha, and the result has separate alpha, as for a color
+// unpacked from a command.
+half4 gradientColor(const device char *scene, PietGradientRef ref, Transform transform, float2 xy) {
+    PietGradientPacked gradient = PietGradient_read(scene, ref);
+    // Sample at the center of the pixel.
+    float2 p = transformPoint(transform, xy + 0.5);
+    float t = (gradient.flags & PIET_GRADIENT_RADIAL) ? length(p) : p.x;
+    switch (gradient.flags & PIET_GRADIENT_EXTEND_MASK) {
+        case PIET_GRADIENT_EXTEND_REPEAT:
+            t = fract(t);
+            break;
+        case PIET_GRADIENT_EXTEND_REFLECT:
+            t = 1.0 - abs(fract(0.5 * t) * 2.0 - 1.0);
+            break;
+        default:
+            t = saturate(t);
+    }
+    PietGradientStopRef stopRef = gradient.stops_ix;
+    float prevOffset = PietGradientStop_offset(scene, stopRef);
+    half4 prev = unpack_unorm4x8_srgb_to_half(PietGradientStop_rgba_color(scene, stopRef));
+    prev.rgb *= prev.a;
+    half4 color = prev;
+    for (uint i = 1; i < gradient.n_stops; i++) {
+        stopRef += sizeof(PietGradientStopPacked);
+        float offset = PietGradientStop_offset(scene, stopRef);
+        half4 next = unpack_unorm4x8_srgb_to_half(PietGradientStop_rgba_color(scene, stopRef));
+        next.rgb *= next.a;
+        if (t < offset) {
+            color = mix(prev, next, half(saturate((t - prevOffset) / (offset - prevOffset))));
+            break;
+        }
+        prevOffset = offset;
+        prev = next;
+        color = next;
+    }
+    if (color.a > 0.0h) {
+        color.rgb /= color.a;
+    }
+    return color;
+}
+
+// A pixel of an image, clamped to its edges, with premultiplied alpha.
+half4 imagePixel(const device char *scene, PietImagePacked image, float2 xy) {
+    int2 ixy = clamp(int2(xy), int2(0), int2(image.width - 1, image.height - 1));
+    uint rgba = *(const device uint *)(scene + image.data_ix + (ixy.y * image.width + ixy.x) * 4);
+    half4 color = unpack_unorm4x8_srgb_to_half(rgba);
+    color.rgb *= color.a;
+    return color;
+}
+
+// The color of an image fill at a pixel. Bilinear sampling is in linear space
+// with premultiplied alpha, and the result has separate alpha, as for a color
+// unpacked from a command.
+half4 imageColor(const device char *scene, PietImageFillRef ref, Transform transform, float2 xy) {
+    PietImageFillPacked fill = PietImageFill_read(scene, ref);
+    PietImageRef imageRef = SceneHeader_images(scene, 0) + fill.image_ix * sizeof(PietImagePacked);
+    PietImagePacked image = PietImage_read(scene, imageRef);
+    // Sample at the center of the pixel.
+    float2 p = transformPoint(transform, xy + 0.5);
+    half4 color;
+    if (fill.flags & PIET_IMAGE_BILINEAR) {
+        // Interpolate between the centers of the four nearest image pixels.
+        float2 p0 = floor(p - 0.5);
+        half2 f = half2(p - 0.5 - p0);
+        half4 top = mix(imagePixel(scene, image, p0), imagePixel(scene, image, p0 + float2(1.0, 0.0)), f.x);
+        half4 bottom = mix(imagePixel(scene, image, p0 + float2(0.0, 1.0)), imagePixel(scene, image, p0 + 1.0), f.x);
+        color = mix(top, bottom, f.y);
+    } else {
+        color = imagePixel(scene, image, floor(p));
+    }
+    if (color.a > 0.0h) {
+        color.rgb /= color.a;
+    }
+    return color;
+}
+
+half3 screen(half3 b, half3 s) {
+    return b + s - b * s;
+}
+
+half3 hardLight(half3 b, half3 s) {
+    return select(screen(b, 2.0 * s - 1.0), b * 2.0 * s, s <= 0.5);
+}
+
+half3 colorDodge(half3 b, half3 s) {
+    half3 result = select(min(b / (1.0 - s), 1.0), 1.0, s >= 1.0);
+    return select(result, 0.0, b == 0.0);
+}
+
+half3 colorBurn(half3 b, half3 s) {
+    half3 result = select(1.0 - min((1.0 - b) / s, 1.0), 0.0, s == 0.0);
+    return select(result, 1.0, b >= 1.0);
+}
+
+half3 softLight(half3 b, half3 s) {
+    half3 d = select(sqrt(b), ((16.0 * b - 12.0) * b + 4.0) * b, b <= 0.25);
+    return select(b + (2.0 * s - 1.0) * (d - b), b - (1.0 - 2.0 * s) * b * (1.0 - b), s <= 0.5);
+}
+
+half lum(half3 c) {
+    return dot(c, half3(0.3, 0.59, 0.11));
+}
+
+half sat(half3 c) {
+    return max(c.r, max(c.g, c.b)) - min(c.r, min(c.g, c.b));
+}
+
+// Shift a color to luminance l, then bring it back into gamut, keeping its
+// luminance.
+half3 setLum(half3 c, half l) {
+    c += l - lum(c);
+    l = lum(c);
+    half n = min(c.r, min(c.g, c.b));
+    half x = max(c.r, max(c.g, c.b));
+    if (n < 0.0) {
+        c = l + (c - l) * l / (l - n);
+    } else if (x > 1.0) {
+        c = l + (c - l) * (1.0 - l) / (x - l);
+    }
+    return c;
+}
+
+// Scale a color to saturation s, keeping its hue.
+half3 setSat(half3 c, half s) {
+    half n = min(c.r, min(c.g, c.b));
+    half x = max(c.r, max(c.g, c.b));
+    return x > n ? (c - n) * s / (x - n) : half3(0.0);
+}
+
+// The blend function B(Cb, Cs) of the W3C Compositing and Blending spec, for
+// colors with separate alpha.
+half3 blendColors(half3 cb, half3 cs, uint mode) {
+    switch (mode) {
+        case PIET_BLEND_MULTIPLY:
+            return cb * cs;
+        case PIET_BLEND_SCREEN:
+            return screen(cb, cs);
+        case PIET_BLEND_OVERLAY:
+            return hardLight(cs, cb);
+        case PIET_BLEND_DARKEN:
+            return min(cb, cs);
+        case PIET_BLEND_LIGHTEN:
+            return max(cb, cs);
+        case PIET_BLEND_COLOR_DODGE:
+            return colorDodge(cb, cs);
+        case PIET_BLEND_COLOR_BURN:
+            return colorBurn(cb, cs);
+        case PIET_BLEND_HARD_LIGHT:
+            return hardLight(cb, cs);
+        case PIET_BLEND_SOFT_LIGHT:
+            return softLight(cb, cs);
+        case PIET_BLEND_DIFFERENCE:
+            return abs(cb - cs);
+        case PIET_BLEND_EXCLUSION:
+            return cb + cs - 2.0 * cb * cs;
+        case PIET_BLEND_HUE:
+            return setLum(setSat(cs, sat(cb)), lum(cb));
+        case PIET_BLEND_SATURATION:
+            return setLum(setSat(cb, sat(cs)), lum(cb));
+        case PIET_BLEND_COLOR:
+            return setLum(cs, lum(cb));
+        case PIET_BLEND_LUMINOSITY:
+            return setLum(cb, lum(cs));
+        default:
+            return cs;
+    }
+}
+
+half3 linearToSrgb(half3 rgb) {
+    return select(1.055 * pow(rgb, 1/2.4) - 0.055, 12.92 * rgb, rgb < 0.0031308);
+}
+
+half3 srgbToLinear(half3 rgb) {
+    return select(pow((rgb + 0.055) * (1 / 1.055), 2.4), rgb * (1 / 12.92), rgb <= 0.04045);
+}
+
+// Composite a layer onto the premultiplied color below it, with opacity alpha.
+// Both are in linear space, but as in CSS and SVG, the blend function is applied
+// to sRGB-encoded colors.
+half4 compositeLayer(half4 below, half4 layer, half alpha, uint blend) {
+    half4 src = layer * alpha;
+    if (blend == PIET_BLEND_NORMAL) {
+        return below * (1.0 - src.a) + src;
+    }
+    // co = cs * (1 - ab) + cb * (1 - as) + as * ab * B(Cb, Cs)
+    half3 cb = below.a > 0.0h ? below.rgb / below.a : half3(0.0);
+    half3 cs = src.a > 0.0h ? src.rgb / src.a : half3(0.0);
+    half3 b = srgbToLinear(blendColors(linearToSrgb(saturate(cb)), linearToSrgb(saturate(cs)), blend));
+    half3 rgb = src.rgb * (1.0 - below.a) + below.rgb * (1.0 - src.a) + src.a * below.a * b;
+    return half4(rgb, src.a + below.a * (1.0 - src.a));
+}
+
 // Interpret the commands in the command list to produce a pixel.
 kernel void
 renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
              const device char *tiles [[buffer(0)]],
+             const device char *scene [[buffer(1)]],
+             constant uint2 &tileGrid [[buffer(2)]],
              uint2 gid [[thread_position_in_grid]],
              uint2 tgid [[threadgroup_position_in_grid]])
 {
-    uint tileIx = tgid.y * maxTilesWidth + tgid.x;
-    const device char *src = tiles + tileIx * tileBufSize;
+    uint tileIx = tgid.y * tileGrid.x + tgid.x;
+    const device char *src = tiles + tileIx * tileChunkSize;
     uint x = gid.x;
     uint y = gid.y;
     float2 xy = float2(x, y);
 
     // Render state (maybe factor out?)
-    half3 rgb = half3(1.0);
+    // Premultiplied color of the current layer, starting with the background.
+    half4 rgba = unpack_unorm4x8_srgb_to_half(SceneHeader_background(scene, 0));
+    rgba.rgb *= rgba.a;
     float df = 1e9;
     half signedArea = 0.0;
+    // Coverage of the current clip, and those of the enclosing clips.
+    half clip = 1.0;
+    half clipStack[maxGroupDepth];
+    uint clipDepth = 0;
+    // Enclosing layers. Pushing a layer also pushes the clip.
+    half4 layerStack[maxGroupDepth];
+    uint layerDepth = 0;
+    // Color of the paint for the next draw command, if paintPending.
+    half4 paint;
+    bool paintPending = false;
 
     ushort cmd;
     while (1) {
         // Note: this has to be the biggest struct (could use a union for this)
-        CmdFill cmdBuf = *(const device CmdFill *)src;
+        CmdJoin cmdBuf = *(const device CmdJoin *)src;
         cmd = cmdBuf.cmd;
         if (cmd == CMD_END) {
             break;
@@ -542,7 +1471,7 @@ renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
             case CMD_CIRCLE: {
                 const thread CmdCircle *circle = (const thread CmdCircle *)&cmdBuf;
                 src += sizeof(CmdCircle);
-                ushort4 bbox = circle->bbox;
+                int4 bbox = circle->bbox;
                 float2 xy0 = float2(bbox.x, bbox.y);
                 float2 xy1 = float2(bbox.z, bbox.w);
                 float2 center = mix(xy0, xy1, 0.5);
@@ -551,7 +1480,7 @@ renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
                 // But see WebRender ellipse.glsl (linked in notes)
                 float circleR = min(center.x - xy0.x, center.y - xy0.y);
                 float alpha = saturate(circleR - r);
-                rgb = mix(rgb, half3(0.0), alpha);
+                rgba = mix(rgba, half4(0.0, 0.0, 0.0, 1.0), alpha * clip);
                 break;
             }
             case CMD_LINE: {
@@ -560,12 +1489,25 @@ renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
                 stroke(df, xy, line->start, line->end);
                 break;
             }
+            case CMD_LINE_BUTT: {
+                const thread CmdLineButt *line = (const thread CmdLineButt *)&cmdBuf;
+                src += sizeof(CmdLineButt);
+                strokeButt(df, xy, line->start, line->end, line->halfWidth);
+                break;
+            }
+            case CMD_JOIN: {
+                const thread CmdJoin *join = (const thread CmdJoin *)&cmdBuf;
+                src += sizeof(CmdJoin);
+                strokeJoinPiece(df, xy, join->point, join->dirIn, join->dirOut, join->cut, join->halfWidth);
+                break;
+            }
             case CMD_STROKE: {
                 const thread CmdStroke *stroke = (const thread CmdStroke *)&cmdBuf;
                 src += sizeof(CmdStroke);
                 half alpha = renderDf(df, stroke->halfWidth);
-                half4 fg = unpack_unorm4x8_srgb_to_half(stroke->rgba);
-                rgb = mix(rgb, fg.rgb, fg.a * alpha);
+                half4 fg = paintPending ? paint : unpack_unorm4x8_srgb_to_half(stroke->rgba);
+                paintPending = false;
+                rgba = mix(rgba, half4(fg.rgb, 1.0), fg.a * alpha * clip);
                 df = 1e9;
                 break;
             }
@@ -601,20 +1543,67 @@ renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
             case CMD_DRAW_FILL: {
                 const thread CmdDrawFill *draw = (const thread CmdDrawFill *)&cmdBuf;
                 src += sizeof(CmdDrawFill);
-                half alpha = signedArea + half(draw->backdrop);
-                alpha = min(abs(alpha), 1.0h); // nonzero winding rule
-                // even-odd is: alpha = abs(alpha - 2.0 * round(0.5 * alpha))
-                // also: abs(2 * fract(0.5 * (x - 1.0)) - 1.0)
-                half4 fg = unpack_unorm4x8_srgb_to_half(draw->rgba);
-                rgb = mix(rgb, fg.rgb, fg.a * alpha);
+                half alpha = fillAlpha(signedArea + half(draw->backdrop), draw->flags);
+                half4 fg = paintPending ? paint : unpack_unorm4x8_srgb_to_half(draw->rgba);
+                paintPending = false;
+                rgba = mix(rgba, half4(fg.rgb, 1.0), fg.a * alpha * clip);
                 signedArea = 0.0;
                 break;
             }
             case CMD_SOLID: {
                 const thread CmdSolid *solid = (const thread CmdSolid *)&cmdBuf;
                 src += sizeof(CmdSolid);
-                half4 fg = unpack_unorm4x8_srgb_to_half(solid->rgba);
-                rgb = mix(rgb, fg.rgb, fg.a);
+                half4 fg = paintPending ? paint : unpack_unorm4x8_srgb_to_half(solid->rgba);
+                paintPending = false;
+                rgba = mix(rgba, half4(fg.rgb, 1.0), fg.a * clip);
+                break;
+            }
+            case CMD_BEGIN_CLIP: {
+                const thread CmdBeginClip *begin = (const thread CmdBeginClip *)&cmdBuf;
+                src += sizeof(CmdBeginClip);
+                clipStack[clipDepth++] = clip;
+                clip *= fillAlpha(signedArea + half(begin->backdrop), begin->flags);
+                signedArea = 0.0;
+                break;
+            }
+            case CMD_END_CLIP:
+                src += sizeof(CmdEndClip);
+                clip = clipStack[--clipDepth];
+                break;
+            case CMD_GRADIENT: {
+                const thread CmdGradient *gradient = (const thread CmdGradient *)&cmdBuf;
+                src += sizeof(CmdGradient);
+                Transform transform = {gradient->mat, gradient->translate};
+                paint = gradientColor(scene, gradient->gradient, transform, xy);
+                paintPending = true;
+                break;
+            }
+            case CMD_IMAGE: {
+                const thread CmdImage *image = (const thread CmdImage *)&cmdBuf;
+                src += sizeof(CmdImage);
+                Transform transform = {image->mat, image->translate};
+                paint = imageColor(scene, image->fill, transform, xy);
+                paintPending = true;
+                break;
+            }
+            case CMD_BEGIN_LAYER:
+                src += sizeof(CmdBeginLayer);
+                layerStack[layerDepth++] = rgba;
+                clipStack[clipDepth++] = clip;
+                rgba = half4(0.0);
+                clip = 1.0;
+                break;
+            case CMD_END_LAYER: {
+                const thread CmdEndLayer *end = (const thread CmdEndLayer *)&cmdBuf;
+                src += sizeof(CmdEndLayer);
+                half4 layer = rgba;
+                clip = clipStack[--clipDepth];
+                rgba = compositeLayer(layerStack[--layerDepth], layer, end->alpha * clip, end->blend);
+                break;
+            }
+            case CMD_JUMP: {
+                const thread CmdJump *jump = (const thread CmdJump *)&cmdBuf;
+                src = tiles + jump->next;
                 break;
             }
             case CMD_BAIL:
@@ -622,8 +1611,10 @@ renderKernel(texture2d<half, access::write> outTexture [[texture(0)]],
         }
     }
     // Linear to sRGB conversion. Note that if we had writable sRGB textures
-    // we could let this be done in the write call.
-    rgb = select(1.055 * pow(rgb, 1/2.4) - 0.055, 12.92 * rgb, rgb < 0.0031308);
-    half4 rgba = half4(rgb, 1.0);
-    outTexture.write(rgba, gid);
+    // we could let this be done in the write call. The output is premultiplied
+    // after conversion, as expected by compositors.
+    half alpha = saturate(rgba.a);
+    half3 rgb = rgba.a > 0.0h ? rgba.rgb / rgba.a : half3(0.0);
+    rgb = linearToSrgb(rgb);
+    outTexture.write(half4(rgb * alpha, alpha), gid);
 }
@@ -2,10 +2,16 @@
 
 @import MetalKit;
 
+#include <stdatomic.h>
+
 #import "PietRenderer.h"
 #import "PietShaderTypes.h"
 #include "piet_metal.h"
 
+// Number of frames that can be on the GPU at once. Each has its own TilePool, so
+// that one can be reset while the others are in use.
+static const NSUInteger maxFramesInFlight = 3;
+
 @implementation PietRenderer {
     id<MTLDevice> _device;
     id<MTLComputePipelineState> _tilePipelineState;
@@ -16,8 +22,15 @@
     id<MTLTexture> _loTexture;
     id<MTLBuffer> _sceneBuf;
     id<MTLBuffer> _tileBuf;
+    id<MTLBuffer> _poolBufs[maxFramesInFlight];
+    NSUInteger _frameIx;
+    dispatch_semaphore_t _frameSemaphore;
+    // Set when the command lists of a frame overflowed the pool.
+    atomic_bool _poolOverflowed;
+    uint _nPoolChunks;
     id<MTLBuffer> _vertexBuf;
     vector_uint2 _viewportSize;
+    TileGrid _tileGrid;
 }
 
 - (nonnull instancetype)initWithMetalKitView:(nonnull MTKView *)mtkView {
@@ -46,42 +59,51 @@
         _renderPipelineState = [_device newRenderPipelineStateWithDescriptor:pipelineDescriptor error: &error];
 
         _commandQueue = [_device newCommandQueue];
-        
-        NSUInteger tileBufSizeBytes = maxTilesWidth * maxTilesHeight * tileBufSize;
+
         // Note: consider using managed here, worth experimenting with.
         MTLResourceOptions sceneOptions = MTLResourceStorageModeShared | MTLResourceCPUCacheModeWriteCombined;
         _sceneBuf = [_device newBufferWithLength:16*1024*1024 options:sceneOptions];
-        _tileBuf = [_device newBufferWithLength:tileBufSizeBytes options:MTLResourceStorageModePrivate];
+        // The tile buffer depends on the viewport, and is allocated on resize.
+        for (NSUInteger i = 0; i < maxFramesInFlight; i++) {
+            _poolBufs[i] = [_device newBufferWithLength:sizeof(TilePool) options:MTLResourceStorageModeShared];
+        }
+        _frameSemaphore = dispatch_semaphore_create(maxFramesInFlight);
+        atomic_init(&_poolOverflowed, false);
     }
     return self;
 }
 
 - (void)drawInMTKView:(nonnull MTKView *)view {
-    id<MTLCommandBuffer> commandBuffer = [_commandQueue commandBuffer];
-    commandBuffer.label = @"RenderCommand";
-
     uint nTilesX = (_viewportSize.x + tileWidth - 1) / tileWidth;
     uint nTilesY = (_viewportSize.y + tileHeight - 1) / tileHeight;
 
-    uint nTilerGroupsX = (nTilesX + tilerGroupWidth - 1) / tilerGroupWidth;
-    uint nTilerGroupsY = (nTilesY + tilerGroupHeight - 1) / tilerGroupHeight;
+    // Wait for the frame that last used this pool to finish, so it can be reset.
+    dispatch_semaphore_wait(_frameSemaphore, DISPATCH_TIME_FOREVER);
+    id<MTLBuffer> poolBuf = _poolBufs[_frameIx];
+    _frameIx = (_frameIx + 1) % maxFramesInFlight;
 
-    // Run tile compute shader.
-    id<MTLComputeCommandEncoder> computeEncoder = [commandBuffer computeCommandEncoder];
-    [computeEncoder setComputePipelineState:_tilePipelineState];
-    [computeEncoder setTexture:_loTexture atIndex:0];
-    [computeEncoder setBuffer:_sceneBuf offset:0 atIndex:0];
-    [computeEncoder setBuffer:_tileBuf offset:0 atIndex:1];
-    MTLSize tilegroupSize = MTLSizeMake(tilerGroupWidth, tilerGroupHeight, 1);
-    MTLSize tilegroupCount = MTLSizeMake(nTilerGroupsX, nTilerGroupsY, 1);
-    [computeEncoder dispatchThreadgroups:tilegroupCount threadsPerThreadgroup:tilegroupSize];
-    [computeEncoder endEncoding];
+    // Grow the pool if the command lists of an earlier frame overflowed it.
+    if (atomic_exchange(&_poolOverflowed, false)) {
+        _nPoolChunks *= 2;
+        [self allocTileBuf];
+    }
+    TilePool *pool = (TilePool *)poolBuf.contents;
+    pool->nChunks = _nPoolChunks;
+    pool->nAllocated = 0;
+    pool->overflow = 0;
+
+    id<MTLCommandBuffer> commandBuffer = [_commandQueue commandBuffer];
+    commandBuffer.label = @"RenderCommand";
+
+    [self encodeTile:commandBuffer pool:poolBuf];
 
     // Run compute shader for rendering.
-    computeEncoder = [commandBuffer computeCommandEncoder];
+    id<MTLComputeCommandEncoder> computeEncoder = [commandBuffer computeCommandEncoder];
     [computeEncoder setComputePipelineState:_computePipelineState];
     [computeEncoder setTexture:_texture atIndex:0];
     [computeEncoder setBuffer:_tileBuf offset:0 atIndex:0];
+    [computeEncoder setBuffer:_sceneBuf offset:0 atIndex:1];
+    [computeEncoder setBytes:&_tileGrid length:sizeof(TileGrid) atIndex:2];
     MTLSize threadgroupSize = MTLSizeMake(tileWidth, tileHeight, 1);
     MTLSize threadgroupCount = MTLSizeMake(nTilesX, nTilesY, 1);
     [computeEncoder dispatchThreadgroups:threadgroupCount threadsPerThreadgroup:threadgroupSize];
@@ -99,9 +121,45 @@
         [renderEncoder endEncoding];
         [commandBuffer presentDrawable:view.currentDrawable];
     }
+
+    // Check the pool once the frame is done, rather than waiting for it here. If
+    // it overflowed, some tiles of this frame are incomplete, and the next frame
+    // is tiled with a larger pool.
+    dispatch_semaphore_t frameSemaphore = _frameSemaphore;
+    [commandBuffer addCompletedHandler:^(id<MTLCommandBuffer> buffer) {
+        if (pool->overflow) {
+            atomic_store(&self->_poolOverflowed, true);
+        }
+        dispatch_semaphore_signal(frameSemaphore);
+    }];
     [commandBuffer commit];
 }
 
+// Build the command lists of the tiles, allocating extra chunks from the pool.
+- (void)encodeTile:(id<MTLCommandBuffer>)commandBuffer pool:(id<MTLBuffer>)poolBuf {
+    uint nTilerGroupsX = _tileGrid.n_tiles_x / tilerGroupWidth;
+    uint nTilerGroupsY = _tileGrid.n_tiles_y / tilerGroupHeight;
+
+    id<MTLComputeCommandEncoder> computeEncoder = [commandBuffer computeCommandEncoder];
+    [computeEncoder setComputePipelineState:_tilePipelineState];
+    [computeEncoder setTexture:_loTexture atIndex:0];
+    [computeEncoder setBuffer:_sceneBuf offset:0 atIndex:0];
+    [computeEncoder setBuffer:_tileBuf offset:0 atIndex:1];
+    [computeEncoder setBytes:&_tileGrid length:sizeof(TileGrid) atIndex:2];
+    [computeEncoder setBuffer:poolBuf offset:0 atIndex:3];
+    MTLSize tilegroupSize = MTLSizeMake(tilerGroupWidth, tilerGroupHeight, 1);
+    MTLSize tilegroupCount = MTLSizeMake(nTilerGroupsX, nTilerGroupsY, 1);
+    [computeEncoder dispatchThreadgroups:tilegroupCount threadsPerThreadgroup:tilegroupSize];
+    [computeEncoder endEncoding];
+}
+
+- (void)allocTileBuf {
+    NSUInteger tileBufSizeBytes = tile_buf_size(_tileGrid, _nPoolChunks);
+    if (_tileBuf == nil || _tileBuf.length != tileBufSizeBytes) {
+        _tileBuf = [_device newBufferWithLength:tileBufSizeBytes options:MTLResourceStorageModePrivate];
+    }
+}
+
 - (void)mtkView:(nonnull MTKView *)view drawableSizeWillChange:(CGSize)size {
     _viewportSize.x = size.width;
     _viewportSize.y = size.height;
@@ -118,9 +176,16 @@
     uint nTilesX = (_viewportSize.x + tileWidth - 1) / tileWidth;
     uint nTilesY = (_viewportSize.y + tileHeight - 1) / tileHeight;
 
-    descriptor.width = nTilesX;
-    descriptor.height = nTilesY;
+    // The tiler writes a solid color for every tile of the grid.
+    _tileGrid = tile_grid(_viewportSize.x, _viewportSize.y);
+    descriptor.width = _tileGrid.n_tiles_x;
+    descriptor.height = _tileGrid.n_tiles_y;
     _loTexture = [_device newTextureWithDescriptor:descriptor];
+
+    // Start with a pool for a quarter of the tiles to need a second chunk; it
+    // grows if the scene needs more.
+    _nPoolChunks = MAX(_tileGrid.n_tiles_x * _tileGrid.n_tiles_y / 4, 64);
+    [self allocTileBuf];
     
     uint vertexLen = nTilesX * nTilesY * sizeof(RenderVertex);
     MTLResourceOptions vertexOptions = MTLResourceStorageModeShared | MTLResourceCPUCacheModeWriteCombined;
@@ -201,7 +266,13 @@
 */
 
 - (void)initScene {
-    init_test_scene(_sceneBuf.contents, _sceneBuf.allocatedSize);
+    size_t sceneSize = init_test_scene(_sceneBuf.contents, _sceneBuf.length);
+    if (sceneSize > _sceneBuf.length) {
+        // Scene didn't fit, grow the buffer and try again.
+        MTLResourceOptions sceneOptions = MTLResourceStorageModeShared | MTLResourceCPUCacheModeWriteCombined;
+        _sceneBuf = [_device newBufferWithLength:sceneSize options:sceneOptions];
+        init_test_scene(_sceneBuf.contents, _sceneBuf.length);
+    }
 }
 
 @end
@@ -21,21 +21,54 @@ typedef enum RenderVertexInputIndex
 #define tilerGroupWidth 16
 #define tilerGroupHeight 2
 
-// The number of bytes in a buffer for a single tile.
-// For prototyping, this is a hard maximum, but for production we'd want
-// a mechanism to overflow.
-#define tileBufSize 4096
+// The command list of each tile starts in its own chunk of the tile buffer,
+// and continues in chunks from a pool shared by all tiles, which follows the
+// first chunks of the tiles in the tile buffer.
+#define tileChunkSize 512
 
-// For simplicity, we're going to hardcode these dimensions. For production,
-// they need to be dynamic.
-#define maxTilesWidth 256
-#define maxTilesHeight 256
+// The dimensions of the tile grid are not fixed: they are given by the TileGrid
+// of piet_metal.h, passed to the kernels as a uint2 in buffer(2).
+
+// State of the pool of command list chunks, in buffer(3) of the tiler. The
+// host resets it before tiling. If the pool runs out, the tiler sets overflow,
+// and the host should grow the pool and tile again.
+typedef struct TilePool {
+    // Capacity of the pool, in chunks
+    uint nChunks;
+    // Number of chunks handed out, incremented atomically
+    uint nAllocated;
+    uint overflow;
+} TilePool;
+
+// Maximum nesting depth of groups, as the tiler keeps a fixed-size stack.
+#define maxGroupDepth 8
+
+// Tolerance in pixels for flattening curve segments in the tiler, and a cap
+// on the number of lines a single segment is flattened into. The encoder splits
+// curves that would need more.
+#define flattenTolerance 0.1
+#define maxFlattenSegs 64
+
+// Distance in pixels that a miter or bevel join piece extends along the lines
+// it joins, so that it overlaps their bodies rather than leaving a seam.
+#define joinOverlap 1.0
+
+typedef struct SceneHeader {
+    // Offset in bytes to the root group
+    uint rootGroupIx;
+    // The image resource table, an array of PietImage
+    uint nImages;
+    uint imagesIx;
+    // Color the scene is drawn over, which may be transparent
+    uint background;
+} SceneHeader;
 
 typedef struct SimpleGroup {
     uint nItems;
     // Offset in bytes to items
     uint itemsIx;
-    vector_ushort4 bbox[1];
+    // Followed by nItems bboxes (x0, y0, x1, y1), which may be negative
+    int bbox[1][4];
 } SimpleGroup;
 
 typedef struct PietCircle {
@@ -45,7 +78,7 @@ typedef struct PietCircle {
 // A single line to be stroked, with default parameters
 typedef struct PietStrokeLine {
     uint itemType;
-    uint flags; // reserved, partially for alignment
+    uint flags; // stroke style; joins don't apply to a single line
     uint rgbaColor;
     float width;
     vector_float2 start;
@@ -54,7 +87,7 @@ typedef struct PietStrokeLine {
 
 typedef struct PietFill {
     uint itemType;
-    uint flags; // will be used for winding number rule
+    uint flags; // PIET_FILL_EVEN_ODD selects the fill rule
     uint rgbaColor;
     uint nPoints;
     uint pointsIx;
@@ -66,14 +99,108 @@ typedef struct PietStrokePolyLine {
     float width;
     uint nPoints;
     uint pointsIx;
+    uint flags; // stroke style
+    float miterLimit;
 } PietStrokePolyLine;
 
+// A nested group, its bbox is the union of the transformed bboxes of its items
+typedef struct PietGroup {
+    uint itemType;
+    uint groupIx;
+    // Transform to the parent's coordinates, as kurbo Affine coefficients
+    vector_float2 translate;
+    vector_float4 mat;
+} PietGroup;
+
+// A gradient, referenced by draw items with PIET_PAINT_GRADIENT. The transform
+// is to the coordinates of the gradient, in which a linear gradient goes from
+// (0, 0) to (1, 0), and a radial gradient is the unit circle.
+typedef struct PietGradient {
+    vector_float4 mat;
+    vector_float2 translate;
+    uint flags; // PIET_GRADIENT_RADIAL and the extend mode
+    uint nStops;
+    uint stopsIx;
+} PietGradient;
+
+typedef struct PietGradientStop {
+    float offset;
+    uint rgbaColor;
+} PietGradientStop;
+
+// An image fill, referenced by draw items with PIET_PAINT_IMAGE. The transform
+// is to the pixels of the image.
+typedef struct PietImageFill {
+    vector_float4 mat;
+    vector_float2 translate;
+    uint flags; // PIET_IMAGE_BILINEAR selects the sampling mode
+    uint imageIx; // index in the image resource table
+} PietImageFill;
+
+// An entry in the image resource table. The pixels are RGBA8 in sRGB, with
+// separate alpha, in rows from the top.
+typedef struct PietImage {
+    uint width;
+    uint height;
+    uint dataIx;
+} PietImage;
+
+// A line, quadratic or cubic Bézier segment. Unused points repeat the end point.
+typedef struct PietSegment {
+    uint segType;
+    uint flags; // PIET_SEG_SUBPATH_START, PIET_SEG_SUBPATH_END, PIET_SEG_CLOSED
+    vector_float2 points[4];
+} PietSegment;
+
+// Fill of a path made of curve segments, flattened by the tiler
+typedef struct PietFillPath {
+    uint itemType;
+    uint flags; // as in PietFill
+    uint rgbaColor;
+    uint nSegs;
+    uint segsIx;
+} PietFillPath;
+
+// Stroke of a path made of curve segments, flattened by the tiler
+typedef struct PietStrokePath {
+    uint itemType;
+    uint flags; // stroke style
+    uint rgbaColor;
+    float width;
+    uint nSegs;
+    uint segsIx;
+    float miterLimit;
+} PietStrokePath;
+
+// A group clipped to the inside of a path
+typedef struct PietClip {
+    uint itemType;
+    uint flags; // PIET_FILL_EVEN_ODD selects the fill rule
+    uint nSegs;
+    uint segsIx;
+    uint groupIx;
+} PietClip;
+
+// A group composited on its own, then onto the scene with opacity alpha and
+// one of the PIET_BLEND_* modes
+typedef struct PietLayer {
+    uint itemType;
+    float alpha;
+    uint groupIx;
+    uint blend;
+} PietLayer;
+
 typedef union PietItem {
     uint itemType;
     PietCircle circle;
     PietStrokeLine line;
     PietFill fill;
     PietStrokePolyLine poly;
+    PietGroup group;
+    PietFillPath fillPath;
+    PietStrokePath strokePath;
+    PietClip clip;
+    PietLayer layer;
 } PietItem;
 
 // This should be an enum but the storage needs to be of fixed size
@@ -81,3 +208,69 @@ typedef union PietItem {
 #define PIET_ITEM_LINE 2
 #define PIET_ITEM_FILL 3
 #define PIET_ITEM_STROKE_POLYLINE 4
+#define PIET_ITEM_GROUP 5
+#define PIET_ITEM_FILL_PATH 6
+#define PIET_ITEM_STROKE_PATH 7
+#define PIET_ITEM_CLIP 8
+#define PIET_ITEM_LAYER 9
+
+#define PIET_SEG_LINE 1
+#define PIET_SEG_QUAD 2
+#define PIET_SEG_CUBIC 3
+
+// Flag bits for fill items
+#define PIET_FILL_EVEN_ODD 1
+
+// Flag bit in draw items whose rgbaColor is the offset of a PietGradient
+// rather than a color.
+#define PIET_PAINT_GRADIENT (1 << 8)
+
+// Flag bit in draw items whose rgbaColor is the offset of a PietImageFill.
+#define PIET_PAINT_IMAGE (1 << 9)
+
+// Image fill flag selecting bilinear rather than nearest-neighbor sampling.
+#define PIET_IMAGE_BILINEAR 1
+
+// Gradient flags: the shape, and how it extends past its end stops.
+#define PIET_GRADIENT_RADIAL 1
+#define PIET_GRADIENT_EXTEND_MASK (3 << 1)
+#define PIET_GRADIENT_EXTEND_PAD 0
+#define PIET_GRADIENT_EXTEND_REPEAT (1 << 1)
+#define PIET_GRADIENT_EXTEND_REFLECT (2 << 1)
+
+// Blend modes of layers, as in the W3C Compositing and Blending spec.
+#define PIET_BLEND_NORMAL 0
+#define PIET_BLEND_MULTIPLY 1
+#define PIET_BLEND_SCREEN 2
+#define PIET_BLEND_OVERLAY 3
+#define PIET_BLEND_DARKEN 4
+#define PIET_BLEND_LIGHTEN 5
+#define PIET_BLEND_COLOR_DODGE 6
+#define PIET_BLEND_COLOR_BURN 7
+#define PIET_BLEND_HARD_LIGHT 8
+#define PIET_BLEND_SOFT_LIGHT 9
+#define PIET_BLEND_DIFFERENCE 10
+#define PIET_BLEND_EXCLUSION 11
+#define PIET_BLEND_HUE 12
+#define PIET_BLEND_SATURATION 13
+#define PIET_BLEND_COLOR 14
+#define PIET_BLEND_LUMINOSITY 15
+
+// Stroke style flags. The default of 0 is round caps and joins.
+#define PIET_STROKE_CAP_MASK 3
+#define PIET_STROKE_CAP_ROUND 0
+#define PIET_STROKE_CAP_BUTT 1
+#define PIET_STROKE_CAP_SQUARE 2
+#define PIET_STROKE_JOIN_MASK (3 << 2)
+#define PIET_STROKE_JOIN_ROUND 0
+#define PIET_STROKE_JOIN_MITER (1 << 2)
+#define PIET_STROKE_JOIN_BEVEL (2 << 2)
+// Set on a polyline whose last point is the same as its first, to join the
+// ends rather than cap them.
+#define PIET_STROKE_CLOSED (1 << 4)
+
+// Segment flags, marking the first and last segments of subpaths. Closed
+// subpaths have PIET_SEG_CLOSED on both, and are joined rather than capped.
+#define PIET_SEG_SUBPATH_START 1
+#define PIET_SEG_SUBPATH_END 2
+#define PIET_SEG_CLOSED 4
@@ -1,3 +1,20 @@
+#include <stddef.h>
 #include <stdint.h>
 
-void init_test_scene(uint8_t *buf, ssize_t buf_size);
+// The grid of tiles for a render target. It covers the target, rounded up to
+// whole threadgroups of the tiler, and is passed to the kernels as a uint2.
+typedef struct TileGrid {
+    uint32_t n_tiles_x;
+    uint32_t n_tiles_y;
+} TileGrid;
+
+// Returns the tile grid for a render target of the given size in pixels.
+TileGrid tile_grid(uint32_t width, uint32_t height);
+
+// Returns the number of bytes needed for the tile buffer, with a pool of
+// n_pool_chunks chunks for command lists that overflow their first chunk.
+size_t tile_buf_size(TileGrid grid, uint32_t n_pool_chunks);
+
+// Returns the number of bytes needed for the scene; if this is more than
+// buf_size, nothing is written.
+size_t init_test_scene(uint8_t *buf, size_t buf_size);
@@ -10,13 +10,13 @@ extern crate quote;
 
 use std::collections::HashSet;
 use std::fmt::Write;
-use std::ops::Deref;
 
 use proc_macro::TokenStream;
-use syn::{parse::Parse, parse::ParseStream, parse_macro_input, spanned::Spanned};
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use syn::{parse_macro_input, spanned::Spanned};
 use syn::{
-    Data, Expr, ExprLit, Fields, FieldsNamed, FieldsUnnamed, GenericArgument, ItemEnum, ItemStruct,
-    Lit, PathArguments, TypeArray, TypePath,
+    Data, DataEnum, DataStruct, Expr, ExprLit, ExprPath, Fields, FieldsNamed, FieldsUnnamed,
+    GenericArgument, ItemEnum, ItemStruct, Lit, PathArguments, TypeArray, TypePath,
 };
 
 #[derive(Clone, Copy, PartialEq)]
@@ -37,6 +37,10 @@ enum GpuType {
     /// Used mostly for the body of enum variants.
     InlineStruct(String),
     Ref(Box<GpuType>),
+    /// A variable-length array, whose length is the named `u32` field of the struct.
+    /// It is either the last field of the struct, with its elements following the
+    /// struct, or behind a `Ref`.
+    Array(Box<GpuType>, String),
 }
 
 struct GpuEnum {
@@ -44,8 +48,11 @@ struct GpuEnum {
     variants: Vec<(String, Vec<GpuType>)>,
 }
 
+/// A field of a struct, with its name.
+type Field = (String, GpuType);
+
 enum GpuTypeDef {
-    Struct(String, Vec<(String, GpuType)>),
+    Struct(String, Vec<Field>),
     Enum(GpuEnum),
 }
 
@@ -56,6 +63,26 @@ struct GpuModule {
     defs: Vec<GpuTypeDef>,
 }
 
+/// A variable-length array field of a struct, with the layout its accessors need.
+struct ArrayField {
+    name: String,
+    elem: GpuType,
+    len_field: String,
+    /// Byte offset of the length in the struct.
+    len_offset: usize,
+    elements: ArrayElements,
+    /// Distance in bytes between elements.
+    stride: usize,
+}
+
+/// Where the elements of an array are.
+enum ArrayElements {
+    /// The elements follow the struct, starting at this byte offset from it.
+    Trailing(usize),
+    /// The elements are at the ref stored at this byte offset in the struct.
+    Ref(usize),
+}
+
 impl GpuScalar {
     fn metal_typename(self) -> &'static str {
         match self {
@@ -79,6 +106,66 @@ impl GpuScalar {
         }
     }
 
+    /// The GLSL type of an unpacked scalar, widened to 32 bits.
+    fn glsl_typename(self) -> &'static str {
+        match self {
+            GpuScalar::F32 => "float",
+            GpuScalar::I8 | GpuScalar::I16 | GpuScalar::I32 => "int",
+            GpuScalar::U8 | GpuScalar::U16 | GpuScalar::U32 => "uint",
+        }
+    }
+
+    fn glsl_vector_typename(self, size: usize) -> String {
+        let prefix = match self {
+            GpuScalar::F32 => "vec",
+            GpuScalar::I8 | GpuScalar::I16 | GpuScalar::I32 => "ivec",
+            GpuScalar::U8 | GpuScalar::U16 | GpuScalar::U32 => "uvec",
+        };
+        format!("{}{}", prefix, size)
+    }
+
+    /// The WGSL type of an unpacked scalar, widened to 32 bits.
+    fn wgsl_typename(self) -> &'static str {
+        match self {
+            GpuScalar::F32 => "f32",
+            GpuScalar::I8 | GpuScalar::I16 | GpuScalar::I32 => "i32",
+            GpuScalar::U8 | GpuScalar::U16 | GpuScalar::U32 => "u32",
+        }
+    }
+
+    /// WGSL expression reading a scalar at byte `offset` from the word `base`, from
+    /// the `u32` array `buf`. Scalars of less than 32 bits are extracted from their
+    /// word, and signed ones are sign-extended.
+    fn wgsl_load(self, buf: &str, base: &str, offset: usize) -> String {
+        let word = wgsl_word(buf, base, offset / 4);
+        let bit_offset = (offset % 4) * 8;
+        let bits = self.size() * 8;
+        match self {
+            GpuScalar::F32 => format!("bitcast<f32>({})", word),
+            GpuScalar::I32 => format!("bitcast<i32>({})", word),
+            GpuScalar::U32 => word,
+            GpuScalar::I8 | GpuScalar::I16 => format!(
+                "extractBits(bitcast<i32>({}), {}u, {}u)",
+                word, bit_offset, bits
+            ),
+            GpuScalar::U8 | GpuScalar::U16 => {
+                format!("extractBits({}, {}u, {}u)", word, bit_offset, bits)
+            }
+        }
+    }
+
+    fn rust_type(self) -> TokenStream2 {
+        match self {
+            GpuScalar::F32 => quote!(f32),
+            GpuScalar::I8 => quote!(i8),
+            GpuScalar::I16 => quote!(i16),
+            GpuScalar::I32 => quote!(i32),
+            GpuScalar::U8 => quote!(u8),
+            GpuScalar::U16 => quote!(u16),
+            GpuScalar::U32 => quote!(u32),
+        }
+    }
+
     fn size(self) -> usize {
         match self {
             GpuScalar::F32 | GpuScalar::I32 | GpuScalar::U32 => 4,
@@ -124,13 +211,209 @@ fn simplified_add(var_name: &str, c: usize) -> String {
     }
 }
 
+/// WGSL expression for the word `i` words after the word index `base`, in the
+/// `u32` array `buf`.
+fn wgsl_word(buf: &str, base: &str, i: usize) -> String {
+    if i == 0 {
+        format!("{}[{}]", buf, base)
+    } else if base.contains(' ') {
+        format!("{}[({}) + {}u]", buf, base, i)
+    } else {
+        format!("{}[{} + {}u]", buf, base, i)
+    }
+}
+
 /// Return number of `uints` required to store `num_bytes` bytes.
 fn size_in_uints(num_bytes: usize) -> usize {
     // a `uint` has a size of 4 bytes, (size_in_bytes + 4 - 1) / 4
     (num_bytes + 3) / 4
 }
 
-fn generate_hlsl_value_extractor(size_in_bits: u32) -> String {
+/// The layout module of piet-metal, which the generated Rust code relies on.
+fn layout_path() -> TokenStream2 {
+    quote!(::piet_metal::layout)
+}
+
+/// An implementation of `PietMetal` for a struct with the given fields, laid out
+/// from the layouts of the field types.
+fn rust_struct_impl(ident: &syn::Ident, fields: &[(syn::Ident, TokenStream2)]) -> TokenStream2 {
+    let layout = layout_path();
+    let name = ident.to_string();
+    let packed_name = format!("{}Packed", name);
+    let ref_name = format!("{}Ref", name);
+    let field_names: Vec<_> = fields.iter().map(|(name, _)| name).collect();
+    let field_strs = field_names.iter().map(|name| name.to_string());
+    let field_types: Vec<_> = fields.iter().map(|(_, ty)| ty).collect();
+    let n_fields = Literal::usize_unsuffixed(fields.len());
+    let ixs: Vec<_> = (0..fields.len()).map(Literal::usize_unsuffixed).collect();
+    let layouts = quote! {
+        [#((
+            <#field_types as #layout::PietMetal>::SIZE,
+            <#field_types as #layout::PietMetal>::ALIGN
+        )),*]
+    };
+    // An empty struct doesn't touch the buffer.
+    let buf = if fields.is_empty() {
+        format_ident!("_buf")
+    } else {
+        format_ident!("buf")
+    };
+    // The fields of a tagged struct follow the tag.
+    let offsets = |start: usize| {
+        let start = Literal::usize_unsuffixed(start);
+        if fields.is_empty() {
+            quote!()
+        } else {
+            quote! {
+                const OFFSETS: [usize; #n_fields] = #layout::field_offsets(#start, #layouts);
+            }
+        }
+    };
+    let writer = |start| {
+        let offsets = offsets(start);
+        quote! {
+            #offsets
+            #(#layout::PietMetal::write(&self.#field_names, &mut buf[OFFSETS[#ixs]..]);)*
+        }
+    };
+    let reader = |start| {
+        let offsets = offsets(start);
+        quote! {
+            #offsets
+            #ident {
+                #(#field_names: <#field_types as #layout::PietMetal>::read(&buf[OFFSETS[#ixs]..]),)*
+            }
+        }
+    };
+    let (write, write_tagged) = (writer(0), writer(4));
+    let (read, read_tagged) = (reader(0), reader(4));
+    quote! {
+        #[automatically_derived]
+        impl #layout::PietMetal for #ident {
+            const ALIGN: usize = #layout::struct_align(&#layouts);
+            const SIZE: usize = #layout::struct_size(0, &#layouts);
+            const TAGGED_SIZE: usize = #layout::struct_size(4, &#layouts);
+            const NAME: &'static str = #name;
+            const METAL_ACCESSOR: bool = false;
+
+            fn write(&self, #buf: &mut [u8]) {
+                #write
+            }
+
+            fn read(#buf: &[u8]) -> #ident {
+                #read
+            }
+
+            fn write_tagged(&self, #buf: &mut [u8]) {
+                #write_tagged
+            }
+
+            fn read_tagged(#buf: &[u8]) -> #ident {
+                #read_tagged
+            }
+
+            fn metal_type() -> String {
+                String::from(#packed_name)
+            }
+
+            fn metal_ref_type() -> String {
+                String::from(#ref_name)
+            }
+
+            fn metal_def(tagged: bool) -> String {
+                #layout::metal_struct(#name, tagged, &[#((
+                    #field_strs,
+                    <#field_types as #layout::PietMetal>::metal_type(),
+                    <#field_types as #layout::PietMetal>::METAL_ACCESSOR
+                )),*])
+            }
+        }
+    }
+}
+
+/// An implementation of `PietMetal` for an enum with the given variants, which have
+/// at most one field. The tag is followed by the field, tagged if it's a struct.
+fn rust_enum_impl(
+    ident: &syn::Ident,
+    variants: &[(syn::Ident, Option<TokenStream2>)],
+) -> TokenStream2 {
+    let layout = layout_path();
+    let name = ident.to_string();
+    let ref_name = format!("{}Ref", name);
+    let variant_strs = variants.iter().map(|(variant, _)| variant.to_string());
+    let body_types: Vec<_> = variants.iter().filter_map(|(_, ty)| ty.as_ref()).collect();
+    let mut writers = Vec::new();
+    let mut readers = Vec::new();
+    // Tags are 1-based, as in the Metal definitions.
+    for (i, (variant, ty)) in variants.iter().enumerate() {
+        let tag = Literal::u32_suffixed(i as u32 + 1);
+        let tag_writer = quote!(#layout::PietMetal::write(&#tag, buf););
+        if let Some(ty) = ty {
+            writers.push(quote! {
+                #ident::#variant(body) => {
+                    #tag_writer
+                    #layout::PietMetal::write_tagged(body, buf);
+                }
+            });
+            readers.push(quote! {
+                #tag => Some(#ident::#variant(<#ty as #layout::PietMetal>::read_tagged(buf)))
+            });
+        } else {
+            writers.push(quote!(#ident::#variant => { #tag_writer }));
+            readers.push(quote!(#tag => Some(#ident::#variant)));
+        }
+    }
+    quote! {
+        impl #ident {
+            /// Read the enum from the start of `buf`, or `None` if the tag is unknown.
+            pub fn try_read(buf: &[u8]) -> Option<#ident> {
+                match <u32 as #layout::PietMetal>::read(buf) {
+                    #(#readers,)*
+                    _ => None,
+                }
+            }
+        }
+
+        #[automatically_derived]
+        impl #layout::PietMetal for #ident {
+            const ALIGN: usize = 4;
+            const SIZE: usize = #layout::enum_size(&[#(
+                <#body_types as #layout::PietMetal>::TAGGED_SIZE
+            ),*]);
+            const NAME: &'static str = #name;
+            const VARIANTS: &'static [&'static str] = &[#(
+                <#body_types as #layout::PietMetal>::NAME
+            ),*];
+            const METAL_ACCESSOR: bool = false;
+
+            fn write(&self, buf: &mut [u8]) {
+                match self {
+                    #(#writers)*
+                }
+            }
+
+            fn read(buf: &[u8]) -> #ident {
+                #ident::try_read(buf).expect("unknown tag")
+            }
+
+            fn metal_type() -> String {
+                String::from(#name)
+            }
+
+            fn metal_ref_type() -> String {
+                String::from(#ref_name)
+            }
+
+            fn metal_def(_tagged: bool) -> String {
+                #layout::metal_enum(#name, Self::SIZE, &[#(#variant_strs),*])
+            }
+        }
+    }
+}
+
+/// A function extracting a value from a packed `uint`, with the given function
+/// qualifier (`inline` in HLSL).
+fn generate_value_extractor(size_in_bits: u32, qualifier: &str) -> String {
     if size_in_bits > 31 {
         panic!("nonsensical to generate an extractor for a value with bit size greater than 31");
     }
@@ -138,13 +421,13 @@ fn generate_hlsl_value_extractor(size_in_bits: u32) -> String {
 
     let mask_width: usize = 2_usize.pow(size_in_bits) - 1;
 
-    write!(
+    writeln!(
         extractor,
-        "inline uint extract_{}bit_value(uint bit_shift, uint package) {{\n",
-        size_in_bits
+        "{}uint extract_{}bit_value(uint bit_shift, uint package) {{",
+        qualifier, size_in_bits
     )
     .unwrap();
-    write!(extractor, "    uint mask = {};\n", mask_width).unwrap();
+    writeln!(extractor, "    uint mask = {};", mask_width).unwrap();
     write!(
         extractor,
         "{}",
@@ -160,6 +443,8 @@ fn generate_hlsl_value_extractor(size_in_bits: u32) -> String {
 struct StoredField {
     name: String,
     ty: GpuType,
+    /// Offset in bits in the packed field. Values are little-endian, so the first
+    /// field is in the low bits.
     offset: usize,
 }
 
@@ -193,6 +478,51 @@ struct SpecifiedStruct {
 }
 
 impl StoredField {
+    /// Whether the field has scalars of less than 32 bits, which are unpacked.
+    fn is_packed(&self) -> bool {
+        match self.ty {
+            GpuType::Scalar(scalar) | GpuType::Vector(scalar, _) => scalar.size() < 4,
+            _ => false,
+        }
+    }
+
+    /// GLSL expression unpacking the field from `packed`, a packed field of type
+    /// `packed_ty`. Signed values are sign-extended.
+    fn glsl_unpacked_value(&self, packed: &str, packed_ty: &GpuType) -> String {
+        let n_words = match packed_ty {
+            GpuType::Vector(_, size) => *size,
+            _ => 1,
+        };
+        let extract = |scalar: GpuScalar, bit_offset: usize| {
+            let bits = scalar.size() * 8;
+            let word = if n_words == 1 {
+                packed.to_string()
+            } else {
+                format!("{}[{}]", packed, bit_offset / 32)
+            };
+            let value = format!("extract_{}bit_value({}, {})", bits, bit_offset % 32, word);
+            match scalar {
+                GpuScalar::I8 | GpuScalar::I16 => {
+                    format!("int({} << {}) >> {}", value, 32 - bits, 32 - bits)
+                }
+                _ => value,
+            }
+        };
+        match self.ty {
+            GpuType::Scalar(scalar) => extract(scalar, self.offset),
+            GpuType::Vector(scalar, size) => {
+                let elements: Vec<_> = (0..size)
+                    .map(|i| extract(scalar, self.offset + i * scalar.size() * 8))
+                    .collect();
+                format!("{}({})", self.ty.glsl_typename(), elements.join(", "))
+            }
+            _ => panic!(
+                "only expected small types, got: {}",
+                self.ty.glsl_typename()
+            ),
+        }
+    }
+
     fn generate_hlsl_unpacker(&self, packed_struct_name: &str, packed_field_name: &str) -> String {
         let mut unpacker = String::new();
 
@@ -214,9 +544,9 @@ impl StoredField {
                     )
                     .unwrap();
 
-                    write!(
+                    writeln!(
                         unpacker,
-                        "    result = extract_{}bit_value({}, {});\n",
+                        "    result = extract_{}bit_value({}, {});",
                         size_in_bits, self.offset, packed_field_name
                     )
                     .unwrap();
@@ -245,12 +575,12 @@ impl StoredField {
                     .unwrap();
 
                     for i in 0..unpacked_size {
-                        write!(
+                        writeln!(
                             unpacker,
-                            "    result[{}] = extract_{}bit_value({}, {});\n",
+                            "    result[{}] = extract_{}bit_value({}, {});",
                             i,
                             scalar_size_in_bits,
-                            32 - (i + 1) * scalar_size_in_bits,
+                            self.offset + i * scalar_size_in_bits,
                             packed_field_name
                         )
                         .unwrap();
@@ -288,8 +618,10 @@ impl PackedField {
     ) -> Result<PackResult, String> {
         if !self.is_closed() {
             let field_size = field_type.size(module);
+            // Fields are aligned in the packed field as they are in Metal.
+            let offset = self.size + align_padding(self.size, field_type.alignment(module));
 
-            if field_size + self.size > 4 {
+            if offset + field_size > 4 {
                 if self.is_empty() {
                     self.stored_fields.push(StoredField {
                         name: String::from(field_name),
@@ -303,11 +635,11 @@ impl PackedField {
                     Ok(PackResult::FailAndClosed)
                 }
             } else {
-                self.size += field_size;
+                self.size = offset + field_size;
                 self.stored_fields.push(StoredField {
                     name: String::from(field_name),
                     ty: field_type.clone(),
-                    offset: 32 - self.size * 8,
+                    offset: offset * 8,
                 });
                 Ok(PackResult::SuccessAndOpen)
             }
@@ -358,12 +690,13 @@ impl PackedField {
                             },
                             GpuType::InlineStruct(_) => Ok(Some(pfty.clone())),
                             GpuType::Ref(inner) => {
-                                if let GpuType::InlineStruct(_) = inner.deref() {
+                                if let GpuType::InlineStruct(_) = inner.element() {
                                     Ok(Some(pfty.clone()))
                                 } else {
                                     Ok(Some(GpuType::Scalar(GpuScalar::U32)))
                                 }
                             }
+                            GpuType::Array(..) => unreachable!("arrays aren't packed"),
                         }
                     }
                     _ => match self.stored_fields.iter().any(|pf| pf.ty.size(module) == 32) {
@@ -431,7 +764,7 @@ impl PackedField {
                     simplified_add("ref", current_offset)
                 )),
                 GpuType::Ref(inner) => {
-                    if let GpuType::InlineStruct(isn) = inner.deref() {
+                    if let GpuType::InlineStruct(isn) = inner.element() {
                         Ok(format!(
                             "    {}Ref {} = buf.Load({});\n",
                             isn,
@@ -446,6 +779,7 @@ impl PackedField {
                         ))
                     }
                 }
+                GpuType::Array(..) => unreachable!("arrays aren't packed"),
             }
         } else {
             Err("cannot generate field reader from an open packed field".into())
@@ -463,9 +797,9 @@ impl PackedField {
 
             match ty {
                 GpuType::InlineStruct(_) => {
-                    write!(
+                    writeln!(
                         field_accessor,
-                        "inline {}Packed {}_{}(ByteAddressBuffer buf, {} ref) {{\n",
+                        "inline {}Packed {}_{}(ByteAddressBuffer buf, {} ref) {{",
                         ty.hlsl_typename(),
                         packed_struct_name,
                         self.name,
@@ -474,9 +808,9 @@ impl PackedField {
                     .unwrap();
                 }
                 _ => {
-                    write!(
+                    writeln!(
                         field_accessor,
-                        "inline {} {}_{}(ByteAddressBuffer buf, {} ref) {{\n",
+                        "inline {} {}_{}(ByteAddressBuffer buf, {} ref) {{",
                         ty.hlsl_typename(),
                         packed_struct_name,
                         self.name,
@@ -509,6 +843,23 @@ impl PackedField {
         unpackers
     }
 
+    /// The type of the packed field in a GLSL packed struct.
+    fn glsl_typename(&self) -> String {
+        match self.ty.as_ref().expect("packed field is open") {
+            GpuType::InlineStruct(name) => format!("{}Packed", name),
+            ty => ty.glsl_typename(),
+        }
+    }
+
+    /// Alignment of the packed field, as in Metal.
+    fn alignment(&self, module: &GpuModule) -> usize {
+        self.stored_fields
+            .iter()
+            .map(|sf| sf.ty.alignment(module))
+            .max()
+            .unwrap_or(1)
+    }
+
     fn size(&self, module: &GpuModule) -> Result<usize, String> {
         if let Some(ty) = &self.ty {
             Ok(ty.size(module))
@@ -524,7 +875,7 @@ impl PackedStruct {
 
         let mut current_packed_field = PackedField::new();
         for (field_name, ty) in fields {
-            match current_packed_field.pack(module, &ty, &field_name).unwrap() {
+            match current_packed_field.pack(module, ty, field_name).unwrap() {
                 PackResult::SuccessAndClosed => {
                     packed_fields.push(current_packed_field.clone());
                     current_packed_field = PackedField::new();
@@ -532,7 +883,13 @@ impl PackedStruct {
                 PackResult::FailAndClosed => {
                     packed_fields.push(current_packed_field.clone());
                     current_packed_field = PackedField::new();
-                    current_packed_field.pack(module, &ty, &field_name).unwrap();
+                    // A field too large to share a packed field closes it at once.
+                    if let PackResult::SuccessAndClosed =
+                        current_packed_field.pack(module, ty, field_name).unwrap()
+                    {
+                        packed_fields.push(current_packed_field.clone());
+                        current_packed_field = PackedField::new();
+                    }
                 }
                 _ => {}
             }
@@ -559,9 +916,9 @@ impl PackedStruct {
 
         let ref_type = format!("{}Ref", self.name);
 
-        write!(
+        writeln!(
             r,
-            "inline {} {}_read(ByteAddressBuffer buf, {} ref) {{\n",
+            "inline {} {}_read(ByteAddressBuffer buf, {} ref) {{",
             self.name, self.name, ref_type,
         )
         .unwrap();
@@ -610,24 +967,24 @@ impl PackedStruct {
         let mut r = String::new();
 
         // The packed struct definition (is missing variable sized arrays)
-        write!(r, "struct {} {{\n", self.name).unwrap();
+        writeln!(r, "struct {} {{", self.name).unwrap();
         if self.is_enum_variant {
-            write!(r, "    uint tag;\n").unwrap();
+            writeln!(r, "    uint tag;").unwrap();
         }
 
         for packed_field in self.packed_fields.iter() {
             match packed_field.ty.as_ref().unwrap() {
                 GpuType::InlineStruct(name) => {
                     // a packed struct will only store the packed version of any structs
-                    write!(r, "    {}Packed {};\n", name, packed_field.name)
+                    writeln!(r, "    {}Packed {};", name, packed_field.name)
                 }
-                _ => write!(
+                _ => writeln!(
                     r,
-                    "    {} {};\n",
+                    "    {} {};",
                     packed_field
                         .ty
                         .as_ref()
-                        .expect(&format!("packed field {} has no type", packed_field.name))
+                        .unwrap_or_else(|| panic!("packed field {} has no type", packed_field.name))
                         .hlsl_typename(),
                     packed_field.name
                 ),
@@ -647,6 +1004,86 @@ impl PackedStruct {
 
         r
     }
+
+    /// The packed struct in GLSL, with its reader, accessors for the packed fields,
+    /// and unpackers for the fields stored in less than 32 bits. Values are read
+    /// from the `uint` array `buf`.
+    fn to_glsl(&self, module: &GpuModule, buf: &str) -> String {
+        let mut r = String::new();
+        let ref_type = format!("{}Ref", self.name);
+
+        writeln!(r, "struct {} {{", self.name).unwrap();
+        if self.is_enum_variant {
+            writeln!(r, "    uint tag;").unwrap();
+        } else if self.packed_fields.is_empty() {
+            // GLSL doesn't allow empty structs.
+            writeln!(r, "    uint dummy;").unwrap();
+        }
+        for packed_field in &self.packed_fields {
+            writeln!(
+                r,
+                "    {} {};",
+                packed_field.glsl_typename(),
+                packed_field.name
+            )
+            .unwrap();
+        }
+        write!(r, "}};\n\n").unwrap();
+
+        let mut reader = String::new();
+        let mut accessors = String::new();
+        let mut unpackers = String::new();
+        let mut offset = if self.is_enum_variant { 4 } else { 0 };
+        for packed_field in &self.packed_fields {
+            let alignment = packed_field.alignment(module);
+            offset += align_padding(offset, alignment);
+            let load = packed_field.ty.as_ref().unwrap().glsl_load(buf, offset);
+            writeln!(reader, "    result.{} = {};", packed_field.name, load).unwrap();
+            write!(
+                accessors,
+                "{} {}_{}({} ref) {{\n    uint ix = ref >> 2;\n    return {};\n}}\n\n",
+                packed_field.glsl_typename(),
+                self.name,
+                packed_field.name,
+                ref_type,
+                load
+            )
+            .unwrap();
+            for stored_field in &packed_field.stored_fields {
+                if stored_field.is_packed() {
+                    write!(
+                        unpackers,
+                        "{} {}_unpack_{}({} {}) {{\n    return {};\n}}\n\n",
+                        stored_field.ty.glsl_typename(),
+                        self.name,
+                        stored_field.name,
+                        packed_field.glsl_typename(),
+                        packed_field.name,
+                        stored_field.glsl_unpacked_value(
+                            &packed_field.name,
+                            packed_field.ty.as_ref().unwrap()
+                        ),
+                    )
+                    .unwrap();
+                }
+            }
+            let size = packed_field.size(module).unwrap();
+            offset += size + align_padding(size, alignment);
+        }
+
+        writeln!(r, "{} {}_read({} ref) {{", self.name, self.name, ref_type).unwrap();
+        writeln!(r, "    uint ix = ref >> 2;").unwrap();
+        write!(r, "    {} result;\n\n", self.name).unwrap();
+        if self.is_enum_variant {
+            writeln!(r, "    result.tag = {}[ix];", buf).unwrap();
+        }
+        write!(r, "{}", reader).unwrap();
+        write!(r, "    return result;\n}}\n\n").unwrap();
+        write!(r, "{}", accessors).unwrap();
+        write!(r, "{}", unpackers).unwrap();
+
+        r
+    }
 }
 
 impl SpecifiedStruct {
@@ -664,10 +1101,10 @@ impl SpecifiedStruct {
         let mut r = String::new();
 
         // The packed struct definition (is missing variable sized arrays)
-        write!(r, "struct {} {{\n", self.name).unwrap();
+        writeln!(r, "struct {} {{", self.name).unwrap();
 
         for (field_name, field_type) in self.fields.iter() {
-            write!(r, "    {} {};\n", field_type.hlsl_typename(), field_name).unwrap()
+            writeln!(r, "    {} {};", field_type.hlsl_typename(), field_name).unwrap()
         }
         write!(r, "{}", "};\n\n").unwrap();
 
@@ -677,9 +1114,9 @@ impl SpecifiedStruct {
     fn generate_hlsl_unpacker(&self) -> String {
         let mut r = String::new();
 
-        write!(
+        writeln!(
             r,
-            "inline {} {}_unpack({} packed_form) {{\n",
+            "inline {} {}_unpack({} packed_form) {{",
             self.name, self.packed_form.name, self.packed_form.name,
         )
         .unwrap();
@@ -696,23 +1133,25 @@ impl SpecifiedStruct {
                         .find(|&sf| sf.name == field_name.as_str())
                         .is_some()
                 })
-                .expect(&format!(
-                    "no packed field stores {} in {}Packed",
-                    field_name, self.name
-                ));
+                .unwrap_or_else(|| {
+                    panic!(
+                        "no packed field stores {} in {}Packed",
+                        field_name, self.name
+                    )
+                });
             match field_type {
                 GpuType::InlineStruct(name) => {
-                    write!(
+                    writeln!(
                         r,
-                        "    result.{} = {}Packed_unpack(packed_form.{});\n",
+                        "    result.{} = {}Packed_unpack(packed_form.{});",
                         field_name, name, packed_field.name
                     )
                     .unwrap();
                 }
                 _ => {
-                    write!(
+                    writeln!(
                         r,
-                        "    result.{} = {}_unpack_{}(packed_form.{});\n",
+                        "    result.{} = {}_unpack_{}(packed_form.{});",
                         field_name, self.packed_form.name, field_name, packed_field.name
                     )
                     .unwrap();
@@ -731,6 +1170,69 @@ impl SpecifiedStruct {
 
         r
     }
+
+    /// The unpacked struct in GLSL, with a function unpacking it from the packed
+    /// struct, and a reader.
+    fn to_glsl(&self) -> String {
+        let mut r = String::new();
+        let packed_name = &self.packed_form.name;
+
+        writeln!(r, "struct {} {{", self.name).unwrap();
+        if self.fields.is_empty() {
+            writeln!(r, "    uint dummy;").unwrap();
+        }
+        for (field_name, field_type) in &self.fields {
+            writeln!(r, "    {} {};", field_type.glsl_typename(), field_name).unwrap();
+        }
+        write!(r, "}};\n\n").unwrap();
+
+        writeln!(
+            r,
+            "{} {}_unpack({} packed_form) {{",
+            self.name, packed_name, packed_name
+        )
+        .unwrap();
+        writeln!(r, "    {} result;", self.name).unwrap();
+        if !self.fields.is_empty() {
+            writeln!(r).unwrap();
+        }
+        for (field_name, field_type) in &self.fields {
+            let packed_field = self
+                .packed_form
+                .packed_fields
+                .iter()
+                .find(|pf| pf.stored_fields.iter().any(|sf| &sf.name == field_name))
+                .unwrap_or_else(|| {
+                    panic!("no packed field stores {} in {}", field_name, packed_name)
+                });
+            let stored_field = packed_field
+                .stored_fields
+                .iter()
+                .find(|sf| &sf.name == field_name)
+                .unwrap();
+            let value = match field_type {
+                GpuType::InlineStruct(name) => {
+                    format!("{}Packed_unpack(packed_form.{})", name, packed_field.name)
+                }
+                _ if stored_field.is_packed() => format!(
+                    "{}_unpack_{}(packed_form.{})",
+                    packed_name, field_name, packed_field.name
+                ),
+                _ => format!("packed_form.{}", packed_field.name),
+            };
+            writeln!(r, "    result.{} = {};", field_name, value).unwrap();
+        }
+        write!(r, "\n    return result;\n}}\n\n").unwrap();
+
+        write!(
+            r,
+            "{} {}_read({}Ref ref) {{\n    return {}_unpack({}_read(ref));\n}}\n\n",
+            self.name, self.name, self.name, packed_name, packed_name
+        )
+        .unwrap();
+
+        r
+    }
 }
 
 impl GpuType {
@@ -741,12 +1243,13 @@ impl GpuType {
             GpuType::InlineStruct(name) => format!("{}Packed", name),
             // TODO: probably want to have more friendly names for simple struct refs.
             GpuType::Ref(inner) => {
-                if let GpuType::InlineStruct(name) = inner.deref() {
+                if let GpuType::InlineStruct(name) = inner.element() {
                     format!("{}Ref", name)
                 } else {
                     "uint".into()
                 }
             }
+            GpuType::Array(elem, _) => elem.metal_typename(),
         }
     }
 
@@ -772,12 +1275,100 @@ impl GpuType {
             GpuType::InlineStruct(name) => name.to_string(),
             // TODO: probably want to have more friendly names for simple struct refs.
             GpuType::Ref(inner) => {
-                if let GpuType::InlineStruct(name) = inner.deref() {
+                if let GpuType::InlineStruct(name) = inner.element() {
+                    format!("{}Ref", name)
+                } else {
+                    "uint".into()
+                }
+            }
+            GpuType::Array(elem, _) => elem.hlsl_typename(),
+        }
+    }
+
+    /// The GLSL type of an unpacked value. A vector of one element is the packed
+    /// form of a vector of smaller scalars, and is a scalar.
+    fn glsl_typename(&self) -> String {
+        match self {
+            GpuType::Scalar(scalar) | GpuType::Vector(scalar, 1) => scalar.glsl_typename().into(),
+            GpuType::Vector(scalar, size) => scalar.glsl_vector_typename(*size),
+            GpuType::InlineStruct(name) => name.to_string(),
+            GpuType::Ref(inner) => {
+                if let GpuType::InlineStruct(name) = inner.element() {
                     format!("{}Ref", name)
                 } else {
                     "uint".into()
                 }
             }
+            GpuType::Array(elem, _) => elem.glsl_typename(),
+        }
+    }
+
+    /// GLSL expression loading a packed field of this type at byte `offset` from
+    /// `ref`, from the `uint` array `buf`. `ix` is `ref` in words.
+    fn glsl_load(&self, buf: &str, offset: usize) -> String {
+        let word = |i: usize| format!("{}[{}]", buf, simplified_add("ix", offset / 4 + i));
+        match self {
+            GpuType::Scalar(scalar) | GpuType::Vector(scalar, 1) => match scalar {
+                GpuScalar::F32 => format!("uintBitsToFloat({})", word(0)),
+                GpuScalar::I32 => format!("int({})", word(0)),
+                _ => word(0),
+            },
+            GpuType::Vector(scalar, size) => {
+                let words: Vec<_> = (0..*size).map(word).collect();
+                let uvec = format!("uvec{}({})", size, words.join(", "));
+                match scalar {
+                    GpuScalar::F32 => format!("uintBitsToFloat({})", uvec),
+                    GpuScalar::I32 => format!("ivec{}({})", size, uvec),
+                    _ => uvec,
+                }
+            }
+            GpuType::InlineStruct(name) => {
+                format!("{}Packed_read({})", name, simplified_add("ref", offset))
+            }
+            GpuType::Ref(_) => word(0),
+            GpuType::Array(..) => unreachable!("arrays are read by element"),
+        }
+    }
+
+    /// The WGSL type of an unpacked value.
+    fn wgsl_typename(&self) -> String {
+        match self {
+            GpuType::Scalar(scalar) => scalar.wgsl_typename().into(),
+            GpuType::Vector(scalar, size) => format!("vec{}<{}>", size, scalar.wgsl_typename()),
+            GpuType::InlineStruct(name) => name.to_string(),
+            GpuType::Ref(inner) => {
+                if let GpuType::InlineStruct(name) = inner.element() {
+                    format!("{}Ref", name)
+                } else {
+                    "u32".into()
+                }
+            }
+            GpuType::Array(elem, _) => elem.wgsl_typename(),
+        }
+    }
+
+    /// WGSL expression reading a value of this type at byte `offset` from the word
+    /// `base`, from the `u32` array `buf`. A struct is read at byte `offset` in the
+    /// value referenced by the `offset` argument of the accessor.
+    fn wgsl_load(&self, buf: &str, base: &str, offset: usize) -> String {
+        match self {
+            GpuType::Scalar(scalar) => scalar.wgsl_load(buf, base, offset),
+            GpuType::Vector(scalar, size) => {
+                let elements: Vec<_> = (0..*size)
+                    .map(|i| scalar.wgsl_load(buf, base, offset + i * scalar.size()))
+                    .collect();
+                format!("{}({})", self.wgsl_typename(), elements.join(", "))
+            }
+            GpuType::InlineStruct(name) => {
+                let offset = if offset == 0 {
+                    "offset".to_string()
+                } else {
+                    format!("offset + {}u", offset)
+                };
+                format!("{}_read({})", name, offset)
+            }
+            GpuType::Ref(_) => wgsl_word(buf, base, offset / 4),
+            GpuType::Array(..) => unreachable!("arrays are read by element"),
         }
     }
 
@@ -785,8 +1376,11 @@ impl GpuType {
         match self {
             GpuType::Scalar(scalar) => scalar.size(),
             GpuType::Vector(scalar, size) => scalar.size() * size,
-            GpuType::InlineStruct(name) => module.resolve_by_name(&name).unwrap().size(module),
+            // A struct takes its size in an array, as in Metal.
+            GpuType::InlineStruct(name) => module.resolve_by_name(name).unwrap().stride(module),
             GpuType::Ref(_name) => 4,
+            // The elements of a trailing array follow the struct.
+            GpuType::Array(..) => 0,
         }
     }
 
@@ -795,8 +1389,53 @@ impl GpuType {
         match self {
             GpuType::Scalar(scalar) => scalar.size(),
             GpuType::Vector(scalar, size) => scalar.size() * size,
-            GpuType::InlineStruct(name) => module.resolve_by_name(&name).unwrap().alignment(module),
+            GpuType::InlineStruct(name) => module.resolve_by_name(name).unwrap().alignment(module),
             GpuType::Ref(_name) => 4,
+            GpuType::Array(..) => 1,
+        }
+    }
+
+    /// Alignment of the elements of an array. Vectors are read as packed vectors,
+    /// so they are only aligned to their scalars.
+    fn array_alignment(&self, module: &GpuModule) -> usize {
+        match self {
+            GpuType::InlineStruct(_) => self.alignment(module),
+            _ => 4,
+        }
+    }
+
+    /// The type of the elements of an array, or the type itself.
+    fn element(&self) -> &GpuType {
+        match self {
+            GpuType::Array(elem, _) => elem,
+            ty => ty,
+        }
+    }
+
+    /// The elements and length field of an array, trailing or behind a ref.
+    fn array(&self) -> Option<(&GpuType, &str)> {
+        match self {
+            GpuType::Array(elem, len_field) => Some((elem, len_field)),
+            GpuType::Ref(inner) => inner.array(),
+            _ => None,
+        }
+    }
+
+    /// The type of the field in the generated Rust struct. Refs are byte offsets.
+    fn rust_type(&self) -> TokenStream2 {
+        match self {
+            GpuType::Scalar(scalar) => scalar.rust_type(),
+            GpuType::Vector(scalar, size) => {
+                let ty = scalar.rust_type();
+                let size = Literal::usize_unsuffixed(*size);
+                quote!([#ty; #size])
+            }
+            GpuType::InlineStruct(name) => {
+                let name = format_ident!("{}", name);
+                quote!(#name)
+            }
+            GpuType::Ref(_) => quote!(u32),
+            GpuType::Array(elem, _) => elem.rust_type(),
         }
     }
 
@@ -807,6 +1446,7 @@ impl GpuType {
             GpuType::Vector(_, _) => true,
             GpuType::InlineStruct(_) => false,
             GpuType::Ref(_) => true,
+            GpuType::Array(..) => false,
         }
     }
 
@@ -840,50 +1480,391 @@ impl GpuType {
                 Err("unknown path case".into())
             }
             syn::Type::Array(TypeArray { elem, len, .. }) => {
-                if let Some(elem) = GpuScalar::from_syn(&elem) {
+                if let Some(len_field) = expr_ident(len) {
+                    let elem = GpuType::from_syn(elem)?;
+                    return Ok(GpuType::Array(Box::new(elem), len_field));
+                }
+                if let Some(elem) = GpuScalar::from_syn(elem) {
                     if let Some(len) = expr_int_lit(len) {
                         // maybe sanity-check length here
                         Ok(GpuType::Vector(elem, len))
                     } else {
-                        Err("can't deal with variable length scalar arrays".into())
+                        Err("the length of an array must be a literal or a field".into())
                     }
                 } else {
                     Err("can't deal with non-scalar arrays".into())
                 }
             }
+            syn::Type::Slice(_) => {
+                Err("a variable-length array needs the field with its length, as in [T; n]".into())
+            }
             _ => Err("unknown type".into()),
         }
     }
 }
 
-impl GpuTypeDef {
-    fn from_syn(item: &syn::Item) -> Result<Self, String> {
-        match item {
-            syn::Item::Struct(ItemStruct {
-                ident,
-                fields: Fields::Named(FieldsNamed { named, .. }),
-                ..
-            }) => {
-                let mut fields = Vec::new();
-                for field in named {
-                    let field_ty = GpuType::from_syn(&field.ty)?;
-                    let field_name = field.ident.as_ref().ok_or("need name".to_string())?;
-                    fields.push((field_name.to_string(), field_ty));
-                }
-                Ok(GpuTypeDef::Struct(ident.to_string(), fields))
-            }
-            syn::Item::Enum(ItemEnum {
-                ident, variants, ..
-            }) => {
-                let mut v = Vec::new();
-                for variant in variants {
+/// `var * c`, or `var` if `c = 1`.
+fn scaled(var_name: &str, c: usize) -> String {
+    if c == 1 {
+        String::from(var_name)
+    } else {
+        format!("{} * {}", var_name, c)
+    }
+}
+
+impl ArrayField {
+    /// The name of the elements if they are structs or enums, which the indexed
+    /// accessors return refs to rather than reading them.
+    fn elem_struct(&self) -> Option<&str> {
+        if let GpuType::InlineStruct(name) = &self.elem {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Metal accessors for the length, and for an element of the array of the
+    /// struct `name`.
+    fn to_metal(&self, name: &str) -> String {
+        let mut r = String::new();
+        let rn = format!("{}Ref", name);
+        let packed = format!("((const device {}Packed *)(buf + ref))", name);
+        writeln!(
+            r,
+            "uint {}_{}_len(const device char *buf, {} ref) {{",
+            name, self.name, rn
+        )
+        .unwrap();
+        writeln!(r, "    return {}->{};", packed, self.len_field).unwrap();
+        writeln!(r, "}}").unwrap();
+        let base = match self.elements {
+            ArrayElements::Trailing(start) => format!("ref + {}", start),
+            ArrayElements::Ref(_) => format!("{}->{}", packed, self.name),
+        };
+        if let Some(elem_name) = self.elem_struct() {
+            writeln!(
+                r,
+                "{}Ref {}_{}_at(const device char *buf, {} ref, uint i) {{",
+                elem_name, name, self.name, rn
+            )
+            .unwrap();
+            writeln!(r, "    return {} + i * {};", base, self.stride).unwrap();
+        } else {
+            // Vectors are read packed, as the elements are only aligned to words.
+            let elem_ty = match self.elem {
+                GpuType::Vector(..) => format!("packed_{}", self.elem.metal_typename()),
+                _ => self.elem.metal_typename(),
+            };
+            writeln!(
+                r,
+                "{} {}_{}_at(const device char *buf, {} ref, uint i) {{",
+                self.elem.metal_typename(),
+                name,
+                self.name,
+                rn
+            )
+            .unwrap();
+            let base = match self.elements {
+                ArrayElements::Trailing(_) => base,
+                ArrayElements::Ref(_) => {
+                    writeln!(r, "    uint base = {};", base).unwrap();
+                    "base".into()
+                }
+            };
+            writeln!(
+                r,
+                "    return ((const device {} *)(buf + {}))[i];",
+                elem_ty, base
+            )
+            .unwrap();
+        }
+        writeln!(r, "}}").unwrap();
+        r
+    }
+
+    /// GLSL accessors for the length, and for an element of the array of the struct
+    /// `name`, reading from the `uint` array `buf`.
+    fn to_glsl(&self, name: &str, buf: &str) -> String {
+        let mut r = String::new();
+        let rn = format!("{}Ref", name);
+        let word = |offset: usize| format!("{}[{}]", buf, simplified_add("(ref >> 2)", offset / 4));
+        writeln!(r, "uint {}_{}_len({} ref) {{", name, self.name, rn).unwrap();
+        writeln!(r, "    return {};", word(self.len_offset)).unwrap();
+        writeln!(r, "}}\n").unwrap();
+        if let Some(elem_name) = self.elem_struct() {
+            let base = match self.elements {
+                ArrayElements::Trailing(start) => simplified_add("ref", start),
+                ArrayElements::Ref(offset) => word(offset),
+            };
+            writeln!(
+                r,
+                "{}Ref {}_{}_at({} ref, uint i) {{",
+                elem_name, name, self.name, rn
+            )
+            .unwrap();
+            writeln!(r, "    return {} + i * {};", base, self.stride).unwrap();
+        } else {
+            let base = match self.elements {
+                ArrayElements::Trailing(start) => simplified_add("(ref >> 2)", start / 4),
+                ArrayElements::Ref(offset) => format!("({} >> 2)", word(offset)),
+            };
+            writeln!(
+                r,
+                "{} {}_{}_at({} ref, uint i) {{",
+                self.elem.glsl_typename(),
+                name,
+                self.name,
+                rn
+            )
+            .unwrap();
+            writeln!(
+                r,
+                "    uint ix = {} + {};",
+                base,
+                scaled("i", self.stride / 4)
+            )
+            .unwrap();
+            writeln!(r, "    return {};", self.elem.glsl_load(buf, 0)).unwrap();
+        }
+        writeln!(r, "}}\n").unwrap();
+        r
+    }
+
+    /// WGSL accessors for the length, and for an element of the array of the struct
+    /// `name`, reading from the `u32` array `buf`.
+    fn to_wgsl(&self, name: &str, buf: &str) -> String {
+        let mut r = String::new();
+        let rn = format!("{}Ref", name);
+        let word = |offset: usize| wgsl_word(buf, "offset >> 2u", offset / 4);
+        writeln!(r, "fn {}_{}_len(offset: {}) -> u32 {{", name, self.name, rn).unwrap();
+        writeln!(r, "    return {};", word(self.len_offset)).unwrap();
+        writeln!(r, "}}\n").unwrap();
+        if let Some(elem_name) = self.elem_struct() {
+            let base = match self.elements {
+                ArrayElements::Trailing(start) => format!("offset + {}u", start),
+                ArrayElements::Ref(offset) => word(offset),
+            };
+            writeln!(
+                r,
+                "fn {}_{}_at(offset: {}, i: u32) -> {}Ref {{",
+                name, self.name, rn, elem_name
+            )
+            .unwrap();
+            writeln!(r, "    return {} + i * {}u;", base, self.stride).unwrap();
+        } else {
+            let base = match self.elements {
+                ArrayElements::Trailing(start) => format!("(offset >> 2u) + {}u", start / 4),
+                ArrayElements::Ref(offset) => format!("({} >> 2u)", word(offset)),
+            };
+            let i = if self.stride == 4 {
+                "i".to_string()
+            } else {
+                format!("i * {}u", self.stride / 4)
+            };
+            writeln!(
+                r,
+                "fn {}_{}_at(offset: {}, i: u32) -> {} {{",
+                name,
+                self.name,
+                rn,
+                self.elem.wgsl_typename()
+            )
+            .unwrap();
+            writeln!(r, "    let ix = {} + {};", base, i).unwrap();
+            writeln!(r, "    return {};", self.elem.wgsl_load(buf, "ix", 0)).unwrap();
+        }
+        writeln!(r, "}}\n").unwrap();
+        r
+    }
+
+    /// HLSL accessors for the length, and for an element of the array of the struct
+    /// `name`.
+    fn to_hlsl(&self, name: &str) -> String {
+        let mut r = String::new();
+        let rn = format!("{}Ref", name);
+        writeln!(
+            r,
+            "inline uint {}_{}_len(ByteAddressBuffer buf, {} ref) {{",
+            name, self.name, rn
+        )
+        .unwrap();
+        writeln!(
+            r,
+            "    return buf.Load({});",
+            simplified_add("ref", self.len_offset)
+        )
+        .unwrap();
+        writeln!(r, "}}\n").unwrap();
+        let base = match self.elements {
+            ArrayElements::Trailing(start) => simplified_add("ref", start),
+            ArrayElements::Ref(offset) => format!("buf.Load({})", simplified_add("ref", offset)),
+        };
+        let address = format!("{} + i * {}", base, self.stride);
+        if let Some(elem_name) = self.elem_struct() {
+            writeln!(
+                r,
+                "inline {}Ref {}_{}_at(ByteAddressBuffer buf, {} ref, uint i) {{",
+                elem_name, name, self.name, rn
+            )
+            .unwrap();
+            writeln!(r, "    return {};", address).unwrap();
+        } else {
+            let load = match self.elem {
+                GpuType::Vector(_, size) => format!("buf.Load{}({})", size, address),
+                _ => format!("buf.Load({})", address),
+            };
+            let value = match self.elem {
+                GpuType::Scalar(GpuScalar::F32) | GpuType::Vector(GpuScalar::F32, _) => {
+                    format!("asfloat({})", load)
+                }
+                GpuType::Scalar(GpuScalar::I32) | GpuType::Vector(GpuScalar::I32, _) => {
+                    format!("asint({})", load)
+                }
+                _ => load,
+            };
+            writeln!(
+                r,
+                "inline {} {}_{}_at(ByteAddressBuffer buf, {} ref, uint i) {{",
+                self.elem.hlsl_typename(),
+                name,
+                self.name,
+                rn
+            )
+            .unwrap();
+            writeln!(r, "    return {};", value).unwrap();
+        }
+        writeln!(r, "}}\n").unwrap();
+        r
+    }
+
+    /// Methods of the Rust struct `ident` writing the elements of the array, and
+    /// reading one of them. Writing the elements also sets the length.
+    fn to_rust(&self, ident: &syn::Ident) -> TokenStream2 {
+        let layout = layout_path();
+        let elem_ty = self.elem.rust_type();
+        let field = format_ident!("{}", self.name);
+        let len = format_ident!("{}", self.len_field);
+        let stride = Literal::usize_unsuffixed(self.stride);
+        let at = format_ident!("{}_at", self.name);
+        let out_of_bounds = format!("{} index out of bounds", self.name);
+        match self.elements {
+            ArrayElements::Trailing(start) => {
+                let start = Literal::usize_unsuffixed(start);
+                let size_with = format_ident!("size_with_{}", self.name);
+                let write_with = format_ident!("write_with_{}", self.name);
+                let size_doc = format!(
+                    "Size in bytes of the struct followed by `len` elements of `{}`.",
+                    self.name
+                );
+                let write_doc = format!(
+                    "Write the struct followed by the elements of `{}`, setting `{}` to \
+                     their number.",
+                    self.name, self.len_field
+                );
+                let at_doc = format!(
+                    "Read element `i` of `{}` from `buf`, which starts with the struct.",
+                    self.name
+                );
+                quote! {
+                    #[doc = #size_doc]
+                    pub const fn #size_with(len: usize) -> usize {
+                        #start + len * #stride
+                    }
+
+                    #[doc = #write_doc]
+                    pub fn #write_with(&mut self, buf: &mut [u8], elements: &[#elem_ty]) {
+                        self.#len = elements.len() as u32;
+                        <#ident as #layout::PietMetal>::write(self, buf);
+                        for (i, element) in elements.iter().enumerate() {
+                            #layout::PietMetal::write(element, &mut buf[#start + i * #stride..]);
+                        }
+                    }
+
+                    #[doc = #at_doc]
+                    pub fn #at(&self, buf: &[u8], i: usize) -> #elem_ty {
+                        assert!(i < self.#len as usize, #out_of_bounds);
+                        <#elem_ty as #layout::PietMetal>::read(&buf[#start + i * #stride..])
+                    }
+                }
+            }
+            ArrayElements::Ref(_) => {
+                let write = format_ident!("write_{}", self.name);
+                let write_doc = format!(
+                    "Write the elements of `{}` at `offset` in `buf`, pointing `{}` to them \
+                     and setting `{}` to their number.",
+                    self.name, self.name, self.len_field
+                );
+                let at_doc = format!(
+                    "Read element `i` of `{}` from `buf`, which `{}` points into.",
+                    self.name, self.name
+                );
+                quote! {
+                    #[doc = #write_doc]
+                    pub fn #write(&mut self, buf: &mut [u8], offset: usize, elements: &[#elem_ty]) {
+                        self.#field = offset as u32;
+                        self.#len = elements.len() as u32;
+                        for (i, element) in elements.iter().enumerate() {
+                            #layout::PietMetal::write(element, &mut buf[offset + i * #stride..]);
+                        }
+                    }
+
+                    #[doc = #at_doc]
+                    pub fn #at(&self, buf: &[u8], i: usize) -> #elem_ty {
+                        assert!(i < self.#len as usize, #out_of_bounds);
+                        let offset = self.#field as usize + i * #stride;
+                        <#elem_ty as #layout::PietMetal>::read(&buf[offset..])
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl GpuTypeDef {
+    fn from_syn(item: &syn::Item) -> Result<Self, String> {
+        match item {
+            syn::Item::Struct(ItemStruct {
+                ident,
+                fields: Fields::Named(FieldsNamed { named, .. }),
+                ..
+            }) => {
+                let mut fields = Vec::new();
+                for field in named {
+                    let field_ty = GpuType::from_syn(&field.ty)?;
+                    let field_name = field.ident.as_ref().ok_or("need name".to_string())?;
+                    fields.push((field_name.to_string(), field_ty));
+                }
+                if fields
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .any(|(_, ty)| matches!(ty, GpuType::Array(..)))
+                {
+                    return Err(
+                        "a variable-length array must be the last field, or behind a Ref".into(),
+                    );
+                }
+                Ok(GpuTypeDef::Struct(ident.to_string(), fields))
+            }
+            syn::Item::Enum(ItemEnum {
+                ident, variants, ..
+            }) => {
+                let mut v = Vec::new();
+                for variant in variants {
                     let vname = variant.ident.to_string();
                     let mut fields = Vec::new();
                     if let Fields::Unnamed(FieldsUnnamed { unnamed, .. }) = &variant.fields {
                         for field in unnamed {
-                            fields.push(GpuType::from_syn(&field.ty)?);
+                            let ty = GpuType::from_syn(&field.ty)?;
+                            if ty.array().is_some() {
+                                return Err("variable-length arrays can only be in structs".into());
+                            }
+                            fields.push(ty);
                         }
                     }
+                    if fields.len() > 1 {
+                        return Err("enum variants can have at most one field".into());
+                    }
                     v.push((vname, fields));
                 }
                 let en = GpuEnum {
@@ -901,7 +1882,7 @@ impl GpuTypeDef {
 
     fn name(&self) -> &str {
         match self {
-            GpuTypeDef::Struct(name, _) => &name,
+            GpuTypeDef::Struct(name, _) => name,
             GpuTypeDef::Enum(en) => &en.name,
         }
     }
@@ -951,6 +1932,65 @@ impl GpuTypeDef {
         }
     }
 
+    /// Byte offsets of the fields of a struct, after the tag if it is in a variant.
+    fn field_offsets(&self, module: &GpuModule) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        if let GpuTypeDef::Struct(name, fields) = self {
+            let mut offset = 0;
+            if module.enum_variants.contains(name) {
+                offset += 4;
+            }
+            for (_name, field) in fields {
+                offset += align_padding(offset, field.alignment(module));
+                offsets.push(offset);
+                offset += field.size(module);
+            }
+        }
+        offsets
+    }
+
+    /// The variable-length arrays of a struct.
+    fn array_fields(&self, module: &GpuModule) -> Vec<ArrayField> {
+        let mut arrays = Vec::new();
+        if let GpuTypeDef::Struct(_name, fields) = self {
+            let offsets = self.field_offsets(module);
+            let offset_of = |name: &str| {
+                let ix = fields.iter().position(|(n, _)| n == name).unwrap();
+                offsets[ix]
+            };
+            for ((field_name, ty), offset) in fields.iter().zip(&offsets) {
+                if let Some((elem, len_field)) = ty.array() {
+                    let elements = match ty {
+                        GpuType::Array(..) => {
+                            let stride = self.stride(module);
+                            let alignment = elem.array_alignment(module);
+                            ArrayElements::Trailing(stride + align_padding(stride, alignment))
+                        }
+                        _ => ArrayElements::Ref(*offset),
+                    };
+                    arrays.push(ArrayField {
+                        name: field_name.clone(),
+                        elem: elem.clone(),
+                        len_field: len_field.into(),
+                        len_offset: offset_of(len_field),
+                        elements,
+                        stride: elem.size(module),
+                    });
+                }
+            }
+        }
+        arrays
+    }
+
+    /// Size of the definition in an array, as `sizeof` in Metal.
+    fn stride(&self, module: &GpuModule) -> usize {
+        let size = self.size(module);
+        match self {
+            GpuTypeDef::Struct(..) => size + align_padding(size, self.alignment(module)),
+            GpuTypeDef::Enum(_) => size_in_uints(size) * 4,
+        }
+    }
+
     /// Alignment of the body of the definition.
     fn alignment(&self, module: &GpuModule) -> usize {
         match self {
@@ -964,7 +2004,8 @@ impl GpuTypeDef {
                 }
                 alignment
             }
-            GpuTypeDef::Enum(_en) => unimplemented!(),
+            // The tag and body are `uint`s.
+            GpuTypeDef::Enum(_en) => 4,
         }
     }
 
@@ -973,74 +2014,78 @@ impl GpuTypeDef {
         match self {
             GpuTypeDef::Struct(name, fields) => {
                 let rn = format!("{}Ref", name);
-                // The packed struct definition (is missing variable sized arrays)
-                write!(r, "struct {}Packed {{\n", name).unwrap();
+                // The packed struct definition, without a trailing array
+                let fields = split_trailing_array(fields).0;
+                writeln!(r, "struct {}Packed {{", name).unwrap();
                 if module.enum_variants.contains(name) {
-                    write!(r, "    uint tag;\n").unwrap();
+                    writeln!(r, "    uint tag;").unwrap();
                 }
                 for (field_name, ty) in fields {
-                    write!(r, "    {} {};\n", ty.metal_typename(), field_name).unwrap();
+                    writeln!(r, "    {} {};", ty.metal_typename(), field_name).unwrap();
                 }
-                write!(r, "}};\n").unwrap();
+                writeln!(r, "}};").unwrap();
                 // Read of packed structure
-                write!(
+                writeln!(
                     r,
-                    "{}Packed {}_read(const device char *buf, {} ref) {{\n",
+                    "{}Packed {}_read(const device char *buf, {} ref) {{",
                     name, name, rn
                 )
                 .unwrap();
-                write!(
+                writeln!(
                     r,
-                    "    return *((const device {}Packed *)(buf + ref));\n",
+                    "    return *((const device {}Packed *)(buf + ref));",
                     name
                 )
                 .unwrap();
-                write!(r, "}}\n").unwrap();
+                writeln!(r, "}}").unwrap();
                 // Unpacked field accessors
                 for (field_name, ty) in fields {
                     if ty.is_small() {
                         let tn = ty.metal_typename();
-                        write!(
+                        writeln!(
                             r,
-                            "{} {}_{}(const device char *buf, {} ref) {{\n",
+                            "{} {}_{}(const device char *buf, {} ref) {{",
                             tn, name, field_name, rn
                         )
                         .unwrap();
-                        write!(
+                        writeln!(
                             r,
-                            "    return ((const device {}Packed *)(buf + ref))->{};\n",
+                            "    return ((const device {}Packed *)(buf + ref))->{};",
                             name, field_name
                         )
                         .unwrap();
-                        write!(r, "}}\n").unwrap();
+                        writeln!(r, "}}").unwrap();
                     }
                 }
+                for array in self.array_fields(module) {
+                    r.push_str(&array.to_metal(name));
+                }
             }
             GpuTypeDef::Enum(en) => {
                 let rn = format!("{}Ref", en.name);
-                write!(r, "struct {} {{\n", en.name).unwrap();
-                write!(r, "    uint tag;\n").unwrap();
+                writeln!(r, "struct {} {{", en.name).unwrap();
+                writeln!(r, "    uint tag;").unwrap();
                 let size = self.size(module);
                 let body_size = ((size + 3) >> 2) - 1;
-                write!(r, "    uint body[{}];\n", body_size).unwrap();
-                write!(r, "}};\n").unwrap();
-                write!(
+                writeln!(r, "    uint body[{}];", body_size).unwrap();
+                writeln!(r, "}};").unwrap();
+                writeln!(
                     r,
-                    "uint {}_tag(const device char *buf, {} ref) {{\n",
+                    "uint {}_tag(const device char *buf, {} ref) {{",
                     en.name, rn
                 )
                 .unwrap();
-                write!(
+                writeln!(
                     r,
-                    "    return ((const device {} *)(buf + ref))->tag;\n",
+                    "    return ((const device {} *)(buf + ref))->tag;",
                     en.name
                 )
                 .unwrap();
-                write!(r, "}}\n").unwrap();
+                writeln!(r, "}}").unwrap();
                 // TODO: current code base is 1-based, but we could switch to 0
                 let mut tag = 1;
                 for (name, _fields) in &en.variants {
-                    write!(r, "#define {}_{} {}\n", en.name, name, tag).unwrap();
+                    writeln!(r, "#define {}_{} {}", en.name, name, tag).unwrap();
                     tag += 1;
                 }
             }
@@ -1048,31 +2093,228 @@ impl GpuTypeDef {
         r
     }
 
+    /// A Rust type with the same definition, implementing `PietMetal` to encode it
+    /// with the layout of the generated Metal reader.
+    fn to_rust(&self, module: &GpuModule) -> TokenStream2 {
+        match self {
+            GpuTypeDef::Struct(name, fields) => {
+                let ident = format_ident!("{}", name);
+                // The elements of a trailing array are written after the struct.
+                let fields: Vec<_> = split_trailing_array(fields)
+                    .0
+                    .iter()
+                    .map(|(name, ty)| (format_ident!("{}", name), ty.rust_type()))
+                    .collect();
+                let field_names = fields.iter().map(|(name, _)| name);
+                let field_types = fields.iter().map(|(_, ty)| ty);
+                let imp = rust_struct_impl(&ident, &fields);
+                let arrays: Vec<_> = self
+                    .array_fields(module)
+                    .iter()
+                    .map(|array| array.to_rust(&ident))
+                    .collect();
+                let array_impl = if arrays.is_empty() {
+                    quote!()
+                } else {
+                    quote! {
+                        impl #ident {
+                            #(#arrays)*
+                        }
+                    }
+                };
+                quote! {
+                    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+                    pub struct #ident {
+                        #(pub #field_names: #field_types,)*
+                    }
+
+                    #imp
+
+                    #array_impl
+                }
+            }
+            GpuTypeDef::Enum(en) => {
+                let ident = format_ident!("{}", en.name);
+                let variants: Vec<_> = en
+                    .variants
+                    .iter()
+                    .map(|(name, fields)| {
+                        (
+                            format_ident!("{}", name),
+                            fields.first().map(GpuType::rust_type),
+                        )
+                    })
+                    .collect();
+                let defs = variants.iter().map(|(variant, ty)| match ty {
+                    Some(ty) => quote!(#variant(#ty)),
+                    None => quote!(#variant),
+                });
+                let imp = rust_enum_impl(&ident, &variants);
+                quote! {
+                    #[derive(Clone, Copy, Debug, PartialEq)]
+                    pub enum #ident {
+                        #(#defs,)*
+                    }
+
+                    #imp
+                }
+            }
+        }
+    }
+
+    fn to_glsl(&self, module: &GpuModule) -> String {
+        let mut r = String::new();
+        let buf = &module.name;
+        match self {
+            GpuTypeDef::Struct(name, fields) => {
+                let fields = split_trailing_array(fields).0.to_vec();
+                let structure = SpecifiedStruct::new(module, name, fields);
+                write!(r, "{}", structure.packed_form.to_glsl(module, buf)).unwrap();
+                write!(r, "{}", structure.to_glsl()).unwrap();
+                for array in self.array_fields(module) {
+                    r.push_str(&array.to_glsl(name, buf));
+                }
+            }
+            GpuTypeDef::Enum(en) => {
+                let rn = format!("{}Ref", en.name);
+                let body_size = size_in_uints(self.size(module)) - 1;
+                writeln!(r, "struct {} {{", en.name).unwrap();
+                writeln!(r, "    uint tag;").unwrap();
+                writeln!(r, "    uint body[{}];", body_size).unwrap();
+                write!(r, "}};\n\n").unwrap();
+                write!(
+                    r,
+                    "uint {}_tag({} ref) {{\n    return {}[ref >> 2];\n}}\n\n",
+                    en.name, rn, buf
+                )
+                .unwrap();
+                writeln!(r, "{} {}_read({} ref) {{", en.name, en.name, rn).unwrap();
+                writeln!(r, "    uint ix = ref >> 2;").unwrap();
+                write!(r, "    {} result;\n\n", en.name).unwrap();
+                writeln!(r, "    result.tag = {}[ix];", buf).unwrap();
+                writeln!(r, "    for (uint i = 0; i < {}; i++) {{", body_size).unwrap();
+                writeln!(r, "        result.body[i] = {}[ix + 1 + i];", buf).unwrap();
+                writeln!(r, "    }}").unwrap();
+                write!(r, "    return result;\n}}\n\n").unwrap();
+            }
+        }
+        r
+    }
+
+    fn to_wgsl(&self, module: &GpuModule) -> String {
+        let mut r = String::new();
+        let buf = &module.name;
+        match self {
+            GpuTypeDef::Struct(name, fields) => {
+                let rn = format!("{}Ref", name);
+                let fields = split_trailing_array(fields).0;
+                writeln!(r, "struct {} {{", name).unwrap();
+                // WGSL has no empty structs.
+                if fields.is_empty() {
+                    writeln!(r, "    dummy: u32,").unwrap();
+                }
+                for (field_name, ty) in fields {
+                    writeln!(r, "    {}: {},", field_name, ty.wgsl_typename()).unwrap();
+                }
+                writeln!(r, "}}\n").unwrap();
+                let offsets = self.field_offsets(module);
+                for ((field_name, ty), offset) in fields.iter().zip(offsets) {
+                    writeln!(
+                        r,
+                        "fn {}_{}(offset: {}) -> {} {{",
+                        name,
+                        field_name,
+                        rn,
+                        ty.wgsl_typename()
+                    )
+                    .unwrap();
+                    writeln!(
+                        r,
+                        "    return {};",
+                        ty.wgsl_load(buf, "offset >> 2u", offset)
+                    )
+                    .unwrap();
+                    writeln!(r, "}}\n").unwrap();
+                }
+                writeln!(r, "fn {}_read(offset: {}) -> {} {{", name, rn, name).unwrap();
+                if fields.is_empty() {
+                    writeln!(r, "    return {}(0u);", name).unwrap();
+                } else {
+                    writeln!(r, "    return {}(", name).unwrap();
+                    for (field_name, _ty) in fields {
+                        writeln!(r, "        {}_{}(offset),", name, field_name).unwrap();
+                    }
+                    writeln!(r, "    );").unwrap();
+                }
+                writeln!(r, "}}\n").unwrap();
+                for array in self.array_fields(module) {
+                    r.push_str(&array.to_wgsl(name, buf));
+                }
+            }
+            GpuTypeDef::Enum(en) => {
+                let rn = format!("{}Ref", en.name);
+                writeln!(r, "fn {}_tag(offset: {}) -> u32 {{", en.name, rn).unwrap();
+                writeln!(r, "    return {};", wgsl_word(buf, "offset >> 2u", 0)).unwrap();
+                writeln!(r, "}}\n").unwrap();
+                // The body of a variant follows the tag, which a struct includes.
+                for (variant, fields) in &en.variants {
+                    if let Some(ty) = fields.first() {
+                        let offset = match ty {
+                            GpuType::InlineStruct(_) => 0,
+                            _ => 4,
+                        };
+                        writeln!(
+                            r,
+                            "fn {}_{}_read(offset: {}) -> {} {{",
+                            en.name,
+                            variant,
+                            rn,
+                            ty.wgsl_typename()
+                        )
+                        .unwrap();
+                        writeln!(
+                            r,
+                            "    return {};",
+                            ty.wgsl_load(buf, "offset >> 2u", offset)
+                        )
+                        .unwrap();
+                        writeln!(r, "}}\n").unwrap();
+                    }
+                }
+            }
+        }
+        r
+    }
+
     fn to_hlsl(&self, module: &GpuModule) -> String {
         let mut r = String::new();
 
         match self {
             GpuTypeDef::Struct(name, fields) => {
-                let structure = SpecifiedStruct::new(module, name, fields.clone());
+                let fields = split_trailing_array(fields).0.to_vec();
+                let structure = SpecifiedStruct::new(module, name, fields);
                 write!(r, "{}", structure.packed_form.to_hlsl(module)).unwrap();
                 write!(r, "{}", structure.to_hlsl()).unwrap();
+                for array in self.array_fields(module) {
+                    r.push_str(&array.to_hlsl(name));
+                }
             }
             GpuTypeDef::Enum(en) => {
                 let rn = format!("{}Ref", en.name);
 
-                write!(r, "struct {} {{\n", en.name).unwrap();
-                write!(r, "    uint tag;\n").unwrap();
+                writeln!(r, "struct {} {{", en.name).unwrap();
+                writeln!(r, "    uint tag;").unwrap();
 
                 let size = self.size(module);
                 println!("size: {}", size);
                 // TODO: this sometimes predicts incorrect number of u32s needed to store body (differences with metal alignment)
                 let body_size = ((size + 3) >> 2) - 1;
 
-                write!(r, "    uint body[{}];\n", body_size).unwrap();
-                write!(r, "}};\n").unwrap();
-                write!(
+                writeln!(r, "    uint body[{}];", body_size).unwrap();
+                writeln!(r, "}};").unwrap();
+                writeln!(
                     r,
-                    "inline uint {}_tag(ByteAddressBuffer buf, {} ref) {{\n",
+                    "inline uint {}_tag(ByteAddressBuffer buf, {} ref) {{",
                     en.name, rn
                 )
                 .unwrap();
@@ -1086,16 +2328,16 @@ impl GpuTypeDef {
 
                 write!(r, "{}", "inline void PietItem_read_into(ByteAddressBuffer src, uint src_ref, RWByteAddressBuffer dst, uint dst_ref) {\n").unwrap();
                 for i in 0..quotient_in_u32x4 {
-                    write!(
+                    writeln!(
                         r,
-                        "    uint4 group{} = src.Load4({});\n",
+                        "    uint4 group{} = src.Load4({});",
                         i,
                         simplified_add("src_ref", i * 16)
                     )
                     .unwrap();
-                    write!(
+                    writeln!(
                         r,
-                        "    dst.Store4({}, group{});\n",
+                        "    dst.Store4({}, group{});",
                         simplified_add("dst_ref", i * 16),
                         i,
                     )
@@ -1112,9 +2354,9 @@ impl GpuTypeDef {
                             simplified_add("src_ref", quotient_in_u32x4 * 16)
                         )
                         .unwrap();
-                        write!(
+                        writeln!(
                             r,
-                            "    dst.Store{}({}, group{});\n",
+                            "    dst.Store{}({}, group{});",
                             remainder_in_u32s,
                             simplified_add("dst_ref", quotient_in_u32x4 * 16),
                             quotient_in_u32x4,
@@ -1142,17 +2384,85 @@ impl GpuModule {
                 defs.push(def);
             }
         }
-        Ok(GpuModule {
+        let module = GpuModule {
             name,
             enum_variants,
             defs,
-        })
+        };
+        module.check_arrays()?;
+        Ok(module)
+    }
+
+    /// Check the variable-length arrays: their length is a `u32` field of their
+    /// struct, their elements are made of whole words, and a struct ending with one
+    /// is only behind refs.
+    fn check_arrays(&self) -> Result<(), String> {
+        let trailing: HashSet<&str> = self
+            .defs
+            .iter()
+            .filter_map(|def| match def {
+                GpuTypeDef::Struct(name, fields) if split_trailing_array(fields).1.is_some() => {
+                    Some(name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        for def in &self.defs {
+            let types: Vec<&GpuType> = match def {
+                GpuTypeDef::Struct(_, fields) => fields.iter().map(|(_, ty)| ty).collect(),
+                GpuTypeDef::Enum(en) => en.variants.iter().flat_map(|(_, v)| v).collect(),
+            };
+            for ty in types {
+                let inline = ty.array().map_or(ty, |(elem, _)| elem);
+                if let GpuType::InlineStruct(name) = inline {
+                    if trailing.contains(name.as_str()) {
+                        return Err(format!(
+                            "{} ends with a variable-length array, so it can only be behind a Ref",
+                            name
+                        ));
+                    }
+                }
+            }
+            if let GpuTypeDef::Struct(name, fields) = def {
+                for (field_name, ty) in fields {
+                    if let Some((elem, len_field)) = ty.array() {
+                        let has_len = fields.iter().any(|(n, t)| {
+                            n == len_field && matches!(t, GpuType::Scalar(GpuScalar::U32))
+                        });
+                        if !has_len {
+                            return Err(format!(
+                                "the length of {}.{} must be a u32 field of {}",
+                                name, field_name, name
+                            ));
+                        }
+                        let whole_words = match elem {
+                            GpuType::Scalar(scalar) | GpuType::Vector(scalar, _) => {
+                                scalar.size() == 4
+                            }
+                            GpuType::InlineStruct(elem_name) => {
+                                self.resolve_by_name(elem_name)?.stride(self) % 4 == 0
+                            }
+                            GpuType::Ref(inner) => inner.array().is_none(),
+                            GpuType::Array(..) => false,
+                        };
+                        if !whole_words {
+                            return Err(format!(
+                                "the elements of {}.{} must be 32-bit scalars or vectors, refs, \
+                                 or types whose size is a multiple of 4 bytes",
+                                name, field_name
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
     fn resolve_by_name(&self, name: &str) -> Result<&GpuTypeDef, String> {
         for def in &self.defs {
             if def.name() == name {
-                return Ok(&def);
+                return Ok(def);
             }
         }
         Err(format!("could not find {} in module", name))
@@ -1161,7 +2471,7 @@ impl GpuModule {
     fn to_metal(&self) -> String {
         let mut r = String::new();
         for def in &self.defs {
-            write!(&mut r, "typedef uint {}Ref;\n", def.name()).unwrap();
+            writeln!(&mut r, "typedef uint {}Ref;", def.name()).unwrap();
         }
         for def in &self.defs {
             r.push_str(&def.to_metal(self));
@@ -1169,25 +2479,108 @@ impl GpuModule {
         r
     }
 
+    fn to_rust(&self) -> TokenStream2 {
+        let defs = self.defs.iter().map(|def| def.to_rust(self));
+        quote!(#(#defs)*)
+    }
+
+    /// GLSL definitions of the types, reading them from the `uint` array of a
+    /// storage buffer named after the module, which the including shader declares.
+    fn to_glsl(&self) -> String {
+        let mut r = String::new();
+
+        write!(&mut r, "{}", generate_value_extractor(8, "")).unwrap();
+        write!(&mut r, "{}", generate_value_extractor(16, "")).unwrap();
+
+        // GLSL has no typedef.
+        for def in &self.defs {
+            writeln!(&mut r, "#define {}Ref uint", def.name()).unwrap();
+            if let GpuTypeDef::Struct(..) = def {
+                writeln!(&mut r, "#define {}PackedRef uint", def.name()).unwrap();
+            }
+        }
+
+        writeln!(&mut r).unwrap();
+        for def in &self.defs {
+            r.push_str(&def.to_glsl(self));
+        }
+
+        for def in &self.defs {
+            let name = def.name();
+            if !(self.enum_variants.contains(name)) {
+                writeln!(
+                    r,
+                    "#define {}_SIZE {}",
+                    to_snake_case(name).to_uppercase(),
+                    def.stride(self)
+                )
+                .unwrap();
+            }
+            // Tags are 1-based, as in the Metal definitions.
+            if let GpuTypeDef::Enum(en) = def {
+                for (i, (name, _fields)) in en.variants.iter().enumerate() {
+                    writeln!(r, "#define {}_{} {}", en.name, name, i + 1).unwrap();
+                }
+            }
+        }
+        r
+    }
+
+    /// WGSL definitions of the types, with accessors reading their fields from the
+    /// `array<u32>` of a storage buffer named after the module, which the including
+    /// shader declares.
+    fn to_wgsl(&self) -> String {
+        let mut r = String::new();
+
+        for def in &self.defs {
+            writeln!(&mut r, "alias {}Ref = u32;", def.name()).unwrap();
+        }
+
+        writeln!(&mut r).unwrap();
+        for def in &self.defs {
+            r.push_str(&def.to_wgsl(self));
+        }
+
+        for def in &self.defs {
+            let name = def.name();
+            if !(self.enum_variants.contains(name)) {
+                writeln!(
+                    r,
+                    "const {}_SIZE: u32 = {}u;",
+                    to_snake_case(name).to_uppercase(),
+                    def.stride(self)
+                )
+                .unwrap();
+            }
+            // Tags are 1-based, as in the Metal definitions.
+            if let GpuTypeDef::Enum(en) = def {
+                for (i, (name, _fields)) in en.variants.iter().enumerate() {
+                    writeln!(r, "const {}_{}: u32 = {}u;", en.name, name, i + 1).unwrap();
+                }
+            }
+        }
+        r
+    }
+
     fn to_hlsl(&self) -> String {
         let mut r = String::new();
 
-        write!(&mut r, "{}", generate_hlsl_value_extractor(8)).unwrap();
-        write!(&mut r, "{}", generate_hlsl_value_extractor(16)).unwrap();
+        write!(&mut r, "{}", generate_value_extractor(8, "inline ")).unwrap();
+        write!(&mut r, "{}", generate_value_extractor(16, "inline ")).unwrap();
 
         for def in &self.defs {
             match def {
-                GpuTypeDef::Struct(name, _) => {
-                    write!(&mut r, "typedef uint {}Ref;\n", def.name()).unwrap();
-                    write!(&mut r, "typedef uint {}PackedRef;\n", def.name()).unwrap();
+                GpuTypeDef::Struct(..) => {
+                    writeln!(&mut r, "typedef uint {}Ref;", def.name()).unwrap();
+                    writeln!(&mut r, "typedef uint {}PackedRef;", def.name()).unwrap();
                 }
                 GpuTypeDef::Enum(_) => {
-                    write!(&mut r, "typedef uint {}Ref;\n", def.name()).unwrap();
+                    writeln!(&mut r, "typedef uint {}Ref;", def.name()).unwrap();
                 }
             }
         }
 
-        write!(&mut r, "\n").unwrap();
+        writeln!(&mut r).unwrap();
         for def in &self.defs {
             r.push_str(&def.to_hlsl(self));
         }
@@ -1195,9 +2588,9 @@ impl GpuModule {
         for def in &self.defs {
             let name = def.name();
             if !(self.enum_variants.contains(name)) {
-                write!(
+                writeln!(
                     r,
-                    "#define {}_SIZE {}\n",
+                    "#define {}_SIZE {}",
                     to_snake_case(name).to_uppercase(),
                     def.size(self)
                 )
@@ -1206,7 +2599,7 @@ impl GpuModule {
             if let GpuTypeDef::Enum(en) = def {
                 let mut tag: usize = 0;
                 for (name, _fields) in &en.variants {
-                    write!(r, "#define {}_{} {}\n", en.name, name, tag).unwrap();
+                    writeln!(r, "#define {}_{} {}", en.name, name, tag).unwrap();
                     tag += 1;
                 }
             }
@@ -1215,10 +2608,6 @@ impl GpuModule {
     }
 }
 
-// Probably don't need this, will use ItemMod instead.
-#[derive(Debug)]
-struct Items(Vec<syn::Item>);
-
 fn ty_as_single_ident(ty: &syn::Type) -> Option<String> {
     if let syn::Type::Path(TypePath {
         path: syn::Path { segments, .. },
@@ -1247,6 +2636,24 @@ fn expr_int_lit(e: &Expr) -> Option<usize> {
     }
 }
 
+/// Split the fields of a struct into those of the struct itself, and a trailing
+/// variable-length array.
+fn split_trailing_array(fields: &[Field]) -> (&[Field], Option<&Field>) {
+    match fields.split_last() {
+        Some((last, rest)) if matches!(last.1, GpuType::Array(..)) => (rest, Some(last)),
+        _ => (fields, None),
+    }
+}
+
+/// The field named by the length of a variable-length array.
+fn expr_ident(e: &Expr) -> Option<String> {
+    if let Expr::Path(ExprPath { path, .. }) = e {
+        path.get_ident().map(|ident| ident.to_string())
+    } else {
+        None
+    }
+}
+
 fn align_padding(offset: usize, alignment: usize) -> usize {
     offset.wrapping_neg() & (alignment - 1)
 }
@@ -1257,10 +2664,32 @@ pub fn piet_metal(input: TokenStream) -> TokenStream {
     //println!("input: {:#?}", input);
     let module = GpuModule::from_syn(&input).unwrap();
     let gen_metal_fn = format_ident!("gen_metal_{}", input.ident);
+    let gen_glsl_fn = format_ident!("gen_glsl_{}", input.ident);
+    let gen_wgsl_fn = format_ident!("gen_wgsl_{}", input.ident);
     let result = module.to_metal();
+    let glsl = module.to_glsl();
+    let wgsl = module.to_wgsl();
+    let vis = &input.vis;
+    let mod_name = &input.ident;
+    let rust = module.to_rust();
     let expanded = quote! {
-        fn #gen_metal_fn() {
-            println!("{}", #result);
+        #vis mod #mod_name {
+            #rust
+        }
+
+        /// The Metal definitions of the types, with readers for each of them.
+        #vis fn #gen_metal_fn() -> String {
+            String::from(#result)
+        }
+
+        /// The GLSL definitions of the types, with readers for each of them.
+        #vis fn #gen_glsl_fn() -> String {
+            String::from(#glsl)
+        }
+
+        /// The WGSL definitions of the types, with accessors for their fields.
+        #vis fn #gen_wgsl_fn() -> String {
+            String::from(#wgsl)
         }
     };
     expanded.into()
@@ -1274,21 +2703,54 @@ pub fn derive_piet_metal(input: TokenStream) -> TokenStream {
         .into()
 }
 
+/// Implement `PietMetal` for a struct with named fields, or an enum whose variants
+/// have at most one field. The field types must implement `PietMetal` themselves.
 fn derive_proc_metal_impl(input: syn::DeriveInput) -> Result<proc_macro2::TokenStream, syn::Error> {
-    println!("input: {:#?}", input);
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "PietMetal can't be derived for generic types",
+        ));
+    }
     match &input.data {
-        Data::Struct { .. } => {
-            println!("it's a struct!");
+        Data::Struct(DataStruct {
+            fields: Fields::Named(FieldsNamed { named, .. }),
+            ..
+        }) => {
+            let fields: Vec<_> = named
+                .iter()
+                .map(|field| {
+                    let ty = &field.ty;
+                    (field.ident.clone().unwrap(), quote!(#ty))
+                })
+                .collect();
+            Ok(rust_struct_impl(&input.ident, &fields))
         }
-        _ => (),
-    }
-    let s = "this is a string";
-    let expanded = quote! {
-        fn foo() {
-            println!("this was generated by proc macro: {}", #s);
+        Data::Enum(DataEnum { variants, .. }) => {
+            let mut v = Vec::new();
+            for variant in variants {
+                let ty = match &variant.fields {
+                    Fields::Unit => None,
+                    Fields::Unnamed(FieldsUnnamed { unnamed, .. }) if unnamed.len() == 1 => {
+                        let ty = &unnamed[0].ty;
+                        Some(quote!(#ty))
+                    }
+                    fields => {
+                        return Err(syn::Error::new(
+                            fields.span(),
+                            "PietMetal enum variants can have at most one unnamed field",
+                        ))
+                    }
+                };
+                v.push((variant.ident.clone(), ty));
+            }
+            Ok(rust_enum_impl(&input.ident, &v))
         }
-    };
-    Ok(expanded)
+        _ => Err(syn::Error::new(
+            input.span(),
+            "PietMetal can only be derived for structs with named fields and enums",
+        )),
+    }
 }
 
 #[proc_macro]
@@ -1306,14 +2768,34 @@ pub fn piet_hlsl(input: TokenStream) -> TokenStream {
     expanded.into()
 }
 
-impl Parse for Items {
-    fn parse(input: ParseStream) -> Result<Self, syn::Error> {
-        let mut items = Vec::new();
-        while !input.is_empty() {
-            items.push(input.parse()?)
+#[proc_macro]
+pub fn piet_glsl(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemMod);
+    let module = GpuModule::from_syn(&input).unwrap();
+    let gen_glsl_fn = format_ident!("gen_glsl_{}", input.ident);
+    let result = module.to_glsl();
+    let vis = &input.vis;
+    let expanded = quote! {
+        #vis fn #gen_glsl_fn() -> String {
+            String::from(#result)
         }
-        Ok(Items(items))
-    }
+    };
+    expanded.into()
+}
+
+#[proc_macro]
+pub fn piet_wgsl(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemMod);
+    let module = GpuModule::from_syn(&input).unwrap();
+    let gen_wgsl_fn = format_ident!("gen_wgsl_{}", input.ident);
+    let result = module.to_wgsl();
+    let vis = &input.vis;
+    let expanded = quote! {
+        #vis fn #gen_wgsl_fn() -> String {
+            String::from(#result)
+        }
+    };
+    expanded.into()
 }
 
 fn to_snake_case(mut str: &str) -> String {
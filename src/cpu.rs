@@ -0,0 +1,2518 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! CPU reference implementation of the tile and render kernels.
+//!
+//! This follows `tileKernel` and `renderKernel` in `PietRender.metal` closely, so that
+//! a scene produced by `Encoder` can be rendered and inspected without a GPU. It is
+//! written for clarity rather than speed: each tile is processed on its own, and the
+//! threadgroup-level culling of the GPU version (which is only an optimization) is not
+//! modeled.
+
+use crate::{
+    BlendMode, FillRule, LineCap, LineJoin, PietClip, PietFill, PietFillPath, PietGradient,
+    PietGradientStop, PietImage, PietImageFill, PietItem, PietSegment, PietStrokeLine,
+    PietStrokePath, PietStrokePolyLine, SceneHeader, SegType, SimpleGroup, StrokeStyle,
+    FILL_EVEN_ODD, FLATTEN_TOLERANCE, GRADIENT_EXTEND_MASK, GRADIENT_EXTEND_REFLECT,
+    GRADIENT_EXTEND_REPEAT, GRADIENT_RADIAL, IMAGE_BILINEAR, MAX_FLATTEN_SEGS, MAX_GROUP_DEPTH,
+    PAINT_GRADIENT, PAINT_IMAGE, SEG_CLOSED, SEG_SUBPATH_END, SEG_SUBPATH_START, STROKE_CLOSED,
+    TILE_CHUNK_SIZE,
+};
+
+use crate::layout::PietMetal;
+
+// Keep these in sync with PietShaderTypes.h
+
+/// Size in pixels of an individual tile.
+pub const TILE_WIDTH: usize = 16;
+pub const TILE_HEIGHT: usize = 16;
+
+/// Distance in pixels that a miter or bevel join piece extends along the lines
+/// it joins, so that it overlaps their bodies rather than leaving a seam.
+const JOIN_OVERLAP: f32 = 1.0;
+
+/// A command in the per-tile command list.
+///
+/// These correspond to the `Cmd*` structs in `PietRender.metal`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cmd {
+    Circle {
+        bbox: [i32; 4],
+    },
+    Line {
+        start: [f32; 2],
+        end: [f32; 2],
+    },
+    /// A line with butt ends, the body of a stroke that is not round.
+    LineButt {
+        start: [f32; 2],
+        end: [f32; 2],
+        half_width: f32,
+    },
+    /// A miter or bevel join, between lines in directions `dir_in` and `dir_out`.
+    /// The outer edge is cut off at distance `cut` from `point`.
+    Join {
+        point: [f32; 2],
+        dir_in: [f32; 2],
+        dir_out: [f32; 2],
+        cut: f32,
+        half_width: f32,
+    },
+    Stroke {
+        half_width: f32,
+        rgba: u32,
+    },
+    Fill {
+        start: [f32; 2],
+        end: [f32; 2],
+    },
+    FillEdge {
+        sign: f32,
+        y: f32,
+    },
+    DrawFill {
+        backdrop: i32,
+        rgba: u32,
+        fill_rule: FillRule,
+    },
+    Solid {
+        rgba: u32,
+    },
+    /// Push a clip, with coverage given by the signed area buffer, as for `DrawFill`.
+    BeginClip {
+        backdrop: i32,
+        fill_rule: FillRule,
+    },
+    /// Pop the innermost clip.
+    EndClip,
+    /// Push a layer, drawing the following commands onto a transparent buffer.
+    BeginLayer,
+    /// Pop the innermost layer, compositing it onto the one underneath with opacity
+    /// `alpha` and blend mode `blend`.
+    EndLayer {
+        alpha: f32,
+        blend: BlendMode,
+    },
+    /// Paint the next `Stroke`, `DrawFill` or `Solid` with a gradient, rather than
+    /// its color. The transform is from pixels to the coordinates of the gradient.
+    Gradient {
+        gradient: u32,
+        mat: [f32; 4],
+        translate: [f32; 2],
+    },
+    /// Paint the next draw command with an image fill, as for `Gradient`. The
+    /// transform is from pixels to those of the image.
+    Image {
+        fill: u32,
+        mat: [f32; 4],
+        translate: [f32; 2],
+    },
+}
+
+impl Cmd {
+    /// Size in bytes of the command in the GPU version.
+    fn size(&self) -> usize {
+        match self {
+            Cmd::EndClip | Cmd::BeginLayer => 2,
+            Cmd::Stroke { .. } | Cmd::FillEdge { .. } | Cmd::Solid { .. } => 8,
+            Cmd::EndLayer { .. } => 8,
+            Cmd::DrawFill { .. } | Cmd::BeginClip { .. } => 12,
+            Cmd::Circle { .. } | Cmd::Line { .. } | Cmd::LineButt { .. } | Cmd::Fill { .. } => 20,
+            Cmd::Join { .. } | Cmd::Gradient { .. } | Cmd::Image { .. } => 32,
+        }
+    }
+}
+
+/// Size in bytes of the largest command, `CmdJoin`, which is always left free at the
+/// end of a chunk.
+const MAX_CMD_SIZE: usize = 32;
+
+/// A chunk of the command list of a tile, of `TILE_CHUNK_SIZE` bytes.
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub cmds: Vec<Cmd>,
+    /// The offset in the tile buffer that the command list continues at, if the
+    /// chunk ends with `CMD_JUMP`.
+    pub jump: Option<usize>,
+}
+
+/// The tile buffer of the GPU version, as sized by `TileGrid::tile_buf_size`: a
+/// first chunk for each tile, followed by a pool of chunks that longer command
+/// lists continue into.
+#[derive(Clone, Debug)]
+pub struct TileBuf {
+    /// The first chunks of the tiles, then the chunks handed out from the pool.
+    pub chunks: Vec<Chunk>,
+    n_tiles: usize,
+    n_pool_chunks: usize,
+    /// Set when a tile needed a chunk beyond the end of the pool, as in `TilePool`.
+    /// That tile is started over in its first chunk, so its command list is
+    /// incomplete.
+    pub overflow: bool,
+}
+
+impl TileBuf {
+    pub fn new(n_tiles: usize, n_pool_chunks: usize) -> TileBuf {
+        TileBuf {
+            chunks: vec![Default::default(); n_tiles],
+            n_tiles,
+            n_pool_chunks,
+            overflow: false,
+        }
+    }
+
+    /// The commands of a tile, following the jumps from its first chunk.
+    pub fn cmds(&self, tile_ix: usize) -> Vec<Cmd> {
+        let mut cmds = Vec::new();
+        let mut chunk = &self.chunks[tile_ix];
+        loop {
+            cmds.extend_from_slice(&chunk.cmds);
+            match chunk.jump {
+                Some(offset) => chunk = &self.chunks[offset / TILE_CHUNK_SIZE],
+                None => return cmds,
+            }
+        }
+    }
+
+    /// Hand out a chunk from the pool, returning its index in `chunks`.
+    fn alloc_chunk(&mut self) -> Option<usize> {
+        let n_allocated = self.chunks.len() - self.n_tiles;
+        if n_allocated >= self.n_pool_chunks {
+            self.overflow = true;
+            return None;
+        }
+        self.chunks.push(Default::default());
+        Some(self.chunks.len() - 1)
+    }
+}
+
+/// The result of tiling, for a single tile.
+#[derive(Clone, Debug, Default)]
+pub struct Tile {
+    pub cmds: Vec<Cmd>,
+    /// If set, the tile is a single opaque color and the command list is not
+    /// interpreted (`CMD_BAIL` in the GPU version). This includes tiles with nothing
+    /// drawn, over an opaque background.
+    pub solid_color: Option<u32>,
+}
+
+/// Builds the command list for a tile, following `TileEncoder`.
+///
+/// The commands are also laid out in the chunks of a `TileBuf`, while `cmds` keeps
+/// the whole list, as if the pool were unlimited.
+struct TileEncoder<'a> {
+    cmds: Vec<Cmd>,
+    buf: &'a mut TileBuf,
+    tile_ix: usize,
+    // Index of the current chunk in `buf`, and the bytes written to it.
+    chunk_ix: usize,
+    chunk_len: usize,
+    solid_color: u32,
+    // Number of clips and layers pushed in the command list and not yet popped.
+    push_depth: usize,
+    // Whether a paint has been encoded for the next draw command.
+    paint_pending: bool,
+}
+
+impl<'a> TileEncoder<'a> {
+    fn new(background: u32, buf: &'a mut TileBuf, tile_ix: usize) -> TileEncoder<'a> {
+        // Only an opaque color can be output without conversion.
+        let opaque = (background & 0xff00_0000) == 0xff00_0000;
+        TileEncoder {
+            cmds: Vec::new(),
+            buf,
+            tile_ix,
+            chunk_ix: tile_ix,
+            chunk_len: 0,
+            solid_color: if opaque { background } else { 0 },
+            push_depth: 0,
+            paint_pending: false,
+        }
+    }
+
+    /// Add a command, continuing in a new chunk if this one is full, as `reserve`
+    /// does.
+    fn push(&mut self, cmd: Cmd) {
+        let size = cmd.size();
+        if self.chunk_len + size + MAX_CMD_SIZE > TILE_CHUNK_SIZE {
+            match self.buf.alloc_chunk() {
+                Some(chunk_ix) => {
+                    self.buf.chunks[self.chunk_ix].jump = Some(chunk_ix * TILE_CHUNK_SIZE);
+                    self.chunk_ix = chunk_ix;
+                    self.chunk_len = 0;
+                }
+                // Start the tile over, so that writes stay in bounds.
+                None => self.restart(),
+            }
+        }
+        self.cmds.push(cmd);
+        self.buf.chunks[self.chunk_ix].cmds.push(cmd);
+        self.chunk_len += size;
+    }
+
+    /// Continue writing at the start of the first chunk of the tile. Chunks taken
+    /// from the pool are not given back.
+    fn restart(&mut self) {
+        self.chunk_ix = self.tile_ix;
+        self.chunk_len = 0;
+        self.buf.chunks[self.tile_ix] = Default::default();
+    }
+
+    fn encode_circle(&mut self, bbox: [i32; 4]) {
+        self.push(Cmd::Circle { bbox });
+        self.solid_color = 0;
+    }
+
+    fn encode_line(&mut self, start: [f32; 2], end: [f32; 2]) {
+        self.push(Cmd::Line { start, end });
+    }
+
+    fn encode_line_butt(&mut self, start: [f32; 2], end: [f32; 2], half_width: f32) {
+        self.push(Cmd::LineButt {
+            start,
+            end,
+            half_width,
+        });
+    }
+
+    fn encode_join(
+        &mut self,
+        point: [f32; 2],
+        dir_in: [f32; 2],
+        dir_out: [f32; 2],
+        cut: f32,
+        half_width: f32,
+    ) {
+        self.push(Cmd::Join {
+            point,
+            dir_in,
+            dir_out,
+            cut,
+            half_width,
+        });
+    }
+
+    fn encode_gradient(&mut self, gradient: u32, transform: Transform) {
+        self.push(Cmd::Gradient {
+            gradient,
+            mat: transform.mat,
+            translate: transform.translate,
+        });
+        self.paint_pending = true;
+    }
+
+    fn encode_image(&mut self, fill: u32, transform: Transform) {
+        self.push(Cmd::Image {
+            fill,
+            mat: transform.mat,
+            translate: transform.translate,
+        });
+        self.paint_pending = true;
+    }
+
+    fn encode_stroke(&mut self, rgba: u32, width: f32) {
+        self.push(Cmd::Stroke {
+            half_width: 0.5 * width,
+            rgba,
+        });
+        self.solid_color = 0;
+        self.paint_pending = false;
+    }
+
+    fn encode_fill(&mut self, start: [f32; 2], end: [f32; 2]) {
+        self.push(Cmd::Fill { start, end });
+    }
+
+    fn encode_fill_edge(&mut self, sign: f32, y: f32) {
+        self.push(Cmd::FillEdge { sign, y });
+    }
+
+    fn encode_draw_fill(&mut self, rgba: u32, backdrop: i32, fill_rule: FillRule) {
+        self.push(Cmd::DrawFill {
+            backdrop,
+            rgba,
+            fill_rule,
+        });
+        self.solid_color = 0;
+        self.paint_pending = false;
+    }
+
+    fn encode_solid(&mut self, rgba: u32) {
+        // Inside a clip or layer, even an opaque solid doesn't cover what is underneath.
+        // With a gradient, `rgba` is not a color.
+        let opaque = (rgba & 0xff00_0000) == 0xff00_0000 && !self.paint_pending;
+        if opaque && self.push_depth == 0 {
+            self.solid_color = rgba;
+            self.cmds.clear();
+            self.restart();
+        } else {
+            self.solid_color = 0;
+        }
+        self.push(Cmd::Solid { rgba });
+        self.paint_pending = false;
+    }
+
+    fn encode_begin_clip(&mut self, backdrop: i32, fill_rule: FillRule) {
+        self.push(Cmd::BeginClip {
+            backdrop,
+            fill_rule,
+        });
+        self.push_depth += 1;
+    }
+
+    fn encode_end_clip(&mut self) {
+        self.push(Cmd::EndClip);
+        self.push_depth -= 1;
+    }
+
+    fn encode_begin_layer(&mut self) {
+        self.push(Cmd::BeginLayer);
+        self.push_depth += 1;
+    }
+
+    fn encode_end_layer(&mut self, alpha: f32, blend: BlendMode) {
+        self.push(Cmd::EndLayer { alpha, blend });
+        self.push_depth -= 1;
+    }
+
+    fn end(self) -> Tile {
+        if self.solid_color != 0 {
+            Tile {
+                cmds: Vec::new(),
+                solid_color: Some(self.solid_color),
+            }
+        } else {
+            Tile {
+                cmds: self.cmds,
+                solid_color: None,
+            }
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], ix: usize) -> u32 {
+    u32::from_le_bytes([buf[ix], buf[ix + 1], buf[ix + 2], buf[ix + 3]])
+}
+
+fn read_point(buf: &[u8], ix: usize) -> [f32; 2] {
+    [
+        f32::from_bits(read_u32(buf, ix)),
+        f32::from_bits(read_u32(buf, ix + 4)),
+    ]
+}
+
+/// Equivalent of the shader `sign` function, which is 0 at 0 (unlike `f32::signum`).
+fn sign(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn saturate(x: f32) -> f32 {
+    x.clamp(0.0, 1.0)
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+/// Unit vector from `start` to `end`, or zero if they coincide.
+fn direction(start: [f32; 2], end: [f32; 2]) -> [f32; 2] {
+    let v = [end[0] - start[0], end[1] - start[1]];
+    let len = v[0].hypot(v[1]);
+    if len > 0.0 {
+        [v[0] / len, v[1] / len]
+    } else {
+        [0.0, 0.0]
+    }
+}
+
+fn xy_min_max(start: [f32; 2], end: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+    (
+        [start[0].min(end[0]), start[1].min(end[1])],
+        [start[0].max(end[0]), start[1].max(end[1])],
+    )
+}
+
+/// Test whether the line through `start` and `end` crosses the given rectangle.
+///
+/// This is the "all four corners are on the same side of the line" cull used
+/// throughout the tiler.
+fn line_crosses_rect(start: [f32; 2], end: [f32; 2], x0: f32, y0: f32, x1: f32, y1: f32) -> bool {
+    // set up line equation, ax + by + c = 0
+    let a = end[1] - start[1];
+    let b = start[0] - end[0];
+    let c = -(a * start[0] + b * start[1]);
+    let left = a * x0;
+    let right = a * x1;
+    let top = b * y0;
+    let bot = b * y1;
+    let s00 = sign(top + left + c);
+    let s01 = sign(top + right + c);
+    let s10 = sign(bot + left + c);
+    let s11 = sign(bot + right + c);
+    s00 * s01 + s00 * s10 + s00 * s11 < 3.0
+}
+
+/// An affine transform, as stored in group items: the linear part, as the first
+/// four coefficients of a kurbo `Affine`, and the translation.
+#[derive(Clone, Copy, Debug)]
+struct Transform {
+    mat: [f32; 4],
+    translate: [f32; 2],
+}
+
+impl Transform {
+    const IDENTITY: Transform = Transform {
+        mat: [1.0, 0.0, 0.0, 1.0],
+        translate: [0.0, 0.0],
+    };
+
+    fn apply(&self, p: [f32; 2]) -> [f32; 2] {
+        let m = &self.mat;
+        [
+            m[0] * p[0] + m[2] * p[1] + self.translate[0],
+            m[1] * p[0] + m[3] * p[1] + self.translate[1],
+        ]
+    }
+
+    /// The transform that applies `other`, then `self`.
+    fn then(&self, other: &Transform) -> Transform {
+        let (a, b) = (&self.mat, &other.mat);
+        Transform {
+            mat: [
+                a[0] * b[0] + a[2] * b[1],
+                a[1] * b[0] + a[3] * b[1],
+                a[0] * b[2] + a[2] * b[3],
+                a[1] * b[2] + a[3] * b[3],
+            ],
+            translate: self.apply(other.translate),
+        }
+    }
+
+    /// The inverse transform. A degenerate transform maps everything to the
+    /// translation, rather than having no inverse.
+    fn inverse(&self) -> Transform {
+        let m = &self.mat;
+        let det = m[0] * m[3] - m[1] * m[2];
+        let inv_det = if det != 0.0 { det.recip() } else { 0.0 };
+        let mat = [
+            m[3] * inv_det,
+            -m[1] * inv_det,
+            -m[2] * inv_det,
+            m[0] * inv_det,
+        ];
+        let t = self.translate;
+        Transform {
+            mat,
+            translate: [
+                -(mat[0] * t[0] + mat[2] * t[1]),
+                -(mat[1] * t[0] + mat[3] * t[1]),
+            ],
+        }
+    }
+
+    /// The factor by which stroke widths are scaled.
+    fn scale(&self) -> f32 {
+        let m = &self.mat;
+        (m[0] * m[3] - m[1] * m[2]).abs().sqrt()
+    }
+
+    /// Bounding box of a transformed bbox.
+    fn bbox(&self, bbox: [i32; 4]) -> ([f32; 2], [f32; 2]) {
+        let [x0, y0, x1, y1] = bbox.map(|x| x as f32);
+        let corners = [[x0, y0], [x1, y0], [x0, y1], [x1, y1]].map(|p| self.apply(p));
+        let mut xymin = corners[0];
+        let mut xymax = corners[0];
+        for p in &corners[1..] {
+            xymin = [xymin[0].min(p[0]), xymin[1].min(p[1])];
+            xymax = [xymax[0].max(p[0]), xymax[1].max(p[1])];
+        }
+        (xymin, xymax)
+    }
+}
+
+/// A curve segment as read from the scene; unused points repeat the end point.
+struct Segment {
+    seg_type: u32,
+    flags: u32,
+    points: [[f32; 2]; 4],
+}
+
+impl Segment {
+    fn read(buf: &[u8], ix: usize) -> Segment {
+        let seg = PietSegment::read(&buf[ix..]);
+        Segment {
+            seg_type: seg.seg_type,
+            flags: seg.flags,
+            points: [seg.p0, seg.p1, seg.p2, seg.p3],
+        }
+    }
+
+    fn transform(mut self, transform: &Transform) -> Segment {
+        self.points = self.points.map(|p| transform.apply(p));
+        self
+    }
+
+    /// Bounding box of the control points, which contains the curve.
+    fn bbox(&self) -> ([f32; 2], [f32; 2]) {
+        let mut xymin = self.points[0];
+        let mut xymax = self.points[0];
+        for p in &self.points[1..] {
+            xymin = [xymin[0].min(p[0]), xymin[1].min(p[1])];
+            xymax = [xymax[0].max(p[0]), xymax[1].max(p[1])];
+        }
+        (xymin, xymax)
+    }
+
+    /// Number of lines to flatten into, by Wang's formula.
+    ///
+    /// This subdivides uniformly in t, which is conservative but needs no
+    /// state beyond the segment itself, so it is cheap to do at tile time. The
+    /// count is clamped to `MAX_FLATTEN_SEGS`, which the encoder keeps curves
+    /// within, as long as groups aren't placed at a larger scale.
+    fn flatten_count(&self) -> usize {
+        let p = &self.points;
+        let dd = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+            (a[0] - 2.0 * b[0] + c[0]).hypot(a[1] - 2.0 * b[1] + c[1])
+        };
+        let m = if self.seg_type == SegType::Cubic as u32 {
+            0.75 * dd(p[0], p[1], p[2]).max(dd(p[1], p[2], p[3]))
+        } else if self.seg_type == SegType::Quad as u32 {
+            0.25 * dd(p[0], p[1], p[2])
+        } else {
+            return 1;
+        };
+        let n = (m / FLATTEN_TOLERANCE).sqrt().ceil();
+        (n as usize).clamp(1, MAX_FLATTEN_SEGS)
+    }
+
+    fn eval(&self, t: f32) -> [f32; 2] {
+        let p = &self.points;
+        let mt = 1.0 - t;
+        let (w0, w1, w2, w3) = if self.seg_type == SegType::Cubic as u32 {
+            (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t)
+        } else if self.seg_type == SegType::Quad as u32 {
+            (mt * mt, 2.0 * mt * t, t * t, 0.0)
+        } else {
+            (mt, t, 0.0, 0.0)
+        };
+        [
+            w0 * p[0][0] + w1 * p[1][0] + w2 * p[2][0] + w3 * p[3][0],
+            w0 * p[0][1] + w1 * p[1][1] + w2 * p[2][1] + w3 * p[3][1],
+        ]
+    }
+
+    /// Point `i` of the segment flattened into `n` lines.
+    fn flat_point(&self, i: usize, n: usize) -> [f32; 2] {
+        if i == 0 {
+            self.points[0]
+        } else if i == n {
+            self.points[3]
+        } else {
+            self.eval(i as f32 / n as f32)
+        }
+    }
+
+    /// Call `f` on each line of the flattened segment.
+    fn flatten(&self, mut f: impl FnMut([f32; 2], [f32; 2])) {
+        let n = self.flatten_count();
+        let mut start = self.points[0];
+        for i in 1..=n {
+            let end = self.flat_point(i, n);
+            f(start, end);
+            start = end;
+        }
+    }
+
+    /// Direction of the first line of the flattened segment.
+    fn start_dir(&self) -> [f32; 2] {
+        let n = self.flatten_count();
+        direction(self.points[0], self.flat_point(1, n))
+    }
+
+    /// Direction of the last line of the flattened segment.
+    fn end_dir(&self) -> [f32; 2] {
+        let n = self.flatten_count();
+        direction(self.flat_point(n - 1, n), self.points[3])
+    }
+}
+
+const TW: f32 = TILE_WIDTH as f32;
+const TH: f32 = TILE_HEIGHT as f32;
+
+/// Tiling state for one tile, following `tileKernel`.
+struct Tiler<'a> {
+    scene: &'a [u8],
+    // Top left corner of the tile, in pixels.
+    x0: f32,
+    y0: f32,
+    // Transform of the group being traversed, to pixels.
+    transform: Transform,
+    encoder: TileEncoder<'a>,
+}
+
+impl<'a> Tiler<'a> {
+    /// Set up the tiling of the tile at (`tile_x`, `tile_y`), whose index in the
+    /// tile buffer is `tile_ix`.
+    fn new(
+        scene: &'a [u8],
+        tile_x: usize,
+        tile_y: usize,
+        buf: &'a mut TileBuf,
+        tile_ix: usize,
+    ) -> Tiler<'a> {
+        let header = SceneHeader::read(scene);
+        Tiler {
+            scene,
+            x0: (tile_x * TILE_WIDTH) as f32,
+            y0: (tile_y * TILE_HEIGHT) as f32,
+            transform: Transform::IDENTITY,
+            encoder: TileEncoder::new(header.background, buf, tile_ix),
+        }
+    }
+
+    fn tile_scene(mut self) -> Tile {
+        let header = SceneHeader::read(self.scene);
+        self.tile_group(header.root as usize, 0);
+        self.encoder.end()
+    }
+
+    /// Traverse a group, descending into nested groups that hit the tile.
+    ///
+    /// The GPU version uses an explicit stack of depth `MAX_GROUP_DEPTH`.
+    fn tile_group(&mut self, group_ix: usize, depth: usize) {
+        let scene = self.scene;
+        let (x0, y0) = (self.x0, self.y0);
+        let group = SimpleGroup::read(&scene[group_ix..]);
+        for i in 0..group.n_items as usize {
+            let bbox = group.bboxes_at(&scene[group_ix..], i);
+            let (xymin, xymax) = self.transform.bbox(bbox);
+            let hit = xymax[0] >= x0 && xymin[0] < x0 + TW && xymax[1] >= y0 && xymin[1] < y0 + TH;
+            if !hit {
+                continue;
+            }
+            let item_ref = group.items_ix as usize + i * PietItem::SIZE;
+            match PietItem::try_read(&scene[item_ref..]) {
+                Some(PietItem::Circle(_)) => {
+                    let circle_bbox = [xymin[0], xymin[1], xymax[0], xymax[1]].map(|x| x as i32);
+                    self.encoder.encode_circle(circle_bbox);
+                }
+                Some(PietItem::Line(line)) => self.tile_stroke_line(line),
+                Some(PietItem::Fill(fill)) => self.tile_fill(fill),
+                Some(PietItem::Poly(poly)) => self.tile_stroke_poly_line(poly),
+                Some(PietItem::FillPath(fill)) => self.tile_fill_path(fill),
+                Some(PietItem::StrokePath(stroke)) => self.tile_stroke_path(stroke),
+                Some(PietItem::Clip(clip)) if depth + 1 < MAX_GROUP_DEPTH => {
+                    self.tile_clip(clip, depth + 1)
+                }
+                Some(PietItem::Layer(layer)) if depth + 1 < MAX_GROUP_DEPTH => {
+                    self.encoder.encode_begin_layer();
+                    self.tile_group(layer.group as usize, depth + 1);
+                    let blend = BlendMode::from_u32(layer.blend);
+                    self.encoder.encode_end_layer(layer.alpha, blend);
+                }
+                Some(PietItem::Group(child)) if depth + 1 < MAX_GROUP_DEPTH => {
+                    let parent_transform = self.transform;
+                    self.transform = parent_transform.then(&Transform {
+                        mat: child.mat,
+                        translate: child.translate,
+                    });
+                    self.tile_group(child.group as usize, depth + 1);
+                    self.transform = parent_transform;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn tile_stroke_line(&mut self, line: PietStrokeLine) {
+        let start = self.transform.apply(line.start);
+        let end = self.transform.apply(line.end);
+        let width = line.width * self.transform.scale();
+        let style = StrokeStyle::from_flags(line.flags, 1.0);
+        if self.stroke_styled_line(start, end, None, true, true, &style, width) {
+            self.encode_paint(line.flags, line.rgba_color);
+            self.encoder.encode_stroke(line.rgba_color, width);
+        }
+    }
+
+    fn tile_fill(&mut self, fill: PietFill) {
+        let n_points = fill.n_points as usize;
+        let pts_ix = fill.points_ix as usize;
+        let mut backdrop = 0.0;
+        let mut any_fill = false;
+        for j in 0..n_points {
+            let start = self.read_point(pts_ix + j * 8);
+            let end_j = if j + 1 == n_points { 0 } else { j + 1 };
+            let end = self.read_point(pts_ix + end_j * 8);
+            self.fill_line(start, end, &mut backdrop, &mut any_fill);
+        }
+        self.draw_fill(fill.rgba_color, fill.flags, backdrop, any_fill);
+    }
+
+    fn tile_fill_path(&mut self, fill: PietFillPath) {
+        let (backdrop, any_fill) = self.fill_segments(fill.segs_ix, fill.n_segs);
+        self.draw_fill(fill.rgba_color, fill.flags, backdrop, any_fill);
+    }
+
+    /// Tile a clip and its group.
+    ///
+    /// The clip is only encoded in tiles that its edges cross. The group is
+    /// skipped in tiles outside the clip, and drawn unclipped in tiles inside it.
+    fn tile_clip(&mut self, clip: PietClip, depth: usize) {
+        let (backdrop, any_fill) = self.fill_segments(clip.segs_ix, clip.n_segs);
+        let fill_rule = fill_rule_from_flags(clip.flags);
+        if any_fill {
+            self.encoder.encode_begin_clip(backdrop as i32, fill_rule);
+            self.tile_group(clip.group as usize, depth);
+            self.encoder.encode_end_clip();
+        } else if winding_inside(backdrop as i32, fill_rule) {
+            self.tile_group(clip.group as usize, depth);
+        }
+    }
+
+    /// Accumulate the segments of a fill into the tile, returning the backdrop
+    /// and whether any lines were encoded.
+    fn fill_segments(&mut self, segs_ix: u32, n_segs: u32) -> (f32, bool) {
+        let y0 = self.y0;
+        let mut backdrop = 0.0;
+        let mut any_fill = false;
+        for j in 0..n_segs as usize {
+            let seg = self.read_segment(segs_ix as usize + j * PietSegment::SIZE);
+            let (xymin, xymax) = seg.bbox();
+            if !(xymax[1] >= y0 && xymin[1] < y0 + TH) {
+                continue;
+            }
+            seg.flatten(|start, end| self.fill_line(start, end, &mut backdrop, &mut any_fill));
+        }
+        (backdrop, any_fill)
+    }
+
+    /// Accumulate one line of a fill into the tile.
+    fn fill_line(
+        &mut self,
+        start: [f32; 2],
+        end: [f32; 2],
+        backdrop: &mut f32,
+        any_fill: &mut bool,
+    ) {
+        let (x0, y0) = (self.x0, self.y0);
+        let (xymin, xymax) = xy_min_max(start, end);
+        // This is the y-based cull done in the strip pass of the GPU version.
+        if !(xymax[1] >= y0 && xymin[1] < y0 + TH) {
+            return;
+        }
+        // set up line equation, ax + by + c = 0
+        let a = end[1] - start[1];
+        let b = start[0] - end[0];
+        let c = -(a * start[0] + b * start[1]);
+        let left = a * x0;
+        let ytop = y0.max(xymin[1]);
+        let ybot = (y0 + TH).min(xymax[1]);
+        let top = b * ytop;
+        // top left of tile
+        let s_top_left = sign(left + y0 * b + c);
+        let s00 = sign(top + left + c);
+        if s_top_left == sign(a) && xymin[1] <= y0 {
+            *backdrop -= s00;
+        }
+        let crosses = line_crosses_rect(start, end, x0, ytop, x0 + TW, ybot);
+        // Lines touching the left edge count as crossing it, and vertical lines on
+        // it belong to this tile rather than the one to the left.
+        if xymin[0] < x0 && xymax[0] >= x0 {
+            let y_edge = mix(start[1], end[1], (start[0] - x0) / b);
+            if y_edge >= y0 && y_edge < y0 + TH {
+                // line intersects left edge of this tile; the sign is that of
+                // the line equation above the intersection
+                self.encoder.encode_fill_edge(-sign(b), y_edge);
+                if b > 0.0 {
+                    self.encoder.encode_fill(start, [x0, y_edge]);
+                } else {
+                    self.encoder.encode_fill([x0, y_edge], end);
+                }
+                *any_fill = true;
+            } else if crosses {
+                self.encoder.encode_fill(start, end);
+                *any_fill = true;
+            }
+        } else if crosses && xymin[0] < x0 + TW && xymax[0] >= x0 {
+            self.encoder.encode_fill(start, end);
+            *any_fill = true;
+        }
+    }
+
+    fn draw_fill(&mut self, rgba: u32, flags: u32, backdrop: f32, any_fill: bool) {
+        let fill_rule = fill_rule_from_flags(flags);
+        let backdrop = backdrop as i32;
+        if any_fill {
+            self.encode_paint(flags, rgba);
+            self.encoder.encode_draw_fill(rgba, backdrop, fill_rule);
+        } else if winding_inside(backdrop, fill_rule) {
+            self.encode_paint(flags, rgba);
+            self.encoder.encode_solid(rgba);
+        }
+    }
+
+    /// Encode the paint of a draw item, ahead of its draw command, if it is not
+    /// a solid color.
+    fn encode_paint(&mut self, flags: u32, rgba: u32) {
+        if flags & PAINT_GRADIENT != 0 {
+            let gradient = PietGradient::read(&self.scene[rgba as usize..]);
+            let transform = self.paint_transform(gradient.mat, gradient.translate);
+            self.encoder.encode_gradient(rgba, transform);
+        } else if flags & PAINT_IMAGE != 0 {
+            let fill = PietImageFill::read(&self.scene[rgba as usize..]);
+            let transform = self.paint_transform(fill.mat, fill.translate);
+            self.encoder.encode_image(rgba, transform);
+        }
+    }
+
+    /// The transform from pixels to the coordinates of a paint, given the one from
+    /// the coordinates of the item.
+    fn paint_transform(&self, mat: [f32; 4], translate: [f32; 2]) -> Transform {
+        let to_paint = Transform { mat, translate };
+        to_paint.then(&self.transform.inverse())
+    }
+
+    fn tile_stroke_poly_line(&mut self, poly: PietStrokePolyLine) {
+        let pts_ix = poly.points_ix as usize;
+        let n_points = poly.n_points as usize;
+        let style = StrokeStyle::from_flags(poly.flags, poly.miter_limit);
+        let width = poly.width * self.transform.scale();
+        let (scene, transform) = (self.scene, self.transform);
+        let point = |i: usize| transform.apply(read_point(scene, pts_ix + i * 8));
+        // The last point of a closed polyline is its first, so the line before
+        // the first one is found by wrapping around.
+        let closed = poly.flags & STROKE_CLOSED != 0;
+        let mut any_stroke = false;
+        for j in 0..n_points.saturating_sub(1) {
+            let start = point(j);
+            let end = point(j + 1);
+            if start == end {
+                continue;
+            }
+            // Repeated points are skipped when looking for neighboring lines.
+            let prev_ix = (0..j).rev().find(|&i| point(i) != start);
+            let prev_ix = match prev_ix {
+                None if closed => ((j + 1)..n_points).rev().find(|&i| point(i) != start),
+                _ => prev_ix,
+            };
+            let prev_dir = prev_ix.map(|i| direction(point(i), start));
+            let cap_end = !closed && ((j + 2)..n_points).all(|i| point(i) == end);
+            any_stroke |= self.stroke_styled_line(
+                start,
+                end,
+                prev_dir,
+                prev_dir.is_none(),
+                cap_end,
+                &style,
+                width,
+            );
+        }
+        if any_stroke {
+            self.encode_paint(poly.flags, poly.rgba_color);
+            self.encoder.encode_stroke(poly.rgba_color, width);
+        }
+    }
+
+    fn tile_stroke_path(&mut self, stroke: PietStrokePath) {
+        let style = StrokeStyle::from_flags(stroke.flags, stroke.miter_limit);
+        let width = stroke.width * self.transform.scale();
+        let reach = style.reach(width) + 0.5;
+        let seg_ix = |j: usize| stroke.segs_ix as usize + j * PietSegment::SIZE;
+        let mut any_stroke = false;
+        // The first segment of the current subpath.
+        let mut first_ix = 0;
+        for j in 0..stroke.n_segs as usize {
+            let seg = self.read_segment(seg_ix(j));
+            if seg.flags & SEG_SUBPATH_START != 0 {
+                first_ix = j;
+            }
+            let (xymin, xymax) = seg.bbox();
+            if !self.bbox_near(xymin, xymax, reach) {
+                continue;
+            }
+            let open = seg.flags & SEG_CLOSED == 0;
+            let mut prev_dir = if seg.flags & SEG_SUBPATH_START != 0 {
+                None
+            } else {
+                Some(self.read_segment(seg_ix(j - 1)).end_dir())
+            };
+            let n = seg.flatten_count();
+            let mut start = seg.points[0];
+            for i in 1..=n {
+                let end = seg.flat_point(i, n);
+                if start == end {
+                    continue;
+                }
+                let cap_start = i == 1 && open && seg.flags & SEG_SUBPATH_START != 0;
+                let last = i == n && seg.flags & SEG_SUBPATH_END != 0;
+                let near = self.stroke_styled_line(
+                    start,
+                    end,
+                    prev_dir,
+                    cap_start,
+                    last && open,
+                    &style,
+                    width,
+                );
+                let dir = direction(start, end);
+                if near && last && !open {
+                    let first_dir = self.read_segment(seg_ix(first_ix)).start_dir();
+                    self.stroke_join(end, dir, first_dir, &style, 0.5 * width);
+                }
+                any_stroke |= near;
+                prev_dir = Some(dir);
+                start = end;
+            }
+        }
+        if any_stroke {
+            self.encode_paint(stroke.flags, stroke.rgba_color);
+            self.encoder.encode_stroke(stroke.rgba_color, width);
+        }
+    }
+
+    /// Read a point, transformed to pixels.
+    fn read_point(&self, ix: usize) -> [f32; 2] {
+        self.transform.apply(read_point(self.scene, ix))
+    }
+
+    /// Read a segment, transformed to pixels.
+    fn read_segment(&self, ix: usize) -> Segment {
+        Segment::read(self.scene, ix).transform(&self.transform)
+    }
+
+    /// Test whether a bbox is within distance `d` of the tile.
+    fn bbox_near(&self, xymin: [f32; 2], xymax: [f32; 2], d: f32) -> bool {
+        let (x0, y0) = (self.x0, self.y0);
+        xymax[1] > y0 - d && xymin[1] < y0 + TH + d && xymax[0] > x0 - d && xymin[0] < x0 + TW + d
+    }
+
+    /// Test whether a line is within distance `d` of the tile.
+    fn line_near(&self, start: [f32; 2], end: [f32; 2], d: f32) -> bool {
+        let (x0, y0) = (self.x0, self.y0);
+        let (xymin, xymax) = xy_min_max(start, end);
+        self.bbox_near(xymin, xymax, d)
+            && line_crosses_rect(start, end, x0 - d, y0 - d, x0 + TW + d, y0 + TH + d)
+    }
+
+    /// Add one line of a stroke to the tile, if it is near enough to affect it.
+    ///
+    /// Along with the line itself, this adds the join with the previous line (in
+    /// direction `prev_dir`) and the caps, as needed. With round caps and joins,
+    /// only the line is needed, as the distance field takes care of those.
+    #[allow(clippy::too_many_arguments)]
+    fn stroke_styled_line(
+        &mut self,
+        start: [f32; 2],
+        end: [f32; 2],
+        prev_dir: Option<[f32; 2]>,
+        cap_start: bool,
+        cap_end: bool,
+        style: &StrokeStyle,
+        width: f32,
+    ) -> bool {
+        // TODO: is this bound as tight as it can be?
+        if !self.line_near(start, end, style.reach(width) + 0.5) {
+            return false;
+        }
+        if style.cap == LineCap::Round && style.join == LineJoin::Round {
+            self.encoder.encode_line(start, end);
+            return true;
+        }
+        let hw = 0.5 * width;
+        let dir = direction(start, end);
+        let mut body_start = start;
+        let mut body_end = end;
+        if cap_start {
+            match style.cap {
+                LineCap::Butt => (),
+                LineCap::Round => self.encoder.encode_line(start, start),
+                LineCap::Square => body_start = [start[0] - hw * dir[0], start[1] - hw * dir[1]],
+            }
+        }
+        if cap_end {
+            match style.cap {
+                LineCap::Butt => (),
+                LineCap::Round => self.encoder.encode_line(end, end),
+                LineCap::Square => body_end = [end[0] + hw * dir[0], end[1] + hw * dir[1]],
+            }
+        }
+        self.encoder.encode_line_butt(body_start, body_end, hw);
+        if let Some(prev_dir) = prev_dir {
+            self.stroke_join(start, prev_dir, dir, style, hw);
+        }
+        true
+    }
+
+    /// Add the join between lines in directions `dir_in` and `dir_out` at `point`.
+    fn stroke_join(
+        &mut self,
+        point: [f32; 2],
+        dir_in: [f32; 2],
+        dir_out: [f32; 2],
+        style: &StrokeStyle,
+        hw: f32,
+    ) {
+        if style.join == LineJoin::Round {
+            self.encoder.encode_line(point, point);
+            return;
+        }
+        let (n_in, n_out) = join_normals(dir_in, dir_out);
+        let m = [n_in[0] + n_out[0], n_in[1] + n_out[1]];
+        let m_len = m[0].hypot(m[1]);
+        if m_len < 1e-6 {
+            // The path doubles back; the butt ends of the lines coincide.
+            return;
+        }
+        // Cosine of half the angle between the normals.
+        let k = dot(n_in, m) / m_len;
+        let cut = if style.join == LineJoin::Miter && k * style.miter_limit >= 1.0 {
+            hw / k
+        } else {
+            hw * k
+        };
+        self.encoder.encode_join(point, dir_in, dir_out, cut, hw);
+    }
+}
+
+/// The fill rule selected by the flags of a fill or clip item.
+fn fill_rule_from_flags(flags: u32) -> FillRule {
+    if flags & FILL_EVEN_ODD != 0 {
+        FillRule::EvenOdd
+    } else {
+        FillRule::NonZero
+    }
+}
+
+/// Whether a winding number is inside a fill, used for tiles with no edges.
+fn winding_inside(winding: i32, fill_rule: FillRule) -> bool {
+    match fill_rule {
+        FillRule::NonZero => winding != 0,
+        FillRule::EvenOdd => winding % 2 != 0,
+    }
+}
+
+/// Coverage of a fill, from the signed area with the backdrop added.
+fn fill_alpha(area: f32, fill_rule: FillRule) -> f32 {
+    match fill_rule {
+        FillRule::NonZero => area.abs().min(1.0),
+        // distance to the nearest even winding number
+        FillRule::EvenOdd => (area - 2.0 * (0.5 * area).round()).abs(),
+    }
+}
+
+/// The unit normals of a join on its outer side, for the incoming and outgoing
+/// lines. The outer side is opposite to the direction of the turn.
+fn join_normals(dir_in: [f32; 2], dir_out: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+    let cross = dir_in[0] * dir_out[1] - dir_in[1] * dir_out[0];
+    let s = if cross >= 0.0 { -1.0 } else { 1.0 };
+    (
+        [-s * dir_in[1], s * dir_in[0]],
+        [-s * dir_out[1], s * dir_out[0]],
+    )
+}
+
+/// Build the per-tile command lists for a scene.
+///
+/// The result is in row-major order, `n_tiles_x` tiles per row.
+pub fn tile_scene(scene: &[u8], n_tiles_x: usize, n_tiles_y: usize) -> Vec<Tile> {
+    tile_scene_with_pool(scene, n_tiles_x, n_tiles_y, usize::MAX).0
+}
+
+/// Build the per-tile command lists for a scene, also laying them out in a tile
+/// buffer with a pool of `n_pool_chunks` chunks.
+///
+/// The tiles are processed in order, where the GPU version hands out the chunks of
+/// the pool in whatever order the tiles reach them. The returned tiles have their
+/// whole command lists, even if the pool overflowed.
+pub fn tile_scene_with_pool(
+    scene: &[u8],
+    n_tiles_x: usize,
+    n_tiles_y: usize,
+    n_pool_chunks: usize,
+) -> (Vec<Tile>, TileBuf) {
+    let mut buf = TileBuf::new(n_tiles_x * n_tiles_y, n_pool_chunks);
+    let mut tiles = Vec::with_capacity(n_tiles_x * n_tiles_y);
+    for tile_y in 0..n_tiles_y {
+        for tile_x in 0..n_tiles_x {
+            let tile_ix = tiles.len();
+            tiles.push(Tiler::new(scene, tile_x, tile_y, &mut buf, tile_ix).tile_scene());
+        }
+    }
+    (tiles, buf)
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x * (1.0 / 12.92)
+    } else {
+        ((x + 0.055) * (1.0 / 1.055)).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x < 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Equivalent of `unpack_unorm4x8_srgb_to_half`.
+fn unpack_srgb(rgba: u32) -> [f32; 4] {
+    let bytes = rgba.to_le_bytes();
+    let unorm = |b: u8| b as f32 * (1.0 / 255.0);
+    [
+        srgb_to_linear(unorm(bytes[0])),
+        srgb_to_linear(unorm(bytes[1])),
+        srgb_to_linear(unorm(bytes[2])),
+        unorm(bytes[3]),
+    ]
+}
+
+/// Draw the color `fg` with coverage `alpha` over a premultiplied color. The alpha
+/// of `fg` is expected to be included in `alpha`.
+fn mix_rgba(rgba: &mut [f32; 4], fg: [f32; 4], alpha: f32) {
+    for i in 0..3 {
+        rgba[i] = mix(rgba[i], fg[i], alpha);
+    }
+    rgba[3] = mix(rgba[3], 1.0, alpha);
+}
+
+/// The color of a gradient at a pixel, following `gradientColor`.
+///
+/// Stops are interpolated in linear space with premultiplied alpha, and the result
+/// has separate alpha, as for a color unpacked from a command.
+fn gradient_color(scene: &[u8], gradient_ix: u32, transform: &Transform, xy: [f32; 2]) -> [f32; 4] {
+    let gradient = PietGradient::read(&scene[gradient_ix as usize..]);
+    // Sample at the center of the pixel.
+    let p = transform.apply([xy[0] + 0.5, xy[1] + 0.5]);
+    let t = if gradient.flags & GRADIENT_RADIAL != 0 {
+        p[0].hypot(p[1])
+    } else {
+        p[0]
+    };
+    let t = match gradient.flags & GRADIENT_EXTEND_MASK {
+        GRADIENT_EXTEND_REPEAT => t - t.floor(),
+        GRADIENT_EXTEND_REFLECT => 1.0 - ((0.5 * t - (0.5 * t).floor()) * 2.0 - 1.0).abs(),
+        _ => saturate(t),
+    };
+    let stop = |i: usize| {
+        let ix = gradient.stops_ix as usize + i * PietGradientStop::SIZE;
+        let stop = PietGradientStop::read(&scene[ix..]);
+        (stop.offset, premultiply(unpack_srgb(stop.rgba_color)))
+    };
+    let (mut prev_offset, mut prev) = stop(0);
+    let mut color = prev;
+    for i in 1..gradient.n_stops as usize {
+        let (offset, next) = stop(i);
+        if t < offset {
+            let u = saturate((t - prev_offset) / (offset - prev_offset));
+            color = lerp_rgba(prev, next, u);
+            break;
+        }
+        prev_offset = offset;
+        prev = next;
+        color = next;
+    }
+    unpremultiply(color)
+}
+
+/// The color of an image fill at a pixel, following `imageColor`.
+///
+/// Pixels outside the image are clamped to its edges. Bilinear sampling is in linear
+/// space with premultiplied alpha, and the result has separate alpha.
+fn image_color(scene: &[u8], fill_ix: u32, transform: &Transform, xy: [f32; 2]) -> [f32; 4] {
+    let fill = PietImageFill::read(&scene[fill_ix as usize..]);
+    let header = SceneHeader::read(scene);
+    let image_ref = header.images as usize + fill.image_ix as usize * PietImage::SIZE;
+    let image = PietImage::read(&scene[image_ref..]);
+    let pixel = |x: f32, y: f32| {
+        let x = (x as i32).clamp(0, image.width as i32 - 1) as usize;
+        let y = (y as i32).clamp(0, image.height as i32 - 1) as usize;
+        let ix = image.data_ix as usize + (y * image.width as usize + x) * 4;
+        premultiply(unpack_srgb(read_u32(scene, ix)))
+    };
+    // Sample at the center of the pixel.
+    let p = transform.apply([xy[0] + 0.5, xy[1] + 0.5]);
+    let color = if fill.flags & IMAGE_BILINEAR != 0 {
+        // Interpolate between the centers of the four nearest image pixels.
+        let (x, y) = (p[0] - 0.5, p[1] - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let top = lerp_rgba(pixel(x0, y0), pixel(x0 + 1.0, y0), x - x0);
+        let bottom = lerp_rgba(pixel(x0, y0 + 1.0), pixel(x0 + 1.0, y0 + 1.0), x - x0);
+        lerp_rgba(top, bottom, y - y0)
+    } else {
+        pixel(p[0].floor(), p[1].floor())
+    };
+    unpremultiply(color)
+}
+
+fn lerp_rgba(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    [0, 1, 2, 3].map(|i| mix(a[i], b[i], t))
+}
+
+fn premultiply(c: [f32; 4]) -> [f32; 4] {
+    [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]]
+}
+
+fn unpremultiply(c: [f32; 4]) -> [f32; 4] {
+    if c[3] > 0.0 {
+        [c[0] / c[3], c[1] / c[3], c[2] / c[3], c[3]]
+    } else {
+        c
+    }
+}
+
+/// Composite a layer onto the one underneath, following `compositeLayer`. Both
+/// are premultiplied, in linear space, and the layer is scaled by `alpha`.
+fn composite_layer(below: [f32; 4], layer: [f32; 4], alpha: f32, blend: BlendMode) -> [f32; 4] {
+    let src = layer.map(|c| c * alpha);
+    if blend == BlendMode::Normal {
+        return [0, 1, 2, 3].map(|i| below[i] * (1.0 - src[3]) + src[i]);
+    }
+    // The blend function applies where both are present:
+    // co = cs * (1 - ab) + cb * (1 - as) + as * ab * B(Cb, Cs)
+    // As in CSS and SVG, B is applied to sRGB-encoded colors.
+    let to_srgb = |c: [f32; 4]| {
+        let c = unpremultiply(c);
+        [0, 1, 2].map(|i| linear_to_srgb(saturate(c[i])))
+    };
+    let b = blend_colors(to_srgb(below), to_srgb(src), blend).map(srgb_to_linear);
+    let (ab, as_) = (below[3], src[3]);
+    let mut result = [0.0; 4];
+    for i in 0..3 {
+        result[i] = src[i] * (1.0 - ab) + below[i] * (1.0 - as_) + as_ * ab * b[i];
+    }
+    result[3] = as_ + ab * (1.0 - as_);
+    result
+}
+
+/// The blend function B(Cb, Cs) of the W3C Compositing and Blending spec, for
+/// colors with separate alpha.
+fn blend_colors(cb: [f32; 3], cs: [f32; 3], blend: BlendMode) -> [f32; 3] {
+    let separable = |f: fn(f32, f32) -> f32| [0, 1, 2].map(|i| f(cb[i], cs[i]));
+    match blend {
+        BlendMode::Normal => cs,
+        BlendMode::Multiply => separable(|b, s| b * s),
+        BlendMode::Screen => separable(screen),
+        BlendMode::Overlay => separable(|b, s| hard_light(s, b)),
+        BlendMode::Darken => separable(f32::min),
+        BlendMode::Lighten => separable(f32::max),
+        BlendMode::ColorDodge => separable(|b, s| {
+            if b == 0.0 {
+                0.0
+            } else if s >= 1.0 {
+                1.0
+            } else {
+                (b / (1.0 - s)).min(1.0)
+            }
+        }),
+        BlendMode::ColorBurn => separable(|b, s| {
+            if b >= 1.0 {
+                1.0
+            } else if s == 0.0 {
+                0.0
+            } else {
+                1.0 - ((1.0 - b) / s).min(1.0)
+            }
+        }),
+        BlendMode::HardLight => separable(hard_light),
+        BlendMode::SoftLight => separable(|b, s| {
+            if s <= 0.5 {
+                b - (1.0 - 2.0 * s) * b * (1.0 - b)
+            } else {
+                let d = if b <= 0.25 {
+                    ((16.0 * b - 12.0) * b + 4.0) * b
+                } else {
+                    b.sqrt()
+                };
+                b + (2.0 * s - 1.0) * (d - b)
+            }
+        }),
+        BlendMode::Difference => separable(|b, s| (b - s).abs()),
+        BlendMode::Exclusion => separable(|b, s| b + s - 2.0 * b * s),
+        BlendMode::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+        BlendMode::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+        BlendMode::Color => set_lum(cs, lum(cb)),
+        BlendMode::Luminosity => set_lum(cb, lum(cs)),
+    }
+}
+
+fn screen(b: f32, s: f32) -> f32 {
+    b + s - b * s
+}
+
+fn hard_light(b: f32, s: f32) -> f32 {
+    if s <= 0.5 {
+        b * 2.0 * s
+    } else {
+        screen(b, 2.0 * s - 1.0)
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+/// Shift a color to luminance `l`, then bring it back into gamut, keeping its
+/// luminance.
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    let c = c.map(|x| x + d);
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    if n < 0.0 {
+        c.map(|v| l + (v - l) * l / (l - n))
+    } else if x > 1.0 {
+        c.map(|v| l + (v - l) * (1.0 - l) / (x - l))
+    } else {
+        c
+    }
+}
+
+/// Scale a color to saturation `s`, keeping its hue. Equivalent to `SetSat` in the
+/// spec, which orders the components.
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    if x > n {
+        c.map(|v| (v - n) * s / (x - n))
+    } else {
+        [0.0; 3]
+    }
+}
+
+fn to_unorm8(x: f32) -> u8 {
+    (saturate(x) * 255.0).round() as u8
+}
+
+/// Interpret the command list of a tile for one pixel, following `renderKernel`.
+///
+/// The result is premultiplied, in sRGB.
+fn render_pixel(scene: &[u8], cmds: &[Cmd], x: usize, y: usize) -> [u8; 4] {
+    let xy = [x as f32, y as f32];
+    let header = SceneHeader::read(scene);
+    // Premultiplied color of the current layer, starting with the background.
+    let mut color = premultiply(unpack_srgb(header.background));
+    let mut df = 1e9f32;
+    let mut signed_area = 0.0f32;
+    // Coverage of the current clip, and those of the enclosing clips.
+    let mut clip = 1.0f32;
+    let mut clip_stack = Vec::new();
+    // Enclosing layers. Pushing a layer also pushes the clip.
+    let mut layer_stack = Vec::new();
+    // Color of the paint for the next draw command, if not the command's color.
+    let mut paint = None;
+    for cmd in cmds {
+        match *cmd {
+            Cmd::Circle { bbox } => {
+                let xy0 = [bbox[0] as f32, bbox[1] as f32];
+                let xy1 = [bbox[2] as f32, bbox[3] as f32];
+                let center = [mix(xy0[0], xy1[0], 0.5), mix(xy0[1], xy1[1], 0.5)];
+                let r = (xy[0] - center[0]).hypot(xy[1] - center[1]);
+                let circle_r = (center[0] - xy0[0]).min(center[1] - xy0[1]);
+                let alpha = saturate(circle_r - r);
+                mix_rgba(&mut color, [0.0, 0.0, 0.0, 1.0], alpha * clip);
+            }
+            Cmd::Line { start, end } => {
+                let line_vec = [end[0] - start[0], end[1] - start[1]];
+                let d_pos = [xy[0] - start[0], xy[1] - start[1]];
+                let len2 = dot(line_vec, line_vec);
+                // A zero length line is a dot, used for round caps and joins.
+                let t = if len2 > 0.0 {
+                    saturate(dot(line_vec, d_pos) / len2)
+                } else {
+                    0.0
+                };
+                let field = (line_vec[0] * t - d_pos[0]).hypot(line_vec[1] * t - d_pos[1]);
+                df = df.min(field);
+            }
+            Cmd::LineButt {
+                start,
+                end,
+                half_width,
+            } => {
+                // Distance field of a rectangle, offset by the half width so that
+                // it combines with the round lines.
+                let dir = direction(start, end);
+                let d_pos = [xy[0] - start[0], xy[1] - start[1]];
+                let len = dot(dir, [end[0] - start[0], end[1] - start[1]]);
+                let u = dot(dir, d_pos);
+                let v = (dir[0] * d_pos[1] - dir[1] * d_pos[0]).abs();
+                let field = v.max(half_width - u).max(half_width + u - len);
+                df = df.min(field);
+            }
+            Cmd::Join {
+                point,
+                dir_in,
+                dir_out,
+                cut,
+                half_width,
+            } => {
+                // Intersection of half-planes: the outer edges of both lines,
+                // the cut, and limits on how far the piece extends along the
+                // lines and to the inner side.
+                let (n_in, n_out) = join_normals(dir_in, dir_out);
+                let m = direction([0.0, 0.0], [n_in[0] + n_out[0], n_in[1] + n_out[1]]);
+                let d_pos = [xy[0] - point[0], xy[1] - point[1]];
+                let edge = dot(d_pos, n_in).max(dot(d_pos, n_out)) - half_width;
+                let along = (-dot(d_pos, dir_in)).max(dot(d_pos, dir_out)) - JOIN_OVERLAP;
+                let mid = (dot(d_pos, m) - cut).max(-dot(d_pos, m) - half_width);
+                df = df.min(edge.max(along).max(mid) + half_width);
+            }
+            Cmd::Stroke { half_width, rgba } => {
+                let alpha = saturate(half_width + 0.5 - df);
+                let fg = paint.take().unwrap_or_else(|| unpack_srgb(rgba));
+                mix_rgba(&mut color, fg, fg[3] * alpha * clip);
+                df = 1e9;
+            }
+            Cmd::Fill { start, end } => {
+                let start = [start[0] - xy[0], start[1] - xy[1]];
+                let end = [end[0] - xy[0], end[1] - xy[1]];
+                let window = [saturate(start[1]), saturate(end[1])];
+                if window[0] != window[1] {
+                    let t = [
+                        (window[0] - start[1]) / (end[1] - start[1]),
+                        (window[1] - start[1]) / (end[1] - start[1]),
+                    ];
+                    let xs = [mix(start[0], end[0], t[0]), mix(start[0], end[0], t[1])];
+                    let xmin = xs[0].min(xs[1]).min(1.0) - 1e-6;
+                    let xmax = xs[0].max(xs[1]);
+                    let b = xmax.min(1.0);
+                    let c = b.max(0.0);
+                    let d = xmin.max(0.0);
+                    let area = (b + 0.5 * (d * d - c * c) - xmin) / (xmax - xmin);
+                    signed_area += area * (window[0] - window[1]);
+                }
+            }
+            Cmd::FillEdge { sign, y } => {
+                signed_area += sign * saturate(xy[1] - y + 1.0);
+            }
+            Cmd::DrawFill {
+                backdrop,
+                rgba,
+                fill_rule,
+            } => {
+                let alpha = fill_alpha(signed_area + backdrop as f32, fill_rule);
+                let fg = paint.take().unwrap_or_else(|| unpack_srgb(rgba));
+                mix_rgba(&mut color, fg, fg[3] * alpha * clip);
+                signed_area = 0.0;
+            }
+            Cmd::Solid { rgba } => {
+                let fg = paint.take().unwrap_or_else(|| unpack_srgb(rgba));
+                mix_rgba(&mut color, fg, fg[3] * clip);
+            }
+            Cmd::BeginClip {
+                backdrop,
+                fill_rule,
+            } => {
+                clip_stack.push(clip);
+                clip *= fill_alpha(signed_area + backdrop as f32, fill_rule);
+                signed_area = 0.0;
+            }
+            Cmd::EndClip => {
+                clip = clip_stack.pop().unwrap_or(1.0);
+            }
+            Cmd::Gradient {
+                gradient,
+                mat,
+                translate,
+            } => {
+                let transform = Transform { mat, translate };
+                paint = Some(gradient_color(scene, gradient, &transform, xy));
+            }
+            Cmd::Image {
+                fill,
+                mat,
+                translate,
+            } => {
+                let transform = Transform { mat, translate };
+                paint = Some(image_color(scene, fill, &transform, xy));
+            }
+            Cmd::BeginLayer => {
+                layer_stack.push(color);
+                clip_stack.push(clip);
+                color = [0.0; 4];
+                clip = 1.0;
+            }
+            Cmd::EndLayer { alpha, blend } => {
+                let layer = color;
+                clip = clip_stack.pop().unwrap_or(1.0);
+                let below = layer_stack.pop().unwrap_or([0.0; 4]);
+                color = composite_layer(below, layer, alpha * clip, blend);
+            }
+        }
+    }
+    // Premultiply after conversion to sRGB, as expected by compositors.
+    let [r, g, b, a] = unpremultiply(color);
+    let a = saturate(a);
+    [
+        to_unorm8(linear_to_srgb(r) * a),
+        to_unorm8(linear_to_srgb(g) * a),
+        to_unorm8(linear_to_srgb(b) * a),
+        to_unorm8(a),
+    ]
+}
+
+/// Render tiled command lists to an RGBA8 pixel buffer, with premultiplied alpha.
+///
+/// The `tiles` argument is as returned by `tile_scene`, and must cover the image. The
+/// scene is also read, for the stops of gradients.
+pub fn render_tiles(
+    scene: &[u8],
+    tiles: &[Tile],
+    n_tiles_x: usize,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let mut buf = vec![0; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let tile = &tiles[(y / TILE_HEIGHT) * n_tiles_x + x / TILE_WIDTH];
+            let rgba = match tile.solid_color {
+                // Solid tiles are drawn directly from the color, without conversion.
+                Some(color) => color.to_le_bytes(),
+                None => render_pixel(scene, &tile.cmds, x, y),
+            };
+            let ix = (y * width + x) * 4;
+            buf[ix..ix + 4].copy_from_slice(&rgba);
+        }
+    }
+    buf
+}
+
+/// Render a scene to an RGBA8 pixel buffer, `width * height * 4` bytes.
+///
+/// The pixels are sRGB with premultiplied alpha. They are opaque unless the scene
+/// has a background that isn't.
+pub fn render(scene: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let n_tiles_x = width.div_ceil(TILE_WIDTH);
+    let n_tiles_y = height.div_ceil(TILE_HEIGHT);
+    let tiles = tile_scene(scene, n_tiles_x, n_tiles_y);
+    render_tiles(scene, &tiles, n_tiles_x, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Encoder, Extend, GradientStop, GroupRef, LinearGradient, Sampling, StrokeStyle};
+    use kurbo::{Affine, BezPath, Line, Point, Rect};
+
+    /// 0xRRGGBBAA, as passed to the encoder.
+    const RED: u32 = 0xff00_00ff;
+    const BLACK: u32 = 0x0000_00ff;
+
+    const WHITE_PIXEL: [u8; 4] = [255, 255, 255, 255];
+    const RED_PIXEL: [u8; 4] = [255, 0, 0, 255];
+    const BLACK_PIXEL: [u8; 4] = [0, 0, 0, 255];
+    /// Half coverage of black over white, which is mixed in linear space.
+    const GRAY_PIXEL: [u8; 4] = [188, 188, 188, 255];
+
+    /// Encode a scene whose root group is filled in by `f`.
+    fn encode(f: impl FnOnce(&mut Encoder)) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.begin_group();
+        f(&mut encoder);
+        encoder.end_group();
+        encoder.finish().as_bytes().to_vec()
+    }
+
+    /// A rendered image, as RGBA8 pixels.
+    struct Image {
+        width: usize,
+        pixels: Vec<u8>,
+    }
+
+    impl Image {
+        fn render(scene: &[u8], width: usize, height: usize) -> Image {
+            Image {
+                width,
+                pixels: render(scene, width, height),
+            }
+        }
+
+        fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+            let ix = (y * self.width + x) * 4;
+            [0, 1, 2, 3].map(|i| self.pixels[ix + i])
+        }
+    }
+
+    fn rect_points(x0: f64, y0: f64, x1: f64, y1: f64) -> [Point; 4] {
+        [
+            Point::new(x0, y0),
+            Point::new(x1, y0),
+            Point::new(x1, y1),
+            Point::new(x0, y1),
+        ]
+    }
+
+    /// Add a rect to `path` as a closed subpath, clockwise in y-down coordinates.
+    fn add_rect(path: &mut BezPath, x0: f64, y0: f64, x1: f64, y1: f64) {
+        let points = rect_points(x0, y0, x1, y1);
+        path.move_to(points[0]);
+        for &p in &points[1..] {
+            path.line_to(p);
+        }
+        path.close_path();
+    }
+
+    #[test]
+    fn fill_rect() {
+        // Three tiles, with the left edge of the rect in the first and the right
+        // edge in the last.
+        let scene = encode(|e| e.fill(&rect_points(8.5, -1.0, 40.0, 17.0), RED, FillRule::NonZero));
+        let tiles = tile_scene(&scene, 3, 1);
+        let rgba = RED.to_be();
+        assert_eq!(
+            tiles[0].cmds,
+            [
+                Cmd::Fill {
+                    start: [8.5, 17.0],
+                    end: [8.5, -1.0]
+                },
+                Cmd::DrawFill {
+                    backdrop: 0,
+                    rgba,
+                    fill_rule: FillRule::NonZero
+                },
+            ]
+        );
+        assert_eq!(tiles[0].solid_color, None);
+        // Inside the rect, the tile is a solid color with no commands.
+        assert!(tiles[1].cmds.is_empty());
+        assert_eq!(tiles[1].solid_color, Some(rgba));
+        // The left edge is accounted for by the backdrop.
+        assert_eq!(
+            tiles[2].cmds,
+            [
+                Cmd::Fill {
+                    start: [40.0, -1.0],
+                    end: [40.0, 17.0]
+                },
+                Cmd::DrawFill {
+                    backdrop: 1,
+                    rgba,
+                    fill_rule: FillRule::NonZero
+                },
+            ]
+        );
+
+        assert_eq!(render_pixel(&scene, &tiles[0].cmds, 7, 8), WHITE_PIXEL);
+        // Half covered, mixed in linear space.
+        assert_eq!(
+            render_pixel(&scene, &tiles[0].cmds, 8, 8),
+            [255, 188, 188, 255]
+        );
+        assert_eq!(render_pixel(&scene, &tiles[0].cmds, 9, 8), RED_PIXEL);
+        assert_eq!(render_pixel(&scene, &tiles[2].cmds, 39, 8), RED_PIXEL);
+        assert_eq!(render_pixel(&scene, &tiles[2].cmds, 40, 8), WHITE_PIXEL);
+
+        let image = Image::render(&scene, 48, 16);
+        for y in 0..16 {
+            assert_eq!(image.pixel(0, y), WHITE_PIXEL);
+            assert_eq!(image.pixel(8, y), [255, 188, 188, 255]);
+            assert_eq!(image.pixel(24, y), RED_PIXEL);
+            assert_eq!(image.pixel(47, y), WHITE_PIXEL);
+        }
+    }
+
+    #[test]
+    fn stroke_line() {
+        let line = Line::new((4.0, 8.0), (28.0, 8.0));
+        let scene = encode(|e| e.stroke_line(line, 4.0, BLACK, &StrokeStyle::default()));
+        let tiles = tile_scene(&scene, 2, 2);
+        // Round caps and joins are drawn from the distance to the line.
+        let cmds = [
+            Cmd::Line {
+                start: [4.0, 8.0],
+                end: [28.0, 8.0],
+            },
+            Cmd::Stroke {
+                half_width: 2.0,
+                rgba: BLACK.to_be(),
+            },
+        ];
+        assert_eq!(tiles[0].cmds, cmds);
+        assert_eq!(tiles[1].cmds, cmds);
+        // The line doesn't reach the second row of tiles.
+        for tile in &tiles[2..] {
+            assert!(tile.cmds.is_empty());
+            assert_eq!(tile.solid_color, Some(0xffff_ffff));
+        }
+
+        // Coverage is at the top left corner of a pixel, so the edges of the stroke
+        // are at y = 6 and y = 10.
+        let column = [5, 6, 7, 8, 9, 10, 11].map(|y| render_pixel(&scene, &cmds, 16, y));
+        assert_eq!(
+            column,
+            [
+                WHITE_PIXEL,
+                GRAY_PIXEL,
+                BLACK_PIXEL,
+                BLACK_PIXEL,
+                BLACK_PIXEL,
+                GRAY_PIXEL,
+                WHITE_PIXEL
+            ]
+        );
+        let image = Image::render(&scene, 32, 32);
+        // The round caps reach 2 past the ends.
+        assert_eq!(image.pixel(1, 8), WHITE_PIXEL);
+        assert_eq!(image.pixel(2, 8), GRAY_PIXEL);
+        assert_eq!(image.pixel(3, 8), BLACK_PIXEL);
+        assert_eq!(image.pixel(29, 8), BLACK_PIXEL);
+        assert_eq!(image.pixel(30, 8), GRAY_PIXEL);
+        assert_eq!(image.pixel(31, 8), WHITE_PIXEL);
+        assert_eq!(image.pixel(16, 20), WHITE_PIXEL);
+    }
+
+    #[test]
+    fn fill_rules() {
+        // Nested squares in the same direction, with winding number 2 in the inner
+        // one, which covers the middle tile.
+        let mut path = BezPath::new();
+        add_rect(&mut path, -1.0, -1.0, 49.0, 49.0);
+        add_rect(&mut path, 12.0, 12.0, 36.0, 36.0);
+        let render_with = |fill_rule| {
+            let scene = encode(|e| e.fill_path(&path, RED, fill_rule));
+            (tile_scene(&scene, 3, 3), Image::render(&scene, 48, 48))
+        };
+
+        let (tiles, image) = render_with(FillRule::NonZero);
+        assert_eq!(tiles[4].solid_color, Some(RED.to_be()));
+        assert_eq!(
+            tiles[0].cmds.last(),
+            Some(&Cmd::DrawFill {
+                backdrop: 1,
+                rgba: RED.to_be(),
+                fill_rule: FillRule::NonZero
+            })
+        );
+        for &(x, y) in &[(4, 4), (14, 14), (24, 24), (40, 40)] {
+            assert_eq!(image.pixel(x, y), RED_PIXEL);
+        }
+
+        let (tiles, image) = render_with(FillRule::EvenOdd);
+        // Nothing is drawn in the middle tile.
+        assert_eq!(tiles[4].solid_color, Some(0xffff_ffff));
+        assert_eq!(
+            tiles[0].cmds.last(),
+            Some(&Cmd::DrawFill {
+                backdrop: 1,
+                rgba: RED.to_be(),
+                fill_rule: FillRule::EvenOdd
+            })
+        );
+        for &(x, y) in &[(4, 4), (40, 40)] {
+            assert_eq!(image.pixel(x, y), RED_PIXEL);
+        }
+        for &(x, y) in &[(14, 14), (24, 24), (34, 34)] {
+            assert_eq!(image.pixel(x, y), WHITE_PIXEL);
+        }
+    }
+
+    /// The pixels of row `y` of a render at `xs`.
+    fn row(image: &Image, y: usize, xs: &[usize]) -> Vec<[u8; 4]> {
+        xs.iter().map(|&x| image.pixel(x, y)).collect()
+    }
+
+    #[test]
+    fn caps() {
+        let line = Line::new((8.0, 8.0), (24.0, 8.0));
+        let stroke = |cap| {
+            let style = StrokeStyle {
+                cap,
+                ..Default::default()
+            };
+            let scene = encode(|e| e.stroke_line(line, 4.0, BLACK, &style));
+            (tile_scene(&scene, 2, 1), Image::render(&scene, 32, 16))
+        };
+        let (w, g, b) = (WHITE_PIXEL, GRAY_PIXEL, BLACK_PIXEL);
+
+        let (tiles, image) = stroke(LineCap::Butt);
+        assert_eq!(
+            tiles[0].cmds[0],
+            Cmd::LineButt {
+                start: [8.0, 8.0],
+                end: [24.0, 8.0],
+                half_width: 2.0
+            }
+        );
+        // The stroke ends at the ends of the line, which are half covered.
+        let xs = [6, 7, 8, 9, 23, 24, 25, 26];
+        assert_eq!(row(&image, 8, &xs), [w, w, g, b, b, g, w, w]);
+
+        // Square caps extend by half the width.
+        let (tiles, image) = stroke(LineCap::Square);
+        assert_eq!(
+            tiles[0].cmds[0],
+            Cmd::LineButt {
+                start: [6.0, 8.0],
+                end: [26.0, 8.0],
+                half_width: 2.0
+            }
+        );
+        assert_eq!(row(&image, 8, &xs), [g, b, b, b, b, b, b, g]);
+        assert_eq!(image.pixel(5, 8), w);
+        assert_eq!(image.pixel(27, 8), w);
+        // The corners are square, unlike those of round caps.
+        assert_eq!(image.pixel(6, 6), g);
+        assert_eq!(image.pixel(6, 10), g);
+
+        let (_, image) = stroke(LineCap::Round);
+        assert_eq!(image.pixel(6, 6), w);
+        assert_eq!(image.pixel(6, 8), g);
+    }
+
+    #[test]
+    fn joins() {
+        // A right angle, with the outer corner of the stroke at (28, 28). The miter
+        // is sqrt(2) times the width.
+        let points = [
+            Point::new(4.0, 24.0),
+            Point::new(24.0, 24.0),
+            Point::new(24.0, 4.0),
+        ];
+        let stroke = |join, miter_limit| {
+            let style = StrokeStyle {
+                join,
+                miter_limit,
+                ..Default::default()
+            };
+            let scene = encode(|e| e.polyline(&points, BLACK, 8.0, &style));
+            let tiles = tile_scene(&scene, 2, 2);
+            let join = tiles[3]
+                .cmds
+                .iter()
+                .find(|cmd| matches!(cmd, Cmd::Join { .. }))
+                .copied();
+            (join, Image::render(&scene, 32, 32))
+        };
+        let join_cut = |join: Option<Cmd>| match join {
+            Some(Cmd::Join { cut, .. }) => cut,
+            _ => panic!("expected a join, got {:?}", join),
+        };
+        let k = std::f32::consts::FRAC_1_SQRT_2;
+
+        // The miter reaches the corner.
+        let (join, image) = stroke(LineJoin::Miter, 1.5);
+        assert!((join_cut(join) - 4.0 / k).abs() < 1e-5);
+        assert_eq!(image.pixel(27, 27), BLACK_PIXEL);
+
+        // Past the limit, the join is a bevel, which cuts off the corner.
+        let (miter_past_limit, image) = stroke(LineJoin::Miter, 1.4);
+        assert!((join_cut(miter_past_limit) - 4.0 * k).abs() < 1e-5);
+        // The bevel goes from (28, 24) to (24, 28).
+        assert_eq!(image.pixel(27, 27), WHITE_PIXEL);
+        assert_eq!(image.pixel(26, 26), GRAY_PIXEL);
+        assert_eq!(image.pixel(25, 25), BLACK_PIXEL);
+        let (bevel, bevel_image) = stroke(LineJoin::Bevel, 4.0);
+        assert_eq!(bevel, miter_past_limit);
+        assert_eq!(bevel_image.pixels, image.pixels);
+
+        // Both join the outer edges of the lines.
+        for &(x, y) in &[(27, 21), (21, 27)] {
+            assert_eq!(image.pixel(x, y), BLACK_PIXEL);
+        }
+    }
+
+    #[test]
+    fn placed_groups() {
+        let scene = encode(|e| {
+            e.begin_group();
+            e.fill(&rect_points(0.0, 0.0, 8.0, 8.0), RED, FillRule::NonZero);
+            let group = e.end_group().unwrap();
+            e.place_group(group, Affine::translate((16.0, 16.0)));
+            e.place_group(group, Affine::translate((32.0, 0.0)) * Affine::scale(2.0));
+        });
+
+        // Each placement refers to the same group, which is encoded once.
+        let root = SceneHeader::read(&scene).root as usize;
+        let group = SimpleGroup::read(&scene[root..]);
+        assert_eq!(group.n_items, 3);
+        let placed: Vec<_> = (0..3)
+            .map(|i| match group.items_ix_at(&scene, i) {
+                PietItem::Group(placed) => placed,
+                item => panic!("expected a group, got {:?}", item),
+            })
+            .collect();
+        assert!(placed.iter().all(|p| p.group == placed[0].group));
+        assert_eq!(placed[1].translate, [16.0, 16.0]);
+        assert_eq!(placed[2].mat, [2.0, 0.0, 0.0, 2.0]);
+        // The bboxes are transformed.
+        assert_eq!(group.bboxes_at(&scene[root..], 1), [16, 16, 24, 24]);
+        assert_eq!(group.bboxes_at(&scene[root..], 2), [32, 0, 48, 16]);
+
+        let image = Image::render(&scene, 48, 32);
+        let red = [(0, 0), (7, 7), (16, 16), (23, 23), (32, 0), (47, 15)];
+        for &(x, y) in &red {
+            assert_eq!(image.pixel(x, y), RED_PIXEL, "at ({}, {})", x, y);
+        }
+        let white = [(8, 8), (15, 15), (24, 24), (31, 8), (40, 16)];
+        for &(x, y) in &white {
+            assert_eq!(image.pixel(x, y), WHITE_PIXEL, "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn unplaced_groups() {
+        let scene = encode(|e| {
+            e.begin_group();
+            e.fill(&rect_points(0.0, 0.0, 8.0, 8.0), RED, FillRule::NonZero);
+            let group = e.end_group_unplaced().unwrap();
+            e.fill(&rect_points(0.0, 8.0, 8.0, 16.0), BLACK, FillRule::NonZero);
+            e.place_group(group, Affine::translate((16.0, 0.0)));
+        });
+
+        // The group is only referenced where it is placed.
+        let root = SceneHeader::read(&scene).root as usize;
+        let group = SimpleGroup::read(&scene[root..]);
+        assert_eq!(group.n_items, 2);
+
+        let image = Image::render(&scene, 32, 16);
+        assert_eq!(image.pixel(4, 4), WHITE_PIXEL);
+        assert_eq!(image.pixel(4, 12), BLACK_PIXEL);
+        assert_eq!(image.pixel(20, 4), RED_PIXEL);
+    }
+
+    #[test]
+    #[should_panic(expected = "the root group can't be unplaced")]
+    fn root_group_unplaced() {
+        let mut encoder = Encoder::new();
+        encoder.begin_group();
+        encoder.end_group_unplaced();
+    }
+
+    /// Add a rect filled red at the bottom of `depth` nested groups, returning the
+    /// outermost one.
+    fn deep_group(e: &mut Encoder, depth: usize) -> GroupRef {
+        for _ in 0..depth {
+            e.begin_group();
+        }
+        e.fill(&rect_points(0.0, 0.0, 8.0, 8.0), RED, FillRule::NonZero);
+        let mut group = None;
+        for _ in 0..depth {
+            group = e.end_group();
+        }
+        group.unwrap()
+    }
+
+    #[test]
+    fn deep_placed_groups() {
+        let scene = encode(|e| {
+            let inner = deep_group(e, 4);
+            // Six levels deep, with the inner group placed two levels down.
+            e.begin_group();
+            e.begin_group();
+            e.place_group(inner, Affine::translate((16.0, 0.0)));
+            e.end_group();
+            let outer = e.end_group().unwrap();
+            // With the root and one more group, this reaches MAX_GROUP_DEPTH.
+            e.begin_group();
+            e.place_group(outer, Affine::translate((16.0, 0.0)));
+            e.end_group();
+        });
+
+        let image = Image::render(&scene, 48, 8);
+        for &(x, y) in &[(4, 4), (20, 4), (36, 4)] {
+            assert_eq!(image.pixel(x, y), RED_PIXEL, "at ({}, {})", x, y);
+        }
+        for &(x, y) in &[(12, 4), (28, 4), (44, 4)] {
+            assert_eq!(image.pixel(x, y), WHITE_PIXEL, "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "groups nested too deeply")]
+    fn deep_group_placed_too_deep() {
+        encode(|e| {
+            let inner = deep_group(e, 4);
+            e.begin_group();
+            e.begin_group();
+            e.place_group(inner, Affine::default());
+            e.end_group();
+            let outer = e.end_group().unwrap();
+            // The root, two more groups and the six levels of `outer`.
+            e.begin_group();
+            e.begin_group();
+            e.place_group(outer, Affine::default());
+        });
+    }
+
+    /// Render tiles whose command lists are read from the chunks of `buf`.
+    fn render_chunks(scene: &[u8], tiles: &[Tile], buf: &TileBuf, n_tiles_x: usize) -> Image {
+        let tiles: Vec<_> = tiles
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| Tile {
+                cmds: buf.cmds(i),
+                solid_color: tile.solid_color,
+            })
+            .collect();
+        let width = n_tiles_x * TILE_WIDTH;
+        let height = tiles.len() / n_tiles_x * TILE_HEIGHT;
+        Image {
+            width,
+            pixels: render_tiles(scene, &tiles, n_tiles_x, width, height),
+        }
+    }
+
+    #[test]
+    fn chunked_command_lists() {
+        // Enough small fills in each tile for its command list to take several chunks.
+        let scene = encode(|e| {
+            for i in 0..64 {
+                let x = (i % 8) as f64 * 4.0 + 0.5;
+                let y = (i / 8) as f64 * 4.0 + 0.5;
+                let color = if i % 2 == 0 { RED } else { BLACK };
+                e.fill(
+                    &rect_points(x, y, x + 3.0, y + 3.0),
+                    color,
+                    FillRule::NonZero,
+                );
+            }
+        });
+        let expected = Image::render(&scene, 32, 32);
+
+        let (tiles, buf) = tile_scene_with_pool(&scene, 2, 2, usize::MAX);
+        assert!(!buf.overflow);
+        let n_needed = buf.chunks.len() - tiles.len();
+        assert!(n_needed >= 2 * tiles.len());
+        for (i, tile) in tiles.iter().enumerate() {
+            // Each first chunk jumps into the pool, and following the jumps gives
+            // back the whole command list.
+            assert!(buf.chunks[i].jump.unwrap() >= tiles.len() * TILE_CHUNK_SIZE);
+            assert_eq!(buf.cmds(i), tile.cmds);
+        }
+        for chunk in &buf.chunks {
+            let size: usize = chunk.cmds.iter().map(Cmd::size).sum();
+            assert!(size + MAX_CMD_SIZE <= TILE_CHUNK_SIZE);
+        }
+        let image = render_chunks(&scene, &tiles, &buf, 2);
+        assert_eq!(image.pixels, expected.pixels);
+
+        // One chunk short, the last tile runs out and is started over, so it only
+        // has the end of its command list.
+        let (_, buf) = tile_scene_with_pool(&scene, 2, 2, n_needed - 1);
+        assert!(buf.overflow);
+        assert_eq!(buf.cmds(0), tiles[0].cmds);
+        let cmds = buf.cmds(3);
+        assert!(cmds.len() < tiles[3].cmds.len());
+        assert!(tiles[3].cmds.ends_with(&cmds));
+        let image = render_chunks(&scene, &tiles, &buf, 2);
+        assert_ne!(image.pixels, expected.pixels);
+
+        // Growing the pool as the host does, the scene fits again.
+        let mut n_pool_chunks = 1;
+        let buf = loop {
+            let (_, buf) = tile_scene_with_pool(&scene, 2, 2, n_pool_chunks);
+            if !buf.overflow {
+                break buf;
+            }
+            n_pool_chunks *= 2;
+        };
+        assert!(n_pool_chunks >= n_needed);
+        let image = render_chunks(&scene, &tiles, &buf, 2);
+        assert_eq!(image.pixels, expected.pixels);
+    }
+
+    #[test]
+    fn nested_clips() {
+        let everything = rect_points(-1.0, -1.0, 49.0, 49.0);
+        let scene = encode(|e| {
+            e.push_clip_rect(Rect::new(8.5, 4.0, 40.0, 44.0));
+            e.fill(&everything, RED, FillRule::NonZero);
+            e.push_clip_rect(Rect::new(12.0, 12.0, 48.0, 48.0));
+            e.fill(&everything, BLACK, FillRule::NonZero);
+            e.pop_clip();
+            e.pop_clip();
+        });
+        let tiles = tile_scene(&scene, 3, 3);
+        // Both clips cross the top middle tile.
+        let kinds: Vec<_> = tiles[1]
+            .cmds
+            .iter()
+            .filter(|cmd| !matches!(cmd, Cmd::Fill { .. } | Cmd::FillEdge { .. }))
+            .map(|cmd| match cmd {
+                Cmd::BeginClip { .. } => "begin",
+                Cmd::EndClip => "end",
+                Cmd::DrawFill { .. } | Cmd::Solid { .. } => "draw",
+                _ => panic!("unexpected command {:?}", cmd),
+            })
+            .collect();
+        assert_eq!(kinds, ["begin", "draw", "begin", "draw", "end", "end"]);
+        // Inside both clips, the items are drawn unclipped.
+        assert_eq!(tiles[4].solid_color, Some(BLACK.to_be()));
+
+        let image = Image::render(&scene, 48, 48);
+        // Outside the outer clip.
+        for &(x, y) in &[(4, 20), (20, 2), (44, 20), (20, 45)] {
+            assert_eq!(image.pixel(x, y), WHITE_PIXEL, "at ({}, {})", x, y);
+        }
+        // Inside the outer clip only.
+        for &(x, y) in &[(10, 6), (20, 8), (10, 40)] {
+            assert_eq!(image.pixel(x, y), RED_PIXEL, "at ({}, {})", x, y);
+        }
+        // Inside both.
+        for &(x, y) in &[(12, 12), (24, 24), (39, 43)] {
+            assert_eq!(image.pixel(x, y), BLACK_PIXEL, "at ({}, {})", x, y);
+        }
+        // The outer clip covers half of the pixels at x = 8.
+        assert_eq!(image.pixel(8, 20), [255, 188, 188, 255]);
+    }
+
+    #[test]
+    fn layer_opacity() {
+        let first = rect_points(4.0, 4.0, 28.0, 12.0);
+        let second = rect_points(12.0, 4.0, 36.0, 12.0);
+        let layer = encode(|e| {
+            e.begin_layer(0.5, BlendMode::Normal);
+            e.fill(&first, BLACK, FillRule::NonZero);
+            e.fill(&second, BLACK, FillRule::NonZero);
+            e.end_layer();
+        });
+        let tiles = tile_scene(&layer, 3, 1);
+        assert!(matches!(tiles[1].cmds.first(), Some(Cmd::BeginLayer)));
+        assert!(matches!(
+            tiles[1].cmds.last(),
+            Some(Cmd::EndLayer {
+                alpha,
+                blend: BlendMode::Normal,
+            }) if *alpha == 0.5
+        ));
+        // The layer is faded as a whole, so the overlap is as light as the rest.
+        let image = Image::render(&layer, 48, 16);
+        assert_eq!(
+            row(&image, 8, &[6, 16, 32, 40]),
+            [GRAY_PIXEL, GRAY_PIXEL, GRAY_PIXEL, WHITE_PIXEL]
+        );
+
+        // With the alpha on the items, the overlap is darker.
+        let half_black = 0x0000_0080;
+        let items = encode(|e| {
+            e.fill(&first, half_black, FillRule::NonZero);
+            e.fill(&second, half_black, FillRule::NonZero);
+        });
+        let image = Image::render(&items, 48, 16);
+        assert_eq!(image.pixel(6, 8), image.pixel(32, 8));
+        assert!(image.pixel(16, 8)[0] < image.pixel(6, 8)[0]);
+    }
+
+    #[test]
+    fn gradient_extend() {
+        let render_with = |extend| {
+            let scene = encode(|e| {
+                let paint = e.linear_gradient(&LinearGradient {
+                    start: Point::new(0.0, 0.0),
+                    end: Point::new(16.0, 0.0),
+                    stops: vec![
+                        GradientStop {
+                            offset: 0.0,
+                            rgba: BLACK,
+                        },
+                        GradientStop {
+                            offset: 1.0,
+                            rgba: RED,
+                        },
+                    ],
+                    extend,
+                });
+                e.fill(
+                    &rect_points(-1.0, -1.0, 49.0, 17.0),
+                    paint,
+                    FillRule::NonZero,
+                );
+            });
+            Image::render(&scene, 48, 16)
+        };
+        let pad = render_with(Extend::Pad);
+        // Stops are interpolated along the gradient.
+        let ramp = row(&pad, 8, &[0, 4, 8, 12]);
+        assert!(ramp.windows(2).all(|w| w[0][0] < w[1][0]));
+        assert!(ramp.iter().all(|p| p[1..] == [0, 0, 255]));
+        // Beyond the end stops.
+        assert_eq!(row(&pad, 8, &[16, 20, 40]), [RED_PIXEL; 3]);
+
+        let repeat = render_with(Extend::Repeat);
+        assert_eq!(
+            row(&repeat, 8, &[0, 4, 8, 12]),
+            row(&pad, 8, &[0, 4, 8, 12])
+        );
+        assert_eq!(row(&repeat, 8, &[16, 20, 36]), row(&pad, 8, &[0, 4, 4]));
+
+        // Paints are sampled at pixel centers, so pixel x mirrors pixel 31 - x.
+        let reflect = render_with(Extend::Reflect);
+        assert_eq!(
+            row(&reflect, 8, &[0, 4, 8, 12]),
+            row(&pad, 8, &[0, 4, 8, 12])
+        );
+        assert_eq!(
+            row(&reflect, 8, &[16, 20, 28, 36]),
+            row(&pad, 8, &[15, 11, 3, 4])
+        );
+    }
+
+    #[test]
+    fn image_sampling() {
+        let render_with = |sampling| {
+            let scene = encode(|e| {
+                let image = e.add_image(2, 1, &[255, 0, 0, 255, 0, 0, 0, 255]);
+                let transform = Affine::translate((8.0, 4.0)) * Affine::scale(8.0);
+                e.draw_image(image, transform, sampling);
+            });
+            Image::render(&scene, 32, 16)
+        };
+        let xs: Vec<_> = (8..24).collect();
+
+        let nearest = render_with(Sampling::Nearest);
+        let mut expected = vec![RED_PIXEL; 8];
+        expected.extend_from_slice(&[BLACK_PIXEL; 8]);
+        assert_eq!(row(&nearest, 8, &xs), expected);
+
+        // Each image pixel is flat up to its center, then blends into the next.
+        let bilinear = render_with(Sampling::Bilinear);
+        let pixels = row(&bilinear, 8, &xs);
+        assert_eq!(pixels[..4], [RED_PIXEL; 4]);
+        assert_eq!(pixels[12..], [BLACK_PIXEL; 4]);
+        assert!(pixels[4..12].windows(2).all(|w| w[0][0] > w[1][0]));
+        assert!(pixels.iter().all(|p| p[1..] == [0, 0, 255]));
+        // Outside the image.
+        assert_eq!(row(&bilinear, 8, &[4, 28]), [WHITE_PIXEL; 2]);
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn blend_formulas() {
+        let cb = [0.2, 0.5, 0.8];
+        let cs = [0.6, 0.5, 0.1];
+        let each = |f: fn(f32, f32) -> f32| [0, 1, 2].map(|i| f(cb[i], cs[i]));
+        assert_close(
+            &blend_colors(cb, cs, BlendMode::Multiply),
+            &each(|b, s| b * s),
+        );
+        assert_close(
+            &blend_colors(cb, cs, BlendMode::Screen),
+            &each(|b, s| b + s - b * s),
+        );
+        assert_close(
+            &blend_colors(cb, cs, BlendMode::Difference),
+            &each(|b, s| (b - s).abs()),
+        );
+        assert_close(&blend_colors(cb, cs, BlendMode::Darken), &each(f32::min));
+        // Overlay is hard light with the layers swapped.
+        assert_close(
+            &blend_colors(cb, cs, BlendMode::Overlay),
+            &each(|b, s| {
+                if b <= 0.5 {
+                    s * 2.0 * b
+                } else {
+                    let b = 2.0 * b - 1.0;
+                    s + b - s * b
+                }
+            }),
+        );
+        // Luminosity keeps the hue and saturation of the backdrop.
+        let result = blend_colors(cb, cs, BlendMode::Luminosity);
+        assert_close(&[lum(result)], &[lum(cs)]);
+        assert_close(&[sat(result)], &[sat(cb)]);
+
+        // co = cs * (1 - ab) + cb * (1 - as) + as * ab * B(Cb, Cs), premultiplied.
+        let below = [0.1, 0.25, 0.4, 0.5];
+        let layer = [0.6, 0.5, 0.1, 1.0];
+        // The blend function is applied to the sRGB encoding of the colors.
+        let b = [0, 1, 2].map(|i| srgb_to_linear(linear_to_srgb(cb[i]) * linear_to_srgb(cs[i])));
+        let expected = [0, 1, 2].map(|i| 0.5 * 0.6 * cs[i] + 0.5 * 0.6 * b[i] + 0.4 * below[i]);
+        let result = composite_layer(below, layer, 0.6, BlendMode::Multiply);
+        assert_close(&result[..3], &expected);
+        assert_close(&result[3..], &[0.6 + 0.5 * 0.4]);
+    }
+
+    #[test]
+    fn blend_layers() {
+        let render_with = |blend| {
+            let scene = encode(|e| {
+                e.fill(
+                    &rect_points(-1.0, -1.0, 16.0, 17.0),
+                    BLACK,
+                    FillRule::NonZero,
+                );
+                e.begin_layer(1.0, blend);
+                e.fill(&rect_points(-1.0, -1.0, 33.0, 17.0), RED, FillRule::NonZero);
+                e.end_layer();
+            });
+            let image = Image::render(&scene, 32, 16);
+            row(&image, 8, &[8, 24])
+        };
+        assert_eq!(render_with(BlendMode::Normal), [RED_PIXEL, RED_PIXEL]);
+        assert_eq!(render_with(BlendMode::Multiply), [BLACK_PIXEL, RED_PIXEL]);
+        assert_eq!(render_with(BlendMode::Screen), [RED_PIXEL, WHITE_PIXEL]);
+        assert_eq!(
+            render_with(BlendMode::Difference),
+            [RED_PIXEL, [0, 255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn blend_in_srgb() {
+        // Blend a gray layer onto a gray backdrop, both given as sRGB bytes.
+        let render_with = |below: u8, layer: u8, blend| {
+            let gray = |v: u8| u32::from_be_bytes([v, v, v, 255]);
+            let scene = encode(|e| {
+                let everything = rect_points(-1.0, -1.0, 17.0, 17.0);
+                e.fill(&everything, gray(below), FillRule::NonZero);
+                e.begin_layer(1.0, blend);
+                e.fill(&everything, gray(layer), FillRule::NonZero);
+                e.end_layer();
+            });
+            Image::render(&scene, 16, 16).pixel(8, 8)[0]
+        };
+        // The reference values are the blend functions applied to the sRGB values,
+        // as in CSS: 128 * 128 / 255, 64 + 64 - 64 * 64 / 255 and 192 - 64.
+        assert_eq!(render_with(128, 128, BlendMode::Multiply), 64);
+        assert_eq!(render_with(64, 64, BlendMode::Screen), 112);
+        assert_eq!(render_with(192, 64, BlendMode::Difference), 128);
+    }
+
+    #[test]
+    fn transparent_background() {
+        let scene = encode(|e| {
+            e.set_background(0);
+            e.fill(&rect_points(4.5, 4.0, 12.0, 12.0), RED, FillRule::NonZero);
+            e.fill(
+                &rect_points(20.0, 4.0, 28.0, 12.0),
+                0xff00_0080,
+                FillRule::NonZero,
+            );
+        });
+        // Without an opaque background, no tile is solid.
+        let tiles = tile_scene(&scene, 3, 1);
+        assert_eq!(tiles[2].solid_color, None);
+        assert!(tiles[2].cmds.is_empty());
+
+        // The output is premultiplied.
+        let image = Image::render(&scene, 48, 16);
+        assert_eq!(image.pixel(8, 8), RED_PIXEL);
+        assert_eq!(image.pixel(24, 8), [128, 0, 0, 128]);
+        // Half covered by the opaque fill, up to rounding.
+        let [r, g, b, a] = image.pixel(4, 8);
+        assert_eq!([g, b, a], [0, 0, 128]);
+        assert!(r.abs_diff(a) <= 1);
+        assert_eq!(row(&image, 8, &[2, 16, 40]), [[0; 4]; 3]);
+
+        let scene = encode(|e| e.set_background(0xffff_ff80));
+        assert_eq!(
+            Image::render(&scene, 16, 16).pixel(8, 8),
+            [128, 128, 128, 128]
+        );
+    }
+
+    #[test]
+    fn negative_bboxes() {
+        let scene = encode(|e| {
+            e.fill(
+                &rect_points(-20.5, 4.0, -4.5, 12.0),
+                BLACK,
+                FillRule::NonZero,
+            );
+            e.fill(&rect_points(-8.0, -8.0, 8.0, 8.0), RED, FillRule::NonZero);
+            e.begin_group();
+            e.fill(
+                &rect_points(36.0, -28.0, 44.0, -20.0),
+                BLACK,
+                FillRule::NonZero,
+            );
+            let group = e.end_group().unwrap();
+            e.place_group(group, Affine::translate((-32.0, 48.0)));
+        });
+        let root = SceneHeader::read(&scene).root as usize;
+        let group = SimpleGroup::read(&scene[root..]);
+        // Bboxes are rounded out, towards negative infinity at the top left.
+        assert_eq!(group.bboxes_at(&scene[root..], 0), [-21, 4, -4, 12]);
+        assert_eq!(group.bboxes_at(&scene[root..], 1), [-8, -8, 8, 8]);
+        assert_eq!(group.bboxes_at(&scene[root..], 2), [36, -28, 44, -20]);
+        assert_eq!(group.bboxes_at(&scene[root..], 3), [4, 20, 12, 28]);
+
+        // Only the first column of tiles is hit, by the visible parts of items.
+        let tiles = tile_scene(&scene, 3, 2);
+        let drawn = |tile: &Tile| {
+            tile.cmds
+                .iter()
+                .filter(|cmd| matches!(cmd, Cmd::DrawFill { .. } | Cmd::Solid { .. }))
+                .count()
+        };
+        assert_eq!(drawn(&tiles[0]), 1);
+        assert_eq!(drawn(&tiles[3]), 1);
+        for &i in &[1, 2, 4, 5] {
+            assert_eq!(tiles[i].solid_color, Some(0xffff_ffff), "tile {}", i);
+        }
+
+        let image = Image::render(&scene, 48, 32);
+        assert_eq!(
+            row(&image, 4, &[0, 4, 8, 12]),
+            [RED_PIXEL, RED_PIXEL, WHITE_PIXEL, WHITE_PIXEL]
+        );
+        assert_eq!(
+            row(&image, 24, &[0, 4, 8, 12]),
+            [WHITE_PIXEL, BLACK_PIXEL, BLACK_PIXEL, WHITE_PIXEL]
+        );
+    }
+
+    #[test]
+    fn curves() {
+        // An arch from (4, 44) to (44, 44), with y = 44 - 160 t (1 - t) and
+        // x = 4 + 40 t, so at x = 8 and x = 40 it is at y = 29.6.
+        let mut arch = BezPath::new();
+        arch.move_to((4.0, 44.0));
+        arch.quad_to((24.0, -36.0), (44.0, 44.0));
+        let mut cubic_arch = BezPath::new();
+        cubic_arch.move_to((4.0, 44.0));
+        // The same curve, degree elevated.
+        let c = 44.0 - 160.0 / 3.0;
+        cubic_arch.curve_to((4.0 + 40.0 / 3.0, c), (44.0 - 40.0 / 3.0, c), (44.0, 44.0));
+        let inside = [(8, 36), (24, 8), (40, 36), (24, 40)];
+        let outside = [(8, 20), (24, 2), (40, 20), (2, 40)];
+        for path in &[arch.clone(), cubic_arch] {
+            let scene = encode(|e| e.fill_path(path, RED, FillRule::NonZero));
+            let image = Image::render(&scene, 48, 48);
+            for &(x, y) in &inside {
+                assert_eq!(image.pixel(x, y), RED_PIXEL, "at ({}, {})", x, y);
+            }
+            for &(x, y) in &outside {
+                assert_eq!(image.pixel(x, y), WHITE_PIXEL, "at ({}, {})", x, y);
+            }
+        }
+
+        let style = StrokeStyle::default();
+        let scene = encode(|e| e.stroke_path(&arch, BLACK, 4.0, &style));
+        let image = Image::render(&scene, 48, 48);
+        for &(x, y) in &[(24, 4), (8, 30), (40, 30), (24, 5)] {
+            assert_eq!(image.pixel(x, y), BLACK_PIXEL, "at ({}, {})", x, y);
+        }
+        for &(x, y) in &[(24, 1), (24, 8), (2, 30), (16, 34), (24, 40)] {
+            assert_eq!(image.pixel(x, y), WHITE_PIXEL, "at ({}, {})", x, y);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! Dashing of strokes.
+//!
+//! Dashing is applied when a stroke is encoded: the path is flattened, cut into
+//! dashes along its arc length, and the dashes are encoded as the subpaths of an
+//! ordinary stroke, so that each of them gets caps.
+
+use kurbo::{BezPath, ParamCurve, PathEl, PathSeg, Point};
+
+/// Maximum number of dashes in a stroke. A pattern that is tiny compared to the
+/// path would otherwise make a huge scene out of a single item.
+pub const MAX_DASHES: usize = 10_000;
+
+/// Cut a path into dashes.
+///
+/// The pattern alternates lengths of dashes and gaps, starting with a dash. As in
+/// SVG, a pattern of odd length is repeated to make it even, and the offset is the
+/// distance into the pattern at which to start. The pattern restarts at the beginning
+/// of each subpath.
+///
+/// A non-finite offset is treated as 0.
+///
+/// Returns `None` if the pattern doesn't describe any dashing, because an entry is
+/// negative or not finite or they are all 0, or if there would be more than
+/// `MAX_DASHES` dashes. In that case the stroke should be solid.
+pub fn dash_path(path: &BezPath, pattern: &[f32], offset: f32, tolerance: f64) -> Option<BezPath> {
+    if pattern.iter().any(|&x| !x.is_finite() || x < 0.0) || pattern.iter().all(|&x| x == 0.0) {
+        return None;
+    }
+    let offset = if offset.is_finite() { offset } else { 0.0 };
+    let mut pattern: Vec<f64> = pattern.iter().map(|&x| x as f64).collect();
+    if !pattern.len().is_multiple_of(2) {
+        pattern.extend_from_within(..);
+    }
+    let mut dasher = Dasher::new(&pattern, offset as f64);
+    let mut last = Point::default();
+    let mut start = Point::default();
+    for el in path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => {
+                dasher.end_subpath(false);
+                dasher.begin_subpath(p);
+                start = p;
+                last = p;
+            }
+            PathEl::LineTo(p) => {
+                dasher.line_to(p);
+                last = p;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                flatten_seg(
+                    PathSeg::Quad(kurbo::QuadBez::new(last, p1, p2)),
+                    tolerance,
+                    &mut dasher,
+                );
+                last = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                let seg = PathSeg::Cubic(kurbo::CubicBez::new(last, p1, p2, p3));
+                flatten_seg(seg, tolerance, &mut dasher);
+                last = p3;
+            }
+            PathEl::ClosePath => {
+                dasher.line_to(start);
+                dasher.end_subpath(true);
+                dasher.begin_subpath(start);
+                last = start;
+            }
+        }
+        if dasher.is_full() {
+            return None;
+        }
+    }
+    dasher.end_subpath(false);
+    if dasher.is_full() {
+        return None;
+    }
+    let mut result = BezPath::new();
+    for dash in &dasher.dashes {
+        if dash.points.len() >= 2 {
+            result.move_to(dash.points[0]);
+            for &p in &dash.points[1..] {
+                result.line_to(p);
+            }
+            if dash.closed {
+                result.close_path();
+            }
+        }
+    }
+    Some(result)
+}
+
+/// Feed the lines of a flattened curve segment to the dasher.
+///
+/// This subdivides uniformly, with the number of lines given by Wang's formula, as
+/// the tiler does.
+fn flatten_seg(seg: PathSeg, tolerance: f64, dasher: &mut Dasher) {
+    let dd = |a: Point, b: Point, c: Point| (a.to_vec2() - 2.0 * b.to_vec2() + c.to_vec2()).hypot();
+    let m = match seg {
+        PathSeg::Line(_) => 0.0,
+        PathSeg::Quad(q) => 0.25 * dd(q.p0, q.p1, q.p2),
+        PathSeg::Cubic(c) => 0.75 * dd(c.p0, c.p1, c.p2).max(dd(c.p1, c.p2, c.p3)),
+    };
+    let n = ((m / tolerance).sqrt().ceil() as usize).max(1);
+    for i in 1..=n {
+        dasher.line_to(seg.eval(i as f64 / n as f64));
+    }
+}
+
+#[derive(Default)]
+struct Dash {
+    points: Vec<Point>,
+    // Set when the dash is a whole closed subpath.
+    closed: bool,
+}
+
+struct Dasher<'a> {
+    pattern: &'a [f64],
+    // State at the start of a subpath, after applying the offset.
+    start_ix: usize,
+    start_remaining: f64,
+    // Index into the pattern, and the length remaining in that entry.
+    ix: usize,
+    remaining: f64,
+    last: Point,
+    // The dash in progress, if in a dash rather than a gap.
+    cur: Option<Vec<Point>>,
+    // Index in `dashes` of the first dash of the subpath, if it starts the subpath.
+    first_dash: Option<usize>,
+    dashes: Vec<Dash>,
+}
+
+impl<'a> Dasher<'a> {
+    fn new(pattern: &[f64], offset: f64) -> Dasher<'_> {
+        let total: f64 = pattern.iter().sum();
+        let mut offset = offset.rem_euclid(total);
+        let mut start_ix = 0;
+        // A zero-length dash at the start is kept, so that it gets caps.
+        while offset > 0.0 && offset >= pattern[start_ix] {
+            offset -= pattern[start_ix];
+            start_ix = (start_ix + 1) % pattern.len();
+        }
+        Dasher {
+            pattern,
+            start_ix,
+            start_remaining: pattern[start_ix] - offset,
+            ix: start_ix,
+            remaining: 0.0,
+            last: Point::default(),
+            cur: None,
+            first_dash: None,
+            dashes: Vec::new(),
+        }
+    }
+
+    fn begin_subpath(&mut self, p: Point) {
+        self.ix = self.start_ix;
+        self.remaining = self.start_remaining;
+        self.last = p;
+        if self.ix.is_multiple_of(2) {
+            self.first_dash = Some(self.dashes.len());
+            self.cur = Some(vec![p]);
+        } else {
+            self.first_dash = None;
+        }
+    }
+
+    fn line_to(&mut self, p: Point) {
+        let p0 = self.last;
+        let len = (p - p0).hypot();
+        let mut pos = 0.0;
+        while len - pos > self.remaining {
+            if self.is_full() {
+                return;
+            }
+            pos += self.remaining;
+            let pt = p0.lerp(p, pos / len);
+            match self.cur.take() {
+                Some(mut dash) => {
+                    dash.push(pt);
+                    self.dashes.push(Dash {
+                        points: dash,
+                        closed: false,
+                    });
+                }
+                None => self.cur = Some(vec![pt]),
+            }
+            self.ix = (self.ix + 1) % self.pattern.len();
+            self.remaining = self.pattern[self.ix];
+        }
+        self.remaining -= len - pos;
+        if let Some(dash) = &mut self.cur {
+            dash.push(p);
+        }
+        self.last = p;
+    }
+
+    /// Whether there are more than `MAX_DASHES` dashes, after which the rest of
+    /// the path is ignored.
+    fn is_full(&self) -> bool {
+        self.dashes.len() > MAX_DASHES
+    }
+
+    /// Finish the dash in progress. If the subpath is closed and both starts and
+    /// ends in a dash, those are merged into one, so there is a join rather than
+    /// two caps. If that dash is the whole subpath, it stays closed.
+    fn end_subpath(&mut self, closed: bool) {
+        if let Some(mut dash) = self.cur.take() {
+            match self.first_dash {
+                Some(first_ix) if closed && first_ix < self.dashes.len() => {
+                    let first = std::mem::take(&mut self.dashes[first_ix]);
+                    dash.extend_from_slice(&first.points[1..]);
+                    self.dashes[first_ix].points = dash;
+                }
+                Some(_) if closed => self.dashes.push(Dash {
+                    points: dash,
+                    closed: true,
+                }),
+                _ => self.dashes.push(Dash {
+                    points: dash,
+                    closed: false,
+                }),
+            }
+        }
+        self.first_dash = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 0.1;
+
+    /// The subpaths of a dashed path, with whether each is closed.
+    fn dashes(path: &BezPath, pattern: &[f32], offset: f32) -> Vec<(Vec<Point>, bool)> {
+        let dashed = dash_path(path, pattern, offset, TOLERANCE).unwrap();
+        let mut result: Vec<(Vec<Point>, bool)> = Vec::new();
+        for el in dashed.elements() {
+            match *el {
+                PathEl::MoveTo(p) => result.push((vec![p], false)),
+                PathEl::LineTo(p) => result.last_mut().unwrap().0.push(p),
+                PathEl::ClosePath => result.last_mut().unwrap().1 = true,
+                _ => panic!("unexpected curve in dashes"),
+            }
+        }
+        result
+    }
+
+    /// The extents along the x axis of the dashes of a horizontal line.
+    fn dash_extents(len: f64, pattern: &[f32], offset: f32) -> Vec<(f64, f64)> {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((len, 0.0));
+        dashes(&path, pattern, offset)
+            .iter()
+            .map(|(points, _)| (points[0].x, points.last().unwrap().x))
+            .collect()
+    }
+
+    fn square() -> BezPath {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((10.0, 0.0));
+        path.line_to((10.0, 10.0));
+        path.line_to((0.0, 10.0));
+        path.close_path();
+        path
+    }
+
+    #[test]
+    fn odd_pattern_doubled() {
+        assert_eq!(
+            dash_extents(45.0, &[10.0], 0.0),
+            [(0.0, 10.0), (20.0, 30.0), (40.0, 45.0)]
+        );
+        // 10 on, 5 off, 5 on, then 10 off, 5 on, 5 off.
+        assert_eq!(
+            dash_extents(60.0, &[10.0, 5.0, 5.0], 0.0),
+            [
+                (0.0, 10.0),
+                (15.0, 20.0),
+                (30.0, 35.0),
+                (40.0, 50.0),
+                (55.0, 60.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_wrap() {
+        let expected = [(3.0, 13.0), (18.0, 28.0), (33.0, 40.0)];
+        assert_eq!(dash_extents(40.0, &[10.0, 5.0], 12.0), expected);
+        assert_eq!(dash_extents(40.0, &[10.0, 5.0], -3.0), expected);
+        assert_eq!(dash_extents(40.0, &[10.0, 5.0], 27.0), expected);
+        assert_eq!(dash_extents(40.0, &[10.0, 5.0], -18.0), expected);
+        // Starting in the middle of a dash.
+        assert_eq!(
+            dash_extents(20.0, &[10.0, 5.0], 4.0),
+            [(0.0, 6.0), (11.0, 20.0)]
+        );
+    }
+
+    #[test]
+    fn closed_subpath_dashes_merged() {
+        let pts = |v: &[(f64, f64)]| v.iter().map(|&p| Point::from(p)).collect::<Vec<_>>();
+        // 0..15 and 25..40 along the perimeter are one dash through the start.
+        assert_eq!(
+            dashes(&square(), &[15.0, 10.0], 0.0),
+            [(
+                pts(&[
+                    (5.0, 10.0),
+                    (0.0, 10.0),
+                    (0.0, 0.0),
+                    (10.0, 0.0),
+                    (10.0, 5.0)
+                ]),
+                false
+            )]
+        );
+        // Starting in a gap, the first and last dashes are separate.
+        assert_eq!(
+            dashes(&square(), &[15.0, 10.0], 18.0),
+            [
+                (
+                    pts(&[(7.0, 0.0), (10.0, 0.0), (10.0, 10.0), (8.0, 10.0)]),
+                    false
+                ),
+                (pts(&[(0.0, 8.0), (0.0, 0.0)]), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn closed_subpath_in_one_dash() {
+        let pts = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+            (0.0, 0.0),
+        ];
+        let pts = pts.iter().map(|&p| Point::from(p)).collect::<Vec<_>>();
+        assert_eq!(
+            dashes(&square(), &[100.0, 10.0], 0.0),
+            [(pts.clone(), true)]
+        );
+        // An open subpath of the same shape gets caps.
+        let mut open = BezPath::new();
+        open.move_to(pts[0]);
+        for &p in &pts[1..] {
+            open.line_to(p);
+        }
+        assert_eq!(dashes(&open, &[100.0, 10.0], 0.0), [(pts, false)]);
+    }
+
+    #[test]
+    fn zero_length_entries() {
+        // Dots, including one at the start.
+        assert_eq!(
+            dash_extents(25.0, &[0.0, 10.0], 0.0),
+            [(0.0, 0.0), (10.0, 10.0), (20.0, 20.0)]
+        );
+        // With zero-length gaps, the dashes abut.
+        assert_eq!(
+            dash_extents(25.0, &[10.0, 0.0], 0.0),
+            [(0.0, 10.0), (10.0, 20.0), (20.0, 25.0)]
+        );
+        // A pattern with no length doesn't dash.
+        let path = square();
+        assert!(dash_path(&path, &[0.0, 0.0], 0.0, TOLERANCE).is_none());
+        assert!(dash_path(&path, &[], 0.0, TOLERANCE).is_none());
+        assert!(dash_path(&path, &[5.0, -1.0], 0.0, TOLERANCE).is_none());
+    }
+
+    #[test]
+    fn non_finite_offset_ignored() {
+        let expected = dash_extents(10.0, &[2.0, 1.0], 0.0);
+        for &offset in &[f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(dash_extents(10.0, &[2.0, 1.0], offset), expected);
+        }
+    }
+
+    #[test]
+    fn too_many_dashes() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((1000.0, 0.0));
+        path.quad_to((1500.0, 500.0), (1000.0, 1000.0));
+        let n = MAX_DASHES as f32;
+        assert!(dash_path(&path, &[1e-6, 1e-6], 0.0, TOLERANCE).is_none());
+        // The line alone has twice the limit.
+        assert!(dash_path(&path, &[500.0 / n, 500.0 / n], 0.0, TOLERANCE).is_none());
+        assert_eq!(
+            dash_extents(1000.0, &[1000.0 / n, 1000.0 / n], 0.0).len(),
+            MAX_DASHES / 2
+        );
+    }
+}
@@ -1,47 +1,396 @@
 //  Copyright 2019 The xi-editor authors.
 
-//! Quick and dirty path flattening.
+//! Path flattening.
+//!
+//! Quadratic Béziers are flattened following "Flattening quadratic Béziers" by Raph
+//! Levien. The curve is mapped onto a segment of the parabola y = x², where the
+//! integral of the square root of the curvature has a good closed-form
+//! approximation. The number of lines needed to meet the tolerance is
+//! proportional to that integral, and spacing the points evenly in it gives each
+//! line about the same error. Cubics are approximated by quadratics first, which
+//! uses a small part of the tolerance, and each of those is flattened.
 
-// A proper path flattening algorithm belongs in kurbo. In the meantime, this will let us
-// get something rendered.
+use kurbo::{BezPath, CubicBez, ParamCurve, PathEl, Point, QuadBez};
 
-use kurbo::{BezPath, CubicBez, PathEl, Point};
+/// The fraction of the tolerance used for approximating cubics by quadratics.
+const CUBIC_TO_QUAD_FRACTION: f64 = 0.1;
 
-pub fn flatten_path(path: &BezPath, tolerance: f64) -> Vec<Vec<Point>> {
-    let mut result: Vec<Vec<Point>> = Vec::new();
+/// The approximate integrals can underestimate the error by a few percent, so the
+/// number of lines is computed for this fraction of the tolerance.
+const ERROR_MARGIN: f64 = 0.9;
+
+/// A flattened subpath.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subpath {
+    /// The points of the polyline. For a closed subpath, the last point is the
+    /// same as the first.
+    pub points: Vec<Point>,
+    /// Whether the subpath ends with `ClosePath`, so that a stroke joins the
+    /// closing line to the first one.
+    pub closed: bool,
+}
+
+/// Flatten a path into polylines, one for each subpath, whose distance from the
+/// path is at most `tolerance`.
+///
+/// As in kurbo, a segment that doesn't follow a `MoveTo` starts a subpath at the
+/// current point, which is the start of the last subpath after a `ClosePath`,
+/// or the origin at the start of the path.
+pub fn flatten_path(path: &BezPath, tolerance: f64) -> Vec<Subpath> {
+    let mut result = Vec::new();
     let mut cur_path = None;
     let mut last_pt = Point::default();
     for el in path.elements() {
         match el {
             PathEl::MoveTo(p) => {
-                if let Some(sp) = cur_path.take() {
-                    result.push(sp);
-                }
-                cur_path = Some(vec![*p]);
+                result.extend(cur_path.take());
+                cur_path = Some(Subpath::new(*p));
                 last_pt = *p;
             }
             PathEl::LineTo(p) => {
-                cur_path.as_mut().unwrap().push(*p);
+                let sp = cur_path.get_or_insert_with(|| Subpath::new(last_pt));
+                sp.points.push(*p);
                 last_pt = *p;
             }
+            PathEl::QuadTo(p1, p2) => {
+                let sp = cur_path.get_or_insert_with(|| Subpath::new(last_pt));
+                let q = QuadBez::new(last_pt, *p1, *p2);
+                flatten_quad(&q, tolerance, &mut sp.points);
+                last_pt = *p2;
+            }
             PathEl::CurveTo(p1, p2, p3) => {
-                let cb = CubicBez::new(last_pt, *p1, *p2, *p3);
-                // This is a really hacky way to get finer subdivision. It will
-                // give overly coarse results if the Bézier is close to cubic. But
-                // close enough for now.
-                //
-                // A reasonable approach would be to subdivide the quads based
-                // on the true error, or we could try to do a fancier algorithm.
-                for (_, _, q) in cb.to_quads(tolerance * 1e-2) {
-                    cur_path.as_mut().unwrap().push(q.p2);
-                }
+                let sp = cur_path.get_or_insert_with(|| Subpath::new(last_pt));
+                let c = CubicBez::new(last_pt, *p1, *p2, *p3);
+                flatten_cubic(&c, tolerance, &mut sp.points);
                 last_pt = *p3;
             }
-            _ => (),
+            PathEl::ClosePath => {
+                if let Some(mut sp) = cur_path.take() {
+                    let start = sp.points[0];
+                    if last_pt != start {
+                        sp.points.push(start);
+                    }
+                    sp.closed = true;
+                    last_pt = start;
+                    result.push(sp);
+                }
+            }
         }
     }
-    if let Some(sp) = cur_path.take() {
-        result.push(sp);
-    }
+    result.extend(cur_path);
     result
 }
+
+impl Subpath {
+    fn new(start: Point) -> Subpath {
+        Subpath {
+            points: vec![start],
+            closed: false,
+        }
+    }
+}
+
+/// Append the points of a flattened quadratic Bézier, after its start point.
+pub fn flatten_quad(q: &QuadBez, tolerance: f64, points: &mut Vec<Point>) {
+    let sqrt_tol = (ERROR_MARGIN * tolerance).sqrt();
+    let params = FlattenParams::new(q, sqrt_tol);
+    if params.val > 0.0 {
+        let n = (0.5 * params.val / sqrt_tol).ceil() as usize;
+        for i in 1..n {
+            let t = params.t_at(i as f64 / n as f64);
+            points.push(q.eval(t));
+        }
+    } else if let Some(t) = collinear_turning_point(q) {
+        // The curve goes past one of its ends and comes back along the same line.
+        points.push(q.eval(t));
+    }
+    points.push(q.p2);
+}
+
+/// The parameter where a quadratic Bézier with collinear control points turns
+/// back, if it does between its ends. The derivative is zero there.
+fn collinear_turning_point(q: &QuadBez) -> Option<f64> {
+    let d01 = q.p1 - q.p0;
+    let dd = d01 - (q.p2 - q.p1);
+    let t = d01.dot(dd) / dd.hypot2();
+    if t > 0.0 && t < 1.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Append the points of a flattened cubic Bézier, after its start point.
+///
+/// Spreading the lines over all of the quadratics at once would take fewer of
+/// them, but the error of lines spanning two quadratics isn't bounded, notably
+/// at inflection points.
+pub fn flatten_cubic(c: &CubicBez, tolerance: f64, points: &mut Vec<Point>) {
+    let quad_tolerance = CUBIC_TO_QUAD_FRACTION * tolerance;
+    for (_, _, q) in c.to_quads(quad_tolerance) {
+        flatten_quad(&q, tolerance - quad_tolerance, points);
+    }
+}
+
+/// The mapping of a quadratic Bézier onto the parabola y = x², for flattening.
+struct FlattenParams {
+    // Integral at the ends of the segment of the parabola.
+    a0: f64,
+    a2: f64,
+    // Position of the start on the parabola, and the inverse of the length of the
+    // segment, in the space of the inverse integral.
+    u0: f64,
+    uscale: f64,
+    // The integral of the square root of the curvature, scaled so that half of it
+    // divided by the square root of the tolerance is the number of lines needed.
+    val: f64,
+}
+
+impl FlattenParams {
+    fn new(q: &QuadBez, sqrt_tol: f64) -> FlattenParams {
+        let d01 = q.p1 - q.p0;
+        let d12 = q.p2 - q.p1;
+        let dd = d01 - d12;
+        let cross = (q.p2 - q.p0).cross(dd);
+        // Positions of the ends on the x axis of the parabola, and the scale.
+        let x0 = d01.dot(dd) / cross;
+        let x2 = d12.dot(dd) / cross;
+        let scale = (cross / (dd.hypot() * (x2 - x0))).abs();
+        let a0 = approx_parabola_integral(x0);
+        let a2 = approx_parabola_integral(x2);
+        let val = if cross != 0.0 && scale.is_finite() {
+            let da = (a2 - a0).abs();
+            let sqrt_scale = scale.sqrt();
+            if x0.signum() == x2.signum() {
+                da * sqrt_scale
+            } else {
+                // The segment contains the cusp of the parabola, where the curvature
+                // is greatest. The scale is then limited by the tolerance.
+                let xmin = sqrt_tol / sqrt_scale;
+                sqrt_tol * da / approx_parabola_integral(xmin)
+            }
+        } else {
+            // The control points are collinear, so the quad is a line.
+            0.0
+        };
+        let u0 = approx_parabola_inv_integral(a0);
+        let u2 = approx_parabola_inv_integral(a2);
+        FlattenParams {
+            a0,
+            a2,
+            u0,
+            uscale: 1.0 / (u2 - u0),
+            val,
+        }
+    }
+
+    /// The parameter of the quad at a fraction `x` of the integral.
+    fn t_at(&self, x: f64) -> f64 {
+        let a = self.a0 + (self.a2 - self.a0) * x;
+        let u = approx_parabola_inv_integral(a);
+        (u - self.u0) * self.uscale
+    }
+}
+
+/// An approximation to ∫ (1 + 4x²)^-0.25 dx, the integral of the square root of
+/// the curvature along the parabola y = x².
+fn approx_parabola_integral(x: f64) -> f64 {
+    const D: f64 = 0.67;
+    x / (1.0 - D + (D.powi(4) + 0.25 * x * x).sqrt().sqrt())
+}
+
+/// An approximation to the inverse of `approx_parabola_integral`.
+fn approx_parabola_inv_integral(x: f64) -> f64 {
+    const B: f64 = 0.39;
+    x * (1.0 - B + (B * B + 0.25 * x * x).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::{Line, ParamCurveNearest};
+
+    const TOLERANCE: f64 = 0.1;
+
+    /// The greatest distance from points along the curve to the polyline.
+    fn max_deviation(curve: &impl ParamCurve, points: &[Point]) -> f64 {
+        let lines: Vec<Line> = points.windows(2).map(|w| Line::new(w[0], w[1])).collect();
+        let n_samples = 2000;
+        (0..=n_samples)
+            .map(|i| {
+                let p = curve.eval(i as f64 / n_samples as f64);
+                lines
+                    .iter()
+                    .map(|line| line.nearest(p, 1e-9).1.sqrt())
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Deterministic pseudo-random coordinates in [0, 500).
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64 * 500.0
+        }
+
+        fn point(&mut self) -> Point {
+            Point::new(self.next(), self.next())
+        }
+    }
+
+    fn flattened_quad(q: &QuadBez) -> Vec<Point> {
+        let mut points = vec![q.p0];
+        flatten_quad(q, TOLERANCE, &mut points);
+        points
+    }
+
+    fn flattened_cubic(c: &CubicBez) -> Vec<Point> {
+        let mut points = vec![c.p0];
+        flatten_cubic(c, TOLERANCE, &mut points);
+        points
+    }
+
+    #[test]
+    fn quads_within_tolerance() {
+        let mut rng = Lcg(1);
+        for _ in 0..200 {
+            let q = QuadBez::new(rng.point(), rng.point(), rng.point());
+            let points = flattened_quad(&q);
+            let deviation = max_deviation(&q, &points);
+            assert!(deviation <= TOLERANCE, "{:?}: {}", q, deviation);
+        }
+    }
+
+    #[test]
+    fn cubics_within_tolerance() {
+        let mut rng = Lcg(2);
+        for _ in 0..200 {
+            let c = CubicBez::new(rng.point(), rng.point(), rng.point(), rng.point());
+            let points = flattened_cubic(&c);
+            let deviation = max_deviation(&c, &points);
+            assert!(deviation <= TOLERANCE, "{:?}: {}", c, deviation);
+        }
+    }
+
+    #[test]
+    fn cusp_within_tolerance() {
+        let c = CubicBez::new((0.0, 0.0), (300.0, 200.0), (0.0, 200.0), (300.0, 0.0));
+        let points = flattened_cubic(&c);
+        assert!(max_deviation(&c, &points) <= TOLERANCE);
+        let q = QuadBez::new((0.0, 0.0), (400.0, 10.0), (0.0, 20.0));
+        let points = flattened_quad(&q);
+        assert!(max_deviation(&q, &points) <= TOLERANCE);
+    }
+
+    #[test]
+    fn circle_close_to_optimal() {
+        // A quarter circle of radius 100. Chords of an arc of radius r deviate by
+        // r (1 - cos(θ / 2)), which gives the fewest lines that meet the tolerance.
+        // Some are lost to the margin, and to rounding up for each quadratic.
+        let r = 100.0;
+        let k = 0.5523 * r;
+        let c = CubicBez::new((r, 0.0), (r, k), (k, r), (0.0, r));
+        let points = flattened_cubic(&c);
+        assert!(max_deviation(&c, &points) <= TOLERANCE);
+        let theta = 2.0 * (1.0 - TOLERANCE / r).acos();
+        let optimal = (std::f64::consts::FRAC_PI_2 / theta).ceil() as usize;
+        let n_lines = points.len() - 1;
+        assert!(
+            n_lines <= optimal * 3 / 2,
+            "{} lines, optimal {}",
+            n_lines,
+            optimal
+        );
+    }
+
+    #[test]
+    fn path_elements() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.quad_to((50.0, 100.0), (100.0, 0.0));
+        path.close_path();
+        path.line_to((0.0, 100.0));
+        path.curve_to((0.0, 150.0), (50.0, 200.0), (100.0, 200.0));
+        path.move_to((10.0, 10.0));
+        path.line_to((20.0, 10.0));
+        path.line_to((10.0, 10.0));
+        path.close_path();
+        let subpaths = flatten_path(&path, TOLERANCE);
+        assert_eq!(subpaths.len(), 3);
+        let q = QuadBez::new((0.0, 0.0), (50.0, 100.0), (100.0, 0.0));
+        let quad = &subpaths[0];
+        assert!(quad.closed);
+        assert_eq!(quad.points.first(), quad.points.last());
+        let n = quad.points.len();
+        assert!(max_deviation(&q, &quad.points[..n - 1]) <= TOLERANCE);
+        // After ClosePath, the next segment starts at the start of the subpath.
+        let open = &subpaths[1];
+        assert!(!open.closed);
+        assert_eq!(open.points[..2], [Point::ORIGIN, Point::new(0.0, 100.0)]);
+        assert_eq!(open.points.last(), Some(&Point::new(100.0, 200.0)));
+        // The closing line isn't repeated when the subpath already ends at its start.
+        let triangle = &subpaths[2];
+        assert!(triangle.closed);
+        assert_eq!(triangle.points.len(), 3);
+    }
+
+    #[test]
+    fn path_without_move_to() {
+        let mut path = BezPath::new();
+        path.push(PathEl::LineTo(Point::new(10.0, 0.0)));
+        path.push(PathEl::LineTo(Point::new(10.0, 10.0)));
+        let subpaths = flatten_path(&path, TOLERANCE);
+        assert_eq!(
+            subpaths,
+            vec![Subpath {
+                points: vec![Point::ORIGIN, Point::new(10.0, 0.0), Point::new(10.0, 10.0)],
+                closed: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn collinear_quads_within_tolerance() {
+        let quads = [
+            QuadBez::new((0.0, 0.0), (100.0, 0.0), (0.0, 0.0)),
+            QuadBez::new((0.0, 0.0), (100.0, 0.0), (50.0, 0.0)),
+            QuadBez::new((0.0, 0.0), (-50.0, 0.0), (100.0, 0.0)),
+            QuadBez::new((10.0, 10.0), (200.0, 200.0), (50.0, 50.0)),
+            // Nearly collinear, which takes the general path.
+            QuadBez::new((0.0, 0.0), (100.0, 1e-9), (50.0, 0.0)),
+        ];
+        for q in &quads {
+            let points = flattened_quad(q);
+            let deviation = max_deviation(q, &points);
+            assert!(deviation <= TOLERANCE, "{:?}: {}", q, deviation);
+        }
+        let q = QuadBez::new((0.0, 0.0), (100.0, 0.0), (0.0, 0.0));
+        assert_eq!(flattened_quad(&q), vec![q.p0, Point::new(50.0, 0.0), q.p2]);
+    }
+
+    #[test]
+    fn collinear_cubics_within_tolerance() {
+        let cubics = [
+            CubicBez::new((0.0, 0.0), (200.0, 0.0), (-100.0, 0.0), (50.0, 0.0)),
+            CubicBez::new((0.0, 0.0), (100.0, 0.0), (100.0, 0.0), (0.0, 0.0)),
+            CubicBez::new((0.0, 0.0), (-30.0, -30.0), (130.0, 130.0), (100.0, 100.0)),
+        ];
+        for c in &cubics {
+            let points = flattened_cubic(c);
+            let deviation = max_deviation(c, &points);
+            assert!(deviation <= TOLERANCE, "{:?}: {}", c, deviation);
+        }
+    }
+
+    #[test]
+    fn line_not_subdivided() {
+        let q = QuadBez::new((0.0, 0.0), (50.0, 50.0), (100.0, 100.0));
+        assert_eq!(flattened_quad(&q), vec![q.p0, q.p2]);
+    }
+}
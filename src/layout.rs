@@ -0,0 +1,354 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! The layout of types shared with the shaders.
+//!
+//! `PietMetal` is implemented by `#[derive(PietMetal)]`, and for the types generated
+//! by `piet_metal!`. Types are laid out as in Metal: each field is aligned to its own
+//! alignment, and the size of a struct is rounded up to its alignment, so that it is
+//! also the stride of arrays. A struct in a variant of an enum is tagged: the tag of
+//! the enum is its first field, so the alignment of the other fields takes it into
+//! account. Values are little-endian.
+//!
+//! The derive only generates Metal definitions, which are collected by `MetalSource`.
+//! Types that the GLSL and WGSL shaders also read are declared in `piet_metal!`,
+//! which generates the definitions for all of them.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::marker::PhantomData;
+
+/// A type with a layout shared with the shaders, and safe methods to encode it.
+pub trait PietMetal: Sized {
+    /// Alignment in bytes.
+    const ALIGN: usize;
+    /// Size in bytes, a multiple of the alignment.
+    const SIZE: usize;
+    /// Size in bytes in an enum variant, including the tag.
+    const TAGGED_SIZE: usize = struct_size(4, &[(Self::SIZE, Self::ALIGN)]);
+    /// Name of a derived type, used for the shader definitions.
+    const NAME: &'static str = "";
+    /// Names of the types of the variants of a derived enum.
+    const VARIANTS: &'static [&'static str] = &[];
+    /// Whether the shader has an accessor for fields of this type, which is the case
+    /// for scalars, vectors and refs, but not for structs.
+    const METAL_ACCESSOR: bool = true;
+
+    /// Write the value at the start of `buf`, which must be at least `SIZE` bytes.
+    fn write(&self, buf: &mut [u8]);
+
+    /// Read the value from the start of `buf`.
+    fn read(buf: &[u8]) -> Self;
+
+    /// Write the value as the body of an enum variant, after the tag. The tag is
+    /// written by the enum.
+    fn write_tagged(&self, buf: &mut [u8]) {
+        self.write(&mut buf[field_offsets(4, [(Self::SIZE, Self::ALIGN)])[0]..]);
+    }
+
+    /// Read the value as the body of an enum variant.
+    fn read_tagged(buf: &[u8]) -> Self {
+        Self::read(&buf[field_offsets(4, [(Self::SIZE, Self::ALIGN)])[0]..])
+    }
+
+    /// The type of a field of this type in Metal.
+    fn metal_type() -> String;
+
+    /// The type of a `Ref` to this type in Metal.
+    fn metal_ref_type() -> String {
+        "uint".into()
+    }
+
+    /// The Metal definition of a derived type, with its readers.
+    fn metal_def(_tagged: bool) -> String {
+        String::new()
+    }
+}
+
+/// An offset in bytes to a value of type `T` in the scene.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Ref<T> {
+    offset: u32,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Ref<T> {
+    pub fn new(offset: u32) -> Ref<T> {
+        Ref {
+            offset,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn offset(self) -> u32 {
+        self.offset
+    }
+}
+
+// These are implemented by hand, as deriving them would require `T` to implement them.
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Ref<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> Default for Ref<T> {
+    fn default() -> Ref<T> {
+        Ref::new(0)
+    }
+}
+
+impl<T: PietMetal> PietMetal for Ref<T> {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+
+    fn write(&self, buf: &mut [u8]) {
+        self.offset.write(buf);
+    }
+
+    fn read(buf: &[u8]) -> Ref<T> {
+        Ref::new(u32::read(buf))
+    }
+
+    fn metal_type() -> String {
+        T::metal_ref_type()
+    }
+}
+
+macro_rules! impl_scalar {
+    ($ty:ty, $metal:expr) => {
+        impl PietMetal for $ty {
+            const ALIGN: usize = std::mem::size_of::<$ty>();
+            const SIZE: usize = std::mem::size_of::<$ty>();
+
+            fn write(&self, buf: &mut [u8]) {
+                buf[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+
+            fn read(buf: &[u8]) -> $ty {
+                let mut bytes = [0; std::mem::size_of::<$ty>()];
+                bytes.copy_from_slice(&buf[..Self::SIZE]);
+                <$ty>::from_le_bytes(bytes)
+            }
+
+            fn metal_type() -> String {
+                $metal.into()
+            }
+        }
+
+        // Arrays of 2 to 4 scalars are Metal vectors, aligned to their size rounded up
+        // to a power of two.
+        impl_vector!($ty, $metal, 2);
+        impl_vector!($ty, $metal, 3);
+        impl_vector!($ty, $metal, 4);
+    };
+}
+
+macro_rules! impl_vector {
+    ($ty:ty, $metal:expr, $n:expr) => {
+        impl PietMetal for [$ty; $n] {
+            const ALIGN: usize = (<$ty>::SIZE * $n).next_power_of_two();
+            const SIZE: usize = Self::ALIGN;
+
+            fn write(&self, buf: &mut [u8]) {
+                for (i, x) in self.iter().enumerate() {
+                    x.write(&mut buf[i * <$ty>::SIZE..]);
+                }
+            }
+
+            fn read(buf: &[u8]) -> [$ty; $n] {
+                let mut result = [Default::default(); $n];
+                for (i, x) in result.iter_mut().enumerate() {
+                    *x = <$ty>::read(&buf[i * <$ty>::SIZE..]);
+                }
+                result
+            }
+
+            fn metal_type() -> String {
+                format!("{}{}", $metal, $n)
+            }
+        }
+    };
+}
+
+impl_scalar!(f32, "float");
+impl_scalar!(i8, "char");
+impl_scalar!(i16, "short");
+impl_scalar!(i32, "int");
+impl_scalar!(u8, "uchar");
+impl_scalar!(u16, "ushort");
+impl_scalar!(u32, "uint");
+
+const fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// The offsets of fields with the given sizes and alignments, starting at `start`.
+#[doc(hidden)]
+pub const fn field_offsets<const N: usize>(
+    start: usize,
+    fields: [(usize, usize); N],
+) -> [usize; N] {
+    let mut offsets = [0; N];
+    let mut offset = start;
+    let mut i = 0;
+    while i < N {
+        offset = align_up(offset, fields[i].1);
+        offsets[i] = offset;
+        offset += fields[i].0;
+        i += 1;
+    }
+    offsets
+}
+
+/// The alignment of a struct with fields of the given sizes and alignments.
+#[doc(hidden)]
+pub const fn struct_align(fields: &[(usize, usize)]) -> usize {
+    let mut align = 1;
+    let mut i = 0;
+    while i < fields.len() {
+        if fields[i].1 > align {
+            align = fields[i].1;
+        }
+        i += 1;
+    }
+    align
+}
+
+/// The size of a struct with fields of the given sizes and alignments, starting at
+/// `start`. A tagged struct is aligned to at least the tag.
+#[doc(hidden)]
+pub const fn struct_size(start: usize, fields: &[(usize, usize)]) -> usize {
+    let mut offset = start;
+    let mut i = 0;
+    while i < fields.len() {
+        offset = align_up(offset, fields[i].1) + fields[i].0;
+        i += 1;
+    }
+    let align = struct_align(fields);
+    align_up(offset, if start > 0 && align < 4 { 4 } else { align })
+}
+
+/// The size of an enum with variants of the given tagged sizes: a tag, and a body of
+/// 32-bit words.
+#[doc(hidden)]
+pub const fn enum_size(variants: &[usize]) -> usize {
+    let mut size = 4;
+    let mut i = 0;
+    while i < variants.len() {
+        if variants[i] > size {
+            size = variants[i];
+        }
+        i += 1;
+    }
+    align_up(size, 4)
+}
+
+/// The Metal definition of a struct: the packed struct, its reader, and accessors
+/// for its fields, given as name, type and whether they have an accessor.
+#[doc(hidden)]
+pub fn metal_struct(name: &str, tagged: bool, fields: &[(&str, String, bool)]) -> String {
+    let mut r = String::new();
+    let rn = format!("{}Ref", name);
+    writeln!(r, "struct {}Packed {{", name).unwrap();
+    if tagged {
+        writeln!(r, "    uint tag;").unwrap();
+    }
+    for (field_name, ty, _) in fields {
+        writeln!(r, "    {} {};", ty, field_name).unwrap();
+    }
+    writeln!(r, "}};").unwrap();
+    writeln!(
+        r,
+        "{}Packed {}_read(const device char *buf, {} ref) {{",
+        name, name, rn
+    )
+    .unwrap();
+    writeln!(
+        r,
+        "    return *((const device {}Packed *)(buf + ref));",
+        name
+    )
+    .unwrap();
+    writeln!(r, "}}").unwrap();
+    for (field_name, ty, accessor) in fields {
+        if *accessor {
+            writeln!(
+                r,
+                "{} {}_{}(const device char *buf, {} ref) {{",
+                ty, name, field_name, rn
+            )
+            .unwrap();
+            writeln!(
+                r,
+                "    return ((const device {}Packed *)(buf + ref))->{};",
+                name, field_name
+            )
+            .unwrap();
+            writeln!(r, "}}").unwrap();
+        }
+    }
+    r
+}
+
+/// The Metal definition of an enum of the given size, with a reader for the tag
+/// and its values for the variants.
+#[doc(hidden)]
+pub fn metal_enum(name: &str, size: usize, variants: &[&str]) -> String {
+    let mut r = String::new();
+    let rn = format!("{}Ref", name);
+    writeln!(r, "struct {} {{", name).unwrap();
+    writeln!(r, "    uint tag;").unwrap();
+    writeln!(r, "    uint body[{}];", size / 4 - 1).unwrap();
+    writeln!(r, "}};").unwrap();
+    writeln!(
+        r,
+        "uint {}_tag(const device char *buf, {} ref) {{",
+        name, rn
+    )
+    .unwrap();
+    writeln!(r, "    return ((const device {} *)(buf + ref))->tag;", name).unwrap();
+    writeln!(r, "}}").unwrap();
+    // Tags are 1-based.
+    for (i, variant) in variants.iter().enumerate() {
+        writeln!(r, "#define {}_{} {}", name, variant, i + 1).unwrap();
+    }
+    r
+}
+
+type MetalDefFn = fn(bool) -> String;
+
+/// The Metal definitions of a set of derived types, in the same form as those
+/// generated by `piet_metal!`: typedefs of their refs, then each definition.
+#[derive(Default)]
+pub struct MetalSource {
+    // The name and `metal_def` of each type.
+    defs: Vec<(&'static str, MetalDefFn)>,
+    variants: HashSet<&'static str>,
+}
+
+impl MetalSource {
+    pub fn new() -> MetalSource {
+        Default::default()
+    }
+
+    /// Add the definition of a type. Structs used in the variants of an enum that
+    /// has been added are tagged.
+    pub fn add<T: PietMetal>(&mut self) -> &mut MetalSource {
+        self.defs.push((T::NAME, T::metal_def));
+        self.variants.extend(T::VARIANTS);
+        self
+    }
+
+    pub fn finish(&self) -> String {
+        let mut r = String::new();
+        for (name, _) in &self.defs {
+            writeln!(r, "typedef uint {}Ref;", name).unwrap();
+        }
+        for (name, metal_def) in &self.defs {
+            r.push_str(&metal_def(self.variants.contains(name)));
+        }
+        r
+    }
+}
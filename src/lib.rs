@@ -1,228 +1,1363 @@
 //  Copyright 2019 The xi-editor authors.
 
-use std::mem;
-use std::ptr::copy_nonoverlapping;
 use std::str::FromStr;
 
-use kurbo::{BezPath, Circle, Line, Point, Rect, Shape, Vec2};
+use kurbo::{
+    Affine, BezPath, Circle, CubicBez, Line, ParamCurve, PathEl, Point, QuadBez, Rect, Shape, Vec2,
+};
 
 use roxmltree::Document;
 
+use piet_metal_derive::piet_metal;
+
+// The code generated by `piet_metal!` and `#[derive(PietMetal)]` refers to the
+// layout module by its path from other crates.
+extern crate self as piet_metal;
+
+pub mod cpu;
+mod dash;
 mod flatten;
+pub mod layout;
 
-// Keep these in sync with PietShaderTypes.h
+use layout::PietMetal;
 
-#[repr(C)]
+use scene::{
+    PietCircle, PietClip, PietFill, PietFillPath, PietGradient, PietGradientStop, PietGroup,
+    PietImage, PietImageFill, PietItem, PietLayer, PietSegment, PietStrokeLine, PietStrokePath,
+    PietStrokePolyLine, SceneHeader, SimpleGroup,
+};
+
+// The layout of the scene. This generates the Rust types below, which implement
+// `PietMetal` to write them to the scene buffer and read them back, and the Metal
+// readers in GenTypes.h (see `gen_metal_scene`). The rest of the definitions in
+// PietShaderTypes.h must be kept in sync by hand.
+piet_metal! {
+    pub mod scene {
+        struct SceneHeader {
+            // The root group.
+            root: Ref<SimpleGroup>,
+            // The image resource table.
+            n_images: u32,
+            images: Ref<[PietImage; n_images]>,
+            // Color the scene is drawn over, which may be transparent.
+            background: u32,
+        }
+        struct SimpleGroup {
+            n_items: u32,
+            items_ix: Ref<[PietItem; n_items]>,
+            // Bboxes of the items, following the group.
+            bboxes: [[i32; 4]; n_items],
+        }
+        struct PietCircle {
+        }
+        struct PietStrokeLine {
+            // Stroke style; joins don't apply to a single line.
+            flags: u32,
+            // Or a gradient or image fill, see PAINT_GRADIENT and PAINT_IMAGE.
+            rgba_color: u32,
+            width: f32,
+            start: [f32; 2],
+            end: [f32; 2],
+        }
+        struct PietFill {
+            // FILL_EVEN_ODD selects the fill rule.
+            flags: u32,
+            rgba_color: u32,
+            n_points: u32,
+            points_ix: Ref<[[f32; 2]; n_points]>,
+        }
+        struct PietStrokePolyLine {
+            rgba_color: u32,
+            width: f32,
+            n_points: u32,
+            points_ix: Ref<[[f32; 2]; n_points]>,
+            // Stroke style, and STROKE_CLOSED.
+            flags: u32,
+            miter_limit: f32,
+        }
+        // A nested group; its bbox in the parent is the union of its items,
+        // transformed.
+        struct PietGroup {
+            group: Ref<SimpleGroup>,
+            // Transform to the parent's coordinates, as kurbo `Affine` coefficients.
+            // The translation comes first, so that `mat` is aligned as a `float4`.
+            translate: [f32; 2],
+            mat: [f32; 4],
+        }
+        // A line, quadratic or cubic Bézier segment.
+        struct PietSegment {
+            seg_type: u32,
+            // SEG_SUBPATH_START, SEG_SUBPATH_END, SEG_CLOSED
+            flags: u32,
+            // Unused points repeat the end point.
+            p0: [f32; 2],
+            p1: [f32; 2],
+            p2: [f32; 2],
+            p3: [f32; 2],
+        }
+        // Fill of a path made of curve segments, flattened by the tiler.
+        struct PietFillPath {
+            // As in PietFill.
+            flags: u32,
+            rgba_color: u32,
+            n_segs: u32,
+            segs_ix: Ref<[PietSegment; n_segs]>,
+        }
+        // Stroke of a path made of curve segments, flattened by the tiler.
+        struct PietStrokePath {
+            // Stroke style.
+            flags: u32,
+            rgba_color: u32,
+            width: f32,
+            n_segs: u32,
+            segs_ix: Ref<[PietSegment; n_segs]>,
+            miter_limit: f32,
+        }
+        // A group clipped to the inside of a path. Its bbox in the parent is the
+        // union of its items, intersected with the bbox of the path.
+        struct PietClip {
+            // As in PietFill.
+            flags: u32,
+            n_segs: u32,
+            segs_ix: Ref<[PietSegment; n_segs]>,
+            group: Ref<SimpleGroup>,
+        }
+        struct PietGradientStop {
+            offset: f32,
+            rgba_color: u32,
+        }
+        // A gradient, referenced by draw items with PAINT_GRADIENT.
+        struct PietGradient {
+            // Transform from the coordinates of the item to those of the gradient,
+            // in which a linear gradient goes from (0, 0) to (1, 0), and a radial
+            // gradient is the unit circle.
+            mat: [f32; 4],
+            translate: [f32; 2],
+            // GRADIENT_RADIAL and the extend mode.
+            flags: u32,
+            n_stops: u32,
+            stops_ix: Ref<[PietGradientStop; n_stops]>,
+        }
+        // An image fill, referenced by draw items with PAINT_IMAGE.
+        struct PietImageFill {
+            // Transform from the coordinates of the item to the pixels of the image.
+            mat: [f32; 4],
+            translate: [f32; 2],
+            // IMAGE_BILINEAR selects the sampling mode.
+            flags: u32,
+            // Index in the image resource table.
+            image_ix: u32,
+        }
+        // An entry in the image resource table. The pixels are RGBA8 in sRGB, with
+        // separate alpha, in rows from the top.
+        struct PietImage {
+            width: u32,
+            height: u32,
+            data_ix: Ref<u32>,
+        }
+        // A group composited on its own, then onto the scene with opacity `alpha`
+        // and a blend mode. Its bbox in the parent is the union of its items.
+        struct PietLayer {
+            alpha: f32,
+            group: Ref<SimpleGroup>,
+            // A `BlendMode`.
+            blend: u32,
+        }
+        enum PietItem {
+            Circle(PietCircle),
+            Line(PietStrokeLine),
+            Fill(PietFill),
+            Poly(PietStrokePolyLine),
+            Group(PietGroup),
+            FillPath(PietFillPath),
+            StrokePath(PietStrokePath),
+            Clip(PietClip),
+            Layer(PietLayer),
+        }
+    }
+}
+
+#[repr(u32)]
 #[derive(Clone, Copy)]
-struct SimpleGroup {
-    n_items: u32,
-    items_ix: u32,
+enum SegType {
+    Line = 1,
+    Quad = 2,
+    Cubic = 3,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Default)]
-struct ShortBbox([u16; 4]);
+// Keep these in sync with PietShaderTypes.h
 
-#[repr(C)]
-union PietItem {
-    circle: PietCircle,
-    stroke_line: PietStrokeLine,
-    fill: PietFill,
+/// How the colors of a layer are combined with those underneath it, as in the
+/// W3C Compositing and Blending spec. The result is then composited source-over.
+///
+/// As in CSS and SVG, the blend function is applied to sRGB-encoded colors. The
+/// result is converted back to linear space for compositing.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Normal = 0,
+    Multiply = 1,
+    Screen = 2,
+    Overlay = 3,
+    Darken = 4,
+    Lighten = 5,
+    ColorDodge = 6,
+    ColorBurn = 7,
+    HardLight = 8,
+    SoftLight = 9,
+    Difference = 10,
+    Exclusion = 11,
+    Hue = 12,
+    Saturation = 13,
+    Color = 14,
+    Luminosity = 15,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-struct PietCircle {
-    item_type: ItemType,
+impl BlendMode {
+    /// The blend mode of a layer in the scene. Unknown modes are normal, as in the
+    /// shader.
+    fn from_u32(blend: u32) -> BlendMode {
+        use BlendMode::*;
+        [
+            Normal, Multiply, Screen, Overlay, Darken, Lighten, ColorDodge, ColorBurn, HardLight,
+            SoftLight, Difference, Exclusion, Hue, Saturation, Color, Luminosity,
+        ]
+        .get(blend as usize)
+        .copied()
+        .unwrap_or(Normal)
+    }
 }
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-struct PietStrokeLine {
-    item_type: ItemType,
-    flags: u32,
-    rgba: u32,
-    width: f32,
-    start: (f32, f32),
-    end: (f32, f32),
+/// The rule for determining the inside of a fill from its winding number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillRule {
+    #[default]
+    NonZero,
+    EvenOdd,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-struct PietFill {
-    item_type: ItemType,
-    flags: u32,
-    rgba: u32,
-    n_points: u32,
-    points_ix: u32,
+impl FillRule {
+    fn flags(self) -> u32 {
+        match self {
+            FillRule::NonZero => 0,
+            FillRule::EvenOdd => FILL_EVEN_ODD,
+        }
+    }
 }
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-struct PietStrokePolyLine {
-    item_type: ItemType,
-    rgba: u32,
-    width: f32,
-    n_points: u32,
-    points_ix: u32,
+/// Flag bit in fill items selecting the even-odd fill rule.
+const FILL_EVEN_ODD: u32 = 1;
+
+/// The shape at the ends of an open stroke.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineCap {
+    Butt,
+    #[default]
+    Round,
+    Square,
 }
 
-#[repr(u32)]
-#[derive(Clone, Copy)]
-enum ItemType {
-    Circle = 1,
-    Line = 2,
-    Fill = 3,
-    StrokePolyLine = 4,
+/// The shape where two lines of a stroke meet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineJoin {
+    Miter,
+    #[default]
+    Round,
+    Bevel,
+}
+
+/// The style of a stroke, other than its width.
+///
+/// The default is round caps and joins, which is also the fastest to render.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StrokeStyle {
+    pub cap: LineCap,
+    pub join: LineJoin,
+    /// Limit on the ratio of miter length to stroke width, beyond which a
+    /// miter join is drawn as a bevel. Same as SVG `stroke-miterlimit`.
+    pub miter_limit: f32,
+    /// Alternating lengths of dashes and gaps, as in SVG `stroke-dasharray`.
+    /// Empty for a solid stroke.
+    ///
+    /// The stroke is also solid if an entry is negative or not finite, if all of
+    /// them are 0, or if the pattern would cut the path into more than 10000
+    /// dashes.
+    pub dash_pattern: Vec<f32>,
+    /// Distance into the dash pattern at which the stroke starts. A value that
+    /// isn't finite is treated as 0.
+    pub dash_offset: f32,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> StrokeStyle {
+        StrokeStyle {
+            cap: Default::default(),
+            join: Default::default(),
+            miter_limit: 4.0,
+            dash_pattern: Vec::new(),
+            dash_offset: 0.0,
+        }
+    }
+}
+
+impl StrokeStyle {
+    fn flags(&self) -> u32 {
+        let cap = match self.cap {
+            LineCap::Round => STROKE_CAP_ROUND,
+            LineCap::Butt => STROKE_CAP_BUTT,
+            LineCap::Square => STROKE_CAP_SQUARE,
+        };
+        let join = match self.join {
+            LineJoin::Round => STROKE_JOIN_ROUND,
+            LineJoin::Miter => STROKE_JOIN_MITER,
+            LineJoin::Bevel => STROKE_JOIN_BEVEL,
+        };
+        cap | join
+    }
+
+    fn from_flags(flags: u32, miter_limit: f32) -> StrokeStyle {
+        let cap = match flags & STROKE_CAP_MASK {
+            STROKE_CAP_BUTT => LineCap::Butt,
+            STROKE_CAP_SQUARE => LineCap::Square,
+            _ => LineCap::Round,
+        };
+        let join = match flags & STROKE_JOIN_MASK {
+            STROKE_JOIN_MITER => LineJoin::Miter,
+            STROKE_JOIN_BEVEL => LineJoin::Bevel,
+            _ => LineJoin::Round,
+        };
+        StrokeStyle {
+            cap,
+            join,
+            miter_limit,
+            ..Default::default()
+        }
+    }
+
+    /// The maximum distance from the path that the stroke can cover.
+    fn reach(&self, width: f32) -> f32 {
+        let mut scale = 1.0f32;
+        if self.join == LineJoin::Miter {
+            scale = scale.max(self.miter_limit);
+        }
+        if self.cap == LineCap::Square {
+            scale = scale.max(std::f32::consts::SQRT_2);
+        }
+        0.5 * width * scale
+    }
+}
+
+/// Flag bit in draw items whose `rgba` field is the offset of a `PietGradient`
+/// rather than a color. It is above the bits of the fill rule and stroke style.
+const PAINT_GRADIENT: u32 = 1 << 8;
+
+/// Flag bit in draw items whose `rgba` field is the offset of a `PietImageFill`.
+const PAINT_IMAGE: u32 = 1 << 9;
+
+/// Image fill flag selecting bilinear rather than nearest-neighbor sampling.
+const IMAGE_BILINEAR: u32 = 1;
+
+/// Gradient flags: the shape, and how it extends past its end stops.
+const GRADIENT_RADIAL: u32 = 1;
+const GRADIENT_EXTEND_MASK: u32 = 3 << 1;
+const GRADIENT_EXTEND_PAD: u32 = 0;
+const GRADIENT_EXTEND_REPEAT: u32 = 1 << 1;
+const GRADIENT_EXTEND_REFLECT: u32 = 2 << 1;
+
+/// Stroke style flags. The default of 0 is round caps and joins.
+const STROKE_CAP_MASK: u32 = 3;
+const STROKE_CAP_ROUND: u32 = 0;
+const STROKE_CAP_BUTT: u32 = 1;
+const STROKE_CAP_SQUARE: u32 = 2;
+const STROKE_JOIN_MASK: u32 = 3 << 2;
+const STROKE_JOIN_ROUND: u32 = 0;
+const STROKE_JOIN_MITER: u32 = 1 << 2;
+const STROKE_JOIN_BEVEL: u32 = 2 << 2;
+/// Set on a polyline whose last point is the same as its first, to join the
+/// ends rather than cap them.
+const STROKE_CLOSED: u32 = 1 << 4;
+
+/// Segment flags, marking the first and last segments of subpaths.
+const SEG_SUBPATH_START: u32 = 1;
+const SEG_SUBPATH_END: u32 = 2;
+/// Set on the first and last segments of a closed subpath, which are joined
+/// rather than capped.
+const SEG_CLOSED: u32 = 4;
+
+/// Tolerance used by the tiler when flattening curve segments, in pixels.
+const FLATTEN_TOLERANCE: f32 = 0.1;
+
+/// Tolerance for flattening curves when dashing, in pixels.
+const DASH_TOLERANCE: f64 = FLATTEN_TOLERANCE as f64;
+
+/// Maximum number of lines a single curve segment is flattened into.
+///
+/// The encoder splits curves that need more at the transform they are encoded
+/// with. A group placed at a larger scale with `place_group` can still exceed it,
+/// and is then flattened more coarsely than `FLATTEN_TOLERANCE`.
+const MAX_FLATTEN_SEGS: usize = 64;
+
+/// Maximum nesting depth of groups; the tiler keeps a fixed-size stack.
+const MAX_GROUP_DEPTH: usize = 8;
+
+/// Size in tiles of a threadgroup of the tiler.
+const TILER_GROUP_WIDTH: u32 = 16;
+const TILER_GROUP_HEIGHT: u32 = 2;
+
+/// The number of bytes in a chunk of the command list of a tile.
+const TILE_CHUNK_SIZE: usize = 512;
+
+/// How an item is colored.
+///
+/// A color is given as 0xRRGGBBAA, and converts to a solid paint, so it can be
+/// passed to the drawing methods of `Encoder` directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Paint {
+    Solid(u32),
+    /// A gradient added to the scene by `Encoder::linear_gradient` or
+    /// `Encoder::radial_gradient`.
+    Gradient(GradientRef),
+    /// An image fill added to the scene by `Encoder::image_fill`.
+    Image(ImageFillRef),
+}
+
+impl From<u32> for Paint {
+    fn from(rgba: u32) -> Paint {
+        Paint::Solid(rgba)
+    }
+}
+
+impl Paint {
+    /// The flags and `rgba` field of a draw item with this paint.
+    fn encode(self) -> (u32, u32) {
+        match self {
+            Paint::Solid(rgba) => (0, rgba.to_be()),
+            Paint::Gradient(gradient) => (PAINT_GRADIENT, gradient.gradient_ix as u32),
+            Paint::Image(fill) => (PAINT_IMAGE, fill.fill_ix as u32),
+        }
+    }
+}
+
+/// A gradient that has been written to the scene, and can be used by any number
+/// of items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GradientRef {
+    gradient_ix: usize,
+}
+
+/// A color stop of a gradient.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GradientStop {
+    /// Position along the gradient, from 0 to 1.
+    pub offset: f32,
+    pub rgba: u32,
+}
+
+/// How a gradient is painted beyond its first and last stops.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Extend {
+    /// Continue with the colors of the end stops.
+    #[default]
+    Pad,
+    /// Repeat the gradient.
+    Repeat,
+    /// Repeat the gradient, reversing every other repetition.
+    Reflect,
+}
+
+impl Extend {
+    fn flags(self) -> u32 {
+        match self {
+            Extend::Pad => GRADIENT_EXTEND_PAD,
+            Extend::Repeat => GRADIENT_EXTEND_REPEAT,
+            Extend::Reflect => GRADIENT_EXTEND_REFLECT,
+        }
+    }
+}
+
+/// A gradient along the line from `start` to `end`.
+///
+/// Coordinates are those of the items that use it, so the gradient is transformed
+/// along with them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearGradient {
+    pub start: Point,
+    pub end: Point,
+    pub stops: Vec<GradientStop>,
+    pub extend: Extend,
+}
+
+/// A gradient from the center of a circle, at offset 0, to its edge, at offset 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RadialGradient {
+    pub center: Point,
+    pub radius: f64,
+    pub stops: Vec<GradientStop>,
+    pub extend: Extend,
+}
+
+/// An image in the resource table of the scene, added by `Encoder::add_image`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageRef {
+    image_ix: usize,
+    width: u32,
+    height: u32,
+}
+
+impl ImageRef {
+    /// The size of the image, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+/// An image fill that has been written to the scene, and can be used by any number
+/// of items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageFillRef {
+    fill_ix: usize,
+}
+
+/// How an image is sampled between the centers of its pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    /// The nearest pixel, for pixel art or images drawn at their own size.
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+/// An encoded scene, ready to be uploaded to the GPU.
+#[derive(Default)]
+pub struct Scene {
+    buf: Vec<u8>,
+}
+
+pub struct Encoder {
+    // Allocation is just a bump, growing the buffer as needed.
+    buf: Vec<u8>,
+    // Stack of currently open groups, innermost last.
+    groups: Vec<GroupBuilder>,
+    // The image resource table, written along with the header.
+    images: Vec<PietImage>,
+    // Written along with the header.
+    background: u32,
+}
+
+/// The contents of a group, accumulated until the group is closed.
+///
+/// The group is written to the scene all at once in `end_group`, so that the
+/// number of items need not be known in advance.
+#[derive(Default)]
+struct GroupBuilder {
+    bboxes: Vec<[i32; 4]>,
+    items: Vec<PietItem>,
+    // Union of the bboxes of the items.
+    bbox: Option<Rect>,
+    // Transform from the coordinates of the group to those of its parent.
+    transform: Affine,
+    kind: GroupKind,
+    // Number of levels of groups nested in this one, through its items.
+    nested_depth: usize,
+}
+
+/// What a group was opened for, which determines the item added to the parent.
+#[derive(Default)]
+enum GroupKind {
+    #[default]
+    Plain,
+    /// Opened by `push_clip_path`.
+    Clip(ClipPath),
+    /// Opened by `begin_layer`, with its opacity and blend mode.
+    Layer(f32, BlendMode),
+}
+
+/// A clip path, encoded when the clip is pushed.
+struct ClipPath {
+    flags: u32,
+    // Zero if the path is empty, clipping out everything.
+    n_segs: usize,
+    segs_ix: usize,
+    bbox: Rect,
 }
 
-pub struct Encoder<'a> {
-    buf: &'a mut [u8],
-    free_space: usize,
-    group_count: usize,
+/// A group that has been written to the scene, and can be placed in other groups.
+#[derive(Clone, Copy, Debug)]
+pub struct GroupRef {
     group_ix: usize,
-    // Start index of currently open group.
-    group_start: usize,
+    // Bbox of the items, in the coordinates of the group.
+    bbox: Rect,
+    // Number of levels of groups it takes up when placed, including itself.
+    depth: usize,
 }
 
-impl ShortBbox {
-    fn from_rect(rect: Rect) -> ShortBbox {
-        ShortBbox([
-            rect.x0.floor().max(0.0).min(65535.0) as u16,
-            rect.y0.floor().max(0.0).min(65535.0) as u16,
-            rect.x1.ceil().max(0.0).min(65535.0) as u16,
-            rect.y1.ceil().max(0.0).min(65535.0) as u16,
-        ])
+// Bbox of an item in the coordinates of its group, rounded out to integers. These
+// can be negative, or beyond the render target, so the tiler can reject items
+// with no clamping.
+fn rect_to_bbox(rect: Rect) -> [i32; 4] {
+    // The casts saturate, which only matters for absurdly large coordinates.
+    [
+        rect.x0.floor() as i32,
+        rect.y0.floor() as i32,
+        rect.x1.ceil() as i32,
+        rect.y1.ceil() as i32,
+    ]
+}
+
+/// Size in bytes of an encoded point.
+const POINT_SIZE: usize = 8;
+
+fn point_to_f32s(point: Point) -> [f32; 2] {
+    [point.x as f32, point.y as f32]
+}
+
+impl Scene {
+    /// The encoded bytes of the scene.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The size of the encoded scene, in bytes.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
     }
 }
 
-fn point_to_f32s(point: Point) -> (f32, f32) {
-    (point.x as f32, point.y as f32)
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
 }
 
-impl<'a> Encoder<'a> {
-    pub fn new(buf: &mut [u8]) -> Encoder {
-        Encoder {
-            buf,
-            free_space: 0,
-            group_count: 0,
-            group_start: 0,
-            group_ix: 0,
+/// The grid of tiles for a render target, passed to the kernels along with the
+/// scene.
+///
+/// It covers the render target, rounded up to whole threadgroups of the tiler, so
+/// that every thread of the tiler has a tile to write to.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileGrid {
+    pub n_tiles_x: u32,
+    pub n_tiles_y: u32,
+}
+
+impl TileGrid {
+    /// The grid for a render target of `width` by `height` pixels.
+    pub fn new(width: u32, height: u32) -> TileGrid {
+        let n_groups_x = width.div_ceil(cpu::TILE_WIDTH as u32 * TILER_GROUP_WIDTH);
+        let n_groups_y = height.div_ceil(cpu::TILE_HEIGHT as u32 * TILER_GROUP_HEIGHT);
+        TileGrid {
+            n_tiles_x: n_groups_x * TILER_GROUP_WIDTH,
+            n_tiles_y: n_groups_y * TILER_GROUP_HEIGHT,
         }
     }
 
+    /// The size in bytes of the buffer holding the command lists of the tiles.
+    ///
+    /// This is a first chunk for each tile, followed by a pool of `n_pool_chunks`
+    /// chunks for the command lists that don't fit in one. When the pool runs out,
+    /// the tiler reports it in `TilePool`, and the host should use a larger pool for
+    /// the next frame.
+    pub fn tile_buf_size(&self, n_pool_chunks: u32) -> usize {
+        let n_tiles = self.n_tiles_x as usize * self.n_tiles_y as usize;
+        (n_tiles + n_pool_chunks as usize) * TILE_CHUNK_SIZE
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        let mut encoder = Encoder {
+            buf: Vec::new(),
+            groups: Vec::new(),
+            images: Vec::new(),
+            background: 0xffff_ffff_u32.to_be(),
+        };
+        encoder.alloc(SceneHeader::SIZE);
+        encoder
+    }
+
     pub fn alloc(&mut self, size: usize) -> usize {
-        let result = self.free_space;
-        self.free_space += size;
+        let result = self.buf.len();
+        self.buf.resize(result + size, 0);
         result
     }
 
-    // It's probably better to do this without unsafety (after all, we're just creating bytes).
-    // Probably the thing to do is write proc macros.
-    pub unsafe fn write_struct<T>(&mut self, ix: usize, s: &T) {
-        let len = mem::size_of::<T>();
-        //println!("writing {} bytes at {}", len, ix);
-        copy_nonoverlapping(
-            s as *const T as *const u8,
-            self.buf[ix..ix + len].as_mut_ptr(),
-            len,
+    /// Set the color the scene is drawn over, as 0xRRGGBBAA. The default is opaque
+    /// white.
+    ///
+    /// With a background that isn't opaque, the rendered image has real alpha: use
+    /// 0 for a transparent image.
+    pub fn set_background(&mut self, rgba: u32) {
+        self.background = rgba.to_be();
+    }
+
+    /// Finish encoding, returning the scene.
+    pub fn finish(self) -> Scene {
+        Scene { buf: self.buf }
+    }
+
+    /// Open a group. Items are added to it until `end_group` is called.
+    ///
+    /// Groups can be nested, up to `MAX_GROUP_DEPTH`.
+    pub fn begin_group(&mut self) {
+        assert!(
+            self.groups.len() < MAX_GROUP_DEPTH,
+            "groups nested too deeply"
         );
+        self.groups.push(Default::default());
     }
 
-    pub fn begin_group(&mut self, n_items: usize) {
-        let item_start = mem::size_of::<SimpleGroup>() + n_items * mem::size_of::<ShortBbox>();
-        let total_size = item_start + n_items * mem::size_of::<PietItem>();
-        self.group_start = self.alloc(total_size);
-        self.group_count = n_items;
-        let group = SimpleGroup {
-            n_items: n_items as u32,
-            items_ix: (self.group_start + item_start) as u32,
+    /// Open a group whose items are transformed by `transform`, on top of the
+    /// transforms of the enclosing groups. It is closed by `end_group`.
+    ///
+    /// The points of the items are encoded untransformed, and the tiler applies the
+    /// transform. Stroke widths are scaled by the square root of its determinant,
+    /// so strokes are only exact under uniform scaling.
+    pub fn begin_transform(&mut self, transform: Affine) {
+        assert!(
+            !self.groups.is_empty(),
+            "the root group can't be transformed"
+        );
+        self.begin_group();
+        self.groups.last_mut().unwrap().transform = transform;
+    }
+
+    /// Close the current group, writing the group header, bbox array and items.
+    ///
+    /// A nested group is added as an item to its parent. Otherwise, the group
+    /// becomes the root of the scene. The returned reference can be used to place
+    /// the same group again with `place_group`; it is `None` if the group is empty.
+    pub fn end_group(&mut self) -> Option<GroupRef> {
+        let (group_ref, transform) = self.close_group();
+        match group_ref {
+            Some(group_ref) if !self.groups.is_empty() => self.place_group(group_ref, transform),
+            _ => (),
+        }
+        group_ref
+    }
+
+    /// Close the current group, which must be nested, without adding it to its
+    /// parent. It is only drawn where it is placed with `place_group`.
+    ///
+    /// The transform it was opened with, if any, is ignored.
+    pub fn end_group_unplaced(&mut self) -> Option<GroupRef> {
+        assert!(self.groups.len() > 1, "the root group can't be unplaced");
+        self.close_group().0
+    }
+
+    /// Pop and write a plain group, returning a reference to it if it isn't empty,
+    /// and its transform.
+    fn close_group(&mut self) -> (Option<GroupRef>, Affine) {
+        let builder = self.groups.pop().expect("end_group without begin_group");
+        assert!(
+            matches!(builder.kind, GroupKind::Plain),
+            "end_group for a clip or layer"
+        );
+        let group_start = self.write_group(&builder);
+        if self.groups.is_empty() {
+            let images_ix = self.alloc(self.images.len() * PietImage::SIZE);
+            let mut header = SceneHeader {
+                root: group_start as u32,
+                background: self.background,
+                ..Default::default()
+            };
+            header.write_images(&mut self.buf, images_ix, &self.images);
+            header.write(&mut self.buf);
+        }
+        // An empty group draws nothing, so it needn't appear in the parent.
+        let group_ref = builder.bbox.map(|bbox| GroupRef {
+            group_ix: group_start,
+            bbox,
+            depth: 1 + builder.nested_depth,
+        });
+        (group_ref, builder.transform)
+    }
+
+    /// Clip the items added until the matching `pop_clip` to a rectangle.
+    pub fn push_clip_rect(&mut self, rect: Rect) {
+        let mut path = BezPath::new();
+        path.move_to((rect.x0, rect.y0));
+        path.line_to((rect.x1, rect.y0));
+        path.line_to((rect.x1, rect.y1));
+        path.line_to((rect.x0, rect.y1));
+        path.close_path();
+        self.push_clip_path(&path, FillRule::NonZero);
+    }
+
+    /// Clip the items added until the matching `pop_clip` to the inside of a path.
+    ///
+    /// The items are collected in a group, so clips count towards the limit on the
+    /// nesting depth of groups.
+    pub fn push_clip_path(&mut self, path: &BezPath, fill_rule: FillRule) {
+        assert!(!self.groups.is_empty(), "clip outside of a group");
+        let clip = match self.encode_segments(path, true) {
+            Some((segs_ix, n_segs, bbox)) => ClipPath {
+                flags: fill_rule.flags(),
+                n_segs,
+                segs_ix,
+                bbox,
+            },
+            None => ClipPath {
+                flags: 0,
+                n_segs: 0,
+                segs_ix: 0,
+                bbox: Rect::ZERO,
+            },
+        };
+        self.begin_group();
+        self.groups.last_mut().unwrap().kind = GroupKind::Clip(clip);
+    }
+
+    /// Close the clip opened by the matching `push_clip_rect` or `push_clip_path`.
+    pub fn pop_clip(&mut self) {
+        let builder = self.groups.pop().expect("pop_clip without push_clip");
+        let clip = match &builder.kind {
+            GroupKind::Clip(clip) => clip,
+            _ => panic!("pop_clip for a group that isn't a clip"),
+        };
+        let bbox = match builder.bbox {
+            Some(bbox) if clip.n_segs > 0 => bbox.intersect(clip.bbox),
+            _ => return,
+        };
+        if bbox.area() == 0.0 {
+            return;
+        }
+        let group_start = self.write_group(&builder);
+        let piet_clip = PietClip {
+            flags: clip.flags,
+            n_segs: clip.n_segs as u32,
+            segs_ix: clip.segs_ix as u32,
+            group: group_start as u32,
+        };
+        self.add_item(PietItem::Clip(piet_clip), bbox);
+        self.add_nested_depth(1 + builder.nested_depth);
+    }
+
+    /// Open a layer, a group whose items are composited on their own, then onto the
+    /// scene with opacity `alpha` and blend mode `blend`. It is closed by `end_layer`.
+    ///
+    /// Unlike setting the alpha of each item, this fades the group uniformly where
+    /// items overlap. Layers count towards the limit on the nesting depth of groups.
+    pub fn begin_layer(&mut self, alpha: f32, blend: BlendMode) {
+        assert!(!self.groups.is_empty(), "layer outside of a group");
+        self.begin_group();
+        self.groups.last_mut().unwrap().kind = GroupKind::Layer(alpha, blend);
+    }
+
+    /// Close the layer opened by the matching `begin_layer`.
+    pub fn end_layer(&mut self) {
+        let builder = self.groups.pop().expect("end_layer without begin_layer");
+        let (alpha, blend) = match builder.kind {
+            GroupKind::Layer(alpha, blend) => (alpha, blend),
+            _ => panic!("end_layer for a group that isn't a layer"),
+        };
+        let bbox = match builder.bbox {
+            Some(bbox) if alpha > 0.0 => bbox,
+            _ => return,
         };
-        unsafe {
-            self.write_struct(self.group_start, &group);
+        let group_start = self.write_group(&builder);
+        if alpha >= 1.0 && blend == BlendMode::Normal {
+            // An opaque layer draws the same as a plain group.
+            let group_ref = GroupRef {
+                group_ix: group_start,
+                bbox,
+                depth: 1 + builder.nested_depth,
+            };
+            self.place_group(group_ref, Affine::default());
+            return;
         }
+        let piet_layer = PietLayer {
+            alpha: alpha.min(1.0),
+            group: group_start as u32,
+            blend: blend as u32,
+        };
+        self.add_item(PietItem::Layer(piet_layer), bbox);
+        self.add_nested_depth(1 + builder.nested_depth);
+    }
+
+    /// Write the group header, bbox array and items, returning the group's index.
+    fn write_group(&mut self, builder: &GroupBuilder) -> usize {
+        let n_items = builder.items.len();
+        let item_start = SimpleGroup::size_with_bboxes(n_items);
+        let group_start = self.alloc(item_start + n_items * PietItem::SIZE);
+        let mut group = SimpleGroup::default();
+        group.write_items_ix(&mut self.buf, group_start + item_start, &builder.items);
+        group.write_with_bboxes(&mut self.buf[group_start..], &builder.bboxes);
+        group_start
+    }
+
+    /// Add a group that has already been encoded to the current group, transformed
+    /// by `transform`. Its items are not encoded again.
+    ///
+    /// Panics if this would nest groups deeper than `MAX_GROUP_DEPTH`, counting the
+    /// groups nested in the placed one.
+    pub fn place_group(&mut self, group: GroupRef, transform: Affine) {
+        assert!(
+            self.groups.len() + group.depth <= MAX_GROUP_DEPTH,
+            "groups nested too deeply"
+        );
+        let c = transform.as_coeffs();
+        let piet_group = PietGroup {
+            group: group.group_ix as u32,
+            mat: [c[0] as f32, c[1] as f32, c[2] as f32, c[3] as f32],
+            translate: [c[4] as f32, c[5] as f32],
+        };
+        let bbox = transform.transform_rect_bbox(group.bbox);
+        self.add_item(PietItem::Group(piet_group), bbox);
+        self.add_nested_depth(group.depth);
+    }
+
+    /// Record that the current group has `depth` levels of groups nested in it.
+    fn add_nested_depth(&mut self, depth: usize) {
+        let group = self.groups.last_mut().unwrap();
+        group.nested_depth = group.nested_depth.max(depth);
     }
 
-    pub fn end_group(&mut self) {
-        assert_eq!(self.group_ix, self.group_count);
-        // This will get more interesting when we have nested groups.
+    /// A tolerance in pixels, converted to the coordinates of the current group.
+    ///
+    /// This divides by the largest scale of the transform, so that the tolerance
+    /// holds in every direction.
+    fn local_tolerance(&self, tolerance: f64) -> f64 {
+        let transform = self
+            .groups
+            .iter()
+            .fold(Affine::default(), |a, group| a * group.transform);
+        let [a, b, c, d, _, _] = transform.as_coeffs();
+        let sum = a * a + b * b + c * c + d * d;
+        let det = a * d - b * c;
+        let scale = (0.5 * (sum + (sum * sum - 4.0 * det * det).max(0.0).sqrt())).sqrt();
+        if scale > 0.0 {
+            tolerance / scale
+        } else {
+            tolerance
+        }
     }
 
-    unsafe fn add_item<T>(&mut self, item: &T, bbox: ShortBbox) {
-        assert!(self.group_ix < self.group_count);
-        let bbox_ix = self.group_start
-            + mem::size_of::<SimpleGroup>()
-            + self.group_ix * mem::size_of::<ShortBbox>();
-        self.write_struct(bbox_ix, &bbox);
-        let item_ix = self.group_start
-            + mem::size_of::<SimpleGroup>()
-            + self.group_count * mem::size_of::<ShortBbox>()
-            + self.group_ix * mem::size_of::<PietItem>();
-        self.write_struct(item_ix, item);
-        self.group_ix += 1;
+    fn add_item(&mut self, item: PietItem, bbox: Rect) {
+        let group = self
+            .groups
+            .last_mut()
+            .expect("item added outside of a group");
+        group.bboxes.push(rect_to_bbox(bbox));
+        group.bbox = Some(match group.bbox {
+            None => bbox,
+            Some(old_bbox) => old_bbox.union(bbox),
+        });
+        group.items.push(item);
     }
 
     // Encode a circle. Currently this has a lot of limitations and is mostly used for debugging
     // and performance analysis, but could be expanded to the real thing.
     pub fn circle(&mut self, circle: &Circle) {
-        let piet_circle = PietCircle {
-            item_type: ItemType::Circle,
+        self.add_item(PietItem::Circle(PietCircle {}), circle.bounding_box());
+    }
+
+    /// Add an image to the resource table of the scene.
+    ///
+    /// The pixels are RGBA8 in sRGB, with separate alpha, in rows from the top. Images
+    /// must be added before the root group is closed, as the table is written then.
+    pub fn add_image(&mut self, width: u32, height: u32, data: &[u8]) -> ImageRef {
+        assert!(width > 0 && height > 0, "empty image");
+        assert_eq!(data.len(), width as usize * height as usize * 4);
+        let data_ix = self.alloc(data.len());
+        self.buf[data_ix..data_ix + data.len()].copy_from_slice(data);
+        let image_ix = self.images.len();
+        self.images.push(PietImage {
+            width,
+            height,
+            data_ix: data_ix as u32,
+        });
+        ImageRef {
+            image_ix,
+            width,
+            height,
+        }
+    }
+
+    /// Add an image fill to the scene, returning a paint for items that use it.
+    ///
+    /// The transform places the image in the coordinates of the items, with the
+    /// image covering the rectangle from the origin to its size. Outside of that,
+    /// the fill continues with the colors of the edge pixels.
+    pub fn image_fill(&mut self, image: ImageRef, transform: Affine, sampling: Sampling) -> Paint {
+        if transform.determinant() == 0.0 {
+            return Paint::Solid(0);
+        }
+        let coeffs = transform.inverse().as_coeffs();
+        let piet_fill = PietImageFill {
+            mat: [
+                coeffs[0] as f32,
+                coeffs[1] as f32,
+                coeffs[2] as f32,
+                coeffs[3] as f32,
+            ],
+            translate: [coeffs[4] as f32, coeffs[5] as f32],
+            flags: match sampling {
+                Sampling::Nearest => 0,
+                Sampling::Bilinear => IMAGE_BILINEAR,
+            },
+            image_ix: image.image_ix as u32,
         };
-        unsafe {
-            self.add_item(&piet_circle, ShortBbox::from_rect(circle.bounding_box()));
+        let fill_ix = self.alloc(PietImageFill::SIZE);
+        piet_fill.write(&mut self.buf[fill_ix..]);
+        Paint::Image(ImageFillRef { fill_ix })
+    }
+
+    /// Draw an image, filling the rectangle it covers under the transform.
+    pub fn draw_image(&mut self, image: ImageRef, transform: Affine, sampling: Sampling) {
+        let paint = self.image_fill(image, transform, sampling);
+        let rect = Rect::new(0.0, 0.0, image.width as f64, image.height as f64);
+        let mut path = BezPath::new();
+        path.move_to(transform * Point::new(rect.x0, rect.y0));
+        path.line_to(transform * Point::new(rect.x1, rect.y0));
+        path.line_to(transform * Point::new(rect.x1, rect.y1));
+        path.line_to(transform * Point::new(rect.x0, rect.y1));
+        path.close_path();
+        self.fill_path(&path, paint, FillRule::NonZero);
+    }
+
+    /// Add a linear gradient to the scene, returning a paint for items that use it.
+    pub fn linear_gradient(&mut self, gradient: &LinearGradient) -> Paint {
+        let d = gradient.end - gradient.start;
+        let len2 = d.hypot2();
+        if len2 == 0.0 {
+            // As in SVG, a gradient of zero length is the color of the last stop.
+            return last_stop_paint(&gradient.stops);
+        }
+        // Rotate and scale the line onto the unit interval of the x axis.
+        let (a, b, c, d) = (d.x / len2, -d.y / len2, d.y / len2, d.x / len2);
+        let p = gradient.start;
+        let transform = Affine::new([a, b, c, d, -(a * p.x + c * p.y), -(b * p.x + d * p.y)]);
+        self.encode_gradient(transform, 0, &gradient.stops, gradient.extend)
+    }
+
+    /// Add a radial gradient to the scene, returning a paint for items that use it.
+    pub fn radial_gradient(&mut self, gradient: &RadialGradient) -> Paint {
+        if gradient.radius <= 0.0 {
+            return last_stop_paint(&gradient.stops);
+        }
+        let transform =
+            Affine::scale(gradient.radius.recip()) * Affine::translate(-gradient.center.to_vec2());
+        self.encode_gradient(transform, GRADIENT_RADIAL, &gradient.stops, gradient.extend)
+    }
+
+    /// Write a gradient and its stops.
+    ///
+    /// Stops are clamped to the unit interval, and as in SVG, a stop before the
+    /// previous one is moved to it.
+    fn encode_gradient(
+        &mut self,
+        transform: Affine,
+        flags: u32,
+        stops: &[GradientStop],
+        extend: Extend,
+    ) -> Paint {
+        if stops.len() < 2 {
+            return last_stop_paint(stops);
         }
+        let stops_ix = self.alloc(stops.len() * PietGradientStop::SIZE);
+        let mut offset = 0.0f32;
+        for (i, stop) in stops.iter().enumerate() {
+            offset = offset.max(stop.offset.min(1.0));
+            let piet_stop = PietGradientStop {
+                offset,
+                rgba_color: stop.rgba.to_be(),
+            };
+            piet_stop.write(&mut self.buf[stops_ix + i * PietGradientStop::SIZE..]);
+        }
+        let coeffs = transform.as_coeffs();
+        let piet_gradient = PietGradient {
+            mat: [
+                coeffs[0] as f32,
+                coeffs[1] as f32,
+                coeffs[2] as f32,
+                coeffs[3] as f32,
+            ],
+            translate: [coeffs[4] as f32, coeffs[5] as f32],
+            flags: flags | extend.flags(),
+            n_stops: stops.len() as u32,
+            stops_ix: stops_ix as u32,
+        };
+        let gradient_ix = self.alloc(PietGradient::SIZE);
+        piet_gradient.write(&mut self.buf[gradient_ix..]);
+        Paint::Gradient(GradientRef { gradient_ix })
     }
 
     // Should these be by reference or move?
-    pub fn stroke_line(&mut self, line: Line, width: f32, rgba: u32) {
+    pub fn stroke_line(
+        &mut self,
+        line: Line,
+        width: f32,
+        paint: impl Into<Paint>,
+        style: &StrokeStyle,
+    ) {
+        let paint = paint.into();
+        if self.stroke_dashed(&[line.p0, line.p1], paint, width, style, false) {
+            return;
+        }
+        let (paint_flags, rgba) = paint.encode();
         let piet_stroke_line = PietStrokeLine {
-            item_type: ItemType::Line,
-            flags: Default::default(),
-            rgba: rgba.to_be(),
+            flags: style.flags() | paint_flags,
+            rgba_color: rgba,
             width,
             start: point_to_f32s(line.p0),
             end: point_to_f32s(line.p1),
         };
         // TODO: do we need to add an additional 0.5?
-        let hw = (width * 0.5) as f64;
-        let bbox = line.bounding_box().inflate(hw, hw);
-        unsafe {
-            self.add_item(&piet_stroke_line, ShortBbox::from_rect(bbox));
-        }
+        let reach = style.reach(width) as f64;
+        let bbox = line.bounding_box().inflate(reach, reach);
+        self.add_item(PietItem::Line(piet_stroke_line), bbox);
     }
 
     // Signature will change, need to deal with subpaths and also want curves.
-    pub fn fill(&mut self, points: &[Point], rgba: u32) {
+    pub fn fill(&mut self, points: &[Point], paint: impl Into<Paint>, fill_rule: FillRule) {
         let (points_ix, bbox) = self.encode_points(points);
+        let (paint_flags, rgba) = paint.into().encode();
         let piet_fill = PietFill {
-            item_type: ItemType::Fill,
-            flags: Default::default(),
-            rgba: rgba.to_be(),
+            flags: fill_rule.flags() | paint_flags,
+            rgba_color: rgba,
             n_points: points.len() as u32,
             points_ix: points_ix as u32,
         };
-        unsafe {
-            self.add_item(&piet_fill, ShortBbox::from_rect(bbox));
-        }
+        self.add_item(PietItem::Fill(piet_fill), bbox);
     }
 
-    pub fn polyline(&mut self, points: &[Point], rgba: u32, width: f32) {
+    pub fn polyline(
+        &mut self,
+        points: &[Point],
+        paint: impl Into<Paint>,
+        width: f32,
+        style: &StrokeStyle,
+    ) {
+        self.encode_polyline(points, paint.into(), width, style, false);
+    }
+
+    /// Stroke a closed polyline, with a line from the last point to the first
+    /// which is joined to both of them.
+    pub fn polygon(
+        &mut self,
+        points: &[Point],
+        paint: impl Into<Paint>,
+        width: f32,
+        style: &StrokeStyle,
+    ) {
+        let closed_points;
+        let points = if points.len() > 1 && points.first() != points.last() {
+            closed_points = [points, &points[..1]].concat();
+            &closed_points
+        } else {
+            points
+        };
+        self.encode_polyline(points, paint.into(), width, style, true);
+    }
+
+    fn encode_polyline(
+        &mut self,
+        points: &[Point],
+        paint: Paint,
+        width: f32,
+        style: &StrokeStyle,
+        closed: bool,
+    ) {
+        if self.stroke_dashed(points, paint, width, style, closed) {
+            return;
+        }
         let (points_ix, bbox) = self.encode_points(points);
+        let (paint_flags, rgba) = paint.encode();
+        let closed_flag = if closed { STROKE_CLOSED } else { 0 };
         let piet_poly = PietStrokePolyLine {
-            item_type: ItemType::StrokePolyLine,
-            rgba: rgba.to_be(),
+            rgba_color: rgba,
             width,
             n_points: points.len() as u32,
             points_ix: points_ix as u32,
+            flags: style.flags() | paint_flags | closed_flag,
+            miter_limit: style.miter_limit,
         };
-        let hw = (width * 0.5) as f64;
-        unsafe {
-            self.add_item(&piet_poly, ShortBbox::from_rect(bbox.inflate(hw, hw)));
+        let reach = style.reach(width) as f64;
+        self.add_item(PietItem::Poly(piet_poly), bbox.inflate(reach, reach));
+    }
+
+    /// Fill a path. Curves are encoded as curves, and flattened by the tiler.
+    pub fn fill_path(&mut self, path: &BezPath, paint: impl Into<Paint>, fill_rule: FillRule) {
+        if let Some((segs_ix, n_segs, bbox)) = self.encode_segments(path, true) {
+            let (paint_flags, rgba) = paint.into().encode();
+            let piet_fill = PietFillPath {
+                flags: fill_rule.flags() | paint_flags,
+                rgba_color: rgba,
+                n_segs: n_segs as u32,
+                segs_ix: segs_ix as u32,
+            };
+            self.add_item(PietItem::FillPath(piet_fill), bbox);
+        }
+    }
+
+    /// Stroke a path. Curves are encoded as curves, and flattened by the tiler.
+    pub fn stroke_path(
+        &mut self,
+        path: &BezPath,
+        paint: impl Into<Paint>,
+        width: f32,
+        style: &StrokeStyle,
+    ) {
+        let paint = paint.into();
+        match dash::dash_path(
+            path,
+            &style.dash_pattern,
+            style.dash_offset,
+            self.local_tolerance(DASH_TOLERANCE),
+        ) {
+            Some(dashed) => self.encode_stroke_path(&dashed, paint, width, style),
+            None => self.encode_stroke_path(path, paint, width, style),
         }
     }
 
+    /// Stroke a polyline if the style is dashed, encoding the dashes as a stroke path.
+    ///
+    /// Returns false if the style is solid.
+    fn stroke_dashed(
+        &mut self,
+        points: &[Point],
+        paint: Paint,
+        width: f32,
+        style: &StrokeStyle,
+        closed: bool,
+    ) -> bool {
+        if style.dash_pattern.is_empty() || points.is_empty() {
+            return false;
+        }
+        let mut path = BezPath::new();
+        path.move_to(points[0]);
+        for &p in &points[1..] {
+            path.line_to(p);
+        }
+        if closed {
+            path.close_path();
+        }
+        match dash::dash_path(
+            &path,
+            &style.dash_pattern,
+            style.dash_offset,
+            self.local_tolerance(DASH_TOLERANCE),
+        ) {
+            Some(dashed) => {
+                self.encode_stroke_path(&dashed, paint, width, style);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn encode_stroke_path(
+        &mut self,
+        path: &BezPath,
+        paint: Paint,
+        width: f32,
+        style: &StrokeStyle,
+    ) {
+        if let Some((segs_ix, n_segs, bbox)) = self.encode_segments(path, false) {
+            let (paint_flags, rgba) = paint.encode();
+            let piet_stroke = PietStrokePath {
+                flags: style.flags() | paint_flags,
+                rgba_color: rgba,
+                width,
+                n_segs: n_segs as u32,
+                segs_ix: segs_ix as u32,
+                miter_limit: style.miter_limit,
+            };
+            let reach = style.reach(width) as f64;
+            self.add_item(
+                PietItem::StrokePath(piet_stroke),
+                bbox.inflate(reach, reach),
+            );
+        }
+    }
+
+    /// Encode the segments of a path, returning their index, count and bbox.
+    ///
+    /// If `close` is set, open subpaths are closed with a line, as needed for fills.
+    /// Segments of zero length are dropped, so that each has a direction at its ends.
+    /// Curves are split where the tiler would need more than `MAX_FLATTEN_SEGS` lines
+    /// for them at the current transform. Returns `None` if the path has no segments.
+    fn encode_segments(&mut self, path: &BezPath, close: bool) -> Option<(usize, usize, Rect)> {
+        let tolerance = self.local_tolerance(FLATTEN_TOLERANCE as f64);
+        let mut segs = Vec::new();
+        // Index in `segs` of the first segment of the current subpath.
+        let mut subpath_ix = 0;
+        let mut start = Point::default();
+        let mut last = Point::default();
+        for el in path.elements() {
+            match *el {
+                PathEl::MoveTo(p) => {
+                    if close && last != start {
+                        segs.push((SegType::Line, [last, start, start, start], 0));
+                    }
+                    mark_subpath(&mut segs[subpath_ix..], close);
+                    subpath_ix = segs.len();
+                    start = p;
+                    last = p;
+                }
+                PathEl::LineTo(p) => {
+                    if p != last {
+                        segs.push((SegType::Line, [last, p, p, p], 0));
+                    }
+                    last = p;
+                }
+                PathEl::QuadTo(p1, p2) => {
+                    if p1 != last || p2 != last {
+                        push_curve(&mut segs, SegType::Quad, [last, p1, p2, p2], tolerance);
+                    }
+                    last = p2;
+                }
+                PathEl::CurveTo(p1, p2, p3) => {
+                    if p1 != last || p2 != last || p3 != last {
+                        push_curve(&mut segs, SegType::Cubic, [last, p1, p2, p3], tolerance);
+                    }
+                    last = p3;
+                }
+                PathEl::ClosePath => {
+                    if last != start {
+                        segs.push((SegType::Line, [last, start, start, start], 0));
+                    }
+                    mark_subpath(&mut segs[subpath_ix..], true);
+                    subpath_ix = segs.len();
+                    last = start;
+                }
+            }
+        }
+        if close && last != start {
+            segs.push((SegType::Line, [last, start, start, start], 0));
+        }
+        mark_subpath(&mut segs[subpath_ix..], close);
+        let segs_ix = self.alloc(segs.len() * PietSegment::SIZE);
+        let mut bbox: Option<Rect> = None;
+        for (i, (seg_type, pts, flags)) in segs.iter().enumerate() {
+            for &pt in pts {
+                // The bbox of the control points contains the curve.
+                bbox = Some(match bbox {
+                    None => Rect::from_points(pt, pt),
+                    Some(old_bbox) => old_bbox.union_pt(pt),
+                });
+            }
+            let piet_seg = PietSegment {
+                seg_type: *seg_type as u32,
+                flags: *flags,
+                p0: point_to_f32s(pts[0]),
+                p1: point_to_f32s(pts[1]),
+                p2: point_to_f32s(pts[2]),
+                p3: point_to_f32s(pts[3]),
+            };
+            piet_seg.write(&mut self.buf[segs_ix + i * PietSegment::SIZE..]);
+        }
+        bbox.map(|bbox| (segs_ix, segs.len(), bbox))
+    }
+
     pub fn encode_points(&mut self, points: &[Point]) -> (usize, Rect) {
-        let points_ix = self.alloc(points.len() * mem::size_of::<(f32, f32)>());
+        let points_ix = self.alloc(points.len() * POINT_SIZE);
         let mut dst = points_ix;
         let mut bbox = None;
         for &pt in points {
@@ -230,9 +1365,9 @@ impl<'a> Encoder<'a> {
                 None => Some(Rect::from_points(pt, pt)),
                 Some(old_bbox) => Some(old_bbox.union_pt(pt)),
             };
-            unsafe {
-                self.write_struct(dst, &point_to_f32s(pt));
-                dst += mem::size_of::<(f32, f32)>();
+            for x in &point_to_f32s(pt) {
+                self.buf[dst..dst + 4].copy_from_slice(&x.to_le_bytes());
+                dst += 4;
             }
         }
         let bbox = bbox.expect("encoded empty points vector");
@@ -241,37 +1376,97 @@ impl<'a> Encoder<'a> {
 
     #[allow(unused)]
     fn debug_print(&self) {
-        unsafe {
-            for i in (0..self.free_space).step_by(4) {
-                println!(
-                    "{:04x}: {:08x}",
-                    i,
-                    std::ptr::read((self.buf.as_ptr().add(i) as *const u32))
-                );
-            }
+        for (i, word) in self.buf.chunks_exact(4).enumerate() {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            println!("{:04x}: {:08x}", i * 4, word);
         }
     }
 }
 
+/// The paint of a gradient with too few stops, or of zero size: the color of
+/// the last stop, or transparent if there are none.
+fn last_stop_paint(stops: &[GradientStop]) -> Paint {
+    Paint::Solid(stops.last().map(|stop| stop.rgba).unwrap_or(0))
+}
+
+/// Set the flags on the segments of a subpath, which may be empty.
+fn mark_subpath(segs: &mut [(SegType, [Point; 4], u32)], closed: bool) {
+    let closed_flag = if closed { SEG_CLOSED } else { 0 };
+    if let Some(first) = segs.first_mut() {
+        first.2 |= SEG_SUBPATH_START | closed_flag;
+    }
+    if let Some(last) = segs.last_mut() {
+        last.2 |= SEG_SUBPATH_END | closed_flag;
+    }
+}
+
+/// Add a quad or cubic segment, split into pieces that the tiler flattens into at
+/// most `MAX_FLATTEN_SEGS` lines each at `tolerance`, by Wang's formula as in the
+/// tiler.
+///
+/// The number of pieces is itself capped at `MAX_FLATTEN_SEGS`, so that absurd
+/// coordinates can't blow up the scene; past that, flattening is coarser than
+/// the tolerance.
+fn push_curve(
+    segs: &mut Vec<(SegType, [Point; 4], u32)>,
+    seg_type: SegType,
+    pts: [Point; 4],
+    tolerance: f64,
+) {
+    let dd = |a: Point, b: Point, c: Point| (a.to_vec2() - 2.0 * b.to_vec2() + c.to_vec2()).hypot();
+    let m = match seg_type {
+        SegType::Cubic => 0.75 * dd(pts[0], pts[1], pts[2]).max(dd(pts[1], pts[2], pts[3])),
+        _ => 0.25 * dd(pts[0], pts[1], pts[2]),
+    };
+    // A piece covering 1/k of the range of t needs 1/k of the lines.
+    let n = (m / tolerance).sqrt();
+    let k = (n / MAX_FLATTEN_SEGS as f64)
+        .ceil()
+        .clamp(1.0, MAX_FLATTEN_SEGS as f64) as usize;
+    if k == 1 {
+        segs.push((seg_type, pts, 0));
+        return;
+    }
+    let mut start = pts[0];
+    for i in 0..k {
+        let t0 = i as f64 / k as f64;
+        let t1 = (i + 1) as f64 / k as f64;
+        // Keep the pieces connected, and the end point exact.
+        let end = |p: Point| if i + 1 == k { pts[3] } else { p };
+        let piece = match seg_type {
+            SegType::Cubic => {
+                let c = CubicBez::new(pts[0], pts[1], pts[2], pts[3]).subsegment(t0..t1);
+                [start, c.p1, c.p2, end(c.p3)]
+            }
+            _ => {
+                let q = QuadBez::new(pts[0], pts[1], pts[2]).subsegment(t0..t1);
+                [start, q.p1, end(q.p2), end(q.p2)]
+            }
+        };
+        segs.push((seg_type, piece, 0));
+        start = piece[3];
+    }
+}
+
 #[allow(unused)]
 fn make_cardioid(encoder: &mut Encoder) {
     let n = 97;
     let dth = std::f64::consts::PI * 2.0 / (n as f64);
     let center = Point::new(1024.0, 768.0);
     let r = 750.0;
-    encoder.begin_group((n - 1) * 2);
+    encoder.begin_group();
     for i in 1..n {
         let p0 = center + Vec2::from_angle(i as f64 * dth) * r;
         let p1 = center + Vec2::from_angle(((i * 2) % n) as f64 * dth) * r;
         encoder.circle(&Circle::new(p0, 8.0));
-        encoder.stroke_line(Line::new(p0, p1), 2.0, 0x000080e0);
+        encoder.stroke_line(Line::new(p0, p1), 2.0, 0x000080e0, &Default::default());
     }
     encoder.end_group();
 }
 
 #[allow(unused)]
 fn make_path_test(encoder: &mut Encoder) {
-    encoder.begin_group(1);
+    encoder.begin_group();
     encoder.fill(
         &[
             Point::new(10.0, 10.0),
@@ -279,6 +1474,7 @@ fn make_path_test(encoder: &mut Encoder) {
             Point::new(300.0, 500.0),
         ],
         0x80e0,
+        FillRule::NonZero,
     );
     encoder.end_group();
 }
@@ -289,60 +1485,65 @@ fn make_tiger(encoder: &mut Encoder) {
     let doc = Document::parse(std::str::from_utf8(tiger_svg).unwrap()).unwrap();
     let root = doc.root_element();
     let g = root.first_element_child().unwrap();
-    let mut n_items = 0;
-    for path in g.children() {
-        if path.is_element() {
-            let d = path.attribute("d").unwrap();
-            if let Ok(ref bp) = BezPath::from_svg(d) {
-                let xform_path = kurbo::Affine::scale(scale) * bp;
-                if path.attribute("fill").is_some() {
-                    n_items += count_fill_items(&xform_path);
-                }
-                if path.attribute("stroke").is_some() {
-                    n_items += count_stroke_items(&xform_path);
-                }
-            }
-        }
-    }
-    println!("{} items", n_items);
-    encoder.begin_group(n_items);
+    encoder.begin_group();
+    // The paths are encoded in SVG coordinates, and scaled by the tiler.
+    encoder.begin_transform(Affine::scale(scale));
     for path in g.children() {
         if path.is_element() {
             let d = path.attribute("d").unwrap();
             let bez_path = BezPath::from_svg(d);
             if let Ok(ref bp) = bez_path {
-                let xform_path = kurbo::Affine::scale(scale) * bp;
                 if let Some(fill_color) = path.attribute("fill") {
-                    encode_path(encoder, &xform_path, parse_color(fill_color));
+                    let color = parse_color(fill_color);
+                    let fill_rule = match path.attribute("fill-rule") {
+                        Some("evenodd") => FillRule::EvenOdd,
+                        _ => FillRule::NonZero,
+                    };
+                    if ENCODE_CURVES {
+                        encoder.fill_path(bp, color, fill_rule);
+                    } else {
+                        encode_path(encoder, bp, color, fill_rule, TOLERANCE / scale);
+                    }
                 }
                 if let Some(stroke_color) = path.attribute("stroke") {
                     let width = f32::from_str(path.attribute("stroke-width").unwrap()).unwrap();
-                    let width = width * (scale as f32);
                     let color = parse_color(stroke_color);
-                    encode_path_stroke(encoder, &xform_path, width, color);
+                    let style = parse_stroke_style(path);
+                    // Thin lines are fudged according to their width in pixels.
+                    let (width, color) = thin_line_fudge(width * scale as f32, color);
+                    let width = width / scale as f32;
+                    if ENCODE_CURVES {
+                        encoder.stroke_path(bp, color, width, &style);
+                    } else {
+                        let tolerance = TOLERANCE / scale;
+                        encode_path_stroke(encoder, bp, width, color, &style, tolerance);
+                    }
                 }
             }
         }
     }
     encoder.end_group();
+    encoder.end_group();
 }
 
-const TOLERANCE: f64 = 0.1;
-
-fn count_fill_items(bezpath: &BezPath) -> usize {
-    let flattened = flatten::flatten_path(bezpath, TOLERANCE);
-    flattened.len()
-}
+// If false, paths are flattened before encoding rather than by the tiler.
+const ENCODE_CURVES: bool = true;
 
-fn count_stroke_items(bezpath: &BezPath) -> usize {
-    let flattened = flatten::flatten_path(bezpath, TOLERANCE);
-    flattened.len()
-}
+// Flattening tolerance in pixels, used when paths are flattened before encoding.
+const TOLERANCE: f64 = 0.1;
 
-fn encode_path(encoder: &mut Encoder, bezpath: &BezPath, rgba: u32) {
-    let flattened = flatten::flatten_path(bezpath, TOLERANCE);
+// Note: each subpath is filled separately, so the fill rule only applies within
+// a subpath. Use `Encoder::fill_path` for correct results with multiple subpaths.
+fn encode_path(
+    encoder: &mut Encoder,
+    bezpath: &BezPath,
+    rgba: u32,
+    fill_rule: FillRule,
+    tolerance: f64,
+) {
+    let flattened = flatten::flatten_path(bezpath, tolerance);
     for subpath in &flattened {
-        encoder.fill(subpath, rgba);
+        encoder.fill(&subpath.points, rgba, fill_rule);
     }
 }
 
@@ -350,19 +1551,34 @@ fn encode_path(encoder: &mut Encoder, bezpath: &BezPath, rgba: u32) {
 // optimum rendering of very thin strokes is likely an area for further research.
 const THIN_LINE: f32 = 0.7;
 
-fn encode_path_stroke(encoder: &mut Encoder, bezpath: &BezPath, mut width: f32, mut rgba: u32) {
-    // Fudge very thin lines to get better distance field rendering.
+// Fudge very thin lines to get better distance field rendering.
+fn thin_line_fudge(width: f32, rgba: u32) -> (f32, u32) {
     if width < THIN_LINE {
         let alpha = (rgba & 0xff) as f32;
         // The sqrt here is to compensate for "correct" alpha blending.
         // We probably want a more systematic approach to stroke thickening.
         let alpha = alpha * (width / THIN_LINE).sqrt();
-        rgba = (rgba & !0xff) | (alpha as u32);
-        width = THIN_LINE;
+        (THIN_LINE, (rgba & !0xff) | (alpha as u32))
+    } else {
+        (width, rgba)
     }
-    let flattened = flatten::flatten_path(bezpath, TOLERANCE);
+}
+
+fn encode_path_stroke(
+    encoder: &mut Encoder,
+    bezpath: &BezPath,
+    width: f32,
+    rgba: u32,
+    style: &StrokeStyle,
+    tolerance: f64,
+) {
+    let flattened = flatten::flatten_path(bezpath, tolerance);
     for subpath in &flattened {
-        encoder.polyline(subpath, rgba, width);
+        if subpath.closed {
+            encoder.polygon(&subpath.points, rgba, width, style);
+        } else {
+            encoder.polyline(&subpath.points, rgba, width, style);
+        }
     }
 }
 
@@ -372,6 +1588,47 @@ fn make_test_scene(encoder: &mut Encoder) {
     make_tiger(encoder);
 }
 
+// Note: when attributes are missing, this uses our default of round caps and
+// joins rather than the SVG default of butt caps and miter joins.
+fn parse_stroke_style(node: roxmltree::Node) -> StrokeStyle {
+    let mut style = StrokeStyle::default();
+    match node.attribute("stroke-linecap") {
+        Some("butt") => style.cap = LineCap::Butt,
+        Some("square") => style.cap = LineCap::Square,
+        _ => (),
+    }
+    match node.attribute("stroke-linejoin") {
+        Some("miter") => style.join = LineJoin::Miter,
+        Some("bevel") => style.join = LineJoin::Bevel,
+        _ => (),
+    }
+    if let Some(limit) = node.attribute("stroke-miterlimit") {
+        if let Ok(limit) = f32::from_str(limit) {
+            style.miter_limit = limit.max(1.0);
+        }
+    }
+    if let Some(dashes) = node.attribute("stroke-dasharray") {
+        if dashes != "none" {
+            let pattern = dashes
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty())
+                .map(f32::from_str)
+                .collect::<Result<Vec<_>, _>>();
+            if let Ok(pattern) = pattern {
+                style.dash_pattern = pattern;
+            }
+        }
+    }
+    if let Some(offset) = node.attribute("stroke-dashoffset") {
+        if let Ok(offset) = f32::from_str(offset) {
+            if offset.is_finite() {
+                style.dash_offset = offset;
+            }
+        }
+    }
+    style
+}
+
 fn parse_color(color: &str) -> u32 {
     if color.as_bytes()[0] == b'#' {
         let mut hex = u32::from_str_radix(&color[1..], 16).unwrap();
@@ -384,10 +1641,364 @@ fn parse_color(color: &str) -> u32 {
     }
 }
 
+/// The tile grid for a render target of `width` by `height` pixels.
+#[no_mangle]
+pub extern "C" fn tile_grid(width: u32, height: u32) -> TileGrid {
+    TileGrid::new(width, height)
+}
+
+/// The size in bytes of the tile buffer for `grid`, with a pool of `n_pool_chunks`
+/// chunks for long command lists.
+#[no_mangle]
+pub extern "C" fn tile_buf_size(grid: TileGrid, n_pool_chunks: u32) -> usize {
+    grid.tile_buf_size(n_pool_chunks)
+}
+
+/// Encode the test scene into `scene_buf`, returning the number of bytes it needs.
+///
+/// If that is more than `buf_size`, nothing is written, and the caller should retry
+/// with a larger buffer.
+///
+/// # Safety
+///
+/// `scene_buf` must point to a writable buffer of at least `buf_size` bytes.
 #[no_mangle]
-pub unsafe extern "C" fn init_test_scene(scene_buf: *mut u8, buf_size: usize) {
-    let buf_slice = std::slice::from_raw_parts_mut(scene_buf, buf_size);
-    let mut encoder = Encoder::new(buf_slice);
+pub unsafe extern "C" fn init_test_scene(scene_buf: *mut u8, buf_size: usize) -> usize {
+    let mut encoder = Encoder::new();
     make_test_scene(&mut encoder);
     //encoder.debug_print();
+    let scene = encoder.finish();
+    if scene.len() <= buf_size {
+        std::ptr::copy_nonoverlapping(scene.as_bytes().as_ptr(), scene_buf, scene.len());
+    }
+    scene.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke_style(attrs: &str) -> StrokeStyle {
+        let svg = format!("<path {}/>", attrs);
+        let doc = Document::parse(&svg).unwrap();
+        parse_stroke_style(doc.root_element())
+    }
+
+    /// The points of a polygon with `n` vertices around `center`.
+    fn polygon_points(n: usize, center: Point) -> Vec<Point> {
+        (0..n)
+            .map(|i| {
+                center + Vec2::from_angle(i as f64 * 2.0 * std::f64::consts::PI / n as f64) * 5.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn group_round_trip() {
+        for &n_items in &[0, 1, 3] {
+            let mut encoder = Encoder::new();
+            encoder.begin_group();
+            let polygons: Vec<_> = (0..n_items)
+                .map(|i| polygon_points(i + 3, Point::new(20.0 * i as f64, -10.0)))
+                .collect();
+            for points in &polygons {
+                encoder.fill(points, 0xff00_00ff, FillRule::NonZero);
+            }
+            encoder.end_group();
+            let scene = encoder.finish();
+            let scene = scene.as_bytes();
+            let root = SceneHeader::read(scene).root as usize;
+            let group = SimpleGroup::read(&scene[root..]);
+            assert_eq!(group.n_items as usize, n_items);
+            // The bboxes follow the group, and the items follow the bboxes.
+            let bboxes_start = SimpleGroup::size_with_bboxes(0);
+            let items_start = root + SimpleGroup::size_with_bboxes(n_items);
+            assert_eq!(group.items_ix as usize, items_start);
+            for (i, points) in polygons.iter().enumerate() {
+                let rect = points[1..]
+                    .iter()
+                    .fold(Rect::from_points(points[0], points[0]), |r, &p| {
+                        r.union_pt(p)
+                    });
+                let bbox = group.bboxes_at(&scene[root..], i);
+                assert_eq!(bbox, rect_to_bbox(rect));
+                let bbox_ix = root + bboxes_start + i * <[i32; 4]>::SIZE;
+                assert_eq!(bbox, <[i32; 4]>::read(&scene[bbox_ix..]));
+                let item = group.items_ix_at(scene, i);
+                assert_eq!(
+                    item,
+                    PietItem::read(&scene[items_start + i * PietItem::SIZE..])
+                );
+                match item {
+                    PietItem::Fill(fill) => assert_eq!(fill.n_points as usize, points.len()),
+                    _ => panic!("expected a fill, got {:?}", item),
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "bboxes index out of bounds")]
+    fn group_bboxes_bounds_checked() {
+        let mut buf = vec![0; SimpleGroup::size_with_bboxes(1)];
+        let mut group = SimpleGroup::default();
+        group.write_with_bboxes(&mut buf, &[[0, 0, 1, 1]]);
+        group.bboxes_at(&buf, 1);
+    }
+
+    #[test]
+    fn scene_types_round_trip() {
+        let group = PietGroup {
+            group: 64,
+            translate: [1.0, 2.0],
+            mat: [3.0, 4.0, 5.0, 6.0],
+        };
+        // As in Metal, `translate` is aligned as a float2, and `mat` as a float4.
+        assert_eq!(PietGroup::SIZE, 32);
+        let mut buf = vec![0xff; PietGroup::SIZE];
+        group.write(&mut buf);
+        assert_eq!(buf[0..4], 64u32.to_le_bytes());
+        assert_eq!(buf[8..12], 1.0f32.to_le_bytes());
+        assert_eq!(buf[16..20], 3.0f32.to_le_bytes());
+        assert_eq!(PietGroup::read(&buf), group);
+
+        // Enums start with a 1-based tag.
+        let mut buf = vec![0; PietItem::SIZE];
+        PietItem::Group(group).write(&mut buf);
+        assert_eq!(buf[0..4], 5u32.to_le_bytes());
+        assert_eq!(PietGroup::read_tagged(&buf), group);
+        assert_eq!(PietItem::try_read(&buf), Some(PietItem::Group(group)));
+        PietItem::Circle(PietCircle {}).write(&mut buf);
+        assert_eq!(
+            PietItem::try_read(&buf),
+            Some(PietItem::Circle(PietCircle {}))
+        );
+        buf[0..4].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(PietItem::try_read(&buf), None);
+    }
+
+    #[test]
+    fn dash_offset_must_be_finite() {
+        assert_eq!(stroke_style(r#"stroke-dashoffset="2.5""#).dash_offset, 2.5);
+        for offset in &["NaN", "inf", "-inf"] {
+            let style = stroke_style(&format!(r#"stroke-dashoffset="{}""#, offset));
+            assert_eq!(style.dash_offset, 0.0);
+        }
+    }
+
+    #[test]
+    fn degenerate_gradients() {
+        let red = GradientStop {
+            offset: 0.0,
+            rgba: 0xff00_00ff,
+        };
+        let blue = GradientStop {
+            offset: 1.0,
+            rgba: 0x0000_ffff,
+        };
+        let mut encoder = Encoder::new();
+        let linear = |start, end, stops: &[GradientStop]| LinearGradient {
+            start,
+            end,
+            stops: stops.to_vec(),
+            extend: Extend::Pad,
+        };
+        let (p0, p1) = (Point::new(1.0, 2.0), Point::new(5.0, 2.0));
+        assert_eq!(
+            encoder.linear_gradient(&linear(p0, p0, &[red, blue])),
+            Paint::Solid(blue.rgba)
+        );
+        assert_eq!(
+            encoder.linear_gradient(&linear(p0, p1, &[red])),
+            Paint::Solid(red.rgba)
+        );
+        assert_eq!(
+            encoder.linear_gradient(&linear(p0, p1, &[])),
+            Paint::Solid(0)
+        );
+        let radial = RadialGradient {
+            center: p0,
+            radius: 0.0,
+            stops: vec![red, blue],
+            extend: Extend::Repeat,
+        };
+        assert_eq!(encoder.radial_gradient(&radial), Paint::Solid(blue.rgba));
+        // Nothing is written for any of them.
+        assert_eq!(encoder.finish().len(), Encoder::new().finish().len());
+        assert!(matches!(
+            Encoder::new().linear_gradient(&linear(p0, p1, &[red, blue])),
+            Paint::Gradient(_)
+        ));
+    }
+
+    #[test]
+    fn tile_grid() {
+        let grid = |n_tiles_x, n_tiles_y| TileGrid {
+            n_tiles_x,
+            n_tiles_y,
+        };
+        // Tiler threadgroups cover 256 by 32 pixels.
+        assert_eq!(TileGrid::new(0, 0), grid(0, 0));
+        assert_eq!(TileGrid::new(1, 1), grid(16, 2));
+        assert_eq!(TileGrid::new(256, 32), grid(16, 2));
+        assert_eq!(TileGrid::new(257, 33), grid(32, 4));
+        assert_eq!(TileGrid::new(1024, 768), grid(64, 48));
+
+        let grid = TileGrid::new(257, 33);
+        assert_eq!(grid.tile_buf_size(0), 128 * 512);
+        assert_eq!(grid.tile_buf_size(10), 138 * 512);
+        assert_eq!(TileGrid::new(0, 0).tile_buf_size(3), 3 * 512);
+    }
+
+    #[test]
+    fn init_test_scene_reports_size() {
+        let mut encoder = Encoder::new();
+        make_test_scene(&mut encoder);
+        let expected = encoder.finish();
+        // Too small: nothing is written.
+        let mut buf = vec![0xaa; expected.len() - 1];
+        let size = unsafe { init_test_scene(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(size, expected.len());
+        assert!(buf.iter().all(|&b| b == 0xaa));
+        // Exactly the right size.
+        let mut buf = vec![0xaa; size];
+        assert_eq!(
+            unsafe { init_test_scene(buf.as_mut_ptr(), buf.len()) },
+            size
+        );
+        assert_eq!(buf, expected.as_bytes());
+    }
+
+    #[test]
+    fn scene_grows() {
+        let mut encoder = Encoder::new();
+        let initial_capacity = encoder.buf.capacity();
+        encoder.begin_group();
+        let polygons: Vec<_> = (0..2000)
+            .map(|i| polygon_points(64, Point::new(i as f64, 0.5 * i as f64)))
+            .collect();
+        for points in &polygons {
+            encoder.fill(points, 0xff00_00ff, FillRule::NonZero);
+        }
+        encoder.end_group();
+        let scene = encoder.finish();
+        let scene = scene.as_bytes();
+        assert!(scene.len() > 1 << 20 && scene.len() > 4 * initial_capacity);
+        let root = SceneHeader::read(scene).root as usize;
+        let group = SimpleGroup::read(&scene[root..]);
+        assert_eq!(group.n_items as usize, polygons.len());
+        for (i, points) in polygons.iter().enumerate() {
+            let fill = match group.items_ix_at(scene, i) {
+                PietItem::Fill(fill) => fill,
+                item => panic!("expected a fill, got {:?}", item),
+            };
+            assert_eq!(fill.n_points as usize, points.len());
+            for (j, &p) in points.iter().enumerate() {
+                let ix = fill.points_ix as usize + j * POINT_SIZE;
+                assert_eq!(<[f32; 2]>::read(&scene[ix..]), point_to_f32s(p));
+            }
+        }
+    }
+
+    /// The segments of the path of the last item in the root group, descending into
+    /// nested groups.
+    fn encoded_segments(scene: &Scene) -> Vec<PietSegment> {
+        let scene = scene.as_bytes();
+        let mut group_ix = SceneHeader::read(scene).root as usize;
+        let (segs_ix, n_segs) = loop {
+            let group = SimpleGroup::read(&scene[group_ix..]);
+            match group.items_ix_at(scene, group.n_items as usize - 1) {
+                PietItem::Group(group) => group_ix = group.group as usize,
+                PietItem::FillPath(fill) => break (fill.segs_ix, fill.n_segs),
+                PietItem::StrokePath(stroke) => break (stroke.segs_ix, stroke.n_segs),
+                item => panic!("expected a path, got {:?}", item),
+            }
+        };
+        (0..n_segs as usize)
+            .map(|i| PietSegment::read(&scene[segs_ix as usize + i * PietSegment::SIZE..]))
+            .collect()
+    }
+
+    #[test]
+    fn long_curves_split() {
+        // The number of lines the tiler needs for a segment, before clamping.
+        let flatten_count = |seg: &PietSegment, scale: f32| {
+            let p = [seg.p0, seg.p1, seg.p2, seg.p3];
+            let dd = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+                (a[0] - 2.0 * b[0] + c[0]).hypot(a[1] - 2.0 * b[1] + c[1]) * scale
+            };
+            let m = if seg.seg_type == SegType::Cubic as u32 {
+                0.75 * dd(p[0], p[1], p[2]).max(dd(p[1], p[2], p[3]))
+            } else {
+                0.25 * dd(p[0], p[1], p[2])
+            };
+            (m / FLATTEN_TOLERANCE).sqrt().ceil() as usize
+        };
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.quad_to((500.0, 1000.0), (1000.0, 0.0));
+        path.curve_to((1000.0, 500.0), (0.0, -500.0), (0.0, 0.0));
+        for &scale in &[1.0, 3.0] {
+            let mut encoder = Encoder::new();
+            encoder.begin_group();
+            encoder.begin_transform(Affine::new([scale, 0.0, 0.0, 1.0, 0.0, 0.0]));
+            encoder.fill_path(&path, 0xff00_00ff, FillRule::NonZero);
+            encoder.end_group();
+            encoder.end_group();
+            let scene = encoder.finish();
+            let segs = encoded_segments(&scene);
+            // Each curve is split into pieces, ending at the original end points.
+            let quads: Vec<_> = segs
+                .iter()
+                .filter(|s| s.seg_type == SegType::Quad as u32)
+                .collect();
+            let cubics: Vec<_> = segs
+                .iter()
+                .filter(|s| s.seg_type == SegType::Cubic as u32)
+                .collect();
+            assert!(quads.len() > 1 && cubics.len() > 1);
+            assert_eq!(quads.last().unwrap().p2, [1000.0, 0.0]);
+            assert_eq!(cubics.last().unwrap().p3, [0.0, 0.0]);
+            for (prev, seg) in segs.iter().zip(&segs[1..]) {
+                assert_eq!(prev.p3, seg.p0);
+            }
+            for seg in quads.iter().chain(&cubics) {
+                let n = flatten_count(seg, scale as f32);
+                assert!(n <= MAX_FLATTEN_SEGS, "{} lines for {:?}", n, seg);
+            }
+        }
+
+        // Short curves are kept whole.
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.quad_to((5.0, 10.0), (10.0, 0.0));
+        let mut encoder = Encoder::new();
+        encoder.begin_group();
+        encoder.fill_path(&path, 0xff00_00ff, FillRule::NonZero);
+        encoder.end_group();
+        let segs = encoded_segments(&encoder.finish());
+        assert_eq!(segs.len(), 2);
+        assert_eq!(segs[0].seg_type, SegType::Quad as u32);
+    }
+
+    #[test]
+    fn tiny_dashes_stroke_solid() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((1000.0, 0.0));
+        let stroke = |style: &StrokeStyle| {
+            let mut encoder = Encoder::new();
+            encoder.begin_group();
+            encoder.stroke_path(&path, 0x0000_00ff, 2.0, style);
+            encoder.end_group();
+            encoded_segments(&encoder.finish()).len()
+        };
+        let mut style = StrokeStyle {
+            dash_pattern: vec![1e-6, 1e-6],
+            ..Default::default()
+        };
+        assert_eq!(stroke(&style), 1);
+        style.dash_pattern = vec![100.0, 100.0];
+        assert_eq!(stroke(&style), 5);
+    }
 }
@@ -0,0 +1,113 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! Snapshot tests of the generated GLSL and WGSL.
+
+mod common;
+
+use piet_metal_derive::{piet_glsl, piet_wgsl};
+
+/// Fields of less than 32 bits, which are extracted from their word. The module is
+/// passed to each generator, so that every format is tested on the same types.
+macro_rules! packed_module {
+    ($gen:ident) => {
+        $gen! {
+            mod packed {
+                struct Color {
+                    r: u8,
+                    g: u8,
+                    b: u8,
+                    a: u8,
+                }
+                struct Glyph {
+                    flags: u8,
+                    // Aligned to 2 bytes, after a byte of padding.
+                    advance: u16,
+                    offset: [i16; 2],
+                    // Two words.
+                    size: [u16; 4],
+                    color: Color,
+                    delta: i8,
+                    weight: u8,
+                    next: Ref<Glyph>,
+                }
+                struct Run {
+                    n_glyphs: u16,
+                    glyphs: Ref<Glyph>,
+                }
+                enum Node {
+                    Glyph(Glyph),
+                    Run(Run),
+                }
+            }
+        }
+    };
+}
+
+packed_module!(piet_glsl);
+packed_module!(piet_wgsl);
+
+/// Check that naga accepts the generated GLSL, after declaring the buffer `buf`
+/// that its functions read, so that broken code doesn't become a snapshot.
+fn validate_glsl(buf: &str, source: &str) {
+    let source = format!(
+        "#version 450\n\
+         layout(local_size_x = 1) in;\n\
+         layout(std430, binding = 0) buffer Buf {{ uint {}[]; }};\n\
+         {}\n\
+         void main() {{}}\n",
+        buf, source
+    );
+    let options = naga::front::glsl::Options::from(naga::ShaderStage::Compute);
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&options, &source)
+        .unwrap_or_else(|err| panic!("can't parse the generated GLSL: {:?}", err));
+    validate(&module);
+}
+
+/// As `validate_glsl`, for WGSL.
+fn validate_wgsl(buf: &str, source: &str) {
+    let source = format!(
+        "@group(0) @binding(0) var<storage, read_write> {}: array<u32>;\n{}",
+        buf, source
+    );
+    let module = naga::front::wgsl::parse_str(&source)
+        .unwrap_or_else(|err| panic!("can't parse the generated WGSL: {:?}", err));
+    validate(&module);
+}
+
+fn validate(module: &naga::Module) {
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(module)
+    .unwrap_or_else(|err| panic!("invalid generated shader: {:?}", err));
+}
+
+#[test]
+fn glsl_scene() {
+    let source = piet_metal::gen_glsl_scene();
+    validate_glsl("scene", &source);
+    common::assert_snapshot("scene.glsl", &source);
+}
+
+#[test]
+fn glsl_packed() {
+    let source = gen_glsl_packed();
+    validate_glsl("packed", &source);
+    common::assert_snapshot("packed.glsl", &source);
+}
+
+#[test]
+fn wgsl_scene() {
+    let source = piet_metal::gen_wgsl_scene();
+    validate_wgsl("scene", &source);
+    common::assert_snapshot("scene.wgsl", &source);
+}
+
+#[test]
+fn wgsl_packed() {
+    let source = gen_wgsl_packed();
+    validate_wgsl("packed", &source);
+    common::assert_snapshot("packed.wgsl", &source);
+}
@@ -0,0 +1,34 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! Snapshot testing of generated shader source.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Compare `actual` with the snapshot `name` in `tests/snapshots`. If the
+/// `UPDATE_SNAPSHOTS` environment variable is set, the snapshot is written instead.
+pub fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("can't read {}: {}", path.display(), err));
+    if expected != actual {
+        let line = expected
+            .lines()
+            .zip(actual.lines())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+        panic!(
+            "{} differs from the generated source at line {}, \
+             rerun with UPDATE_SNAPSHOTS=1 to update it",
+            name,
+            line + 1
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//  Copyright 2019 The xi-editor authors.
+
+//! Tests of `#[derive(PietMetal)]`.
+
+mod common;
+
+use piet_metal::layout::{field_offsets, MetalSource, PietMetal, Ref};
+use piet_metal_derive::PietMetal;
+
+#[derive(Clone, Copy, Debug, PartialEq, PietMetal)]
+struct Circle {
+    center: [f32; 2],
+    radius: f32,
+    rgba: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PietMetal)]
+struct Label {
+    size: u16,
+    flags: u8,
+    color: [u8; 4],
+    next: Ref<Label>,
+    origin: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PietMetal)]
+enum Shape {
+    Circle(Circle),
+    Label(Label),
+    Empty,
+}
+
+const CIRCLE: Circle = Circle {
+    center: [1.5, -2.0],
+    radius: 3.0,
+    rgba: 0xff00_80ff,
+};
+
+fn label() -> Label {
+    Label {
+        size: 0x1234,
+        flags: 0x56,
+        color: [1, 2, 3, 4],
+        next: Ref::new(0x100),
+        origin: [10.0, 20.0],
+    }
+}
+
+/// The offsets of the fields of `Label`, as laid out by `field_offsets`.
+fn label_offsets(start: usize) -> [usize; 5] {
+    field_offsets(
+        start,
+        [
+            (u16::SIZE, u16::ALIGN),
+            (u8::SIZE, u8::ALIGN),
+            (<[u8; 4]>::SIZE, <[u8; 4]>::ALIGN),
+            (Ref::<Label>::SIZE, Ref::<Label>::ALIGN),
+            (<[f32; 2]>::SIZE, <[f32; 2]>::ALIGN),
+        ],
+    )
+}
+
+/// Check that the fields of `label` are at `offsets` in `buf`.
+fn assert_label_fields(buf: &[u8], offsets: [usize; 5], label: &Label) {
+    assert_eq!(u16::read(&buf[offsets[0]..]), label.size);
+    assert_eq!(u8::read(&buf[offsets[1]..]), label.flags);
+    assert_eq!(<[u8; 4]>::read(&buf[offsets[2]..]), label.color);
+    assert_eq!(Ref::<Label>::read(&buf[offsets[3]..]), label.next);
+    assert_eq!(<[f32; 2]>::read(&buf[offsets[4]..]), label.origin);
+}
+
+#[test]
+fn struct_layout() {
+    // As in Metal, the float2 is aligned to 8 bytes.
+    assert_eq!(label_offsets(0), [0, 2, 4, 8, 16]);
+    assert_eq!((Label::SIZE, Label::ALIGN), (24, 8));
+    assert_eq!(label_offsets(4), [4, 6, 8, 12, 16]);
+    assert_eq!(Label::TAGGED_SIZE, 24);
+    assert_eq!(
+        (Circle::SIZE, Circle::ALIGN, Circle::TAGGED_SIZE),
+        (16, 8, 24)
+    );
+}
+
+#[test]
+fn struct_round_trip() {
+    let label = label();
+    let mut buf = vec![0; Label::SIZE];
+    label.write(&mut buf);
+    assert_eq!(Label::read(&buf), label);
+    assert_label_fields(&buf, label_offsets(0), &label);
+}
+
+#[test]
+fn tagged_round_trip() {
+    let label = label();
+    let mut buf = vec![0xaa; Label::TAGGED_SIZE];
+    label.write_tagged(&mut buf);
+    assert_eq!(Label::read_tagged(&buf), label);
+    assert_label_fields(&buf, label_offsets(4), &label);
+    // The tag is left for the enum to write.
+    assert_eq!(u32::read(&buf), 0xaaaa_aaaa);
+}
+
+#[test]
+fn enum_round_trip() {
+    assert_eq!(Shape::SIZE, 24);
+    assert_eq!(Shape::VARIANTS, ["Circle", "Label"]);
+    for (tag, shape) in [Shape::Circle(CIRCLE), Shape::Label(label()), Shape::Empty]
+        .iter()
+        .enumerate()
+    {
+        let mut buf = vec![0; Shape::SIZE];
+        shape.write(&mut buf);
+        // Tags are 1-based.
+        assert_eq!(u32::read(&buf), tag as u32 + 1);
+        assert_eq!(Shape::read(&buf), *shape);
+    }
+    let mut buf = vec![0; Shape::SIZE];
+    Shape::Label(label()).write(&mut buf);
+    assert_eq!(Label::read_tagged(&buf), label());
+    assert_label_fields(&buf, label_offsets(4), &label());
+    4u32.write(&mut buf);
+    assert_eq!(Shape::try_read(&buf), None);
+}
+
+#[test]
+fn metal_source() {
+    let source = MetalSource::new()
+        .add::<Circle>()
+        .add::<Label>()
+        .add::<Shape>()
+        .finish();
+    common::assert_snapshot("derive.metal", &source);
+}
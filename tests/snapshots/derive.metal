@@ -0,0 +1,57 @@
+typedef uint CircleRef;
+typedef uint LabelRef;
+typedef uint ShapeRef;
+struct CirclePacked {
+    uint tag;
+    float2 center;
+    float radius;
+    uint rgba;
+};
+CirclePacked Circle_read(const device char *buf, CircleRef ref) {
+    return *((const device CirclePacked *)(buf + ref));
+}
+float2 Circle_center(const device char *buf, CircleRef ref) {
+    return ((const device CirclePacked *)(buf + ref))->center;
+}
+float Circle_radius(const device char *buf, CircleRef ref) {
+    return ((const device CirclePacked *)(buf + ref))->radius;
+}
+uint Circle_rgba(const device char *buf, CircleRef ref) {
+    return ((const device CirclePacked *)(buf + ref))->rgba;
+}
+struct LabelPacked {
+    uint tag;
+    ushort size;
+    uchar flags;
+    uchar4 color;
+    LabelRef next;
+    float2 origin;
+};
+LabelPacked Label_read(const device char *buf, LabelRef ref) {
+    return *((const device LabelPacked *)(buf + ref));
+}
+ushort Label_size(const device char *buf, LabelRef ref) {
+    return ((const device LabelPacked *)(buf + ref))->size;
+}
+uchar Label_flags(const device char *buf, LabelRef ref) {
+    return ((const device LabelPacked *)(buf + ref))->flags;
+}
+uchar4 Label_color(const device char *buf, LabelRef ref) {
+    return ((const device LabelPacked *)(buf + ref))->color;
+}
+LabelRef Label_next(const device char *buf, LabelRef ref) {
+    return ((const device LabelPacked *)(buf + ref))->next;
+}
+float2 Label_origin(const device char *buf, LabelRef ref) {
+    return ((const device LabelPacked *)(buf + ref))->origin;
+}
+struct Shape {
+    uint tag;
+    uint body[5];
+};
+uint Shape_tag(const device char *buf, ShapeRef ref) {
+    return ((const device Shape *)(buf + ref))->tag;
+}
+#define Shape_Circle 1
+#define Shape_Label 2
+#define Shape_Empty 3
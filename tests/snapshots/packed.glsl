@@ -0,0 +1,257 @@
+uint extract_8bit_value(uint bit_shift, uint package) {
+    uint mask = 255;
+    uint result = (package >> bit_shift) & mask;
+
+    return result;
+}
+
+uint extract_16bit_value(uint bit_shift, uint package) {
+    uint mask = 65535;
+    uint result = (package >> bit_shift) & mask;
+
+    return result;
+}
+
+#define ColorRef uint
+#define ColorPackedRef uint
+#define GlyphRef uint
+#define GlyphPackedRef uint
+#define RunRef uint
+#define RunPackedRef uint
+#define NodeRef uint
+
+struct ColorPacked {
+    uint r_g_b_a;
+};
+
+ColorPacked ColorPacked_read(ColorPackedRef ref) {
+    uint ix = ref >> 2;
+    ColorPacked result;
+
+    result.r_g_b_a = packed[ix];
+    return result;
+}
+
+uint ColorPacked_r_g_b_a(ColorPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix];
+}
+
+uint ColorPacked_unpack_r(uint r_g_b_a) {
+    return extract_8bit_value(0, r_g_b_a);
+}
+
+uint ColorPacked_unpack_g(uint r_g_b_a) {
+    return extract_8bit_value(8, r_g_b_a);
+}
+
+uint ColorPacked_unpack_b(uint r_g_b_a) {
+    return extract_8bit_value(16, r_g_b_a);
+}
+
+uint ColorPacked_unpack_a(uint r_g_b_a) {
+    return extract_8bit_value(24, r_g_b_a);
+}
+
+struct Color {
+    uint r;
+    uint g;
+    uint b;
+    uint a;
+};
+
+Color ColorPacked_unpack(ColorPacked packed_form) {
+    Color result;
+
+    result.r = ColorPacked_unpack_r(packed_form.r_g_b_a);
+    result.g = ColorPacked_unpack_g(packed_form.r_g_b_a);
+    result.b = ColorPacked_unpack_b(packed_form.r_g_b_a);
+    result.a = ColorPacked_unpack_a(packed_form.r_g_b_a);
+
+    return result;
+}
+
+Color Color_read(ColorRef ref) {
+    return ColorPacked_unpack(ColorPacked_read(ref));
+}
+
+struct GlyphPacked {
+    uint tag;
+    uint flags_advance;
+    uint offset;
+    uvec2 size;
+    ColorPacked color;
+    uint delta_weight;
+    GlyphRef next;
+};
+
+GlyphPacked GlyphPacked_read(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    GlyphPacked result;
+
+    result.tag = packed[ix];
+    result.flags_advance = packed[ix + 1];
+    result.offset = packed[ix + 2];
+    result.size = uvec2(packed[ix + 4], packed[ix + 5]);
+    result.color = ColorPacked_read(ref + 24);
+    result.delta_weight = packed[ix + 7];
+    result.next = packed[ix + 8];
+    return result;
+}
+
+uint GlyphPacked_flags_advance(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 1];
+}
+
+uint GlyphPacked_offset(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 2];
+}
+
+uvec2 GlyphPacked_size(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return uvec2(packed[ix + 4], packed[ix + 5]);
+}
+
+ColorPacked GlyphPacked_color(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return ColorPacked_read(ref + 24);
+}
+
+uint GlyphPacked_delta_weight(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 7];
+}
+
+GlyphRef GlyphPacked_next(GlyphPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 8];
+}
+
+uint GlyphPacked_unpack_flags(uint flags_advance) {
+    return extract_8bit_value(0, flags_advance);
+}
+
+uint GlyphPacked_unpack_advance(uint flags_advance) {
+    return extract_16bit_value(16, flags_advance);
+}
+
+ivec2 GlyphPacked_unpack_offset(uint offset) {
+    return ivec2(int(extract_16bit_value(0, offset) << 16) >> 16, int(extract_16bit_value(16, offset) << 16) >> 16);
+}
+
+uvec4 GlyphPacked_unpack_size(uvec2 size) {
+    return uvec4(extract_16bit_value(0, size[0]), extract_16bit_value(16, size[0]), extract_16bit_value(0, size[1]), extract_16bit_value(16, size[1]));
+}
+
+int GlyphPacked_unpack_delta(uint delta_weight) {
+    return int(extract_8bit_value(0, delta_weight) << 24) >> 24;
+}
+
+uint GlyphPacked_unpack_weight(uint delta_weight) {
+    return extract_8bit_value(8, delta_weight);
+}
+
+struct Glyph {
+    uint flags;
+    uint advance;
+    ivec2 offset;
+    uvec4 size;
+    Color color;
+    int delta;
+    uint weight;
+    GlyphRef next;
+};
+
+Glyph GlyphPacked_unpack(GlyphPacked packed_form) {
+    Glyph result;
+
+    result.flags = GlyphPacked_unpack_flags(packed_form.flags_advance);
+    result.advance = GlyphPacked_unpack_advance(packed_form.flags_advance);
+    result.offset = GlyphPacked_unpack_offset(packed_form.offset);
+    result.size = GlyphPacked_unpack_size(packed_form.size);
+    result.color = ColorPacked_unpack(packed_form.color);
+    result.delta = GlyphPacked_unpack_delta(packed_form.delta_weight);
+    result.weight = GlyphPacked_unpack_weight(packed_form.delta_weight);
+    result.next = packed_form.next;
+
+    return result;
+}
+
+Glyph Glyph_read(GlyphRef ref) {
+    return GlyphPacked_unpack(GlyphPacked_read(ref));
+}
+
+struct RunPacked {
+    uint tag;
+    uint n_glyphs;
+    GlyphRef glyphs;
+};
+
+RunPacked RunPacked_read(RunPackedRef ref) {
+    uint ix = ref >> 2;
+    RunPacked result;
+
+    result.tag = packed[ix];
+    result.n_glyphs = packed[ix + 1];
+    result.glyphs = packed[ix + 2];
+    return result;
+}
+
+uint RunPacked_n_glyphs(RunPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 1];
+}
+
+GlyphRef RunPacked_glyphs(RunPackedRef ref) {
+    uint ix = ref >> 2;
+    return packed[ix + 2];
+}
+
+uint RunPacked_unpack_n_glyphs(uint n_glyphs) {
+    return extract_16bit_value(0, n_glyphs);
+}
+
+struct Run {
+    uint n_glyphs;
+    GlyphRef glyphs;
+};
+
+Run RunPacked_unpack(RunPacked packed_form) {
+    Run result;
+
+    result.n_glyphs = RunPacked_unpack_n_glyphs(packed_form.n_glyphs);
+    result.glyphs = packed_form.glyphs;
+
+    return result;
+}
+
+Run Run_read(RunRef ref) {
+    return RunPacked_unpack(RunPacked_read(ref));
+}
+
+struct Node {
+    uint tag;
+    uint body[9];
+};
+
+uint Node_tag(NodeRef ref) {
+    return packed[ref >> 2];
+}
+
+Node Node_read(NodeRef ref) {
+    uint ix = ref >> 2;
+    Node result;
+
+    result.tag = packed[ix];
+    for (uint i = 0; i < 9; i++) {
+        result.body[i] = packed[ix + 1 + i];
+    }
+    return result;
+}
+
+#define COLOR_SIZE 4
+#define NODE_SIZE 40
+#define Node_Glyph 1
+#define Node_Run 2
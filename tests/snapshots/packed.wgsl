@@ -0,0 +1,129 @@
+alias ColorRef = u32;
+alias GlyphRef = u32;
+alias RunRef = u32;
+alias NodeRef = u32;
+
+struct Color {
+    r: u32,
+    g: u32,
+    b: u32,
+    a: u32,
+}
+
+fn Color_r(offset: ColorRef) -> u32 {
+    return extractBits(packed[offset >> 2u], 0u, 8u);
+}
+
+fn Color_g(offset: ColorRef) -> u32 {
+    return extractBits(packed[offset >> 2u], 8u, 8u);
+}
+
+fn Color_b(offset: ColorRef) -> u32 {
+    return extractBits(packed[offset >> 2u], 16u, 8u);
+}
+
+fn Color_a(offset: ColorRef) -> u32 {
+    return extractBits(packed[offset >> 2u], 24u, 8u);
+}
+
+fn Color_read(offset: ColorRef) -> Color {
+    return Color(
+        Color_r(offset),
+        Color_g(offset),
+        Color_b(offset),
+        Color_a(offset),
+    );
+}
+
+struct Glyph {
+    flags: u32,
+    advance: u32,
+    offset: vec2<i32>,
+    size: vec4<u32>,
+    color: Color,
+    delta: i32,
+    weight: u32,
+    next: GlyphRef,
+}
+
+fn Glyph_flags(offset: GlyphRef) -> u32 {
+    return extractBits(packed[(offset >> 2u) + 1u], 0u, 8u);
+}
+
+fn Glyph_advance(offset: GlyphRef) -> u32 {
+    return extractBits(packed[(offset >> 2u) + 1u], 16u, 16u);
+}
+
+fn Glyph_offset(offset: GlyphRef) -> vec2<i32> {
+    return vec2<i32>(extractBits(bitcast<i32>(packed[(offset >> 2u) + 2u]), 0u, 16u), extractBits(bitcast<i32>(packed[(offset >> 2u) + 2u]), 16u, 16u));
+}
+
+fn Glyph_size(offset: GlyphRef) -> vec4<u32> {
+    return vec4<u32>(extractBits(packed[(offset >> 2u) + 4u], 0u, 16u), extractBits(packed[(offset >> 2u) + 4u], 16u, 16u), extractBits(packed[(offset >> 2u) + 5u], 0u, 16u), extractBits(packed[(offset >> 2u) + 5u], 16u, 16u));
+}
+
+fn Glyph_color(offset: GlyphRef) -> Color {
+    return Color_read(offset + 24u);
+}
+
+fn Glyph_delta(offset: GlyphRef) -> i32 {
+    return extractBits(bitcast<i32>(packed[(offset >> 2u) + 7u]), 0u, 8u);
+}
+
+fn Glyph_weight(offset: GlyphRef) -> u32 {
+    return extractBits(packed[(offset >> 2u) + 7u], 8u, 8u);
+}
+
+fn Glyph_next(offset: GlyphRef) -> GlyphRef {
+    return packed[(offset >> 2u) + 8u];
+}
+
+fn Glyph_read(offset: GlyphRef) -> Glyph {
+    return Glyph(
+        Glyph_flags(offset),
+        Glyph_advance(offset),
+        Glyph_offset(offset),
+        Glyph_size(offset),
+        Glyph_color(offset),
+        Glyph_delta(offset),
+        Glyph_weight(offset),
+        Glyph_next(offset),
+    );
+}
+
+struct Run {
+    n_glyphs: u32,
+    glyphs: GlyphRef,
+}
+
+fn Run_n_glyphs(offset: RunRef) -> u32 {
+    return extractBits(packed[(offset >> 2u) + 1u], 0u, 16u);
+}
+
+fn Run_glyphs(offset: RunRef) -> GlyphRef {
+    return packed[(offset >> 2u) + 2u];
+}
+
+fn Run_read(offset: RunRef) -> Run {
+    return Run(
+        Run_n_glyphs(offset),
+        Run_glyphs(offset),
+    );
+}
+
+fn Node_tag(offset: NodeRef) -> u32 {
+    return packed[offset >> 2u];
+}
+
+fn Node_Glyph_read(offset: NodeRef) -> Glyph {
+    return Glyph_read(offset);
+}
+
+fn Node_Run_read(offset: NodeRef) -> Run {
+    return Run_read(offset);
+}
+
+const COLOR_SIZE: u32 = 4u;
+const NODE_SIZE: u32 = 40u;
+const Node_Glyph: u32 = 1u;
+const Node_Run: u32 = 2u;
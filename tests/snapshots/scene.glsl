@@ -0,0 +1,1111 @@
+uint extract_8bit_value(uint bit_shift, uint package) {
+    uint mask = 255;
+    uint result = (package >> bit_shift) & mask;
+
+    return result;
+}
+
+uint extract_16bit_value(uint bit_shift, uint package) {
+    uint mask = 65535;
+    uint result = (package >> bit_shift) & mask;
+
+    return result;
+}
+
+#define SceneHeaderRef uint
+#define SceneHeaderPackedRef uint
+#define SimpleGroupRef uint
+#define SimpleGroupPackedRef uint
+#define PietCircleRef uint
+#define PietCirclePackedRef uint
+#define PietStrokeLineRef uint
+#define PietStrokeLinePackedRef uint
+#define PietFillRef uint
+#define PietFillPackedRef uint
+#define PietStrokePolyLineRef uint
+#define PietStrokePolyLinePackedRef uint
+#define PietGroupRef uint
+#define PietGroupPackedRef uint
+#define PietSegmentRef uint
+#define PietSegmentPackedRef uint
+#define PietFillPathRef uint
+#define PietFillPathPackedRef uint
+#define PietStrokePathRef uint
+#define PietStrokePathPackedRef uint
+#define PietClipRef uint
+#define PietClipPackedRef uint
+#define PietGradientStopRef uint
+#define PietGradientStopPackedRef uint
+#define PietGradientRef uint
+#define PietGradientPackedRef uint
+#define PietImageFillRef uint
+#define PietImageFillPackedRef uint
+#define PietImageRef uint
+#define PietImagePackedRef uint
+#define PietLayerRef uint
+#define PietLayerPackedRef uint
+#define PietItemRef uint
+
+struct SceneHeaderPacked {
+    SimpleGroupRef root;
+    uint n_images;
+    PietImageRef images;
+    uint background;
+};
+
+SceneHeaderPacked SceneHeaderPacked_read(SceneHeaderPackedRef ref) {
+    uint ix = ref >> 2;
+    SceneHeaderPacked result;
+
+    result.root = scene[ix];
+    result.n_images = scene[ix + 1];
+    result.images = scene[ix + 2];
+    result.background = scene[ix + 3];
+    return result;
+}
+
+SimpleGroupRef SceneHeaderPacked_root(SceneHeaderPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix];
+}
+
+uint SceneHeaderPacked_n_images(SceneHeaderPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+PietImageRef SceneHeaderPacked_images(SceneHeaderPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+uint SceneHeaderPacked_background(SceneHeaderPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+struct SceneHeader {
+    SimpleGroupRef root;
+    uint n_images;
+    PietImageRef images;
+    uint background;
+};
+
+SceneHeader SceneHeaderPacked_unpack(SceneHeaderPacked packed_form) {
+    SceneHeader result;
+
+    result.root = packed_form.root;
+    result.n_images = packed_form.n_images;
+    result.images = packed_form.images;
+    result.background = packed_form.background;
+
+    return result;
+}
+
+SceneHeader SceneHeader_read(SceneHeaderRef ref) {
+    return SceneHeaderPacked_unpack(SceneHeaderPacked_read(ref));
+}
+
+uint SceneHeader_images_len(SceneHeaderRef ref) {
+    return scene[(ref >> 2) + 1];
+}
+
+PietImageRef SceneHeader_images_at(SceneHeaderRef ref, uint i) {
+    return scene[(ref >> 2) + 2] + i * 12;
+}
+
+struct SimpleGroupPacked {
+    uint n_items;
+    PietItemRef items_ix;
+};
+
+SimpleGroupPacked SimpleGroupPacked_read(SimpleGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    SimpleGroupPacked result;
+
+    result.n_items = scene[ix];
+    result.items_ix = scene[ix + 1];
+    return result;
+}
+
+uint SimpleGroupPacked_n_items(SimpleGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix];
+}
+
+PietItemRef SimpleGroupPacked_items_ix(SimpleGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+struct SimpleGroup {
+    uint n_items;
+    PietItemRef items_ix;
+};
+
+SimpleGroup SimpleGroupPacked_unpack(SimpleGroupPacked packed_form) {
+    SimpleGroup result;
+
+    result.n_items = packed_form.n_items;
+    result.items_ix = packed_form.items_ix;
+
+    return result;
+}
+
+SimpleGroup SimpleGroup_read(SimpleGroupRef ref) {
+    return SimpleGroupPacked_unpack(SimpleGroupPacked_read(ref));
+}
+
+uint SimpleGroup_items_ix_len(SimpleGroupRef ref) {
+    return scene[(ref >> 2)];
+}
+
+PietItemRef SimpleGroup_items_ix_at(SimpleGroupRef ref, uint i) {
+    return scene[(ref >> 2) + 1] + i * 32;
+}
+
+uint SimpleGroup_bboxes_len(SimpleGroupRef ref) {
+    return scene[(ref >> 2)];
+}
+
+ivec4 SimpleGroup_bboxes_at(SimpleGroupRef ref, uint i) {
+    uint ix = (ref >> 2) + 2 + i * 4;
+    return ivec4(uvec4(scene[ix], scene[ix + 1], scene[ix + 2], scene[ix + 3]));
+}
+
+struct PietCirclePacked {
+    uint tag;
+};
+
+PietCirclePacked PietCirclePacked_read(PietCirclePackedRef ref) {
+    uint ix = ref >> 2;
+    PietCirclePacked result;
+
+    result.tag = scene[ix];
+    return result;
+}
+
+struct PietCircle {
+    uint dummy;
+};
+
+PietCircle PietCirclePacked_unpack(PietCirclePacked packed_form) {
+    PietCircle result;
+
+    return result;
+}
+
+PietCircle PietCircle_read(PietCircleRef ref) {
+    return PietCirclePacked_unpack(PietCirclePacked_read(ref));
+}
+
+struct PietStrokeLinePacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    float width;
+    vec2 start;
+    vec2 end;
+};
+
+PietStrokeLinePacked PietStrokeLinePacked_read(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    PietStrokeLinePacked result;
+
+    result.tag = scene[ix];
+    result.flags = scene[ix + 1];
+    result.rgba_color = scene[ix + 2];
+    result.width = uintBitsToFloat(scene[ix + 3]);
+    result.start = uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+    result.end = uintBitsToFloat(uvec2(scene[ix + 6], scene[ix + 7]));
+    return result;
+}
+
+uint PietStrokeLinePacked_flags(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietStrokeLinePacked_rgba_color(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+float PietStrokeLinePacked_width(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 3]);
+}
+
+vec2 PietStrokeLinePacked_start(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+}
+
+vec2 PietStrokeLinePacked_end(PietStrokeLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 6], scene[ix + 7]));
+}
+
+struct PietStrokeLine {
+    uint flags;
+    uint rgba_color;
+    float width;
+    vec2 start;
+    vec2 end;
+};
+
+PietStrokeLine PietStrokeLinePacked_unpack(PietStrokeLinePacked packed_form) {
+    PietStrokeLine result;
+
+    result.flags = packed_form.flags;
+    result.rgba_color = packed_form.rgba_color;
+    result.width = packed_form.width;
+    result.start = packed_form.start;
+    result.end = packed_form.end;
+
+    return result;
+}
+
+PietStrokeLine PietStrokeLine_read(PietStrokeLineRef ref) {
+    return PietStrokeLinePacked_unpack(PietStrokeLinePacked_read(ref));
+}
+
+struct PietFillPacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    uint n_points;
+    uint points_ix;
+};
+
+PietFillPacked PietFillPacked_read(PietFillPackedRef ref) {
+    uint ix = ref >> 2;
+    PietFillPacked result;
+
+    result.tag = scene[ix];
+    result.flags = scene[ix + 1];
+    result.rgba_color = scene[ix + 2];
+    result.n_points = scene[ix + 3];
+    result.points_ix = scene[ix + 4];
+    return result;
+}
+
+uint PietFillPacked_flags(PietFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietFillPacked_rgba_color(PietFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+uint PietFillPacked_n_points(PietFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+uint PietFillPacked_points_ix(PietFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 4];
+}
+
+struct PietFill {
+    uint flags;
+    uint rgba_color;
+    uint n_points;
+    uint points_ix;
+};
+
+PietFill PietFillPacked_unpack(PietFillPacked packed_form) {
+    PietFill result;
+
+    result.flags = packed_form.flags;
+    result.rgba_color = packed_form.rgba_color;
+    result.n_points = packed_form.n_points;
+    result.points_ix = packed_form.points_ix;
+
+    return result;
+}
+
+PietFill PietFill_read(PietFillRef ref) {
+    return PietFillPacked_unpack(PietFillPacked_read(ref));
+}
+
+uint PietFill_points_ix_len(PietFillRef ref) {
+    return scene[(ref >> 2) + 3];
+}
+
+vec2 PietFill_points_ix_at(PietFillRef ref, uint i) {
+    uint ix = (scene[(ref >> 2) + 4] >> 2) + i * 2;
+    return uintBitsToFloat(uvec2(scene[ix], scene[ix + 1]));
+}
+
+struct PietStrokePolyLinePacked {
+    uint tag;
+    uint rgba_color;
+    float width;
+    uint n_points;
+    uint points_ix;
+    uint flags;
+    float miter_limit;
+};
+
+PietStrokePolyLinePacked PietStrokePolyLinePacked_read(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    PietStrokePolyLinePacked result;
+
+    result.tag = scene[ix];
+    result.rgba_color = scene[ix + 1];
+    result.width = uintBitsToFloat(scene[ix + 2]);
+    result.n_points = scene[ix + 3];
+    result.points_ix = scene[ix + 4];
+    result.flags = scene[ix + 5];
+    result.miter_limit = uintBitsToFloat(scene[ix + 6]);
+    return result;
+}
+
+uint PietStrokePolyLinePacked_rgba_color(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+float PietStrokePolyLinePacked_width(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 2]);
+}
+
+uint PietStrokePolyLinePacked_n_points(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+uint PietStrokePolyLinePacked_points_ix(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 4];
+}
+
+uint PietStrokePolyLinePacked_flags(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 5];
+}
+
+float PietStrokePolyLinePacked_miter_limit(PietStrokePolyLinePackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 6]);
+}
+
+struct PietStrokePolyLine {
+    uint rgba_color;
+    float width;
+    uint n_points;
+    uint points_ix;
+    uint flags;
+    float miter_limit;
+};
+
+PietStrokePolyLine PietStrokePolyLinePacked_unpack(PietStrokePolyLinePacked packed_form) {
+    PietStrokePolyLine result;
+
+    result.rgba_color = packed_form.rgba_color;
+    result.width = packed_form.width;
+    result.n_points = packed_form.n_points;
+    result.points_ix = packed_form.points_ix;
+    result.flags = packed_form.flags;
+    result.miter_limit = packed_form.miter_limit;
+
+    return result;
+}
+
+PietStrokePolyLine PietStrokePolyLine_read(PietStrokePolyLineRef ref) {
+    return PietStrokePolyLinePacked_unpack(PietStrokePolyLinePacked_read(ref));
+}
+
+uint PietStrokePolyLine_points_ix_len(PietStrokePolyLineRef ref) {
+    return scene[(ref >> 2) + 3];
+}
+
+vec2 PietStrokePolyLine_points_ix_at(PietStrokePolyLineRef ref, uint i) {
+    uint ix = (scene[(ref >> 2) + 4] >> 2) + i * 2;
+    return uintBitsToFloat(uvec2(scene[ix], scene[ix + 1]));
+}
+
+struct PietGroupPacked {
+    uint tag;
+    SimpleGroupRef group;
+    vec2 translate;
+    vec4 mat;
+};
+
+PietGroupPacked PietGroupPacked_read(PietGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    PietGroupPacked result;
+
+    result.tag = scene[ix];
+    result.group = scene[ix + 1];
+    result.translate = uintBitsToFloat(uvec2(scene[ix + 2], scene[ix + 3]));
+    result.mat = uintBitsToFloat(uvec4(scene[ix + 4], scene[ix + 5], scene[ix + 6], scene[ix + 7]));
+    return result;
+}
+
+SimpleGroupRef PietGroupPacked_group(PietGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+vec2 PietGroupPacked_translate(PietGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 2], scene[ix + 3]));
+}
+
+vec4 PietGroupPacked_mat(PietGroupPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec4(scene[ix + 4], scene[ix + 5], scene[ix + 6], scene[ix + 7]));
+}
+
+struct PietGroup {
+    SimpleGroupRef group;
+    vec2 translate;
+    vec4 mat;
+};
+
+PietGroup PietGroupPacked_unpack(PietGroupPacked packed_form) {
+    PietGroup result;
+
+    result.group = packed_form.group;
+    result.translate = packed_form.translate;
+    result.mat = packed_form.mat;
+
+    return result;
+}
+
+PietGroup PietGroup_read(PietGroupRef ref) {
+    return PietGroupPacked_unpack(PietGroupPacked_read(ref));
+}
+
+struct PietSegmentPacked {
+    uint seg_type;
+    uint flags;
+    vec2 p0;
+    vec2 p1;
+    vec2 p2;
+    vec2 p3;
+};
+
+PietSegmentPacked PietSegmentPacked_read(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    PietSegmentPacked result;
+
+    result.seg_type = scene[ix];
+    result.flags = scene[ix + 1];
+    result.p0 = uintBitsToFloat(uvec2(scene[ix + 2], scene[ix + 3]));
+    result.p1 = uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+    result.p2 = uintBitsToFloat(uvec2(scene[ix + 6], scene[ix + 7]));
+    result.p3 = uintBitsToFloat(uvec2(scene[ix + 8], scene[ix + 9]));
+    return result;
+}
+
+uint PietSegmentPacked_seg_type(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix];
+}
+
+uint PietSegmentPacked_flags(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+vec2 PietSegmentPacked_p0(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 2], scene[ix + 3]));
+}
+
+vec2 PietSegmentPacked_p1(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+}
+
+vec2 PietSegmentPacked_p2(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 6], scene[ix + 7]));
+}
+
+vec2 PietSegmentPacked_p3(PietSegmentPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 8], scene[ix + 9]));
+}
+
+struct PietSegment {
+    uint seg_type;
+    uint flags;
+    vec2 p0;
+    vec2 p1;
+    vec2 p2;
+    vec2 p3;
+};
+
+PietSegment PietSegmentPacked_unpack(PietSegmentPacked packed_form) {
+    PietSegment result;
+
+    result.seg_type = packed_form.seg_type;
+    result.flags = packed_form.flags;
+    result.p0 = packed_form.p0;
+    result.p1 = packed_form.p1;
+    result.p2 = packed_form.p2;
+    result.p3 = packed_form.p3;
+
+    return result;
+}
+
+PietSegment PietSegment_read(PietSegmentRef ref) {
+    return PietSegmentPacked_unpack(PietSegmentPacked_read(ref));
+}
+
+struct PietFillPathPacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+};
+
+PietFillPathPacked PietFillPathPacked_read(PietFillPathPackedRef ref) {
+    uint ix = ref >> 2;
+    PietFillPathPacked result;
+
+    result.tag = scene[ix];
+    result.flags = scene[ix + 1];
+    result.rgba_color = scene[ix + 2];
+    result.n_segs = scene[ix + 3];
+    result.segs_ix = scene[ix + 4];
+    return result;
+}
+
+uint PietFillPathPacked_flags(PietFillPathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietFillPathPacked_rgba_color(PietFillPathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+uint PietFillPathPacked_n_segs(PietFillPathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+PietSegmentRef PietFillPathPacked_segs_ix(PietFillPathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 4];
+}
+
+struct PietFillPath {
+    uint flags;
+    uint rgba_color;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+};
+
+PietFillPath PietFillPathPacked_unpack(PietFillPathPacked packed_form) {
+    PietFillPath result;
+
+    result.flags = packed_form.flags;
+    result.rgba_color = packed_form.rgba_color;
+    result.n_segs = packed_form.n_segs;
+    result.segs_ix = packed_form.segs_ix;
+
+    return result;
+}
+
+PietFillPath PietFillPath_read(PietFillPathRef ref) {
+    return PietFillPathPacked_unpack(PietFillPathPacked_read(ref));
+}
+
+uint PietFillPath_segs_ix_len(PietFillPathRef ref) {
+    return scene[(ref >> 2) + 3];
+}
+
+PietSegmentRef PietFillPath_segs_ix_at(PietFillPathRef ref, uint i) {
+    return scene[(ref >> 2) + 4] + i * 40;
+}
+
+struct PietStrokePathPacked {
+    uint tag;
+    uint flags;
+    uint rgba_color;
+    float width;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    float miter_limit;
+};
+
+PietStrokePathPacked PietStrokePathPacked_read(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    PietStrokePathPacked result;
+
+    result.tag = scene[ix];
+    result.flags = scene[ix + 1];
+    result.rgba_color = scene[ix + 2];
+    result.width = uintBitsToFloat(scene[ix + 3]);
+    result.n_segs = scene[ix + 4];
+    result.segs_ix = scene[ix + 5];
+    result.miter_limit = uintBitsToFloat(scene[ix + 6]);
+    return result;
+}
+
+uint PietStrokePathPacked_flags(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietStrokePathPacked_rgba_color(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+float PietStrokePathPacked_width(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 3]);
+}
+
+uint PietStrokePathPacked_n_segs(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 4];
+}
+
+PietSegmentRef PietStrokePathPacked_segs_ix(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 5];
+}
+
+float PietStrokePathPacked_miter_limit(PietStrokePathPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 6]);
+}
+
+struct PietStrokePath {
+    uint flags;
+    uint rgba_color;
+    float width;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    float miter_limit;
+};
+
+PietStrokePath PietStrokePathPacked_unpack(PietStrokePathPacked packed_form) {
+    PietStrokePath result;
+
+    result.flags = packed_form.flags;
+    result.rgba_color = packed_form.rgba_color;
+    result.width = packed_form.width;
+    result.n_segs = packed_form.n_segs;
+    result.segs_ix = packed_form.segs_ix;
+    result.miter_limit = packed_form.miter_limit;
+
+    return result;
+}
+
+PietStrokePath PietStrokePath_read(PietStrokePathRef ref) {
+    return PietStrokePathPacked_unpack(PietStrokePathPacked_read(ref));
+}
+
+uint PietStrokePath_segs_ix_len(PietStrokePathRef ref) {
+    return scene[(ref >> 2) + 4];
+}
+
+PietSegmentRef PietStrokePath_segs_ix_at(PietStrokePathRef ref, uint i) {
+    return scene[(ref >> 2) + 5] + i * 40;
+}
+
+struct PietClipPacked {
+    uint tag;
+    uint flags;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    SimpleGroupRef group;
+};
+
+PietClipPacked PietClipPacked_read(PietClipPackedRef ref) {
+    uint ix = ref >> 2;
+    PietClipPacked result;
+
+    result.tag = scene[ix];
+    result.flags = scene[ix + 1];
+    result.n_segs = scene[ix + 2];
+    result.segs_ix = scene[ix + 3];
+    result.group = scene[ix + 4];
+    return result;
+}
+
+uint PietClipPacked_flags(PietClipPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietClipPacked_n_segs(PietClipPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+PietSegmentRef PietClipPacked_segs_ix(PietClipPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+SimpleGroupRef PietClipPacked_group(PietClipPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 4];
+}
+
+struct PietClip {
+    uint flags;
+    uint n_segs;
+    PietSegmentRef segs_ix;
+    SimpleGroupRef group;
+};
+
+PietClip PietClipPacked_unpack(PietClipPacked packed_form) {
+    PietClip result;
+
+    result.flags = packed_form.flags;
+    result.n_segs = packed_form.n_segs;
+    result.segs_ix = packed_form.segs_ix;
+    result.group = packed_form.group;
+
+    return result;
+}
+
+PietClip PietClip_read(PietClipRef ref) {
+    return PietClipPacked_unpack(PietClipPacked_read(ref));
+}
+
+uint PietClip_segs_ix_len(PietClipRef ref) {
+    return scene[(ref >> 2) + 2];
+}
+
+PietSegmentRef PietClip_segs_ix_at(PietClipRef ref, uint i) {
+    return scene[(ref >> 2) + 3] + i * 40;
+}
+
+struct PietGradientStopPacked {
+    float offset;
+    uint rgba_color;
+};
+
+PietGradientStopPacked PietGradientStopPacked_read(PietGradientStopPackedRef ref) {
+    uint ix = ref >> 2;
+    PietGradientStopPacked result;
+
+    result.offset = uintBitsToFloat(scene[ix]);
+    result.rgba_color = scene[ix + 1];
+    return result;
+}
+
+float PietGradientStopPacked_offset(PietGradientStopPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix]);
+}
+
+uint PietGradientStopPacked_rgba_color(PietGradientStopPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+struct PietGradientStop {
+    float offset;
+    uint rgba_color;
+};
+
+PietGradientStop PietGradientStopPacked_unpack(PietGradientStopPacked packed_form) {
+    PietGradientStop result;
+
+    result.offset = packed_form.offset;
+    result.rgba_color = packed_form.rgba_color;
+
+    return result;
+}
+
+PietGradientStop PietGradientStop_read(PietGradientStopRef ref) {
+    return PietGradientStopPacked_unpack(PietGradientStopPacked_read(ref));
+}
+
+struct PietGradientPacked {
+    vec4 mat;
+    vec2 translate;
+    uint flags;
+    uint n_stops;
+    PietGradientStopRef stops_ix;
+};
+
+PietGradientPacked PietGradientPacked_read(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    PietGradientPacked result;
+
+    result.mat = uintBitsToFloat(uvec4(scene[ix], scene[ix + 1], scene[ix + 2], scene[ix + 3]));
+    result.translate = uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+    result.flags = scene[ix + 6];
+    result.n_stops = scene[ix + 7];
+    result.stops_ix = scene[ix + 8];
+    return result;
+}
+
+vec4 PietGradientPacked_mat(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec4(scene[ix], scene[ix + 1], scene[ix + 2], scene[ix + 3]));
+}
+
+vec2 PietGradientPacked_translate(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+}
+
+uint PietGradientPacked_flags(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 6];
+}
+
+uint PietGradientPacked_n_stops(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 7];
+}
+
+PietGradientStopRef PietGradientPacked_stops_ix(PietGradientPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 8];
+}
+
+struct PietGradient {
+    vec4 mat;
+    vec2 translate;
+    uint flags;
+    uint n_stops;
+    PietGradientStopRef stops_ix;
+};
+
+PietGradient PietGradientPacked_unpack(PietGradientPacked packed_form) {
+    PietGradient result;
+
+    result.mat = packed_form.mat;
+    result.translate = packed_form.translate;
+    result.flags = packed_form.flags;
+    result.n_stops = packed_form.n_stops;
+    result.stops_ix = packed_form.stops_ix;
+
+    return result;
+}
+
+PietGradient PietGradient_read(PietGradientRef ref) {
+    return PietGradientPacked_unpack(PietGradientPacked_read(ref));
+}
+
+uint PietGradient_stops_ix_len(PietGradientRef ref) {
+    return scene[(ref >> 2) + 7];
+}
+
+PietGradientStopRef PietGradient_stops_ix_at(PietGradientRef ref, uint i) {
+    return scene[(ref >> 2) + 8] + i * 8;
+}
+
+struct PietImageFillPacked {
+    vec4 mat;
+    vec2 translate;
+    uint flags;
+    uint image_ix;
+};
+
+PietImageFillPacked PietImageFillPacked_read(PietImageFillPackedRef ref) {
+    uint ix = ref >> 2;
+    PietImageFillPacked result;
+
+    result.mat = uintBitsToFloat(uvec4(scene[ix], scene[ix + 1], scene[ix + 2], scene[ix + 3]));
+    result.translate = uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+    result.flags = scene[ix + 6];
+    result.image_ix = scene[ix + 7];
+    return result;
+}
+
+vec4 PietImageFillPacked_mat(PietImageFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec4(scene[ix], scene[ix + 1], scene[ix + 2], scene[ix + 3]));
+}
+
+vec2 PietImageFillPacked_translate(PietImageFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(uvec2(scene[ix + 4], scene[ix + 5]));
+}
+
+uint PietImageFillPacked_flags(PietImageFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 6];
+}
+
+uint PietImageFillPacked_image_ix(PietImageFillPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 7];
+}
+
+struct PietImageFill {
+    vec4 mat;
+    vec2 translate;
+    uint flags;
+    uint image_ix;
+};
+
+PietImageFill PietImageFillPacked_unpack(PietImageFillPacked packed_form) {
+    PietImageFill result;
+
+    result.mat = packed_form.mat;
+    result.translate = packed_form.translate;
+    result.flags = packed_form.flags;
+    result.image_ix = packed_form.image_ix;
+
+    return result;
+}
+
+PietImageFill PietImageFill_read(PietImageFillRef ref) {
+    return PietImageFillPacked_unpack(PietImageFillPacked_read(ref));
+}
+
+struct PietImagePacked {
+    uint width;
+    uint height;
+    uint data_ix;
+};
+
+PietImagePacked PietImagePacked_read(PietImagePackedRef ref) {
+    uint ix = ref >> 2;
+    PietImagePacked result;
+
+    result.width = scene[ix];
+    result.height = scene[ix + 1];
+    result.data_ix = scene[ix + 2];
+    return result;
+}
+
+uint PietImagePacked_width(PietImagePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix];
+}
+
+uint PietImagePacked_height(PietImagePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 1];
+}
+
+uint PietImagePacked_data_ix(PietImagePackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+struct PietImage {
+    uint width;
+    uint height;
+    uint data_ix;
+};
+
+PietImage PietImagePacked_unpack(PietImagePacked packed_form) {
+    PietImage result;
+
+    result.width = packed_form.width;
+    result.height = packed_form.height;
+    result.data_ix = packed_form.data_ix;
+
+    return result;
+}
+
+PietImage PietImage_read(PietImageRef ref) {
+    return PietImagePacked_unpack(PietImagePacked_read(ref));
+}
+
+struct PietLayerPacked {
+    uint tag;
+    float alpha;
+    SimpleGroupRef group;
+    uint blend;
+};
+
+PietLayerPacked PietLayerPacked_read(PietLayerPackedRef ref) {
+    uint ix = ref >> 2;
+    PietLayerPacked result;
+
+    result.tag = scene[ix];
+    result.alpha = uintBitsToFloat(scene[ix + 1]);
+    result.group = scene[ix + 2];
+    result.blend = scene[ix + 3];
+    return result;
+}
+
+float PietLayerPacked_alpha(PietLayerPackedRef ref) {
+    uint ix = ref >> 2;
+    return uintBitsToFloat(scene[ix + 1]);
+}
+
+SimpleGroupRef PietLayerPacked_group(PietLayerPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 2];
+}
+
+uint PietLayerPacked_blend(PietLayerPackedRef ref) {
+    uint ix = ref >> 2;
+    return scene[ix + 3];
+}
+
+struct PietLayer {
+    float alpha;
+    SimpleGroupRef group;
+    uint blend;
+};
+
+PietLayer PietLayerPacked_unpack(PietLayerPacked packed_form) {
+    PietLayer result;
+
+    result.alpha = packed_form.alpha;
+    result.group = packed_form.group;
+    result.blend = packed_form.blend;
+
+    return result;
+}
+
+PietLayer PietLayer_read(PietLayerRef ref) {
+    return PietLayerPacked_unpack(PietLayerPacked_read(ref));
+}
+
+struct PietItem {
+    uint tag;
+    uint body[7];
+};
+
+uint PietItem_tag(PietItemRef ref) {
+    return scene[ref >> 2];
+}
+
+PietItem PietItem_read(PietItemRef ref) {
+    uint ix = ref >> 2;
+    PietItem result;
+
+    result.tag = scene[ix];
+    for (uint i = 0; i < 7; i++) {
+        result.body[i] = scene[ix + 1 + i];
+    }
+    return result;
+}
+
+#define SCENE_HEADER_SIZE 16
+#define SIMPLE_GROUP_SIZE 8
+#define PIET_SEGMENT_SIZE 40
+#define PIET_GRADIENT_STOP_SIZE 8
+#define PIET_GRADIENT_SIZE 48
+#define PIET_IMAGE_FILL_SIZE 32
+#define PIET_IMAGE_SIZE 12
+#define PIET_ITEM_SIZE 32
+#define PietItem_Circle 1
+#define PietItem_Line 2
+#define PietItem_Fill 3
+#define PietItem_Poly 4
+#define PietItem_Group 5
+#define PietItem_FillPath 6
+#define PietItem_StrokePath 7
+#define PietItem_Clip 8
+#define PietItem_Layer 9
@@ -0,0 +1,644 @@
+alias SceneHeaderRef = u32;
+alias SimpleGroupRef = u32;
+alias PietCircleRef = u32;
+alias PietStrokeLineRef = u32;
+alias PietFillRef = u32;
+alias PietStrokePolyLineRef = u32;
+alias PietGroupRef = u32;
+alias PietSegmentRef = u32;
+alias PietFillPathRef = u32;
+alias PietStrokePathRef = u32;
+alias PietClipRef = u32;
+alias PietGradientStopRef = u32;
+alias PietGradientRef = u32;
+alias PietImageFillRef = u32;
+alias PietImageRef = u32;
+alias PietLayerRef = u32;
+alias PietItemRef = u32;
+
+struct SceneHeader {
+    root: SimpleGroupRef,
+    n_images: u32,
+    images: PietImageRef,
+    background: u32,
+}
+
+fn SceneHeader_root(offset: SceneHeaderRef) -> SimpleGroupRef {
+    return scene[offset >> 2u];
+}
+
+fn SceneHeader_n_images(offset: SceneHeaderRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn SceneHeader_images(offset: SceneHeaderRef) -> PietImageRef {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn SceneHeader_background(offset: SceneHeaderRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn SceneHeader_read(offset: SceneHeaderRef) -> SceneHeader {
+    return SceneHeader(
+        SceneHeader_root(offset),
+        SceneHeader_n_images(offset),
+        SceneHeader_images(offset),
+        SceneHeader_background(offset),
+    );
+}
+
+fn SceneHeader_images_len(offset: SceneHeaderRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn SceneHeader_images_at(offset: SceneHeaderRef, i: u32) -> PietImageRef {
+    return scene[(offset >> 2u) + 2u] + i * 12u;
+}
+
+struct SimpleGroup {
+    n_items: u32,
+    items_ix: PietItemRef,
+}
+
+fn SimpleGroup_n_items(offset: SimpleGroupRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn SimpleGroup_items_ix(offset: SimpleGroupRef) -> PietItemRef {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn SimpleGroup_read(offset: SimpleGroupRef) -> SimpleGroup {
+    return SimpleGroup(
+        SimpleGroup_n_items(offset),
+        SimpleGroup_items_ix(offset),
+    );
+}
+
+fn SimpleGroup_items_ix_len(offset: SimpleGroupRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn SimpleGroup_items_ix_at(offset: SimpleGroupRef, i: u32) -> PietItemRef {
+    return scene[(offset >> 2u) + 1u] + i * 32u;
+}
+
+fn SimpleGroup_bboxes_len(offset: SimpleGroupRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn SimpleGroup_bboxes_at(offset: SimpleGroupRef, i: u32) -> vec4<i32> {
+    let ix = (offset >> 2u) + 2u + i * 4u;
+    return vec4<i32>(bitcast<i32>(scene[ix]), bitcast<i32>(scene[ix + 1u]), bitcast<i32>(scene[ix + 2u]), bitcast<i32>(scene[ix + 3u]));
+}
+
+struct PietCircle {
+    dummy: u32,
+}
+
+fn PietCircle_read(offset: PietCircleRef) -> PietCircle {
+    return PietCircle(0u);
+}
+
+struct PietStrokeLine {
+    flags: u32,
+    rgba_color: u32,
+    width: f32,
+    start: vec2<f32>,
+    end: vec2<f32>,
+}
+
+fn PietStrokeLine_flags(offset: PietStrokeLineRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietStrokeLine_rgba_color(offset: PietStrokeLineRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietStrokeLine_width(offset: PietStrokeLineRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 3u]);
+}
+
+fn PietStrokeLine_start(offset: PietStrokeLineRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 4u]), bitcast<f32>(scene[(offset >> 2u) + 5u]));
+}
+
+fn PietStrokeLine_end(offset: PietStrokeLineRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 6u]), bitcast<f32>(scene[(offset >> 2u) + 7u]));
+}
+
+fn PietStrokeLine_read(offset: PietStrokeLineRef) -> PietStrokeLine {
+    return PietStrokeLine(
+        PietStrokeLine_flags(offset),
+        PietStrokeLine_rgba_color(offset),
+        PietStrokeLine_width(offset),
+        PietStrokeLine_start(offset),
+        PietStrokeLine_end(offset),
+    );
+}
+
+struct PietFill {
+    flags: u32,
+    rgba_color: u32,
+    n_points: u32,
+    points_ix: u32,
+}
+
+fn PietFill_flags(offset: PietFillRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietFill_rgba_color(offset: PietFillRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietFill_n_points(offset: PietFillRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietFill_points_ix(offset: PietFillRef) -> u32 {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietFill_read(offset: PietFillRef) -> PietFill {
+    return PietFill(
+        PietFill_flags(offset),
+        PietFill_rgba_color(offset),
+        PietFill_n_points(offset),
+        PietFill_points_ix(offset),
+    );
+}
+
+fn PietFill_points_ix_len(offset: PietFillRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietFill_points_ix_at(offset: PietFillRef, i: u32) -> vec2<f32> {
+    let ix = (scene[(offset >> 2u) + 4u] >> 2u) + i * 2u;
+    return vec2<f32>(bitcast<f32>(scene[ix]), bitcast<f32>(scene[ix + 1u]));
+}
+
+struct PietStrokePolyLine {
+    rgba_color: u32,
+    width: f32,
+    n_points: u32,
+    points_ix: u32,
+    flags: u32,
+    miter_limit: f32,
+}
+
+fn PietStrokePolyLine_rgba_color(offset: PietStrokePolyLineRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietStrokePolyLine_width(offset: PietStrokePolyLineRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 2u]);
+}
+
+fn PietStrokePolyLine_n_points(offset: PietStrokePolyLineRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietStrokePolyLine_points_ix(offset: PietStrokePolyLineRef) -> u32 {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietStrokePolyLine_flags(offset: PietStrokePolyLineRef) -> u32 {
+    return scene[(offset >> 2u) + 5u];
+}
+
+fn PietStrokePolyLine_miter_limit(offset: PietStrokePolyLineRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 6u]);
+}
+
+fn PietStrokePolyLine_read(offset: PietStrokePolyLineRef) -> PietStrokePolyLine {
+    return PietStrokePolyLine(
+        PietStrokePolyLine_rgba_color(offset),
+        PietStrokePolyLine_width(offset),
+        PietStrokePolyLine_n_points(offset),
+        PietStrokePolyLine_points_ix(offset),
+        PietStrokePolyLine_flags(offset),
+        PietStrokePolyLine_miter_limit(offset),
+    );
+}
+
+fn PietStrokePolyLine_points_ix_len(offset: PietStrokePolyLineRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietStrokePolyLine_points_ix_at(offset: PietStrokePolyLineRef, i: u32) -> vec2<f32> {
+    let ix = (scene[(offset >> 2u) + 4u] >> 2u) + i * 2u;
+    return vec2<f32>(bitcast<f32>(scene[ix]), bitcast<f32>(scene[ix + 1u]));
+}
+
+struct PietGroup {
+    group: SimpleGroupRef,
+    translate: vec2<f32>,
+    mat: vec4<f32>,
+}
+
+fn PietGroup_group(offset: PietGroupRef) -> SimpleGroupRef {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietGroup_translate(offset: PietGroupRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 2u]), bitcast<f32>(scene[(offset >> 2u) + 3u]));
+}
+
+fn PietGroup_mat(offset: PietGroupRef) -> vec4<f32> {
+    return vec4<f32>(bitcast<f32>(scene[(offset >> 2u) + 4u]), bitcast<f32>(scene[(offset >> 2u) + 5u]), bitcast<f32>(scene[(offset >> 2u) + 6u]), bitcast<f32>(scene[(offset >> 2u) + 7u]));
+}
+
+fn PietGroup_read(offset: PietGroupRef) -> PietGroup {
+    return PietGroup(
+        PietGroup_group(offset),
+        PietGroup_translate(offset),
+        PietGroup_mat(offset),
+    );
+}
+
+struct PietSegment {
+    seg_type: u32,
+    flags: u32,
+    p0: vec2<f32>,
+    p1: vec2<f32>,
+    p2: vec2<f32>,
+    p3: vec2<f32>,
+}
+
+fn PietSegment_seg_type(offset: PietSegmentRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn PietSegment_flags(offset: PietSegmentRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietSegment_p0(offset: PietSegmentRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 2u]), bitcast<f32>(scene[(offset >> 2u) + 3u]));
+}
+
+fn PietSegment_p1(offset: PietSegmentRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 4u]), bitcast<f32>(scene[(offset >> 2u) + 5u]));
+}
+
+fn PietSegment_p2(offset: PietSegmentRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 6u]), bitcast<f32>(scene[(offset >> 2u) + 7u]));
+}
+
+fn PietSegment_p3(offset: PietSegmentRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 8u]), bitcast<f32>(scene[(offset >> 2u) + 9u]));
+}
+
+fn PietSegment_read(offset: PietSegmentRef) -> PietSegment {
+    return PietSegment(
+        PietSegment_seg_type(offset),
+        PietSegment_flags(offset),
+        PietSegment_p0(offset),
+        PietSegment_p1(offset),
+        PietSegment_p2(offset),
+        PietSegment_p3(offset),
+    );
+}
+
+struct PietFillPath {
+    flags: u32,
+    rgba_color: u32,
+    n_segs: u32,
+    segs_ix: PietSegmentRef,
+}
+
+fn PietFillPath_flags(offset: PietFillPathRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietFillPath_rgba_color(offset: PietFillPathRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietFillPath_n_segs(offset: PietFillPathRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietFillPath_segs_ix(offset: PietFillPathRef) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietFillPath_read(offset: PietFillPathRef) -> PietFillPath {
+    return PietFillPath(
+        PietFillPath_flags(offset),
+        PietFillPath_rgba_color(offset),
+        PietFillPath_n_segs(offset),
+        PietFillPath_segs_ix(offset),
+    );
+}
+
+fn PietFillPath_segs_ix_len(offset: PietFillPathRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietFillPath_segs_ix_at(offset: PietFillPathRef, i: u32) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 4u] + i * 40u;
+}
+
+struct PietStrokePath {
+    flags: u32,
+    rgba_color: u32,
+    width: f32,
+    n_segs: u32,
+    segs_ix: PietSegmentRef,
+    miter_limit: f32,
+}
+
+fn PietStrokePath_flags(offset: PietStrokePathRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietStrokePath_rgba_color(offset: PietStrokePathRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietStrokePath_width(offset: PietStrokePathRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 3u]);
+}
+
+fn PietStrokePath_n_segs(offset: PietStrokePathRef) -> u32 {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietStrokePath_segs_ix(offset: PietStrokePathRef) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 5u];
+}
+
+fn PietStrokePath_miter_limit(offset: PietStrokePathRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 6u]);
+}
+
+fn PietStrokePath_read(offset: PietStrokePathRef) -> PietStrokePath {
+    return PietStrokePath(
+        PietStrokePath_flags(offset),
+        PietStrokePath_rgba_color(offset),
+        PietStrokePath_width(offset),
+        PietStrokePath_n_segs(offset),
+        PietStrokePath_segs_ix(offset),
+        PietStrokePath_miter_limit(offset),
+    );
+}
+
+fn PietStrokePath_segs_ix_len(offset: PietStrokePathRef) -> u32 {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietStrokePath_segs_ix_at(offset: PietStrokePathRef, i: u32) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 5u] + i * 40u;
+}
+
+struct PietClip {
+    flags: u32,
+    n_segs: u32,
+    segs_ix: PietSegmentRef,
+    group: SimpleGroupRef,
+}
+
+fn PietClip_flags(offset: PietClipRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietClip_n_segs(offset: PietClipRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietClip_segs_ix(offset: PietClipRef) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietClip_group(offset: PietClipRef) -> SimpleGroupRef {
+    return scene[(offset >> 2u) + 4u];
+}
+
+fn PietClip_read(offset: PietClipRef) -> PietClip {
+    return PietClip(
+        PietClip_flags(offset),
+        PietClip_n_segs(offset),
+        PietClip_segs_ix(offset),
+        PietClip_group(offset),
+    );
+}
+
+fn PietClip_segs_ix_len(offset: PietClipRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietClip_segs_ix_at(offset: PietClipRef, i: u32) -> PietSegmentRef {
+    return scene[(offset >> 2u) + 3u] + i * 40u;
+}
+
+struct PietGradientStop {
+    offset: f32,
+    rgba_color: u32,
+}
+
+fn PietGradientStop_offset(offset: PietGradientStopRef) -> f32 {
+    return bitcast<f32>(scene[offset >> 2u]);
+}
+
+fn PietGradientStop_rgba_color(offset: PietGradientStopRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietGradientStop_read(offset: PietGradientStopRef) -> PietGradientStop {
+    return PietGradientStop(
+        PietGradientStop_offset(offset),
+        PietGradientStop_rgba_color(offset),
+    );
+}
+
+struct PietGradient {
+    mat: vec4<f32>,
+    translate: vec2<f32>,
+    flags: u32,
+    n_stops: u32,
+    stops_ix: PietGradientStopRef,
+}
+
+fn PietGradient_mat(offset: PietGradientRef) -> vec4<f32> {
+    return vec4<f32>(bitcast<f32>(scene[offset >> 2u]), bitcast<f32>(scene[(offset >> 2u) + 1u]), bitcast<f32>(scene[(offset >> 2u) + 2u]), bitcast<f32>(scene[(offset >> 2u) + 3u]));
+}
+
+fn PietGradient_translate(offset: PietGradientRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 4u]), bitcast<f32>(scene[(offset >> 2u) + 5u]));
+}
+
+fn PietGradient_flags(offset: PietGradientRef) -> u32 {
+    return scene[(offset >> 2u) + 6u];
+}
+
+fn PietGradient_n_stops(offset: PietGradientRef) -> u32 {
+    return scene[(offset >> 2u) + 7u];
+}
+
+fn PietGradient_stops_ix(offset: PietGradientRef) -> PietGradientStopRef {
+    return scene[(offset >> 2u) + 8u];
+}
+
+fn PietGradient_read(offset: PietGradientRef) -> PietGradient {
+    return PietGradient(
+        PietGradient_mat(offset),
+        PietGradient_translate(offset),
+        PietGradient_flags(offset),
+        PietGradient_n_stops(offset),
+        PietGradient_stops_ix(offset),
+    );
+}
+
+fn PietGradient_stops_ix_len(offset: PietGradientRef) -> u32 {
+    return scene[(offset >> 2u) + 7u];
+}
+
+fn PietGradient_stops_ix_at(offset: PietGradientRef, i: u32) -> PietGradientStopRef {
+    return scene[(offset >> 2u) + 8u] + i * 8u;
+}
+
+struct PietImageFill {
+    mat: vec4<f32>,
+    translate: vec2<f32>,
+    flags: u32,
+    image_ix: u32,
+}
+
+fn PietImageFill_mat(offset: PietImageFillRef) -> vec4<f32> {
+    return vec4<f32>(bitcast<f32>(scene[offset >> 2u]), bitcast<f32>(scene[(offset >> 2u) + 1u]), bitcast<f32>(scene[(offset >> 2u) + 2u]), bitcast<f32>(scene[(offset >> 2u) + 3u]));
+}
+
+fn PietImageFill_translate(offset: PietImageFillRef) -> vec2<f32> {
+    return vec2<f32>(bitcast<f32>(scene[(offset >> 2u) + 4u]), bitcast<f32>(scene[(offset >> 2u) + 5u]));
+}
+
+fn PietImageFill_flags(offset: PietImageFillRef) -> u32 {
+    return scene[(offset >> 2u) + 6u];
+}
+
+fn PietImageFill_image_ix(offset: PietImageFillRef) -> u32 {
+    return scene[(offset >> 2u) + 7u];
+}
+
+fn PietImageFill_read(offset: PietImageFillRef) -> PietImageFill {
+    return PietImageFill(
+        PietImageFill_mat(offset),
+        PietImageFill_translate(offset),
+        PietImageFill_flags(offset),
+        PietImageFill_image_ix(offset),
+    );
+}
+
+struct PietImage {
+    width: u32,
+    height: u32,
+    data_ix: u32,
+}
+
+fn PietImage_width(offset: PietImageRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn PietImage_height(offset: PietImageRef) -> u32 {
+    return scene[(offset >> 2u) + 1u];
+}
+
+fn PietImage_data_ix(offset: PietImageRef) -> u32 {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietImage_read(offset: PietImageRef) -> PietImage {
+    return PietImage(
+        PietImage_width(offset),
+        PietImage_height(offset),
+        PietImage_data_ix(offset),
+    );
+}
+
+struct PietLayer {
+    alpha: f32,
+    group: SimpleGroupRef,
+    blend: u32,
+}
+
+fn PietLayer_alpha(offset: PietLayerRef) -> f32 {
+    return bitcast<f32>(scene[(offset >> 2u) + 1u]);
+}
+
+fn PietLayer_group(offset: PietLayerRef) -> SimpleGroupRef {
+    return scene[(offset >> 2u) + 2u];
+}
+
+fn PietLayer_blend(offset: PietLayerRef) -> u32 {
+    return scene[(offset >> 2u) + 3u];
+}
+
+fn PietLayer_read(offset: PietLayerRef) -> PietLayer {
+    return PietLayer(
+        PietLayer_alpha(offset),
+        PietLayer_group(offset),
+        PietLayer_blend(offset),
+    );
+}
+
+fn PietItem_tag(offset: PietItemRef) -> u32 {
+    return scene[offset >> 2u];
+}
+
+fn PietItem_Circle_read(offset: PietItemRef) -> PietCircle {
+    return PietCircle_read(offset);
+}
+
+fn PietItem_Line_read(offset: PietItemRef) -> PietStrokeLine {
+    return PietStrokeLine_read(offset);
+}
+
+fn PietItem_Fill_read(offset: PietItemRef) -> PietFill {
+    return PietFill_read(offset);
+}
+
+fn PietItem_Poly_read(offset: PietItemRef) -> PietStrokePolyLine {
+    return PietStrokePolyLine_read(offset);
+}
+
+fn PietItem_Group_read(offset: PietItemRef) -> PietGroup {
+    return PietGroup_read(offset);
+}
+
+fn PietItem_FillPath_read(offset: PietItemRef) -> PietFillPath {
+    return PietFillPath_read(offset);
+}
+
+fn PietItem_StrokePath_read(offset: PietItemRef) -> PietStrokePath {
+    return PietStrokePath_read(offset);
+}
+
+fn PietItem_Clip_read(offset: PietItemRef) -> PietClip {
+    return PietClip_read(offset);
+}
+
+fn PietItem_Layer_read(offset: PietItemRef) -> PietLayer {
+    return PietLayer_read(offset);
+}
+
+const SCENE_HEADER_SIZE: u32 = 16u;
+const SIMPLE_GROUP_SIZE: u32 = 8u;
+const PIET_SEGMENT_SIZE: u32 = 40u;
+const PIET_GRADIENT_STOP_SIZE: u32 = 8u;
+const PIET_GRADIENT_SIZE: u32 = 48u;
+const PIET_IMAGE_FILL_SIZE: u32 = 32u;
+const PIET_IMAGE_SIZE: u32 = 12u;
+const PIET_ITEM_SIZE: u32 = 32u;
+const PietItem_Circle: u32 = 1u;
+const PietItem_Line: u32 = 2u;
+const PietItem_Fill: u32 = 3u;
+const PietItem_Poly: u32 = 4u;
+const PietItem_Group: u32 = 5u;
+const PietItem_FillPath: u32 = 6u;
+const PietItem_StrokePath: u32 = 7u;
+const PietItem_Clip: u32 = 8u;
+const PietItem_Layer: u32 = 9u;